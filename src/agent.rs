@@ -0,0 +1,105 @@
+//! Agents running the sweep of a coordinator on their machine
+//!
+//! `forkbench agent tcp://host:port` registers with the coordinator (see
+//! `coordinator`) with `{"type": "agent", "host": ...}`, which answers with
+//! `{"type": "run", "args": [...]}` holding the options of the sweep, or
+//! `{"type": "reject", "reason": ...}`. The agent then runs the sweep like
+//! `run` does, streaming its results back to the coordinator with `--stream`.
+//! The registration is held open until the agent exits, such that the
+//! coordinator notices an agent dying mid-sweep.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+
+use crate::compare;
+use crate::config::Config;
+use crate::metadata;
+use crate::output::json_string;
+
+/// Usage string printed for `agent --help` and on argument errors
+pub const USAGE: &str = r#"usage: forkbench agent [options] <url>
+
+Joins the coordinator (`forkbench coordinator`) at <url> (`tcp://host:port`),
+runs the sweep it describes and streams the results back to it, identified
+by the hostname of this machine. Results are also reported like by `run`.
+
+options:
+    --help                      Print this message
+"#;
+
+/// Registration of an agent with its coordinator
+pub struct Agent {
+    /// Configuration of the sweep to run, streaming to the coordinator
+    pub config: Config,
+
+    /// Connection the agent registered on, closed when the agent exits
+    _registration: TcpStream,
+}
+
+/// Join the coordinator named on the command line (not including the
+/// program name and command), getting the sweep to run
+pub fn join(args: impl IntoIterator<Item = String>) -> Result<Agent, String> {
+    let mut url = None;
+
+    for arg in args {
+        match arg.as_str() {
+            "--help" | "-h" => {
+                print!("{}", USAGE);
+                std::process::exit(0);
+            }
+            _ if arg.starts_with("--") =>
+                return Err(format!("Unknown argument `{}`", arg)),
+            _ if url.is_none() => url = Some(arg),
+            _ => return Err(String::from("Expected a single coordinator")),
+        }
+    }
+
+    let url = url.ok_or("Expected the URL of the coordinator")?;
+    let addr = url.strip_prefix("tcp://").ok_or_else(|| {
+        format!("Coordinator URL `{}` is not of the form `tcp://host:port`",
+                url)
+    })?;
+
+    // Register, the coordinator answers with the sweep to run
+    let mut registration = TcpStream::connect(addr).map_err(|err| {
+        format!("Failed to connect to coordinator `{}`: {}", url, err)
+    })?;
+    let host = metadata::hostname();
+    let mut reply = String::new();
+    write!(registration, "{{\"type\": \"agent\", \"host\": {}}}\n",
+           json_string(&host))
+        .and_then(|_| BufReader::new(&registration).read_line(&mut reply))
+        .map_err(|err| {
+            format!("Failed to register with coordinator `{}`: {}", url, err)
+        })?;
+
+    let members = compare::json_members(&reply).map_err(|err| {
+        format!("Invalid reply from coordinator `{}`: {}", url, err)
+    })?;
+    let strings = |name: &str| {
+        members.iter().find(|x| x.0 == name)
+            .and_then(|x| compare::json_strings(&x.1).ok())
+            .unwrap_or_default()
+    };
+    match strings("type").first().map(|x| x.as_str()) {
+        Some("run") => {}
+        Some("reject") => {
+            return Err(format!("Rejected by coordinator `{}`: {}", url,
+                               strings("reason").join("")));
+        }
+        _ => return Err(format!("Invalid reply from coordinator `{}`", url)),
+    }
+
+    // Stream the results back, telling the coordinator if its options don't
+    // work on this machine
+    let mut args = strings("args");
+    args.extend([String::from("--stream"), url.clone()]);
+    let config = Config::parse(args).map_err(|err| {
+        let _ = write!(registration, "{{\"type\": \"error\", \"host\": {}, \
+            \"error\": {}}}\n", json_string(&host), json_string(&err));
+        format!("Invalid options from coordinator `{}`: {}", url, err)
+    })?;
+    eprint!("joined coordinator `{}` as `{}`\n", url, host);
+
+    Ok(Agent { config, _registration: registration })
+}
@@ -0,0 +1,1896 @@
+//! Process-creation backends which can be benchmarked
+
+#[cfg(unix)] use std::ffi::CString;
+#[cfg(unix)] use std::cell::{Cell, RefCell};
+
+use libc::*;
+
+#[cfg(unix)] use crate::config::Config;
+use crate::error;
+use crate::usage::Usage;
+#[cfg(unix)] use crate::error::{Error, Syscall};
+
+/// First argument passed to ourselves when spawned as a helper binary by the
+/// `posix_spawn` or `fork_exec` backends
+pub const HELPER_ARG: &str = "__forkbench_helper";
+
+/// Trivial binary launched by the `shell_spawn` backend unless
+/// `--exec-path` is given
+pub const SHELL_TARGET: &str = "/bin/true";
+
+#[cfg(unix)]
+extern "C" {
+    /// Not exposed by the `libc` crate as it is nearly impossible to use
+    /// correctly from Rust. We only ever `_exit()` in the child, which is
+    /// the one thing that is safe to do.
+    fn vfork() -> pid_t;
+}
+
+/// Process-creation mechanisms which can be selected on the command line
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BackendKind {
+    /// `fork()` a child which runs the fuzz case
+    Fork,
+
+    /// `vfork()` a child which immediately `_exit()`s, and run the fuzz case
+    /// once the parent resumes
+    Vfork,
+
+    /// `posix_spawn()` a fresh copy of the benchmark binary as a helper which
+    /// runs the fuzz case, measuring the full spawn pipeline
+    PosixSpawn,
+
+    /// `fork()` a child which `execve()`s a helper instance of the benchmark
+    /// binary (or a user-specified target)
+    ForkExec,
+
+    /// `vfork()` a child which `execve()`s a trivial binary, the way shells
+    /// and `system()`/`popen()` spawn commands
+    ShellSpawn,
+
+    /// Raw `clone()` with a user-supplied set of `CLONE_*` flags
+    Clone,
+
+    /// `clone3()` with `CLONE_PIDFD`, waiting for the child through its
+    /// pidfd rather than its PID
+    Clone3,
+
+    /// FreeBSD `pdfork()`, waiting for the child through its process
+    /// descriptor rather than its PID
+    Pdfork,
+
+    /// `fork()` followed by `execve()` of `criu restore` of a pre-dumped
+    /// process image, comparing checkpoint-based resets against `fork()`
+    CriuRestore,
+
+    /// `std::thread::spawn()` a thread which runs the fuzz case, a best-case
+    /// baseline to compare process creation against
+    Thread,
+
+    /// Dispatch fuzz cases over pipes to a pool of children forked up front
+    /// and reused, rather than creating a process per fuzz case
+    Pool,
+
+    /// Run the fuzz case in the worker itself and reset the pre-fork memory
+    /// from a snapshot afterwards, rather than creating a process
+    Snapshot,
+
+    /// `CreateProcessW()` of a stub which runs the fuzz case, the only
+    /// backend available on Windows
+    CreateProcess,
+}
+
+impl std::str::FromStr for BackendKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fork"        => Ok(BackendKind::Fork),
+            "vfork"       => Ok(BackendKind::Vfork),
+            "posix_spawn" => Ok(BackendKind::PosixSpawn),
+            "fork_exec"   => Ok(BackendKind::ForkExec),
+            "shell_spawn" => Ok(BackendKind::ShellSpawn),
+            "clone"       => Ok(BackendKind::Clone),
+            "clone3"      => Ok(BackendKind::Clone3),
+            "pdfork"      => Ok(BackendKind::Pdfork),
+            "criu_restore" => Ok(BackendKind::CriuRestore),
+            "thread"      => Ok(BackendKind::Thread),
+            "pool"        => Ok(BackendKind::Pool),
+            "snapshot"    => Ok(BackendKind::Snapshot),
+            "create_process" => Ok(BackendKind::CreateProcess),
+            _ => Err(format!("Unknown backend `{}`", s)),
+        }
+    }
+}
+
+impl BackendKind {
+    /// Name of the backend, as selected on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            BackendKind::Fork       => "fork",
+            BackendKind::Vfork      => "vfork",
+            BackendKind::PosixSpawn => "posix_spawn",
+            BackendKind::ForkExec   => "fork_exec",
+            BackendKind::ShellSpawn => "shell_spawn",
+            BackendKind::Clone      => "clone",
+            BackendKind::Clone3     => "clone3",
+            BackendKind::Pdfork     => "pdfork",
+            BackendKind::CriuRestore => "criu_restore",
+            BackendKind::Thread     => "thread",
+            BackendKind::Pool       => "pool",
+            BackendKind::Snapshot   => "snapshot",
+            BackendKind::CreateProcess => "create_process",
+        }
+    }
+
+    /// Whether this backend can be used on the current platform
+    pub fn is_supported(&self) -> bool {
+        match self {
+            BackendKind::Fork | BackendKind::Vfork |
+                BackendKind::PosixSpawn | BackendKind::ForkExec |
+                BackendKind::ShellSpawn | BackendKind::Thread |
+                BackendKind::Pool | BackendKind::Snapshot => cfg!(unix),
+            BackendKind::Clone | BackendKind::Clone3 |
+                BackendKind::CriuRestore =>
+                cfg!(target_os = "linux"),
+            BackendKind::Pdfork => cfg!(target_os = "freebsd"),
+            BackendKind::CreateProcess => cfg!(windows),
+        }
+    }
+
+    /// Whether fuzz cases run in a copy of the benchmark process (or a
+    /// thread of it) rather than a freshly launched executable, such that
+    /// they can run any workload
+    pub fn runs_in_process(&self) -> bool {
+        matches!(self, BackendKind::Fork | BackendKind::Vfork |
+                       BackendKind::Clone | BackendKind::Clone3 |
+                       BackendKind::Pdfork | BackendKind::Thread |
+                       BackendKind::Pool | BackendKind::Snapshot)
+    }
+
+    /// Get the implementation of this backend for running fuzz cases of
+    /// `workload` iterations on behalf of worker `thr_id`. The `snapshot`
+    /// backend is created with `Snapshot::new()` instead, as it needs the
+    /// memory to reset.
+    #[cfg(unix)]
+    pub fn backend(&self, config: &Config, thr_id: usize, workload: u64)
+            -> Box<dyn Backend> {
+        match self {
+            BackendKind::Fork if config.reaper == Reaper::Signalfd =>
+                Box::new(AsyncFork::new(config.in_flight)),
+            #[cfg(target_os = "linux")]
+            BackendKind::Fork if config.ptrace != Ptrace::Off =>
+                Box::new(Traced {
+                    mode:     config.ptrace,
+                    syscalls: config.ptrace_syscalls,
+                }),
+            BackendKind::Fork if config.pipe_handshake => Box::new(PipeFork),
+            BackendKind::Fork if config.ipc != Ipc::Off =>
+                Box::new(IpcFork::new(config.ipc, config.ipc_size)),
+            BackendKind::Fork  => Box::new(Fork),
+            BackendKind::Vfork => Box::new(Vfork),
+            BackendKind::PosixSpawn => Box::new(PosixSpawn(
+                ExecTarget::new(config, thr_id, workload))),
+            BackendKind::ForkExec => Box::new(ForkExec(
+                ExecTarget::new(config, thr_id, workload))),
+            BackendKind::CriuRestore => Box::new(ForkExec(
+                ExecTarget::criu_restore(config, thr_id))),
+            BackendKind::ShellSpawn => Box::new(ShellSpawn(
+                ExecTarget::shell(config, thr_id))),
+            BackendKind::Thread => Box::new(Thread),
+            BackendKind::Pool   => Box::new(Pool::new(config.pool_size)),
+            BackendKind::Snapshot =>
+                unreachable!("Snapshot backends are created by the worker"),
+            #[cfg(target_os = "linux")]
+            BackendKind::Clone =>
+                Box::new(RawClone::new(config.clone_flags)),
+            #[cfg(target_os = "linux")]
+            BackendKind::Clone3 => Box::new(Clone3(config.pidfd_wait)),
+            #[cfg(target_os = "freebsd")]
+            BackendKind::Pdfork => Box::new(Pdfork),
+            _ => unreachable!("Backend {} is not supported", self.name()),
+        }
+    }
+}
+
+/// How the `fork` backend reaps its children
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Reaper {
+    /// Wait for each child right after forking it
+    Serial,
+
+    /// Reap children asynchronously as `SIGCHLD` arrives on a `signalfd`,
+    /// overlapping forking the next child with reaping earlier ones
+    Signalfd,
+}
+
+impl std::str::FromStr for Reaper {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "serial"   => Ok(Reaper::Serial),
+            "signalfd" => Ok(Reaper::Signalfd),
+            _ => Err(format!("Unknown reaper `{}`", s)),
+        }
+    }
+}
+
+impl Reaper {
+    /// Name of the reaper, as selected on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            Reaper::Serial   => "serial",
+            Reaper::Signalfd => "signalfd",
+        }
+    }
+
+    /// Whether this reaper can be used on the current platform
+    pub fn is_supported(&self) -> bool {
+        match self {
+            Reaper::Serial   => true,
+            Reaper::Signalfd => cfg!(target_os = "linux"),
+        }
+    }
+}
+
+/// How the `fork` backend supervises its children with `ptrace()`, like a
+/// debugger-based fuzzer would
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Ptrace {
+    /// Children aren't traced
+    Off,
+
+    /// Each child asks to be traced by its worker with `PTRACE_TRACEME`
+    Traceme,
+
+    /// The worker attaches to each child with `PTRACE_ATTACH`
+    Attach,
+}
+
+impl std::str::FromStr for Ptrace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off"     => Ok(Ptrace::Off),
+            "traceme" => Ok(Ptrace::Traceme),
+            "attach"  => Ok(Ptrace::Attach),
+            _ => Err(format!("Unknown ptrace mode `{}`", s)),
+        }
+    }
+}
+
+impl Ptrace {
+    /// Name of the mode, as selected on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            Ptrace::Off     => "off",
+            Ptrace::Traceme => "traceme",
+            Ptrace::Attach  => "attach",
+        }
+    }
+
+    /// Whether this mode can be used on the current platform
+    pub fn is_supported(&self) -> bool {
+        match self {
+            Ptrace::Off => true,
+            Ptrace::Traceme | Ptrace::Attach => cfg!(target_os = "linux"),
+        }
+    }
+}
+
+/// How children of the `fork` backend send a message back to their worker
+/// after the fuzz case, like the coverage or telemetry of a fuzzer
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Ipc {
+    /// Nothing is sent
+    Off,
+
+    /// Over a `socketpair()` created for each child, which the worker reads
+    /// the message from before reaping it
+    Socketpair,
+
+    /// Through a ring buffer in memory shared by the worker and its
+    /// children, which the worker drains the message from before reaping
+    /// the child
+    Ring,
+}
+
+impl std::str::FromStr for Ipc {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off"        => Ok(Ipc::Off),
+            "socketpair" => Ok(Ipc::Socketpair),
+            "ring"       => Ok(Ipc::Ring),
+            _ => Err(format!("Unknown IPC mode `{}`", s)),
+        }
+    }
+}
+
+impl Ipc {
+    /// Name of the mode, as selected on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            Ipc::Off        => "off",
+            Ipc::Socketpair => "socketpair",
+            Ipc::Ring       => "ring",
+        }
+    }
+}
+
+/// How the `clone3` backend waits for the child through its pidfd
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PidfdWait {
+    /// Reap it with `waitid(P_PIDFD)` right away
+    Waitid,
+
+    /// `poll()` the pidfd until the child exited, then reap it
+    Poll,
+}
+
+impl std::str::FromStr for PidfdWait {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "waitid" => Ok(PidfdWait::Waitid),
+            "poll"   => Ok(PidfdWait::Poll),
+            _ => Err(format!("Unknown pidfd wait `{}`", s)),
+        }
+    }
+}
+
+impl PidfdWait {
+    /// Name of the wait strategy, as selected on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            PidfdWait::Waitid => "waitid",
+            PidfdWait::Poll   => "poll",
+        }
+    }
+}
+
+/// How the `snapshot` backend resets the memory after each fuzz case
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SnapshotReset {
+    /// Copy the whole pristine snapshot back
+    Copy,
+
+    /// Only copy back the pages the fuzz case dirtied, found through the
+    /// soft-dirty bits in `/proc/self/pagemap`
+    Dirty,
+}
+
+impl std::str::FromStr for SnapshotReset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "copy"  => Ok(SnapshotReset::Copy),
+            "dirty" => Ok(SnapshotReset::Dirty),
+            _ => Err(format!("Unknown snapshot reset `{}`", s)),
+        }
+    }
+}
+
+impl SnapshotReset {
+    /// Name of the reset strategy, as selected on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            SnapshotReset::Copy  => "copy",
+            SnapshotReset::Dirty => "dirty",
+        }
+    }
+
+    /// Whether this strategy can be used on the current platform
+    pub fn is_supported(&self) -> bool {
+        match self {
+            SnapshotReset::Copy  => true,
+            SnapshotReset::Dirty => cfg!(target_os = "linux"),
+        }
+    }
+}
+
+/// Names of `clone()` flags which can be supplied on the command line
+#[cfg(target_os = "linux")]
+const CLONE_FLAG_NAMES: &[(&str, c_int)] = &[
+    ("vm",      CLONE_VM),
+    ("fs",      CLONE_FS),
+    ("files",   CLONE_FILES),
+    ("sighand", CLONE_SIGHAND),
+    ("sysvsem", CLONE_SYSVSEM),
+    ("io",      CLONE_IO),
+    ("newipc",  CLONE_NEWIPC),
+    ("newnet",  CLONE_NEWNET),
+    ("newns",   CLONE_NEWNS),
+    ("newpid",  CLONE_NEWPID),
+    ("newuts",  CLONE_NEWUTS),
+    ("newuser", CLONE_NEWUSER),
+    ("newcgroup", CLONE_NEWCGROUP),
+];
+
+/// `clone()` flags only exist on Linux
+#[cfg(not(target_os = "linux"))]
+const CLONE_FLAG_NAMES: &[(&str, c_int)] = &[];
+
+/// Parse a comma separated list of `clone()` flag names (eg. `vm,files`)
+pub fn parse_clone_flags(s: &str) -> Result<c_int, String> {
+    let mut flags = 0;
+
+    for name in s.split(',').map(|x| x.trim()).filter(|x| !x.is_empty()) {
+        let (_, flag) = CLONE_FLAG_NAMES.iter()
+            .find(|(flag_name, _)| *flag_name == name)
+            .ok_or_else(|| format!("Unknown clone flag `{}`", name))?;
+        flags |= flag;
+    }
+
+    // Signal handlers can only be shared with a shared address space
+    #[cfg(target_os = "linux")]
+    if flags & CLONE_SIGHAND != 0 && flags & CLONE_VM == 0 {
+        return Err("Clone flag `sighand` requires `vm`".into());
+    }
+
+    Ok(flags)
+}
+
+/// Render `clone()` flags as the comma separated names they were parsed from
+pub fn clone_flags_names(flags: c_int) -> String {
+    CLONE_FLAG_NAMES.iter()
+        .filter(|(_, flag)| flags & flag != 0)
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Names of the namespaces fuzz cases can `unshare()`
+#[cfg(target_os = "linux")]
+const NAMESPACE_NAMES: &[(&str, c_int)] = &[
+    ("ipc",    CLONE_NEWIPC),
+    ("net",    CLONE_NEWNET),
+    ("mount",  CLONE_NEWNS),
+    ("pid",    CLONE_NEWPID),
+    ("uts",    CLONE_NEWUTS),
+    ("user",   CLONE_NEWUSER),
+    ("cgroup", CLONE_NEWCGROUP),
+];
+
+/// Namespaces only exist on Linux
+#[cfg(not(target_os = "linux"))]
+const NAMESPACE_NAMES: &[(&str, c_int)] = &[];
+
+/// Parse a comma separated list of namespace names (eg. `net,mount`) into
+/// the `CLONE_NEW*` flags `unshare()` takes
+pub fn parse_namespaces(s: &str) -> Result<c_int, String> {
+    let mut flags = 0;
+
+    for name in s.split(',').map(|x| x.trim()).filter(|x| !x.is_empty()) {
+        let (_, flag) = NAMESPACE_NAMES.iter()
+            .find(|(ns_name, _)| *ns_name == name)
+            .ok_or_else(|| format!("Unknown namespace `{}`", name))?;
+        flags |= flag;
+    }
+
+    Ok(flags)
+}
+
+/// Render `CLONE_NEW*` flags as the comma separated namespace names they
+/// were parsed from
+pub fn namespaces_names(flags: c_int) -> String {
+    NAMESPACE_NAMES.iter()
+        .filter(|(_, flag)| flags & flag != 0)
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// A fuzz case to run in a freshly created process
+pub type FuzzCase<'a> = dyn FnMut() + Send + 'a;
+
+/// What the worker observed when reaping a single fuzz case
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Reaped {
+    /// Resource usage of the process, zero if it had none of its own
+    pub usage: Usage,
+
+    /// Clock ticks the worker spent blocked waiting for it to exit
+    pub wait_cycles: u64,
+
+    /// Number of fuzz cases which were killed by a signal or exited with a
+    /// non-zero status, rather than completing
+    pub crashes: u64,
+}
+
+impl Reaped {
+    /// Add a process reaped with the wait `status` and resource usage
+    /// `usage`
+    #[cfg(unix)]
+    fn add(&mut self, status: c_int, usage: &rusage) {
+        let mut fields = self.usage.fields();
+        for (total, field) in fields.iter_mut()
+                .zip(Usage::from(usage).fields().iter()) {
+            *total += field;
+        }
+        self.usage = Usage::from_fields(fields);
+        self.crashes += crashed(status) as u64;
+    }
+}
+
+/// A mechanism for creating a new process (or process-like context) for
+/// every fuzz case
+pub trait Backend {
+    /// Create a new process, run `fuzz_case` once, and wait for the process
+    /// to be torn down
+    fn run(&self, fuzz_case: &mut FuzzCase) -> error::Result<Reaped>;
+
+    /// Reap the processes still running once the last fuzz case was run,
+    /// nothing for backends which wait for each fuzz case right away
+    fn finish(&self) -> error::Result<Reaped> {
+        Ok(Reaped::default())
+    }
+
+    /// Whether `fuzz_case` keeps being run for later fuzz cases (eg. by
+    /// long-lived children), rather than only by the `run()` it was passed
+    /// to. Such fuzz cases can't rely on what it captured being current.
+    fn reruns_fuzz_case(&self) -> bool {
+        false
+    }
+}
+
+/// Whether the wait `status` of a reaped process means it didn't complete,
+/// either killed by a signal or exiting with a non-zero status
+#[cfg(unix)]
+fn crashed(status: c_int) -> bool {
+    !WIFEXITED(status) || WEXITSTATUS(status) != 0
+}
+
+/// Wait for the subchild `pid` to exit
+#[cfg(unix)]
+fn reap(pid: pid_t) -> error::Result<Reaped> {
+    let mut usage: rusage = unsafe { core::mem::zeroed() };
+    let mut status = 0;
+    let it = crate::clock::now();
+    Error::check(Syscall::Wait4, unsafe {
+        wait4(pid, &mut status, 0, &mut usage)
+    })?;
+
+    let mut ret = Reaped {
+        wait_cycles: crate::clock::now() - it,
+        ..Reaped::default()
+    };
+    ret.add(status, &usage);
+    Ok(ret)
+}
+
+/// Classic `fork()` based resets, the fuzz case runs in a copy-on-write copy
+/// of the worker
+#[cfg(unix)]
+pub struct Fork;
+
+#[cfg(unix)]
+impl Backend for Fork {
+    fn run(&self, fuzz_case: &mut FuzzCase) -> error::Result<Reaped> {
+        let subchild = Error::check(Syscall::Fork, unsafe { fork() })?;
+
+        if subchild == 0 {
+            fuzz_case();
+
+            // Done
+            unsafe { exit(0); }
+        } else {
+            // Wait for the subchild to exit
+            reap(subchild)
+        }
+    }
+}
+
+/// `fork()` based resets with a round trip over pipes between the worker and
+/// every child before it exits, the handshake of most fork-based fuzzers:
+/// the worker writes a token once the child exists, and the child reads it,
+/// runs the fuzz case and writes back an acknowledgement. The pipes are
+/// created for every child such that one dying early closes them, rather
+/// than leaving the worker waiting for its acknowledgement.
+#[cfg(unix)]
+pub struct PipeFork;
+
+#[cfg(unix)]
+impl PipeFork {
+    /// Send the token over `token` and wait for the acknowledgement on
+    /// `ack`, which a child crashing before acknowledging never sends
+    fn round_trip(token: c_int, ack: c_int) -> error::Result<()> {
+        let mut byte = 1u8;
+        Error::check(Syscall::Write, unsafe {
+            write(token, &byte as *const u8 as *const c_void, 1)
+        })?;
+        Error::check(Syscall::Read, unsafe {
+            read(ack, &mut byte as *mut u8 as *mut c_void, 1)
+        })?;
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+impl Backend for PipeFork {
+    fn run(&self, fuzz_case: &mut FuzzCase) -> error::Result<Reaped> {
+        let (token_read, token) = create_pipe()?;
+        let (ack, ack_write) = match create_pipe() {
+            Ok(fds) => fds,
+            Err(err) => {
+                unsafe { close(token_read); close(token); }
+                return Err(err);
+            }
+        };
+
+        let subchild = unsafe { fork() };
+        if subchild == 0 {
+            // Run the fuzz case once the token arrived, and acknowledge it
+            let mut byte = 0u8;
+            unsafe {
+                if read(token_read, &mut byte as *mut u8 as *mut c_void, 1)
+                        == 1 {
+                    fuzz_case();
+                    write(ack_write, &byte as *const u8 as *const c_void, 1);
+                }
+
+                // Done
+                exit(0);
+            }
+        }
+
+        // Only the child uses the other ends
+        unsafe { close(token_read); close(ack_write); }
+        let it = crate::clock::now();
+        let handshake = if subchild == -1 {
+            Err(Error::last_os_error(Syscall::Fork))
+        } else {
+            Self::round_trip(token, ack)
+        };
+        let handshake_cycles = crate::clock::now() - it;
+        unsafe { close(token); close(ack); }
+
+        // Don't leave the child behind if the round trip failed
+        if let Err(err) = handshake {
+            if subchild > 0 {
+                unsafe {
+                    kill(subchild, SIGKILL);
+                    waitpid(subchild, core::ptr::null_mut(), 0);
+                }
+            }
+            return Err(err);
+        }
+
+        // Waiting for the acknowledgement counts as waiting for the child
+        let mut reaped = reap(subchild)?;
+        reaped.wait_cycles += handshake_cycles;
+        Ok(reaped)
+    }
+}
+
+/// Size in bytes of the ring of the `ring` IPC mode, larger messages are
+/// streamed through it while the worker drains it
+#[cfg(unix)]
+const IPC_RING: usize = 65536;
+
+/// Ring of the `ring` IPC mode, shared by a worker and its children
+#[cfg(unix)]
+type IpcRing = crate::sync::Ring<u8, IPC_RING>;
+
+/// `fork()` based resets where every child sends a message back to its
+/// worker after the fuzz case, like the coverage map or telemetry of a
+/// fuzzer, over a `socketpair()` or a shared ring buffer. The sockets are
+/// created for every child such that one dying early closes them, rather
+/// than leaving the worker waiting for the rest of its message.
+#[cfg(unix)]
+pub struct IpcFork {
+    /// How the message is sent
+    mode: Ipc,
+
+    /// Message each child sends
+    message: Vec<u8>,
+
+    /// Buffer the worker receives messages into
+    buffer: RefCell<Vec<u8>>,
+
+    /// Ring of the `ring` mode, mapped on the first fuzz case
+    ring: RefCell<Option<crate::sync::Shared<IpcRing>>>,
+}
+
+#[cfg(unix)]
+impl IpcFork {
+    /// Create the backend for messages of `size` bytes sent with `mode`
+    pub fn new(mode: Ipc, size: usize) -> Self {
+        IpcFork {
+            mode,
+            message: (0..size).map(|x| x as u8).collect(),
+            buffer:  RefCell::new(vec![0; size]),
+            ring:    RefCell::new(None),
+        }
+    }
+
+    /// Read the message of a child from `socket` into `buf` until it's
+    /// complete or the child closed its end
+    fn receive(socket: c_int, buf: &mut [u8]) -> error::Result<()> {
+        let mut received = 0;
+        while received < buf.len() {
+            let bytes = Error::check(Syscall::Read, unsafe {
+                read(socket, buf[received..].as_mut_ptr() as *mut c_void,
+                     buf.len() - received)
+            })?;
+            if bytes == 0 {
+                break;
+            }
+            received += bytes as usize;
+        }
+        Ok(())
+    }
+
+    /// Drain the message of child `pid` from `ring` into `buf` until it's
+    /// complete or the child exited
+    fn drain(ring: &IpcRing, pid: pid_t, buf: &mut [u8])
+            -> error::Result<()> {
+        let mut received = 0;
+        while received < buf.len() {
+            let bytes = ring.pop_slice(&mut buf[received..]);
+            received += bytes;
+            if bytes > 0 {
+                continue;
+            }
+
+            // Check whether the child exited without reaping it, taking
+            // whatever it sent right before
+            let mut info: siginfo_t = unsafe { core::mem::zeroed() };
+            Error::check(Syscall::Waitid, unsafe {
+                waitid(P_PID, pid as id_t, &mut info,
+                       WEXITED | WNOHANG | WNOWAIT)
+            })?;
+            if info.si_signo == SIGCHLD {
+                ring.pop_slice(&mut buf[received..]);
+                break;
+            }
+            std::thread::yield_now();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+impl Backend for IpcFork {
+    fn run(&self, fuzz_case: &mut FuzzCase) -> error::Result<Reaped> {
+        if self.mode == Ipc::Ring {
+            let mut ring = self.ring.borrow_mut();
+            if ring.is_none() {
+                *ring = Some(crate::sync::Shared::new()?);
+            }
+        }
+        let ring = self.ring.borrow();
+
+        let mut sockets = [-1; 2];
+        if self.mode == Ipc::Socketpair {
+            Error::check(Syscall::Socketpair, unsafe {
+                socketpair(AF_UNIX, SOCK_STREAM, 0, sockets.as_mut_ptr())
+            })?;
+        }
+
+        let subchild = unsafe { fork() };
+        if subchild == 0 {
+            fuzz_case();
+
+            // Send the message, giving up on it if the worker went away
+            match ring.as_ref() {
+                Some(ring) => {
+                    let mut sent = 0;
+                    while sent < self.message.len() {
+                        sent += ring.push_slice(&self.message[sent..]);
+                        if sent < self.message.len() {
+                            std::thread::yield_now();
+                        }
+                    }
+                }
+                None => {
+                    let mut sent = 0;
+                    while sent < self.message.len() {
+                        let bytes = unsafe {
+                            write(sockets[1],
+                                  self.message[sent..].as_ptr()
+                                      as *const c_void,
+                                  self.message.len() - sent)
+                        };
+                        if bytes <= 0 {
+                            break;
+                        }
+                        sent += bytes as usize;
+                    }
+                }
+            }
+
+            // Done
+            unsafe { exit(0); }
+        }
+
+        // Only the child uses the other end, receive the message before
+        // reaping as it may not fit in the socket buffer
+        let it = crate::clock::now();
+        let mut buffer = self.buffer.borrow_mut();
+        let received = if self.mode == Ipc::Socketpair {
+            unsafe { close(sockets[1]); }
+            let received = if subchild == -1 {
+                Err(Error::last_os_error(Syscall::Fork))
+            } else {
+                Self::receive(sockets[0], &mut buffer)
+            };
+            unsafe { close(sockets[0]); }
+            received
+        } else {
+            Error::check(Syscall::Fork, subchild).and_then(|_| {
+                match ring.as_ref() {
+                    Some(ring) => Self::drain(ring, subchild, &mut buffer),
+                    None => Ok(()),
+                }
+            })
+        };
+        let receive_cycles = crate::clock::now() - it;
+
+        // Don't leave the child behind if receiving failed
+        if let Err(err) = received {
+            if subchild > 0 {
+                unsafe {
+                    kill(subchild, SIGKILL);
+                    waitpid(subchild, core::ptr::null_mut(), 0);
+                }
+            }
+            return Err(err);
+        }
+
+        // Waiting for the message counts as waiting for the child
+        let mut reaped = reap(subchild)?;
+        std::hint::black_box(&*buffer);
+        reaped.wait_cycles += receive_cycles;
+        Ok(reaped)
+    }
+}
+
+/// `fork()` based resets with every child supervised by its worker through
+/// `ptrace()`. The child stops itself right away such that the whole fuzz
+/// case runs traced, and the worker handles its stops until it exits: the
+/// initial one, one at its exit (`PTRACE_O_TRACEEXIT`), and with `syscalls`
+/// one on entry and exit of every system call. Other signals are delivered
+/// as a debugger would.
+#[cfg(target_os = "linux")]
+pub struct Traced {
+    /// How the worker becomes the tracer of the child
+    mode: Ptrace,
+
+    /// Stop the child at every system call
+    syscalls: bool,
+}
+
+#[cfg(target_os = "linux")]
+impl Traced {
+    /// Supervise the traced child `pid` until it exits
+    fn supervise(&self, pid: pid_t) -> error::Result<Reaped> {
+        let null = core::ptr::null_mut::<c_void>();
+        let mut usage: rusage = unsafe { core::mem::zeroed() };
+        let mut status = 0;
+        let it = crate::clock::now();
+
+        // Wait for the child to stop itself before attaching, such that it
+        // can't run ahead untraced
+        if self.mode == Ptrace::Attach {
+            Error::check(Syscall::Wait4, unsafe {
+                wait4(pid, &mut status, WUNTRACED, &mut usage)
+            })?;
+            Error::check(Syscall::Ptrace, unsafe {
+                ptrace(PTRACE_ATTACH, pid, null, null)
+            })?;
+        }
+
+        let mut first = true;
+        loop {
+            Error::check(Syscall::Wait4, unsafe {
+                wait4(pid, &mut status, 0, &mut usage)
+            })?;
+            if !WIFSTOPPED(status) {
+                break;
+            }
+
+            // Kill the child if the worker dies, and report its exit and
+            // tell system call stops apart from `SIGTRAP`s
+            if first {
+                Error::check(Syscall::Ptrace, unsafe {
+                    ptrace(PTRACE_SETOPTIONS, pid, null,
+                           (PTRACE_O_EXITKILL | PTRACE_O_TRACEEXIT |
+                            PTRACE_O_TRACESYSGOOD) as usize as *mut c_void)
+                })?;
+                first = false;
+            }
+
+            // Event and system call stops, and the `SIGSTOP`s of attaching,
+            // are swallowed, anything else is the fuzz case's own signal
+            let signal = WSTOPSIG(status);
+            let deliver = if status >> 16 != 0 || signal == SIGTRAP | 0x80 ||
+                    signal == SIGSTOP {
+                0
+            } else {
+                signal
+            };
+            let request = if self.syscalls { PTRACE_SYSCALL }
+                          else { PTRACE_CONT };
+            Error::check(Syscall::Ptrace, unsafe {
+                ptrace(request, pid, null, deliver as usize as *mut c_void)
+            })?;
+        }
+
+        let mut ret = Reaped {
+            wait_cycles: crate::clock::now() - it,
+            ..Reaped::default()
+        };
+        ret.add(status, &usage);
+        Ok(ret)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Backend for Traced {
+    fn run(&self, fuzz_case: &mut FuzzCase) -> error::Result<Reaped> {
+        let subchild = Error::check(Syscall::Fork, unsafe { fork() })?;
+
+        if subchild == 0 {
+            // Failing to be traced is counted as a crash
+            unsafe {
+                let null = core::ptr::null_mut::<c_void>();
+                if self.mode == Ptrace::Traceme &&
+                        ptrace(PTRACE_TRACEME, 0, null, null) == -1 {
+                    exit(1);
+                }
+                raise(SIGSTOP);
+            }
+
+            fuzz_case();
+
+            // Done
+            unsafe { exit(0); }
+        }
+
+        // Don't leave a stopped child behind if supervising it failed
+        let reaped = self.supervise(subchild);
+        if reaped.is_err() {
+            unsafe {
+                kill(subchild, SIGKILL);
+                waitpid(subchild, core::ptr::null_mut(), 0);
+            }
+        }
+        reaped
+    }
+}
+
+/// `fork()` based resets with asynchronous reaping. `SIGCHLD` is blocked
+/// and received through a `signalfd`, children which exited are reaped
+/// after forking the next one rather than waiting for each in turn, with at
+/// most `in_flight` children alive at a time.
+#[cfg(target_os = "linux")]
+pub struct AsyncFork {
+    /// Maximum number of children alive at a time
+    in_flight: usize,
+
+    /// The `signalfd` receiving `SIGCHLD`, created on the first fuzz case
+    signalfd: Cell<Option<c_int>>,
+
+    /// Number of children which haven't been reaped yet
+    running: Cell<usize>,
+
+    /// Children reaped after a failed fork, reported with the next fuzz case
+    pending: Cell<Reaped>,
+}
+
+#[cfg(target_os = "linux")]
+impl AsyncFork {
+    /// Create a backend keeping at most `in_flight` children alive
+    pub fn new(in_flight: usize) -> Self {
+        AsyncFork {
+            in_flight,
+            signalfd: Cell::new(None),
+            running:  Cell::new(0),
+            pending:  Cell::new(Reaped::default()),
+        }
+    }
+
+    /// Get the `signalfd`, blocking `SIGCHLD` and creating it if needed
+    fn signalfd(&self) -> error::Result<c_int> {
+        if let Some(fd) = self.signalfd.get() {
+            return Ok(fd);
+        }
+
+        let fd = unsafe {
+            let mut mask: sigset_t = core::mem::zeroed();
+            sigemptyset(&mut mask);
+            sigaddset(&mut mask, SIGCHLD);
+
+            let errno = pthread_sigmask(SIG_BLOCK, &mask,
+                                        core::ptr::null_mut());
+            if errno != 0 {
+                return Err(Error::Os { call: Syscall::PthreadSigmask,
+                                       errno });
+            }
+
+            Error::check(Syscall::Signalfd,
+                signalfd(-1, &mask, SFD_NONBLOCK | SFD_CLOEXEC))?
+        };
+        self.signalfd.set(Some(fd));
+        Ok(fd)
+    }
+
+    /// Reap every child which exited, first waiting for one to exit if
+    /// `in_flight` children are alive. Adds them to `reaped`.
+    fn reap_exited(&self, fd: c_int, reaped: &mut Reaped)
+            -> error::Result<()> {
+        while self.running.get() > 0 {
+            // Consume the pending notifications before checking for exited
+            // children, such that a child exiting from here on makes the
+            // `signalfd` readable again. They're coalesced, thus only tell
+            // that some children exited.
+            let mut info: signalfd_siginfo = unsafe { core::mem::zeroed() };
+            let size = core::mem::size_of::<signalfd_siginfo>();
+            while unsafe {
+                read(fd, &mut info as *mut _ as *mut c_void, size)
+            } == size as isize {}
+
+            // Reap all children which exited
+            let mut any = false;
+            while self.running.get() > 0 {
+                let mut rusage: rusage = unsafe { core::mem::zeroed() };
+                let mut status = 0;
+                let pid = Error::check(Syscall::Wait4, unsafe {
+                    wait4(-1, &mut status, WNOHANG, &mut rusage)
+                })?;
+                if pid == 0 {
+                    break;
+                }
+
+                reaped.add(status, &rusage);
+                self.running.set(self.running.get() - 1);
+                any = true;
+            }
+
+            // Wait for a child to exit if there's no room for another one
+            if any || self.running.get() < self.in_flight {
+                break;
+            }
+            let mut fds = pollfd { fd, events: POLLIN, revents: 0 };
+            Error::check(Syscall::Poll, unsafe { poll(&mut fds, 1, -1) })?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Backend for AsyncFork {
+    fn run(&self, fuzz_case: &mut FuzzCase) -> error::Result<Reaped> {
+        let fd = self.signalfd()?;
+
+        let subchild = match Error::check(Syscall::Fork, unsafe { fork() }) {
+            Ok(subchild) => subchild,
+            Err(err) => {
+                // Reap whatever exited, such that our zombies don't keep a
+                // transient failure (eg. hitting the process limit) going
+                let mut reaped = self.pending.get();
+                self.reap_exited(fd, &mut reaped)?;
+                self.pending.set(reaped);
+                return Err(err);
+            }
+        };
+        if subchild == 0 {
+            fuzz_case();
+
+            // Done
+            unsafe { exit(0); }
+        }
+        self.running.set(self.running.get() + 1);
+
+        // Reap whatever exited in the meantime, waiting for a child to exit
+        // if too many are alive
+        let it = crate::clock::now();
+        let mut reaped = self.pending.take();
+        self.reap_exited(fd, &mut reaped)?;
+        reaped.wait_cycles = crate::clock::now() - it;
+        Ok(reaped)
+    }
+
+    fn finish(&self) -> error::Result<Reaped> {
+        // Wait for every child still alive
+        let it = crate::clock::now();
+        let mut reaped = self.pending.take();
+        while self.running.get() > 0 {
+            let mut rusage: rusage = unsafe { core::mem::zeroed() };
+            let mut status = 0;
+            Error::check(Syscall::Wait4, unsafe {
+                wait4(-1, &mut status, 0, &mut rusage)
+            })?;
+
+            reaped.add(status, &rusage);
+            self.running.set(self.running.get() - 1);
+        }
+        reaped.wait_cycles = crate::clock::now() - it;
+        Ok(reaped)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for AsyncFork {
+    fn drop(&mut self) {
+        // Reap the children still alive
+        while self.running.get() > 0 {
+            if unsafe { wait(core::ptr::null_mut()) } == -1 {
+                break;
+            }
+            self.running.set(self.running.get() - 1);
+        }
+
+        if let Some(fd) = self.signalfd.get() {
+            unsafe {
+                close(fd);
+
+                let mut mask: sigset_t = core::mem::zeroed();
+                sigemptyset(&mut mask);
+                sigaddset(&mut mask, SIGCHLD);
+                pthread_sigmask(SIG_UNBLOCK, &mask, core::ptr::null_mut());
+            }
+        }
+    }
+}
+
+/// `vfork()` based process creation. The child shares our address space and
+/// we're suspended until it exits, thus the child immediately `_exit()`s and
+/// the fuzz case is run in the worker once it resumes. This measures the
+/// cost of creating a process without any copy-on-write setup.
+#[cfg(unix)]
+pub struct Vfork;
+
+#[cfg(unix)]
+impl Backend for Vfork {
+    fn run(&self, fuzz_case: &mut FuzzCase) -> error::Result<Reaped> {
+        let subchild = Error::check(Syscall::Vfork, unsafe { vfork() })?;
+
+        if subchild == 0 {
+            // Nothing else is safe to do in a vfork()ed child
+            unsafe { _exit(0); }
+        }
+
+        // Reap the subchild
+        let reaped = reap(subchild)?;
+
+        fuzz_case();
+        Ok(reaped)
+    }
+}
+
+/// A program to launch for every fuzz case, either an instance of this binary
+/// acting as a helper (which runs the workload), a user-specified target, or
+/// `criu` restoring a process image
+#[cfg(unix)]
+pub struct ExecTarget {
+    /// Path to the program
+    path: CString,
+
+    /// Arguments to the program, kept alive for the `argv` pointers
+    _args: Vec<CString>,
+
+    /// Null terminated `argv` for the program
+    argv: Vec<*mut c_char>,
+
+    /// Null terminated (empty) environment for the program
+    envp: [*mut c_char; 1],
+
+    /// Set if this is a user-specified target which does not record its own
+    /// statistics
+    external: bool,
+
+    /// Worker the target records statistics for
+    thr_id: usize,
+}
+
+#[cfg(unix)]
+impl ExecTarget {
+    /// Prepare the arguments for launching the target ahead of time so only
+    /// the launch itself is measured
+    pub fn new(config: &Config, thr_id: usize, workload: u64) -> Self {
+        let (path, args) = if let Some(path) = &config.exec_path {
+            let path = CString::new(path.as_str()).unwrap();
+            (path.clone(), vec![path])
+        } else {
+            let exe = std::env::current_exe()
+                .expect("Failed to get path to the current executable");
+            let path = CString::new(exe.to_str().unwrap()).unwrap();
+
+            (path.clone(), vec![
+                path,
+                CString::new(HELPER_ARG).unwrap(),
+                CString::new(crate::shmem::handle()).unwrap(),
+                CString::new(thr_id.to_string()).unwrap(),
+                CString::new(workload.to_string()).unwrap(),
+                CString::new(config.dirty_memory.unwrap_or(0).to_string())
+                    .unwrap(),
+                CString::new(crate::clock::clock().name()).unwrap(),
+            ])
+        };
+
+        Self::with_args(path, args, config.exec_path.is_some(), thr_id)
+    }
+
+    /// Prepare launching `criu` to restore the process image configured
+    /// with `--criu-images`, waiting for the restored process to exit. It
+    /// records no statistics of its own.
+    pub fn criu_restore(config: &Config, thr_id: usize) -> Self {
+        let path = CString::new(config.criu_path.as_str()).unwrap();
+        let images = config.criu_images.as_deref()
+            .expect("`criu_restore` backend requires an image directory");
+
+        Self::with_args(path.clone(), vec![
+            path,
+            CString::new("restore").unwrap(),
+            CString::new("--images-dir").unwrap(),
+            CString::new(images).unwrap(),
+        ], true, thr_id)
+    }
+
+    /// Prepare launching the trivial binary of the `shell_spawn` backend,
+    /// `--exec-path` if given. It records no statistics of its own.
+    pub fn shell(config: &Config, thr_id: usize) -> Self {
+        let path = CString::new(config.exec_path.as_deref()
+            .unwrap_or(SHELL_TARGET)).unwrap();
+        Self::with_args(path.clone(), vec![path], true, thr_id)
+    }
+
+    /// Prepare launching `path` with the arguments `args`
+    fn with_args(path: CString, args: Vec<CString>, external: bool,
+                 thr_id: usize) -> Self {
+        let mut argv: Vec<*mut c_char> =
+            args.iter().map(|x| x.as_ptr() as *mut c_char).collect();
+        argv.push(core::ptr::null_mut());
+
+        ExecTarget {
+            path,
+            _args: args,
+            argv,
+            envp: [core::ptr::null_mut()],
+            external,
+            thr_id,
+        }
+    }
+
+    /// Called once a launched target has been reaped. External targets can't
+    /// record the fuzz case themselves, thus the worker does it for them.
+    fn reaped(&self) {
+        if self.external {
+            let shmem = unsafe { crate::shmem::get() };
+            shmem.slot(self.thr_id).fuzz_cases
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+}
+
+/// `posix_spawn()` based process creation. Instead of running the fuzz case
+/// in a copy of the worker, a fresh instance of this binary is spawned as a
+/// helper which attaches to the shared memory and runs the fuzz case. This
+/// measures the full spawn pipeline (exec, ELF load, dynamic linking), as
+/// seen by fuzzers which launch a new target for every case.
+#[cfg(unix)]
+pub struct PosixSpawn(ExecTarget);
+
+#[cfg(unix)]
+impl Backend for PosixSpawn {
+    fn run(&self, _fuzz_case: &mut FuzzCase) -> error::Result<Reaped> {
+        let target = &self.0;
+
+        // Unlike most calls the error is returned rather than set in `errno`
+        let mut subchild = 0;
+        let errno = unsafe {
+            posix_spawn(&mut subchild, target.path.as_ptr(),
+                core::ptr::null(), core::ptr::null(),
+                target.argv.as_ptr(), target.envp.as_ptr())
+        };
+        if errno != 0 {
+            return Err(Error::Os { call: Syscall::PosixSpawn, errno });
+        }
+
+        // Wait for the helper to exit
+        let reaped = reap(subchild)?;
+        target.reaped();
+        Ok(reaped)
+    }
+}
+
+/// `fork()` followed by `execve()` of a target in the child, the classic way
+/// of launching a fresh target for every fuzz case. Unlike `posix_spawn()`
+/// the copy-on-write setup of the worker is paid for before the exec.
+#[cfg(unix)]
+pub struct ForkExec(ExecTarget);
+
+#[cfg(unix)]
+impl Backend for ForkExec {
+    fn run(&self, _fuzz_case: &mut FuzzCase) -> error::Result<Reaped> {
+        let target = &self.0;
+
+        let subchild = Error::check(Syscall::Fork, unsafe { fork() })?;
+
+        if subchild == 0 {
+            unsafe {
+                execve(target.path.as_ptr(),
+                       target.argv.as_ptr() as *const *const c_char,
+                       target.envp.as_ptr() as *const *const c_char);
+
+                // Only reached if the exec failed
+                _exit(127);
+            }
+        }
+
+        // Wait for the target to exit
+        let reaped = reap(subchild)?;
+        target.reaped();
+        Ok(reaped)
+    }
+}
+
+/// `vfork()` followed by `execve()` of a trivial binary in the child and
+/// waiting for it, the path shells and `system()`/`popen()` launch commands
+/// with. The worker is suspended from the `vfork()` until the exec, but
+/// nothing of its address space is copied, so this combines the cost of
+/// `vfork()` with the exec, ELF load and exit of a fresh process.
+#[cfg(unix)]
+pub struct ShellSpawn(ExecTarget);
+
+#[cfg(unix)]
+impl Backend for ShellSpawn {
+    fn run(&self, _fuzz_case: &mut FuzzCase) -> error::Result<Reaped> {
+        let target = &self.0;
+
+        let subchild = Error::check(Syscall::Vfork, unsafe { vfork() })?;
+
+        if subchild == 0 {
+            // Only exec and `_exit()` are safe to do in a vfork()ed child,
+            // everything was prepared ahead of time
+            unsafe {
+                execve(target.path.as_ptr(),
+                       target.argv.as_ptr() as *const *const c_char,
+                       target.envp.as_ptr() as *const *const c_char);
+
+                // Only reached if the exec failed
+                _exit(127);
+            }
+        }
+
+        // Wait for the target to exit
+        let reaped = reap(subchild)?;
+        target.reaped();
+        Ok(reaped)
+    }
+}
+
+/// Entry point when this binary is spawned as a helper by the `posix_spawn`
+/// or `fork_exec` backends, arguments are
+/// `HELPER_ARG <shared memory handle> <worker ID> <workload>
+/// <dirty memory bytes> <clock>`
+#[cfg(unix)]
+pub fn helper_main() -> ! {
+    let args: Vec<String> = std::env::args().collect();
+    assert!(args.len() == 7 && args[1] == HELPER_ARG,
+        "Invalid helper arguments");
+
+    let thr_id: usize = args[3].parse().expect("Invalid helper worker ID");
+    let workload: u64 = args[4].parse().expect("Invalid helper workload");
+    let dirty: usize  = args[5].parse().expect("Invalid helper dirty size");
+
+    // Time the fuzz case with the same clock as the parent
+    crate::clock::select(args[6].parse().expect("Invalid helper clock"));
+
+    // Get access to the statistics of the parent benchmark
+    unsafe { crate::shmem::attach(&args[2]) }.unwrap_or_else(|err|
+        panic!("Failed to attach to shared memory: {}", err));
+    let shmem = unsafe { crate::shmem::get() };
+
+    let mut dirtyme = vec![0u8; dirty];
+    crate::fuzz_case(shmem.slot(thr_id), None,
+                     &crate::workload::StackLoads { iterations: workload },
+                     &mut dirtyme);
+
+    unsafe { _exit(0); }
+}
+
+/// Size of the stack given to `clone()`d children
+#[cfg(target_os = "linux")]
+const CLONE_STACK_SIZE: usize = 1024 * 1024;
+
+/// Raw `clone()` based process creation with a user-supplied set of flags,
+/// allowing isolation of which parts of process duplication (address space,
+/// file table, signal handlers, ...) dominate the cost of `fork()`
+#[cfg(target_os = "linux")]
+pub struct RawClone {
+    /// `CLONE_*` flags to pass to `clone()`
+    flags: c_int,
+
+    /// Stack for the child. Only one child exists at a time thus this is
+    /// reused for every fuzz case.
+    stack: Vec<u8>,
+}
+
+#[cfg(target_os = "linux")]
+impl RawClone {
+    /// Create a new `clone()` backend using `flags`
+    pub fn new(flags: c_int) -> Self {
+        RawClone { flags, stack: vec![0u8; CLONE_STACK_SIZE] }
+    }
+}
+
+/// Entry point of `clone()`d children, `arg` is the fuzz case to run
+#[cfg(target_os = "linux")]
+extern "C" fn clone_entry(arg: *mut c_void) -> c_int {
+    let fuzz_case = unsafe { &mut *(arg as *mut &mut FuzzCase) };
+    fuzz_case();
+    0
+}
+
+#[cfg(target_os = "linux")]
+impl Backend for RawClone {
+    fn run(&self, mut fuzz_case: &mut FuzzCase) -> error::Result<Reaped> {
+        // Stacks grow down, start at the (16-byte aligned) top
+        let stack_top = (self.stack.as_ptr() as usize + self.stack.len())
+            & !0xf;
+
+        let subchild = Error::check(Syscall::Clone, unsafe {
+            clone(clone_entry, stack_top as *mut c_void, self.flags | SIGCHLD,
+                  &mut fuzz_case as *mut &mut FuzzCase as *mut c_void)
+        })?;
+
+        // Wait for the subchild to exit
+        reap(subchild)
+    }
+}
+
+/// `clone3()` based process creation with `CLONE_PIDFD`, the child is
+/// waited for and reaped through the pidfd which is race-free against PID
+/// reuse. Otherwise the same as `fork()`.
+#[cfg(target_os = "linux")]
+pub struct Clone3(pub PidfdWait);
+
+#[cfg(target_os = "linux")]
+impl Backend for Clone3 {
+    fn run(&self, fuzz_case: &mut FuzzCase) -> error::Result<Reaped> {
+        let mut pidfd: c_int = -1;
+        let mut args: clone_args = unsafe { core::mem::zeroed() };
+        args.flags       = CLONE_PIDFD as u64;
+        args.pidfd       = &mut pidfd as *mut c_int as u64;
+        args.exit_signal = SIGCHLD as u64;
+
+        let subchild = Error::check(Syscall::Clone3, unsafe {
+            syscall(SYS_clone3, &mut args,
+                    core::mem::size_of::<clone_args>())
+        })?;
+
+        if subchild == 0 {
+            fuzz_case();
+
+            // Done
+            unsafe { exit(0); }
+        }
+
+        // Wait for the subchild to exit, making sure the pidfd is closed
+        let reaped = wait_pidfd(pidfd, self.0);
+        unsafe { close(pidfd); }
+        reaped
+    }
+}
+
+/// FreeBSD `pdfork()` based process creation. The child is waited for by
+/// polling its process descriptor, closing the descriptor then reaps it.
+/// Children of `pdfork()` raise no `SIGCHLD` and aren't visible to
+/// `wait4()`-style reaping of other children. Otherwise the same as
+/// `fork()`.
+#[cfg(target_os = "freebsd")]
+pub struct Pdfork;
+
+#[cfg(target_os = "freebsd")]
+impl Backend for Pdfork {
+    fn run(&self, fuzz_case: &mut FuzzCase) -> error::Result<Reaped> {
+        let mut fd: c_int = -1;
+        let subchild = Error::check(Syscall::Pdfork,
+            unsafe { pdfork(&mut fd, 0) })?;
+
+        if subchild == 0 {
+            fuzz_case();
+
+            // Done
+            unsafe { exit(0); }
+        }
+
+        // Wait for the descriptor to hang up once the subchild exited,
+        // making sure it's closed
+        let it = crate::clock::now();
+        let mut fds = pollfd { fd, events: POLLHUP, revents: 0 };
+        let ret = Error::check(Syscall::Poll, unsafe { poll(&mut fds, 1, -1) });
+        unsafe { close(fd); }
+        ret?;
+
+        // The resource usage and exit status can only be collected by
+        // `wait4()`
+        Ok(Reaped {
+            wait_cycles: crate::clock::now() - it,
+            ..Reaped::default()
+        })
+    }
+}
+
+/// Wait for the child referred to by `pidfd` to exit using `wait`,
+/// returning its resource usage
+#[cfg(target_os = "linux")]
+fn wait_pidfd(pidfd: c_int, wait: PidfdWait) -> error::Result<Reaped> {
+    let it = crate::clock::now();
+
+    if wait == PidfdWait::Poll {
+        let mut fds = pollfd { fd: pidfd, events: POLLIN, revents: 0 };
+        Error::check(Syscall::Poll, unsafe { poll(&mut fds, 1, -1) })?;
+    }
+
+    // The raw system call also returns the resource usage
+    let mut info: siginfo_t = unsafe { core::mem::zeroed() };
+    let mut usage: rusage = unsafe { core::mem::zeroed() };
+    Error::check(Syscall::Waitid, unsafe {
+        syscall(SYS_waitid, P_PIDFD, pidfd, &mut info, WEXITED, &mut usage)
+    })?;
+
+    Ok(Reaped {
+        usage:       Usage::from(&usage),
+        wait_cycles: crate::clock::now() - it,
+        crashes:     (info.si_code != CLD_EXITED ||
+                      unsafe { info.si_status() } != 0) as u64,
+    })
+}
+
+/// Thread based baseline, the fuzz case runs in a freshly spawned thread of
+/// the worker. Thread creation is the cheapest way to get a new schedulable
+/// context, this quantifies how much of the cost is the process itself.
+pub struct Thread;
+
+impl Backend for Thread {
+    fn run(&self, fuzz_case: &mut FuzzCase) -> error::Result<Reaped> {
+        // Scoped so the fuzz case can borrow from the worker
+        let (wait_cycles, panicked) = std::thread::scope(|scope| {
+            let thread = std::thread::Builder::new()
+                .spawn_scoped(scope, fuzz_case)?;
+
+            let it = crate::clock::now();
+            let panicked = thread.join().is_err();
+            Ok::<_, std::io::Error>((crate::clock::now() - it, panicked))
+        })?;
+
+        // Threads have no resource usage of their own, a panic is the
+        // closest they get to crashing
+        Ok(Reaped {
+            wait_cycles,
+            crashes: panicked as u64,
+            ..Reaped::default()
+        })
+    }
+}
+
+/// A child of the `pool` backend, waiting for fuzz cases to run
+#[cfg(unix)]
+struct PoolChild {
+    /// PID of the child
+    pid: pid_t,
+
+    /// Write end of the pipe fuzz cases are dispatched to the child through
+    command: c_int,
+
+    /// Read end of the pipe the child reports completed fuzz cases through
+    done: c_int,
+}
+
+/// Create a pipe, returning its `(read, write)` ends
+#[cfg(unix)]
+fn create_pipe() -> error::Result<(c_int, c_int)> {
+    let mut fds = [0; 2];
+    Error::check(Syscall::Pipe, unsafe { pipe(fds.as_mut_ptr()) })?;
+    Ok((fds[0], fds[1]))
+}
+
+/// Body of a child of the `pool` backend, running `fuzz_case` every time a
+/// command is read from `command` and reporting it done on `done`, until
+/// told to exit with a zero command
+#[cfg(unix)]
+fn pool_child(command: c_int, done: c_int, fuzz_case: &mut FuzzCase) -> ! {
+    let mut byte = 0u8;
+    while unsafe { read(command, &mut byte as *mut u8 as *mut c_void, 1) } == 1
+            && byte != 0 {
+        fuzz_case();
+
+        if unsafe { write(done, &byte as *const u8 as *const c_void, 1) } != 1 {
+            break;
+        }
+    }
+
+    unsafe { _exit(0); }
+}
+
+#[cfg(unix)]
+impl PoolChild {
+    /// Fork a child which runs `fuzz_case` on every command
+    fn spawn(fuzz_case: &mut FuzzCase) -> error::Result<Self> {
+        let (command_read, command) = create_pipe()?;
+        let (done, done_write) = match create_pipe() {
+            Ok(fds) => fds,
+            Err(err) => {
+                unsafe { close(command_read); close(command); }
+                return Err(err);
+            }
+        };
+
+        let pid = unsafe { fork() };
+        if pid == 0 {
+            pool_child(command_read, done_write, fuzz_case);
+        }
+
+        // Only the child uses the other ends
+        unsafe { close(command_read); close(done_write); }
+        let child = PoolChild { pid, command, done };
+        if pid == -1 {
+            let err = Error::last_os_error(Syscall::Fork);
+            drop(child);
+            return Err(err);
+        }
+
+        Ok(child)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for PoolChild {
+    fn drop(&mut self) {
+        // Tell the child to exit. Closing the pipe isn't enough as later
+        // children inherited its write end.
+        unsafe {
+            write(self.command, &0u8 as *const u8 as *const c_void, 1);
+            close(self.command);
+            close(self.done);
+            if self.pid > 0 {
+                waitpid(self.pid, core::ptr::null_mut(), 0);
+            }
+        }
+    }
+}
+
+/// Pre-forked process pool, the architecture of many snapshot fuzzers. The
+/// worker forks `size` children the first time it runs a fuzz case, then
+/// dispatches every fuzz case to the next one of them in turn over a pipe,
+/// measuring the steady-state dispatch throughput. The children are reused,
+/// so nothing is reset between the fuzz cases each of them runs.
+#[cfg(unix)]
+pub struct Pool {
+    /// Number of children in the pool
+    size: usize,
+
+    /// The children, forked on the first fuzz case
+    children: RefCell<Vec<PoolChild>>,
+
+    /// Index of the child the next fuzz case is dispatched to
+    next: Cell<usize>,
+}
+
+#[cfg(unix)]
+impl Pool {
+    /// Create a pool of `size` children
+    pub fn new(size: usize) -> Self {
+        Pool {
+            size,
+            children: RefCell::new(Vec::new()),
+            next:     Cell::new(0),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Backend for Pool {
+    fn run(&self, fuzz_case: &mut FuzzCase) -> error::Result<Reaped> {
+        // Fork the pool the first time around, the children keep running
+        // the fuzz case they were forked with
+        let mut children = self.children.borrow_mut();
+        while children.len() < self.size {
+            children.push(PoolChild::spawn(fuzz_case)?);
+        }
+
+        let child = &children[self.next.get()];
+        self.next.set((self.next.get() + 1) % children.len());
+
+        // Dispatch the fuzz case
+        Error::check(Syscall::Write, unsafe {
+            write(child.command, &1u8 as *const u8 as *const c_void, 1)
+        })?;
+
+        // Wait for it to complete, the child exiting means it failed
+        let it = crate::clock::now();
+        let mut byte = 0u8;
+        let bytes = Error::check(Syscall::Read, unsafe {
+            read(child.done, &mut byte as *mut u8 as *mut c_void, 1)
+        })?;
+        if bytes != 1 {
+            return Err(Error::Os { call: Syscall::Read, errno: EPIPE });
+        }
+
+        // The children are only reaped once the pool is torn down
+        Ok(Reaped {
+            wait_cycles: crate::clock::now() - it,
+            ..Reaped::default()
+        })
+    }
+
+    fn reruns_fuzz_case(&self) -> bool {
+        true
+    }
+}
+
+/// Bit of a `/proc/self/pagemap` entry set if the page was written to since
+/// the soft-dirty bits were last cleared
+#[cfg(target_os = "linux")]
+const PAGEMAP_SOFT_DIRTY: u64 = 1 << 55;
+
+/// Open `path` with `flags`
+#[cfg(target_os = "linux")]
+fn open_file(path: &str, flags: c_int) -> error::Result<c_int> {
+    let path = CString::new(path).unwrap();
+    Error::check(Syscall::Open, unsafe {
+        open(path.as_ptr(), flags | O_CLOEXEC)
+    })
+}
+
+/// Clear the soft-dirty bits of every page of the process
+#[cfg(target_os = "linux")]
+fn clear_soft_dirty(clear_refs: c_int) -> error::Result<()> {
+    Error::check(Syscall::Write, unsafe {
+        write(clear_refs, b"4".as_ptr() as *const c_void, 1)
+    })?;
+    Ok(())
+}
+
+/// In-process snapshot restore, the alternative to `fork()` used by
+/// snapshot fuzzers which keep a single long-lived process. The fuzz case
+/// runs in the worker itself, after which the pre-fork memory (the state a
+/// forked fuzz case gets a copy-on-write copy of) is reset from a pristine
+/// copy. Nothing outside the pre-fork memory is reset.
+#[cfg(unix)]
+pub struct Snapshot {
+    /// Base address of the memory reset after each fuzz case
+    base: *mut u8,
+
+    /// Pristine copy of the memory, of its size
+    pristine: Vec<u8>,
+
+    /// How the memory is reset
+    reset: SnapshotReset,
+
+    /// `/proc/self/pagemap` and `/proc/self/clear_refs`, for dirty resets
+    #[cfg(target_os = "linux")]
+    proc_files: Option<(c_int, c_int)>,
+
+    /// Buffer the pagemap entries of the memory are read into
+    #[cfg(target_os = "linux")]
+    entries: RefCell<Vec<u64>>,
+}
+
+#[cfg(unix)]
+impl Snapshot {
+    /// Snapshot `memory`, which is reset the way `reset` describes after
+    /// every fuzz case
+    ///
+    /// # Safety
+    ///
+    /// `memory` must stay mapped for as long as the backend is used, and
+    /// not be accessed other than by fuzz cases while it is
+    pub unsafe fn new(memory: &mut [u8], reset: SnapshotReset)
+            -> error::Result<Self> {
+        // Only dirty resets need the procfs files
+        #[cfg(target_os = "linux")]
+        let proc_files = if reset == SnapshotReset::Dirty {
+            let pagemap = open_file("/proc/self/pagemap", O_RDONLY)?;
+            let clear_refs = match open_file("/proc/self/clear_refs",
+                                             O_WRONLY) {
+                Ok(fd) => fd,
+                Err(err) => {
+                    close(pagemap);
+                    return Err(err);
+                }
+            };
+            Some((pagemap, clear_refs))
+        } else {
+            None
+        };
+
+        let ret = Snapshot {
+            base: memory.as_mut_ptr(),
+            pristine: memory.to_vec(),
+            reset,
+            #[cfg(target_os = "linux")]
+            proc_files,
+            #[cfg(target_os = "linux")]
+            entries: RefCell::new(vec![0; memory.len()
+                .div_ceil(crate::memory::PAGE_SIZE)]),
+        };
+
+        // Start tracking writes from the pristine state
+        #[cfg(target_os = "linux")]
+        if let Some((_, clear_refs)) = ret.proc_files {
+            clear_soft_dirty(clear_refs)?;
+        }
+
+        Ok(ret)
+    }
+
+    /// Copy `len` bytes at `offset` of the pristine copy back
+    fn restore(&self, offset: usize, len: usize) {
+        unsafe {
+            core::ptr::copy_nonoverlapping(self.pristine.as_ptr().add(offset),
+                                           self.base.add(offset), len);
+        }
+    }
+
+    /// Copy back the pages which were written to since the last reset
+    #[cfg(target_os = "linux")]
+    fn restore_dirty(&self, pagemap: c_int, clear_refs: c_int)
+            -> error::Result<()> {
+        use crate::memory::PAGE_SIZE;
+
+        // Read the entries of all pages of the memory
+        let mut entries = self.entries.borrow_mut();
+        let bytes = entries.len() * 8;
+        let offset = (self.base as usize / PAGE_SIZE * 8) as off_t;
+        let ret = Error::check(Syscall::Read, unsafe {
+            pread(pagemap, entries.as_mut_ptr() as *mut c_void, bytes,
+                  offset)
+        })?;
+        if ret as usize != bytes {
+            return Err(Error::Os { call: Syscall::Read, errno: EIO });
+        }
+
+        for (page, &entry) in entries.iter().enumerate() {
+            if entry & PAGEMAP_SOFT_DIRTY != 0 {
+                let offset = page * PAGE_SIZE;
+                self.restore(offset,
+                             PAGE_SIZE.min(self.pristine.len() - offset));
+            }
+        }
+
+        clear_soft_dirty(clear_refs)
+    }
+}
+
+#[cfg(unix)]
+impl Backend for Snapshot {
+    fn run(&self, fuzz_case: &mut FuzzCase) -> error::Result<Reaped> {
+        fuzz_case();
+
+        // Reset the memory, standing in for reaping the fuzz case
+        let it = crate::clock::now();
+        match self.reset {
+            #[cfg(target_os = "linux")]
+            SnapshotReset::Dirty => {
+                let (pagemap, clear_refs) = self.proc_files.unwrap();
+                self.restore_dirty(pagemap, clear_refs)?;
+            }
+            _ => self.restore(0, self.pristine.len()),
+        }
+
+        Ok(Reaped {
+            wait_cycles: crate::clock::now() - it,
+            ..Reaped::default()
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        if let Some((pagemap, clear_refs)) = self.proc_files {
+            unsafe { close(pagemap); close(clear_refs); }
+        }
+    }
+}
@@ -0,0 +1,811 @@
+//! Library interface for running sweeps, configured with `BenchmarkConfig`
+//!
+//! ```no_run
+//! use forkbench::{BenchmarkConfig, backend::BackendKind};
+//!
+//! fn main() {
+//!     // Workers may relaunch this binary, let it act as them
+//!     forkbench::dispatch();
+//!
+//!     let benchmark = BenchmarkConfig::new()
+//!         .backend(BackendKind::Vfork)
+//!         .max_threads(4)
+//!         .duration_secs(0.5)
+//!         .build()
+//!         .expect("Failed to set up benchmark");
+//!
+//!     for summary in benchmark.run().summaries {
+//!         println!("{:?}: {:.0} forks/s", summary.point,
+//!                  summary.forks_per_second.mean);
+//!     }
+//! }
+//! ```
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::Instant;
+use std::sync::atomic::Ordering;
+
+use crate::config::Config;
+use crate::output::{self, TestResult, PointSummary};
+use crate::sweep::{self, Spacing, TestPoint, ThreadCounts};
+use crate::error::{Error, Result};
+use crate::topology::{PinStrategy, CoreSelection, StatsPlacement};
+use crate::threading::SchedPolicy;
+use crate::backend::{
+    BackendKind, Ipc, PidfdWait, Ptrace, Reaper, SnapshotReset,
+};
+use crate::memory::{HugePages, ForkAdvice};
+use crate::clock::{self, ClockSource};
+use crate::shmem;
+use crate::soak::{Soak, Sample};
+use crate::replay::Replay;
+use crate::workload::{Workload, WorkloadKind};
+
+/// Builder of the configuration of a sweep, starting from the defaults of
+/// the command line
+#[derive(Clone, Default)]
+pub struct BenchmarkConfig {
+    /// Configuration built so far
+    config: Config,
+
+    /// Maximum number of threads to test, all selected logical processors
+    /// if `None`
+    max_threads: Option<usize>,
+
+    /// Work done by each fuzz case, the built-in kernel if `None`
+    workload: Option<Arc<dyn Workload>>,
+}
+
+impl BenchmarkConfig {
+    /// Start from the default configuration
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of samples to have over the thread range
+    pub fn thread_samples(mut self, samples: usize) -> Self {
+        self.config.thread_samples = samples;
+        self
+    }
+
+    /// Number of samples to have over the workload range
+    pub fn workload_samples(mut self, samples: usize) -> Self {
+        self.config.workload_samples = samples;
+        self
+    }
+
+    /// Spacing of the samples of every sampled axis
+    pub fn spacing(mut self, spacing: Spacing) -> Self {
+        self.config.spacing = spacing;
+        self
+    }
+
+    /// Test these thread counts rather than samples of the range
+    pub fn thread_counts(mut self, counts: ThreadCounts) -> Self {
+        self.config.thread_counts = Some(counts);
+        self
+    }
+
+    /// Start from a coarse grid of thread counts and sample around the knee
+    /// of the scaling curve for `rounds` rounds, see `sweep::refine()`
+    pub fn adaptive(mut self, rounds: usize) -> Self {
+        self.config.adaptive = rounds;
+        self
+    }
+
+    /// Search the most threads which keep an efficiency of at least
+    /// `ratio`, see `sweep::search()`
+    pub fn search_efficiency(mut self, ratio: f64) -> Self {
+        self.config.search_efficiency = Some(ratio);
+        self
+    }
+
+    /// Test only `point` rather than a sweep
+    pub fn point(mut self, point: TestPoint) -> Self {
+        self.config.point = Some(point);
+        self
+    }
+
+    /// Run the test point for `secs` seconds, sampling its throughput every
+    /// `interval` seconds, see `soak`
+    pub fn soak(mut self, secs: f64, interval: f64) -> Self {
+        self.config.soak_secs     = Some(secs);
+        self.config.soak_interval = interval;
+        self
+    }
+
+    /// Test the test points of an earlier run selected by `replay` rather
+    /// than a sweep
+    pub fn replay(mut self, replay: Replay) -> Self {
+        self.config.replay = Some(replay);
+        self
+    }
+
+    /// Flag repetitions more than `threshold` scaled median absolute
+    /// deviations away from the median of their test point as outliers,
+    /// see `output::flag_outliers()`
+    pub fn outliers(mut self, threshold: f64) -> Self {
+        self.config.outliers = Some(threshold);
+        self
+    }
+
+    /// Flag test points whose efficiency varies by more than `threshold`
+    /// percent across repetitions as noisy
+    pub fn noise_threshold(mut self, threshold: f64) -> Self {
+        self.config.noise_threshold = threshold;
+        self
+    }
+
+    /// Test these workloads rather than samples of the range
+    pub fn workloads(mut self, workloads: Vec<u64>) -> Self {
+        self.config.workloads = Some(workloads);
+        self
+    }
+
+    /// Maximum number of threads to test
+    pub fn max_threads(mut self, threads: usize) -> Self {
+        self.max_threads = Some(threads);
+        self
+    }
+
+    /// Maximum workload to sample to
+    pub fn max_workload(mut self, workload: usize) -> Self {
+        self.config.max_workload = workload;
+        self
+    }
+
+    /// Only use logical processors of NUMA node `node`
+    pub fn numa_node(mut self, node: u16) -> Self {
+        self.config.numa_node = Some(node);
+        self
+    }
+
+    /// Classes of cores of hybrid CPUs to use
+    pub fn cores(mut self, cores: CoreSelection) -> Self {
+        self.config.cores = cores;
+        self
+    }
+
+    /// Order workers are assigned to logical processors
+    pub fn pinning(mut self, pinning: PinStrategy) -> Self {
+        self.config.pinning = pinning;
+        self
+    }
+
+    /// Pin workers to exactly the logical processors `cpus` (by OS index) in
+    /// this order, rather than selecting them automatically
+    pub fn cpus(mut self, cpus: Vec<usize>) -> Self {
+        self.config.cpus = Some(cpus);
+        self
+    }
+
+    /// Scheduling policy the workers and their fuzz cases run under
+    pub fn sched_policy(mut self, policy: SchedPolicy) -> Self {
+        self.config.sched_policy = policy;
+        self
+    }
+
+    /// Priority of the real-time scheduling policies
+    pub fn sched_priority(mut self, priority: u32) -> Self {
+        self.config.sched_priority = priority;
+        self
+    }
+
+    /// Nice level of the time-sharing scheduling policies
+    pub fn nice(mut self, nice: i32) -> Self {
+        self.config.nice = nice;
+        self
+    }
+
+    /// Bind the statistics and pre-fork memory to NUMA node `node`
+    pub fn mbind_node(mut self, node: u16) -> Self {
+        self.config.mbind_node = Some(node);
+        self
+    }
+
+    /// NUMA node the statistics are placed on relative to the workers
+    pub fn stats_placement(mut self, placement: StatsPlacement) -> Self {
+        self.config.stats_placement = placement;
+        self
+    }
+
+    /// Back the statistics with a file in `dir`
+    pub fn shm_dir(mut self, dir: impl Into<String>) -> Self {
+        self.config.shm_dir = Some(dir.into());
+        self
+    }
+
+    /// Run the workers of each test point in a fresh cgroup created below
+    /// the cgroup v2 directory `dir`
+    pub fn cgroup(mut self, dir: impl Into<String>) -> Self {
+        self.config.cgroup = Some(dir.into());
+        self
+    }
+
+    /// Nest the cgroup of each test point `depth` levels below the directory
+    /// given to `cgroup()`
+    pub fn cgroup_depth(mut self, depth: u32) -> Self {
+        self.config.cgroup_depth = depth;
+        self
+    }
+
+    /// Limit the cgroup of each test point to `cpus` CPUs worth of time
+    pub fn cgroup_cpus(mut self, cpus: f64) -> Self {
+        self.config.cgroup_cpus = Some(cpus);
+        self
+    }
+
+    /// Limit the memory of the cgroup of each test point to `mib` MiB
+    pub fn cgroup_memory(mut self, mib: u64) -> Self {
+        self.config.cgroup_memory = Some(mib);
+        self
+    }
+
+    /// Number of clock ticks each worker runs for per test
+    pub fn duration(mut self, ticks: u64) -> Self {
+        self.config.duration = ticks;
+        self
+    }
+
+    /// Number of seconds each worker runs for per test
+    pub fn duration_secs(mut self, secs: f64) -> Self {
+        self.config.duration_secs = Some(secs);
+        self
+    }
+
+    /// Run exactly `iterations` fuzz cases per worker instead of a duration
+    pub fn iterations(mut self, iterations: u64) -> Self {
+        self.config.iterations = Some(iterations);
+        self
+    }
+
+    /// Number of clock ticks of warmup before each measured window
+    pub fn warmup(mut self, ticks: u64) -> Self {
+        self.config.warmup = ticks;
+        self
+    }
+
+    /// Number of times each test point is run
+    pub fn reps(mut self, reps: usize) -> Self {
+        self.config.reps = reps;
+        self
+    }
+
+    /// Kill the workers of a test which hasn't finished after `secs`
+    /// seconds, failing the test point
+    pub fn timeout_secs(mut self, secs: f64) -> Self {
+        self.config.timeout_secs = Some(secs);
+        self
+    }
+
+    /// Clock all timing is done with
+    pub fn clock(mut self, clock: ClockSource) -> Self {
+        self.config.clock = clock;
+        self
+    }
+
+    /// Run the test points in a random order shuffled with `seed`, results
+    /// are still reported in order
+    pub fn shuffle(mut self, seed: u64) -> Self {
+        self.config.shuffle_seed = Some(seed);
+        self
+    }
+
+    /// Sweep the thread count and workload matrix
+    pub fn overhead(mut self, overhead: bool) -> Self {
+        self.config.bench_overhead = overhead;
+        self
+    }
+
+    /// Number of samples (spaced per `--spacing`) to have over the memory
+    /// range
+    pub fn memory_samples(mut self, samples: usize) -> Self {
+        self.config.memory_samples = samples;
+        self
+    }
+
+    /// Maximum amount of memory made resident before forking, in MiB
+    pub fn max_memory_mib(mut self, mib: usize) -> Self {
+        self.config.max_memory_mib = mib;
+        self
+    }
+
+    /// Number of samples (spaced per `--spacing`) to have over the extra
+    /// mapping count range
+    pub fn vma_samples(mut self, samples: usize) -> Self {
+        self.config.vma_samples = samples;
+        self
+    }
+
+    /// Maximum number of extra mappings created before forking
+    pub fn max_vmas(mut self, vmas: usize) -> Self {
+        self.config.max_vmas = vmas;
+        self
+    }
+
+    /// Number of samples (spaced per `--spacing`) to have over the extra
+    /// open descriptor count range
+    pub fn fd_samples(mut self, samples: usize) -> Self {
+        self.config.fd_samples = samples;
+        self
+    }
+
+    /// Maximum number of extra descriptors held open before forking
+    pub fn max_fds(mut self, fds: usize) -> Self {
+        self.config.max_fds = fds;
+        self
+    }
+
+    /// Number of samples (spaced per `--spacing`) to have over the idle
+    /// thread count range
+    pub fn idle_thread_samples(mut self, samples: usize) -> Self {
+        self.config.idle_thread_samples = samples;
+        self
+    }
+
+    /// Maximum number of idle threads started before forking
+    pub fn max_idle_threads(mut self, threads: usize) -> Self {
+        self.config.max_idle_threads = threads;
+        self
+    }
+
+    /// Huge page mode of the memory made resident before forking
+    pub fn huge_pages(mut self, huge_pages: HugePages) -> Self {
+        self.config.huge_pages = huge_pages;
+        self
+    }
+
+    /// How the memory made resident before forking is advised
+    pub fn fork_advice(mut self, advice: ForkAdvice) -> Self {
+        self.config.fork_advice = advice;
+        self
+    }
+
+    /// Amount of memory the fork advice is applied to, in MiB
+    pub fn fork_advice_mib(mut self, mib: usize) -> Self {
+        self.config.fork_advice_mib = Some(mib);
+        self
+    }
+
+    /// Number of bytes of fresh memory each fuzz case dirties
+    pub fn dirty_memory(mut self, bytes: usize) -> Self {
+        self.config.dirty_memory = Some(bytes);
+        self
+    }
+
+    /// Number of pages inherited from the worker each fuzz case dirties
+    pub fn cow_pages(mut self, pages: usize) -> Self {
+        self.config.cow_pages = pages;
+        self
+    }
+
+    /// Built-in kernel run by each fuzz case
+    pub fn workload_kind(mut self, kind: WorkloadKind) -> Self {
+        self.config.workload_kind = kind;
+        self
+    }
+
+    /// Size in bytes of the buffer accessed by the buffer kernels
+    pub fn working_set(mut self, bytes: usize) -> Self {
+        self.config.working_set = bytes;
+        self
+    }
+
+    /// Distance in bytes between the accesses of the strided kernel
+    pub fn stride(mut self, bytes: usize) -> Self {
+        self.config.stride = bytes;
+        self
+    }
+
+    /// Collect the event counters of each test with `perf_event_open()`
+    pub fn perf(mut self, perf: bool) -> Self {
+        self.config.perf = perf;
+        self
+    }
+
+    /// Measure the frequency of the logical processors of the workers during
+    /// each test
+    pub fn cpu_frequency(mut self, measure: bool) -> Self {
+        self.config.cpu_frequency = measure;
+        self
+    }
+
+    /// Time process creation in the kernel with ftrace tracepoints
+    pub fn trace_fork(mut self, trace: bool) -> Self {
+        self.config.trace_fork = trace;
+        self
+    }
+
+    /// Record each test with `perf record -g`, saving the recordings in
+    /// `dir`
+    pub fn perf_record(mut self, dir: impl Into<String>) -> Self {
+        self.config.perf_record = Some(dir.into());
+        self
+    }
+
+    /// Process-creation backend to benchmark
+    pub fn backend(mut self, backend: BackendKind) -> Self {
+        self.config.backend = backend;
+        self
+    }
+
+    /// Target launched by the `posix_spawn`, `fork_exec` and `shell_spawn`
+    /// backends
+    pub fn exec_path(mut self, path: impl Into<String>) -> Self {
+        self.config.exec_path = Some(path.into());
+        self
+    }
+
+    /// `CLONE_*` flags used by the `clone` backend
+    pub fn clone_flags(mut self, flags: libc::c_int) -> Self {
+        self.config.clone_flags = flags;
+        self
+    }
+
+    /// `CLONE_NEW*` flags of the namespaces each fuzz case `unshare()`s
+    pub fn unshare(mut self, flags: libc::c_int) -> Self {
+        self.config.unshare = flags;
+        self
+    }
+
+    /// How the `clone3` backend waits for children
+    pub fn pidfd_wait(mut self, wait: PidfdWait) -> Self {
+        self.config.pidfd_wait = wait;
+        self
+    }
+
+    /// Directory of the process image restored by the `criu_restore`
+    /// backend
+    pub fn criu_images(mut self, dir: impl Into<String>) -> Self {
+        self.config.criu_images = Some(dir.into());
+        self
+    }
+
+    /// `criu` executable used by the `criu_restore` backend
+    pub fn criu_path(mut self, path: impl Into<String>) -> Self {
+        self.config.criu_path = path.into();
+        self
+    }
+
+    /// Number of children each worker of the `pool` backend keeps
+    pub fn pool_size(mut self, size: usize) -> Self {
+        self.config.pool_size = size;
+        self
+    }
+
+    /// How the `snapshot` backend resets the pre-fork memory
+    pub fn snapshot_reset(mut self, reset: SnapshotReset) -> Self {
+        self.config.snapshot_reset = reset;
+        self
+    }
+
+    /// How the `fork` backend reaps children
+    pub fn reaper(mut self, reaper: Reaper) -> Self {
+        self.config.reaper = reaper;
+        self
+    }
+
+    /// Most children alive at a time per worker when reaping asynchronously
+    pub fn in_flight(mut self, children: usize) -> Self {
+        self.config.in_flight = children;
+        self
+    }
+
+    /// How the `fork` backend supervises children with `ptrace()`
+    pub fn ptrace(mut self, ptrace: Ptrace) -> Self {
+        self.config.ptrace = ptrace;
+        self
+    }
+
+    /// Stop traced children at every system call
+    pub fn ptrace_syscalls(mut self, syscalls: bool) -> Self {
+        self.config.ptrace_syscalls = syscalls;
+        self
+    }
+
+    /// Make a round trip over a pipe with every child of the `fork` backend
+    pub fn pipe_handshake(mut self, handshake: bool) -> Self {
+        self.config.pipe_handshake = handshake;
+        self
+    }
+
+    /// Have every child of the `fork` backend send a message of `size` bytes
+    /// back to its worker with `mode`
+    pub fn ipc(mut self, mode: Ipc, size: usize) -> Self {
+        self.config.ipc      = mode;
+        self.config.ipc_size = size;
+        self
+    }
+
+    /// Run `workload` in each fuzz case rather than the built-in kernel.
+    /// The workload of the test points is ignored, and only backends which
+    /// run fuzz cases in a copy of the benchmark process can be used.
+    pub fn workload(mut self, workload: impl Workload + 'static) -> Self {
+        self.workload = Some(Arc::new(workload));
+        self
+    }
+
+    /// Validate the configuration and set up a benchmark running it
+    pub fn build(self) -> Result<Benchmark> {
+        let config = self.config.finish(self.max_threads)
+            .map_err(Error::Config)?;
+        if self.workload.is_some() && !config.backend.runs_in_process() {
+            return Err(Error::Config(format!("Backend `{}` can't run custom \
+                workloads", config.backend.name())));
+        }
+
+        let mut benchmark = Benchmark::new(config)?;
+        benchmark.workload = self.workload;
+        Ok(benchmark)
+    }
+}
+
+/// Everything measured by a sweep
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Results of every repetition of every test point which didn't fail
+    pub results: Vec<TestResult>,
+
+    /// Summaries of all repetitions of each test point which didn't fail
+    pub summaries: Vec<PointSummary>,
+
+    /// Test points which failed, and why
+    pub failures: Vec<(TestPoint, Error)>,
+
+    /// Signal the sweep was interrupted by, leaving it incomplete
+    pub interrupted: Option<i32>,
+
+    /// Bounds the search for the most threads which keep the efficiency
+    /// asked for narrowed down to, see `sweep::search_bounds()`. `None`
+    /// unless searching.
+    pub search: Option<(u64, Option<u64>)>,
+
+    /// Throughput sampled while soaking, see `soak`. Empty unless soaking.
+    pub samples: Vec<Sample>,
+}
+
+/// A sweep ready to be run
+pub struct Benchmark {
+    /// Configuration of the sweep
+    config: Config,
+
+    /// Work done by each fuzz case, the built-in kernel if `None`
+    workload: Option<Arc<dyn Workload>>,
+}
+
+impl Benchmark {
+    /// Set up for running the sweep configured by `config`, which must have
+    /// been validated (see `Config::parse()`). The clock and shared memory
+    /// are set up by the first benchmark of the process and reused by later
+    /// ones.
+    pub fn new(config: Config) -> Result<Self> {
+        // Select the clock all timing is done with
+        if !clock::is_selected() {
+            clock::select(config.clock);
+        }
+
+        // Create the shared memory
+        if !shmem::is_created() {
+            unsafe {
+                shmem::create(config.shm_dir.as_deref(),
+                              config.stats_node())?;
+            }
+        }
+
+        // Calibrate the cycle counter against the wall clock before any
+        // forks are running
+        clock::calibration();
+
+        Ok(Benchmark { config, workload: None })
+    }
+
+    /// Configuration of the sweep
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// All test points of the sweep
+    pub fn test_points(&self) -> BTreeSet<TestPoint> {
+        sweep::test_points(&self.config)
+    }
+
+    /// All test points of the sweep, in the order they are run
+    pub fn run_order(&self) -> Vec<TestPoint> {
+        sweep::run_order(&self.config, &self.test_points())
+    }
+
+    /// Run repetition `rep` of a single test point
+    pub fn run_point(&self, point: TestPoint, rep: usize)
+            -> Result<TestResult> {
+        self.run_point_monitored(point, rep, |_| ())
+    }
+
+    /// Run repetition `rep` of a single test point, calling `monitor` with
+    /// the number of workers once they are all running. What it returns is
+    /// dropped once they have all exited.
+    pub fn run_point_monitored<T>(&self, point: TestPoint, rep: usize,
+                                  monitor: impl FnOnce(usize) -> T)
+            -> Result<TestResult> {
+        let shmem = unsafe { shmem::get() };
+
+        // Reset statistics, telling external readers how many workers
+        // there are
+        unsafe { shmem::reset(); }
+        shmem.threads.store(point.threads, Ordering::SeqCst);
+
+        // Start a wall-clock timer
+        let start_time = Instant::now();
+
+        // Start a timer on the benchmark clock too
+        let start_cycles = clock::now();
+
+        // Measure the frequency of the processors of the workers while they
+        // run
+        #[cfg(target_os = "linux")]
+        let frequency = self.config.cpu_frequency.then(|| {
+            crate::frequency::Monitor::new(self.config.processors
+                [..point.threads as usize].iter().map(|x| x.cpu()).collect())
+        });
+        #[cfg(target_os = "linux")]
+        let monitor = |threads| {
+            (frequency.as_ref().map(|x| x.start()), monitor(threads))
+        };
+
+        // Profile the processors of the workers while they run, stopped
+        // once they all exited
+        #[cfg(target_os = "linux")]
+        let _recording = self.config.perf_record.as_ref()
+            .map(|_| crate::record::Session::start(&self.config, &point, rep))
+            .transpose()?;
+
+        // Trace process creation in the kernel while the workers run
+        #[cfg(target_os = "linux")]
+        let trace = self.config.trace_fork
+            .then(crate::trace::Session::start).transpose()?;
+
+        // Run all the workers for this test
+        #[cfg(unix)]
+        crate::run_workers(&self.config, shmem, point,
+                           self.workload.as_deref(), monitor)?;
+        #[cfg(windows)]
+        crate::windows::run_workers(&self.config, point, monitor)?;
+
+        // All children are done, log number of cycles. With a warmup only
+        // the window after it is measured.
+        let (elapsed_cycles, elapsed) = if self.config.warmup > 0 {
+            let cycles = clock::now() -
+                shmem.measure_start.load(Ordering::SeqCst);
+            (cycles, clock::cycles_to_ns(cycles) / 1e9)
+        } else {
+            // Get elapsed time in seconds
+            (clock::now() - start_cycles,
+             (Instant::now() - start_time).as_secs_f64())
+        };
+
+        // Match up what was traced, the workers are done creating processes
+        #[cfg(target_os = "linux")]
+        let kernel = trace.map(crate::trace::Session::finish).transpose()?;
+        #[cfg(not(target_os = "linux"))]
+        let kernel = None;
+
+        // Compute fuzz cases/second
+        let fuzz_cases = shmem.fuzz_cases();
+        let fcps = fuzz_cases as f64 / elapsed;
+
+        // Just make sure all workers are "done", this should never happen
+        // unless we broke something
+        let workers = shmem.workers.load(Ordering::SeqCst);
+        if workers != 0 {
+            return Err(Error::WorkersRunning { workers });
+        }
+
+        Ok(TestResult {
+            point,
+            rep,
+            fuzz_cases,
+            forks:           shmem.forks(),
+            attempts:        shmem.attempts(),
+            fork_failures:   shmem.fork_failures(),
+            fork_errno:      shmem.fork_errno(),
+            vm_cycles:       shmem.vm_cycles(),
+            creation_cycles: shmem.creation_cycles(),
+            reap_cycles:     shmem.reap_cycles(),
+            crashes:         shmem.crashes(),
+            latency:         shmem.latency(),
+            worker_latency:  shmem.worker_latency(point.threads as usize),
+            usage:           shmem.usage(),
+            perf:            self.config.perf.then(|| shmem.perf()),
+            #[cfg(target_os = "linux")]
+            frequency:       frequency.and_then(|x| x.frequency()),
+            #[cfg(not(target_os = "linux"))]
+            frequency:       None,
+            kernel,
+            elapsed_cycles,
+            fcps_per_thread: fcps / point.threads as f64,
+            outlier:         false,
+        })
+    }
+
+    /// Get the test points to run once all of `tested` ran, given the
+    /// `summaries` of those which completed and the number of `rounds` of
+    /// test points added so far. Empty once the sweep is done.
+    pub fn next_round(&self, rounds: usize, summaries: &[PointSummary],
+                      tested: &[TestPoint]) -> Vec<TestPoint> {
+        if let Some(ratio) = self.config.search_efficiency {
+            sweep::search(ratio, summaries, tested)
+        } else if rounds < self.config.adaptive {
+            sweep::refine(summaries, tested)
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Run the whole sweep. A test point which fails is recorded as such and
+    /// the sweep carries on with the next one, unless it was interrupted.
+    /// Once all test points ran, those of `next_round()` are added.
+    pub fn run(&self) -> Report {
+        let mut report = Report::default();
+
+        // Sample the throughput while soaking if configured
+        let soak = Soak::new(&self.config);
+
+        let mut tests  = self.run_order();
+        let mut rounds = 0;
+        let mut next   = 0;
+        'points: loop {
+            if next == tests.len() {
+                let refined = self.next_round(rounds, &report.summaries,
+                                              &tests);
+                if refined.is_empty() {
+                    break;
+                }
+                rounds += 1;
+                tests.extend(refined);
+            }
+            let point = tests[next];
+            next += 1;
+
+            let mut results = Vec::new();
+            for rep in 0..self.config.reps {
+                let status = self.run_point_monitored(point, rep,
+                    |threads| soak.watch(threads, rep));
+                report.samples.extend(soak.take());
+
+                // Stop the sweep if we were interrupted, discarding the
+                // test point which was running
+                #[cfg(unix)]
+                if let Some(signal) = crate::signals::interrupted() {
+                    report.interrupted = Some(signal);
+                    break 'points;
+                }
+
+                match status {
+                    Ok(result) => results.push(result),
+                    Err(err) => {
+                        report.failures.push((point, err));
+                        continue 'points;
+                    }
+                }
+            }
+
+            if let Some(threshold) = self.config.outliers {
+                output::flag_outliers(&mut results, threshold);
+            }
+            report.summaries.push(PointSummary::new(&results,
+                self.config.noise_threshold));
+            report.results.extend(results);
+        }
+
+        // Answer the search with every test point which was tested
+        report.search = self.config.search_efficiency.map(|ratio| {
+            sweep::search_bounds(ratio, &report.summaries, &tests)
+        });
+
+        // Report shuffled test points in order
+        report.results.sort_by_key(|x| (x.point, x.rep));
+        report.summaries.sort_by_key(|x| x.point);
+        report.failures.sort_by_key(|x| x.0);
+        report
+    }
+}
@@ -0,0 +1,111 @@
+//! Fresh cgroup v2 leaves the workers of each test point run in
+//!
+//! With `--cgroup <dir>` every test point gets a new cgroup below `dir`,
+//! which must be a cgroup v2 directory delegated to the user running the
+//! benchmark. The workers join it before they start, such that they and
+//! their fuzz cases are isolated from the rest of the system, can be limited
+//! with `--cgroup-cpus` and `--cgroup-memory`, and pay whatever cgroup
+//! membership adds to every process they create. Charges propagate to every
+//! ancestor, which `--cgroup-depth` nests the leaf below to measure.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::config::Config;
+use crate::error::{Error, Result, Syscall};
+
+/// Period of the `cpu.max` quota, in microseconds
+const CPU_PERIOD_US: u64 = 100_000;
+
+/// Number of cgroups created by this process so far, keeping their names
+/// unique
+static CREATED: AtomicU64 = AtomicU64::new(0);
+
+/// A cgroup created for a test point, removed when dropped
+pub struct Cgroup {
+    /// Directories created, outermost first, the last one is the leaf
+    dirs: Vec<PathBuf>,
+}
+
+/// Write `contents` to the interface file `name` of the cgroup `dir`
+fn write(dir: &Path, name: &str, contents: &str) -> Result<()> {
+    let path = dir.join(name);
+    std::fs::write(&path, contents).map_err(|err| {
+        Error::Config(format!("Failed to write `{}` to `{}`: {}",
+                              contents, path.display(), err))
+    })
+}
+
+impl Cgroup {
+    /// Create a cgroup nested `config.cgroup_depth` levels below `parent`,
+    /// with the limits of `config` applied to the leaf
+    pub fn create(parent: &str, config: &Config) -> Result<Self> {
+        // Controllers have to be enabled by every ancestor of a cgroup whose
+        // limits are set
+        let mut controllers = Vec::new();
+        if config.cgroup_cpus.is_some() {
+            controllers.push("+cpu");
+        }
+        if config.cgroup_memory.is_some() {
+            controllers.push("+memory");
+        }
+
+        let mut cgroup = Cgroup { dirs: Vec::new() };
+        let mut dir = PathBuf::from(parent);
+        for level in 0..config.cgroup_depth {
+            if !controllers.is_empty() {
+                write(&dir, "cgroup.subtree_control", &controllers.join(" "))?;
+            }
+
+            dir = if level == 0 {
+                dir.join(format!("forkbench-{}-{}", std::process::id(),
+                                 CREATED.fetch_add(1, Ordering::SeqCst)))
+            } else {
+                dir.join(format!("level{}", level))
+            };
+            std::fs::create_dir(&dir).map_err(|err| {
+                Error::Config(format!("Failed to create cgroup `{}`: {}",
+                                      dir.display(), err))
+            })?;
+            cgroup.dirs.push(dir.clone());
+        }
+
+        // Apply the limits to the leaf only, its ancestors are unlimited
+        if let Some(cpus) = config.cgroup_cpus {
+            write(&dir, "cpu.max", &format!("{} {}",
+                (cpus * CPU_PERIOD_US as f64) as u64, CPU_PERIOD_US))?;
+        }
+        if let Some(mib) = config.cgroup_memory {
+            write(&dir, "memory.max", &(mib * 1024 * 1024).to_string())?;
+        }
+
+        Ok(cgroup)
+    }
+
+    /// Move the calling process into the leaf, along with every process it
+    /// creates from then on
+    pub fn join(&self) -> Result<()> {
+        let procs = self.dirs.last().unwrap().join("cgroup.procs");
+        let os_error = |call, err: std::io::Error| Error::Os {
+            call,
+            errno: err.raw_os_error().unwrap_or(0),
+        };
+
+        // Writing 0 moves the writer
+        let mut file = OpenOptions::new().write(true).open(procs)
+            .map_err(|err| os_error(Syscall::Open, err))?;
+        file.write_all(b"0").map_err(|err| os_error(Syscall::Write, err))
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        // A cgroup can only be removed once all processes in it exited and
+        // it has no children
+        for dir in self.dirs.iter().rev() {
+            let _ = std::fs::remove_dir(dir);
+        }
+    }
+}
@@ -0,0 +1,416 @@
+//! Checkpointing of completed test points, such that a sweep which was
+//! interrupted (or crashed, or the machine rebooted) can be resumed without
+//! measuring everything again
+//!
+//! The checkpoint is a text file starting with a header line identifying the
+//! configuration, followed by one line per result:
+//! `<threads> <workload> <memory MiB> <mappings> <descriptors>
+//! <idle threads> <rep> <fuzz cases> <forks> <attempts> <fork failures>
+//! <fork errno> <vm cycles> <creation cycles> <reap cycles> <crashes>
+//! <elapsed cycles> <fuzz cases/s/thread> <worker latency>
+//! <latency histogram> <usage> <frequency> <kernel timing> [<perf>]`, where
+//! the fork errno is `-` if no fork failed, the worker latency a comma
+//! separated list of `<min>:<max>` ticks of each worker, the histogram a
+//! comma separated list of bucket counts, the resource usage a comma
+//! separated list of its fields, the frequency and kernel timing comma
+//! separated lists of their fields (`-` if they weren't measured, or for
+//! timings which weren't matched up) and the event counts (only with
+//! `--perf`) a comma separated list with `-` for unavailable counters.
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+
+use crate::config::Config;
+use crate::output::TestResult;
+use crate::sweep::TestPoint;
+use crate::histogram::Histogram;
+use crate::perf;
+use crate::frequency::{self, Frequency};
+use crate::trace::{self, Timing};
+use crate::usage::{self, Usage};
+
+/// Magic at the start of the header line
+const MAGIC: &str = "forkbench-checkpoint";
+
+/// Results of an in-progress sweep which are persisted as each test point
+/// completes
+pub struct Checkpoint {
+    /// Checkpoint file, appended to
+    file: File,
+
+    /// Number of repetitions which make a test point complete
+    reps: usize,
+
+    /// Results recorded in the checkpoint so far, by test point and
+    /// repetition
+    results: BTreeMap<(TestPoint, usize), TestResult>,
+}
+
+/// Identify the parts of the configuration which affect the results of a
+/// test point, results are only reused if these match
+fn fingerprint(config: &Config) -> String {
+    format!("backend={} exec_path={:?} clone_flags={} unshare={} \
+        pidfd_wait={} \
+        criu_images={:?} criu_path={:?} pool_size={} snapshot_reset={} \
+        reaper={} in_flight={} ptrace={} ptrace_syscalls={} \
+        pipe_handshake={} ipc={} ipc_size={} \
+        duration={} duration_secs={:?} soak_secs={:?} iterations={:?} \
+        warmup={} reps={} \
+        clock={} cores={} pinning={} cpus={:?} \
+        sched_policy={} sched_priority={} nice={} \
+        numa_node={:?} mbind_node={:?} stats_node={:?} huge_pages={} \
+        cgroup={} cgroup_depth={} cgroup_cpus={:?} cgroup_memory={:?} \
+        fork_advice={} fork_advice_mib={:?} dirty_memory={:?} cow_pages={} \
+        workload_kind={} working_set={} stride={} perf={} \
+        cpu_frequency={} trace_fork={} perf_record={}",
+        config.backend.name(), config.exec_path, config.clone_flags,
+        config.unshare,
+        config.pidfd_wait.name(),
+        config.criu_images, config.criu_path, config.pool_size,
+        config.snapshot_reset.name(), config.reaper.name(), config.in_flight,
+        config.ptrace.name(), config.ptrace_syscalls, config.pipe_handshake,
+        config.ipc.name(), config.ipc_size,
+        config.duration, config.duration_secs, config.soak_secs,
+        config.iterations,
+        config.warmup, config.reps, config.clock.name(),
+        config.cores.name(), config.pinning.name(), config.cpus,
+        config.sched_policy.name(),
+        config.sched_priority, config.nice,
+        config.numa_node, config.mbind_node, config.stats_node(),
+        config.huge_pages.name(),
+        config.cgroup.is_some(), config.cgroup_depth, config.cgroup_cpus,
+        config.cgroup_memory, config.fork_advice.name(),
+        config.fork_advice_mib, config.dirty_memory, config.cow_pages,
+        config.workload_kind.name(), config.working_set, config.stride,
+        config.perf, config.cpu_frequency, config.trace_fork,
+        config.perf_record.is_some())
+}
+
+/// Render a result as a checkpoint line
+fn render(result: &TestResult) -> String {
+    let latency = result.latency.counts().iter().map(|x| x.to_string())
+        .collect::<Vec<_>>().join(",");
+
+    let worker_latency = result.worker_latency.iter()
+        .map(|(min, max)| format!("{}:{}", min, max))
+        .collect::<Vec<_>>().join(",");
+
+    let usage = result.usage.fields().iter().map(|x| x.to_string())
+        .collect::<Vec<_>>().join(",");
+
+    let frequency = result.frequency.map(|frequency| {
+        frequency.fields().iter().map(|x| x.to_string())
+            .collect::<Vec<_>>().join(",")
+    });
+
+    let kernel = result.kernel.map(|timing| {
+        timing.fields().iter().map(|x| x.map(|x| x.to_string())
+            .unwrap_or_else(|| "-".into())).collect::<Vec<_>>().join(",")
+    });
+
+    let perf = result.perf.map(|counts| {
+        counts.iter().map(|x| x.map(|x| x.to_string())
+            .unwrap_or_else(|| "-".into())).collect::<Vec<_>>().join(",")
+    });
+
+    format!("{} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} \
+             {} {}{}\n",
+            result.point.threads, result.point.workload,
+            result.point.memory_mib, result.point.vmas, result.point.fds,
+            result.point.idle_threads, result.rep,
+            result.fuzz_cases, result.forks, result.attempts,
+            result.fork_failures,
+            result.fork_errno.map(|x| x.to_string())
+                .unwrap_or_else(|| "-".into()),
+            result.vm_cycles, result.creation_cycles,
+            result.reap_cycles, result.crashes, result.elapsed_cycles,
+            result.fcps_per_thread, worker_latency,
+            if latency.is_empty() { "0" } else { &latency }, usage,
+            frequency.as_deref().unwrap_or("-"),
+            kernel.as_deref().unwrap_or("-"),
+            perf.map(|x| format!(" {}", x)).unwrap_or_default())
+}
+
+/// Parse a checkpoint line, `None` if it's malformed (eg. cut short by a
+/// crash while it was being written)
+fn parse(line: &str) -> Option<TestResult> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() != 23 && fields.len() != 24 {
+        return None;
+    }
+
+    let worker_latency = fields[18].split(',').map(|x| {
+        let (min, max) = x.split_once(':')?;
+        Some((min.parse().ok()?, max.parse().ok()?))
+    }).collect::<Option<Vec<_>>>()?;
+
+    let mut latency = Histogram::default();
+    for (bucket, count) in fields[19].split(',').enumerate() {
+        if bucket >= crate::histogram::BUCKETS {
+            return None;
+        }
+        latency.add(bucket, count.parse().ok()?);
+    }
+
+    let mut usage = [0; usage::FIELDS];
+    let values: Vec<&str> = fields[20].split(',').collect();
+    if values.len() != usage::FIELDS {
+        return None;
+    }
+    for (field, value) in usage.iter_mut().zip(values) {
+        *field = value.parse().ok()?;
+    }
+
+    let frequency = match fields[21] {
+        "-" => None,
+        field => {
+            let mut values = [0.; frequency::FIELDS];
+            let parsed: Vec<&str> = field.split(',').collect();
+            if parsed.len() != frequency::FIELDS {
+                return None;
+            }
+            for (value, parsed) in values.iter_mut().zip(parsed) {
+                *value = parsed.parse().ok()?;
+            }
+            Some(Frequency::from_fields(values))
+        }
+    };
+
+    let kernel = match fields[22] {
+        "-" => None,
+        field => {
+            let mut values = [None; trace::FIELDS];
+            let parsed: Vec<&str> = field.split(',').collect();
+            if parsed.len() != trace::FIELDS {
+                return None;
+            }
+            for (value, parsed) in values.iter_mut().zip(parsed) {
+                if parsed != "-" {
+                    *value = Some(parsed.parse().ok()?);
+                }
+            }
+            Some(Timing::from_fields(values))
+        }
+    };
+
+    let perf = match fields.get(23) {
+        Some(field) => {
+            let mut counts = [None; perf::COUNTERS];
+            let values: Vec<&str> = field.split(',').collect();
+            if values.len() != perf::COUNTERS {
+                return None;
+            }
+            for (count, value) in counts.iter_mut().zip(values) {
+                if value != "-" {
+                    *count = Some(value.parse().ok()?);
+                }
+            }
+            Some(counts)
+        }
+        None => None,
+    };
+
+    Some(TestResult {
+        point: TestPoint {
+            threads:    fields[0].parse().ok()?,
+            workload:   fields[1].parse().ok()?,
+            memory_mib: fields[2].parse().ok()?,
+            vmas:       fields[3].parse().ok()?,
+            fds:        fields[4].parse().ok()?,
+            idle_threads: fields[5].parse().ok()?,
+        },
+        rep:             fields[6].parse().ok()?,
+        fuzz_cases:      fields[7].parse().ok()?,
+        forks:           fields[8].parse().ok()?,
+        attempts:        fields[9].parse().ok()?,
+        fork_failures:   fields[10].parse().ok()?,
+        fork_errno:      match fields[11] {
+            "-"   => None,
+            errno => Some(errno.parse().ok()?),
+        },
+        vm_cycles:       fields[12].parse().ok()?,
+        creation_cycles: fields[13].parse().ok()?,
+        reap_cycles:     fields[14].parse().ok()?,
+        crashes:         fields[15].parse().ok()?,
+        elapsed_cycles:  fields[16].parse().ok()?,
+        fcps_per_thread: fields[17].parse().ok()?,
+        latency,
+        worker_latency,
+        usage:           Usage::from_fields(usage),
+        perf,
+        frequency,
+        kernel,
+        outlier:         false,
+    })
+}
+
+impl Checkpoint {
+    /// Open the checkpoint `path`. If `resume` is set the results in an
+    /// existing checkpoint are loaded, which fails if it was written with a
+    /// different configuration, otherwise any existing checkpoint is
+    /// discarded.
+    pub fn open(config: &Config, path: &str, resume: bool)
+            -> io::Result<Self> {
+        let header = format!("{} {}\n", MAGIC, fingerprint(config));
+
+        let mut contents = String::new();
+        if resume {
+            match File::open(path) {
+                Ok(mut file) => { file.read_to_string(&mut contents)?; }
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        // Start over if there's nothing to resume
+        if contents.is_empty() {
+            let mut file = File::create(path)?;
+            file.write_all(header.as_bytes())?;
+            file.sync_data()?;
+
+            return Ok(Checkpoint {
+                file,
+                reps:    config.reps,
+                results: BTreeMap::new(),
+            });
+        }
+
+        if !contents.starts_with(&header) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("checkpoint `{}` was written with a different \
+                    configuration", path)));
+        }
+
+        // Later results replace earlier ones of the same repetition, they
+        // were measured again after being cut short
+        let mut results = BTreeMap::new();
+        for result in contents[header.len()..].lines().filter_map(parse) {
+            results.insert((result.point, result.rep), result);
+        }
+
+        // Make sure a line cut short doesn't swallow the next one
+        let mut file = OpenOptions::new().append(true).open(path)?;
+        if !contents.ends_with('\n') {
+            file.write_all(b"\n")?;
+        }
+
+        Ok(Checkpoint { file, reps: config.reps, results })
+    }
+
+    /// Get the results of all repetitions of `point` if it was completed
+    pub fn completed(&self, point: &TestPoint) -> Option<Vec<TestResult>> {
+        (0..self.reps)
+            .map(|rep| self.results.get(&(*point, rep)).cloned())
+            .collect()
+    }
+
+    /// Record the results of all repetitions of a completed test point,
+    /// making sure they're on disk before returning
+    pub fn record(&mut self, results: &[TestResult]) -> io::Result<()> {
+        let lines: String = results.iter().map(render).collect();
+        self.file.write_all(lines.as_bytes())?;
+        self.file.sync_data()?;
+
+        for result in results {
+            self.results.insert((result.point, result.rep), result.clone());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A result with every field set, and the optional ones set if `all`
+    fn result(all: bool) -> TestResult {
+        let mut latency = Histogram::default();
+        latency.add(3, 17);
+        latency.add(9, 2);
+
+        TestResult {
+            point: TestPoint {
+                threads:      8,
+                workload:     100,
+                memory_mib:   64,
+                vmas:         1000,
+                fds:          10,
+                idle_threads: 2,
+            },
+            rep:             1,
+            fuzz_cases:      123456,
+            forks:           123460,
+            attempts:        123470,
+            fork_failures:   if all { 10 } else { 0 },
+            fork_errno:      all.then_some(11),
+            vm_cycles:       987654321,
+            creation_cycles: 55555,
+            reap_cycles:     44444,
+            crashes:         3,
+            elapsed_cycles:  1234567890,
+            fcps_per_thread: 15432.125,
+            latency,
+            worker_latency:  vec![(100, 2000), (150, 1800)],
+            usage:           Usage::from_fields([1, 2, 3, 4, 5, 6]),
+            perf:            all.then_some([Some(1), None, Some(3), None,
+                                            Some(5)]),
+            frequency:       all.then(|| {
+                Frequency::from_fields([2400., 3187.5, 3600.])
+            }),
+            kernel:          all.then(|| {
+                Timing::from_fields([Some(46000.), None, Some(19500.5)])
+            }),
+            outlier:         false,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for all in [false, true] {
+            let result = result(all);
+            let line = render(&result);
+            assert_eq!(line.split_whitespace().count(),
+                       if all { 24 } else { 23 });
+
+            let parsed = parse(&line).unwrap();
+            assert_eq!(parsed.point, result.point);
+            assert_eq!(parsed.rep, result.rep);
+            assert_eq!(parsed.fuzz_cases, result.fuzz_cases);
+            assert_eq!(parsed.forks, result.forks);
+            assert_eq!(parsed.attempts, result.attempts);
+            assert_eq!(parsed.fork_failures, result.fork_failures);
+            assert_eq!(parsed.fork_errno, result.fork_errno);
+            assert_eq!(parsed.vm_cycles, result.vm_cycles);
+            assert_eq!(parsed.creation_cycles, result.creation_cycles);
+            assert_eq!(parsed.reap_cycles, result.reap_cycles);
+            assert_eq!(parsed.crashes, result.crashes);
+            assert_eq!(parsed.elapsed_cycles, result.elapsed_cycles);
+            assert_eq!(parsed.fcps_per_thread, result.fcps_per_thread);
+            assert_eq!(parsed.latency.counts(), result.latency.counts());
+            assert_eq!(parsed.worker_latency, result.worker_latency);
+            assert_eq!(parsed.usage, result.usage);
+            assert_eq!(parsed.perf, result.perf);
+            assert_eq!(parsed.frequency, result.frequency);
+            assert_eq!(parsed.kernel, result.kernel);
+            assert_eq!(render(&parsed), line);
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_lines() {
+        for all in [false, true] {
+            // Cut short between fields
+            let line = render(&result(all));
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            for len in 0..23 {
+                assert!(parse(&fields[..len].join(" ")).is_none());
+            }
+
+            // Cut short within the last list
+            let cut = &line[..line.rfind(',').unwrap()];
+            assert!(parse(cut).is_none());
+        }
+    }
+}
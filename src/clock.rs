@@ -0,0 +1,250 @@
+//! Clock sources used to time the benchmark, and calibration of them against
+//! the wall clock so results can be reported in nanoseconds and forks per
+//! second
+
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// Interval the cycle counter is timed over when it has to be measured
+const CALIBRATION_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A monotonic source of ticks all benchmark timing is done with. Only
+/// differences of readings taken in the same process are ever used.
+pub trait Clock: Send + Sync {
+    /// Name of the clock, as selected on the command line
+    fn name(&self) -> &'static str;
+
+    /// Current reading of the clock in ticks
+    fn now(&self) -> u64;
+
+    /// Frequency of the clock in Hz and where it came from, `None` if it
+    /// has to be measured against the wall clock
+    fn frequency(&self) -> Option<(u64, &'static str)>;
+}
+
+/// The CPU cycle counter, `rdtsc` on x86_64 and `cntvct_el0` on aarch64
+pub struct CycleCounter;
+
+impl Clock for CycleCounter {
+    fn name(&self) -> &'static str {
+        "tsc"
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn now(&self) -> u64 {
+        unsafe { core::arch::x86_64::_rdtsc() }
+    }
+
+    /// There is no TSC on aarch64, use the virtual count of the generic timer
+    /// instead, the `isb` keeps the read from being speculated early
+    #[cfg(target_arch = "aarch64")]
+    fn now(&self) -> u64 {
+        let count: u64;
+        unsafe {
+            std::arch::asm!("isb", "mrs {}, cntvct_el0", out(reg) count,
+                            options(nomem, nostack));
+        }
+        count
+    }
+
+    /// Get the TSC frequency from CPUID leaf 0x15, which reports the ratio of
+    /// the TSC to the core crystal clock. Many CPUs report a zero crystal
+    /// frequency, in which case the TSC has to be measured.
+    #[cfg(target_arch = "x86_64")]
+    fn frequency(&self) -> Option<(u64, &'static str)> {
+        use core::arch::x86_64::__cpuid;
+
+        let max_leaf = __cpuid(0).eax;
+        if max_leaf < 0x15 {
+            return None;
+        }
+
+        let leaf = __cpuid(0x15);
+        if leaf.eax == 0 || leaf.ebx == 0 || leaf.ecx == 0 {
+            return None;
+        }
+
+        Some((leaf.ecx as u64 * leaf.ebx as u64 / leaf.eax as u64, "cpuid"))
+    }
+
+    /// Get the frequency of the generic timer, which the firmware programs
+    /// into `cntfrq_el0`
+    #[cfg(target_arch = "aarch64")]
+    fn frequency(&self) -> Option<(u64, &'static str)> {
+        let frequency: u64;
+        unsafe {
+            std::arch::asm!("mrs {}, cntfrq_el0", out(reg) frequency,
+                            options(nomem, nostack));
+        }
+
+        Some((frequency, "cntfrq")).filter(|x| x.0 != 0)
+    }
+}
+
+/// `clock_gettime(CLOCK_MONOTONIC_RAW)`, in nanoseconds. Slower to read than
+/// the cycle counter, but correct on CPUs whose TSC rate changes with the
+/// core frequency or which isn't synchronized between cores.
+#[cfg(unix)]
+pub struct MonotonicRaw;
+
+#[cfg(unix)]
+impl Clock for MonotonicRaw {
+    fn name(&self) -> &'static str {
+        "monotonic-raw"
+    }
+
+    fn now(&self) -> u64 {
+        // FreeBSD has no raw clock, its precise one is the closest
+        #[cfg(target_os = "freebsd")]
+        const CLOCK: libc::clockid_t = libc::CLOCK_MONOTONIC_PRECISE;
+        #[cfg(not(target_os = "freebsd"))]
+        const CLOCK: libc::clockid_t = libc::CLOCK_MONOTONIC_RAW;
+
+        let mut ts: libc::timespec = unsafe { core::mem::zeroed() };
+        unsafe {
+            libc::clock_gettime(CLOCK, &mut ts);
+        }
+        ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+    }
+
+    fn frequency(&self) -> Option<(u64, &'static str)> {
+        Some((1_000_000_000, "clock_gettime"))
+    }
+}
+
+/// Clock source selection
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ClockSource {
+    /// The cycle counter if it is invariant, otherwise `MonotonicRaw`
+    Auto,
+
+    /// Always use the cycle counter
+    Tsc,
+
+    /// Always use `clock_gettime(CLOCK_MONOTONIC_RAW)`
+    MonotonicRaw,
+}
+
+impl std::str::FromStr for ClockSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto"          => Ok(ClockSource::Auto),
+            "tsc"           => Ok(ClockSource::Tsc),
+            "monotonic-raw" => Ok(ClockSource::MonotonicRaw),
+            _ => Err(format!("Unknown clock source `{}`", s)),
+        }
+    }
+}
+
+impl ClockSource {
+    /// Name of the clock source, as selected on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            ClockSource::Auto         => "auto",
+            ClockSource::Tsc          => "tsc",
+            ClockSource::MonotonicRaw => "monotonic-raw",
+        }
+    }
+
+    /// Whether this clock source can be used on the current platform
+    pub fn is_supported(&self) -> bool {
+        *self != ClockSource::MonotonicRaw || cfg!(unix)
+    }
+
+    /// Get the clock this source selects
+    fn clock(&self) -> &'static dyn Clock {
+        match self {
+            #[cfg(unix)]
+            ClockSource::MonotonicRaw => &MonotonicRaw,
+            #[cfg(unix)]
+            ClockSource::Auto if !invariant_tsc() => &MonotonicRaw,
+            _ => &CycleCounter,
+        }
+    }
+}
+
+/// Clock used for all timing, selected once at startup
+static CLOCK: OnceLock<&'static dyn Clock> = OnceLock::new();
+
+/// Select the clock used for all timing. Must be called before any timing
+/// is done, forked children inherit the selection.
+pub fn select(source: ClockSource) {
+    assert!(CLOCK.set(source.clock()).is_ok(), "Clock already selected");
+}
+
+/// Check whether the clock was selected already
+pub fn is_selected() -> bool {
+    CLOCK.get().is_some()
+}
+
+/// Get the clock used for all timing, the automatically selected one if
+/// `select()` was never called
+pub fn clock() -> &'static dyn Clock {
+    *CLOCK.get_or_init(|| ClockSource::Auto.clock())
+}
+
+/// Current reading of the selected clock
+pub fn now() -> u64 {
+    clock().now()
+}
+
+/// Check whether the TSC ticks at a constant rate regardless of the core
+/// frequency and power state (CPUID 0x80000007 EDX bit 8)
+#[cfg(target_arch = "x86_64")]
+pub fn invariant_tsc() -> bool {
+    use core::arch::x86_64::__cpuid;
+
+    __cpuid(0x80000000).eax >= 0x80000007 &&
+        __cpuid(0x80000007).edx & (1 << 8) != 0
+}
+
+/// The generic timer always counts at a fixed frequency
+#[cfg(target_arch = "aarch64")]
+pub fn invariant_tsc() -> bool {
+    true
+}
+
+/// Frequency of the selected clock and how it was determined
+#[derive(Clone, Copy, Debug)]
+pub struct Calibration {
+    /// Frequency of the clock in Hz
+    pub frequency: u64,
+
+    /// Where the frequency came from: `cpuid`, `cntfrq`, `clock_gettime` or
+    /// `measured`
+    pub source: &'static str,
+}
+
+/// Get the calibration of the selected clock. The first call may take
+/// `CALIBRATION_INTERVAL` to measure the clock against the wall clock.
+pub fn calibration() -> Calibration {
+    static CALIBRATION: OnceLock<Calibration> = OnceLock::new();
+
+    *CALIBRATION.get_or_init(|| {
+        let (frequency, source) = clock().frequency()
+            .unwrap_or_else(|| (measure_frequency(), "measured"));
+
+        Calibration { frequency, source }
+    })
+}
+
+/// Convert a number of clock ticks into nanoseconds
+pub fn cycles_to_ns(cycles: u64) -> f64 {
+    cycles as f64 * 1e9 / calibration().frequency as f64
+}
+
+/// Measure the frequency of the selected clock against the monotonic wall
+/// clock
+fn measure_frequency() -> u64 {
+    let start_time   = Instant::now();
+    let start_cycles = now();
+
+    std::thread::sleep(CALIBRATION_INTERVAL);
+
+    let elapsed_cycles = now() - start_cycles;
+    let elapsed        = start_time.elapsed().as_secs_f64();
+
+    (elapsed_cycles as f64 / elapsed) as u64
+}
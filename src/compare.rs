@@ -0,0 +1,598 @@
+//! Comparison of the results of two runs, eg. of the same sweep on two kernel
+//! versions
+//!
+//! `forkbench compare <old> <new>` reads two results files written in any of
+//! the output formats, averages the repetitions of each test point and
+//! reports the change of each metric in percent, flagging changes for the
+//! worse beyond a threshold as regressions.
+
+use std::collections::BTreeMap;
+use std::io::{self, IsTerminal, Write};
+
+use crate::output::{TestResult, INSTS_PER_WORKLOAD};
+use crate::sweep::TestPoint;
+
+/// Usage string printed for `compare --help` and on argument errors
+pub const USAGE: &str = r#"usage: forkbench compare [options] <old> <new>
+
+Compares two results files written with `--output text`, `csv`, `json` or
+`jsonl`, reporting the change of each metric of each test point in percent.
+
+options:
+    --threshold <percent>       Flag changes for the worse of more than this
+                                many percent as regressions (default 5)
+    --help                      Print this message
+"#;
+
+/// Columns of text output, which has no header row, up to the last one
+/// compared
+const TEXT_COLUMNS: &[&str] = &[
+    "threads", "effective_workload_insts", "efficiency_ratio",
+    "fcps_per_thread", "prefork_memory_mib", "vmas", "fds", "idle_threads",
+    "forks_per_second", "ns_per_fork", "p50_ns", "p90_ns", "p99_ns",
+    "p999_ns", "creation_ns",
+];
+
+/// Metrics compared, and whether higher values are better
+const METRICS: &[(&str, bool)] = &[
+    ("efficiency_ratio", true),
+    ("forks_per_second", true),
+    ("ns_per_fork",      false),
+    ("creation_ns",      false),
+    ("p99_ns",           false),
+];
+
+/// Numeric columns of a single result, by name
+pub type Row = BTreeMap<String, f64>;
+
+/// Results of every repetition of each test point of a run
+pub type Results = BTreeMap<TestPoint, Vec<Row>>;
+
+/// A parsed JSON value
+enum Json {
+    /// A string, `null`, `true` or `false`, none of which are compared, as
+    /// its contents
+    Other(String),
+
+    /// A number
+    Number(f64),
+
+    /// An array of values
+    Array(Vec<Json>),
+
+    /// An object, as its members in order
+    Object(Vec<(String, Json)>),
+}
+
+/// Parser of the JSON documents written by `--output json`
+struct JsonParser<'a> {
+    /// Document being parsed
+    bytes: &'a [u8],
+
+    /// Offset of the next byte to parse
+    pos: usize,
+}
+
+impl JsonParser<'_> {
+    /// Skip whitespace and get the next byte, without consuming it
+    fn peek(&mut self) -> Option<u8> {
+        while self.bytes.get(self.pos)
+                .is_some_and(|x| x.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+        self.bytes.get(self.pos).copied()
+    }
+
+    /// Consume the next byte, which has to be `byte`
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        if self.peek() != Some(byte) {
+            return Err(format!("expected `{}` at offset {}", byte as char,
+                               self.pos));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    /// Parse a value
+    fn value(&mut self) -> Result<Json, String> {
+        match self.peek() {
+            Some(b'{') => {
+                self.pos += 1;
+                let mut members = Vec::new();
+                if self.peek() == Some(b'}') {
+                    self.pos += 1;
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    let key = self.string()?;
+                    self.expect(b':')?;
+                    members.push((key, self.value()?));
+                    if self.peek() == Some(b'}') {
+                        self.pos += 1;
+                        return Ok(Json::Object(members));
+                    }
+                    self.expect(b',')?;
+                }
+            }
+            Some(b'[') => {
+                self.pos += 1;
+                let mut elements = Vec::new();
+                if self.peek() == Some(b']') {
+                    self.pos += 1;
+                    return Ok(Json::Array(elements));
+                }
+                loop {
+                    elements.push(self.value()?);
+                    if self.peek() == Some(b']') {
+                        self.pos += 1;
+                        return Ok(Json::Array(elements));
+                    }
+                    self.expect(b',')?;
+                }
+            }
+            Some(b'"') => self.string().map(Json::Other),
+            Some(_) => {
+                // Numbers and literals run until the next delimiter
+                let start = self.pos;
+                while self.bytes.get(self.pos).is_some_and(|x| {
+                    !b",]} \t\r\n".contains(x)
+                }) {
+                    self.pos += 1;
+                }
+                let token = std::str::from_utf8(&self.bytes[start..self.pos])
+                    .unwrap_or("");
+                match token {
+                    "null" | "true" | "false" =>
+                        Ok(Json::Other(token.to_string())),
+                    _ => token.parse().map(Json::Number).map_err(|_| {
+                        format!("invalid value `{}` at offset {}", token,
+                                start)
+                    }),
+                }
+            }
+            None => Err(String::from("unexpected end of document")),
+        }
+    }
+
+    /// Parse a string
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+
+        let mut ret = Vec::new();
+        loop {
+            let byte = *self.bytes.get(self.pos)
+                .ok_or("unterminated string")?;
+            self.pos += 1;
+
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let escape = *self.bytes.get(self.pos)
+                        .ok_or("unterminated string")?;
+                    self.pos += 1;
+
+                    let chr = match escape {
+                        b'n' => '\n',
+                        b't' => '\t',
+                        b'r' => '\r',
+                        b'b' => '\x08',
+                        b'f' => '\x0c',
+                        b'u' => {
+                            let code = self.bytes.get(self.pos..self.pos + 4)
+                                .and_then(|x| std::str::from_utf8(x).ok())
+                                .and_then(|x| u32::from_str_radix(x, 16).ok())
+                                .ok_or("invalid unicode escape")?;
+                            self.pos += 4;
+                            char::from_u32(code).unwrap_or('\u{fffd}')
+                        }
+                        _ => escape as char,
+                    };
+                    let mut buf = [0u8; 4];
+                    ret.extend_from_slice(chr.encode_utf8(&mut buf)
+                        .as_bytes());
+                }
+                _ => ret.push(byte),
+            }
+        }
+
+        Ok(String::from_utf8_lossy(&ret).into_owned())
+    }
+}
+
+/// Get the numeric members of each object of `results`, booleans (like
+/// `outlier`) as 0 or 1
+fn rows(results: Vec<Json>) -> Vec<Row> {
+    results.into_iter().filter_map(|result| match result {
+        Json::Object(members) => Some(members.into_iter()
+            .filter_map(|(key, value)| match value {
+                Json::Number(value) => Some((key, value)),
+                Json::Other(value) if value == "true"  => Some((key, 1.)),
+                Json::Other(value) if value == "false" => Some((key, 0.)),
+                _ => None,
+            }).collect()),
+        _ => None,
+    }).collect()
+}
+
+/// Get the results of JSON lines output, the `result` of every line of the
+/// `result` type
+fn parse_jsonl(contents: &str) -> Result<Vec<Row>, String> {
+    let mut results = Vec::new();
+    for line in contents.lines().filter(|x| !x.trim().is_empty()) {
+        let mut parser = JsonParser { bytes: line.as_bytes(), pos: 0 };
+        let mut members = match parser.value()? {
+            Json::Object(members) => members,
+            _ => return Err(String::from("line is not an object")),
+        };
+        let is_result = members.iter().any(|(key, value)| {
+            key == "type" && matches!(value, Json::Other(x) if x == "result")
+        });
+        if let Some(pos) = members.iter().position(|x| x.0 == "result") {
+            if is_result {
+                results.push(members.swap_remove(pos).1);
+            }
+        }
+    }
+
+    Ok(rows(results))
+}
+
+/// Get the results of JSON output
+fn parse_json(contents: &str) -> Result<Vec<Row>, String> {
+    let mut parser = JsonParser { bytes: contents.as_bytes(), pos: 0 };
+    let document = parser.value()?;
+
+    let results = match document {
+        Json::Object(members) => members.into_iter()
+            .find(|(key, _)| key == "results").map(|x| x.1),
+        _ => None,
+    };
+    let results = match results {
+        Some(Json::Array(results)) => results,
+        _ => return Err(String::from("no `results` array")),
+    };
+
+    Ok(rows(results))
+}
+
+/// Render `value` as text, arrays as comma separated lists of their
+/// elements
+fn render(value: &Json) -> String {
+    match value {
+        Json::Other(value)  => value.clone(),
+        Json::Number(value) => value.to_string(),
+        Json::Array(elements) =>
+            elements.iter().map(render).collect::<Vec<_>>().join(", "),
+        Json::Object(members) => members.iter()
+            .map(|(key, value)| format!("{}: {}", key, render(value)))
+            .collect::<Vec<_>>().join(", "),
+    }
+}
+
+/// Flatten `value` into `ret` as rendered leaf values, named by the path of
+/// keys leading to them joined with `.`
+fn flatten(name: &str, value: &Json, ret: &mut Vec<(String, String)>) {
+    match value {
+        Json::Object(members) => {
+            for (key, value) in members {
+                let name = if name.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", name, key)
+                };
+                flatten(&name, value, ret);
+            }
+        }
+        _ => ret.push((name.to_string(), render(value))),
+    }
+}
+
+/// Load the `section` object (eg. `metadata` or `config`) of a results file
+/// written with `--output json`, flattened into its leaf values by name.
+/// Other formats have no sections, which is an empty list.
+pub fn load_section(path: &str, section: &str)
+        -> Result<Vec<(String, String)>, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|err| format!("Failed to read `{}`: {}", path, err))?;
+    if !contents.trim_start().starts_with('{') {
+        return Ok(Vec::new());
+    }
+
+    let mut parser = JsonParser { bytes: contents.as_bytes(), pos: 0 };
+    let document = parser.value()
+        .map_err(|err| format!("Failed to parse `{}`: {}", path, err))?;
+
+    let mut ret = Vec::new();
+    if let Json::Object(members) = &document {
+        if let Some((_, value)) = members.iter().find(|x| x.0 == section) {
+            flatten("", value, &mut ret);
+        }
+    }
+    Ok(ret)
+}
+
+/// Split the JSON object `text` into its members, as their keys and the JSON
+/// text of their values
+pub(crate) fn json_members(text: &str)
+        -> Result<Vec<(String, String)>, String> {
+    let mut parser = JsonParser { bytes: text.as_bytes(), pos: 0 };
+    let mut ret = Vec::new();
+
+    parser.expect(b'{')?;
+    if parser.peek() == Some(b'}') {
+        return Ok(ret);
+    }
+    loop {
+        let key = parser.string()?;
+        parser.expect(b':')?;
+        parser.peek();
+        let start = parser.pos;
+        parser.value()?;
+        ret.push((key, text[start..parser.pos].to_string()));
+        if parser.peek() == Some(b'}') {
+            return Ok(ret);
+        }
+        parser.expect(b',')?;
+    }
+}
+
+/// Split the JSON array `text` into the JSON text of its elements
+pub(crate) fn json_elements(text: &str) -> Result<Vec<String>, String> {
+    let mut parser = JsonParser { bytes: text.as_bytes(), pos: 0 };
+    let mut ret = Vec::new();
+
+    parser.expect(b'[')?;
+    if parser.peek() == Some(b']') {
+        return Ok(ret);
+    }
+    loop {
+        parser.peek();
+        let start = parser.pos;
+        parser.value()?;
+        ret.push(text[start..parser.pos].to_string());
+        if parser.peek() == Some(b']') {
+            return Ok(ret);
+        }
+        parser.expect(b',')?;
+    }
+}
+
+/// Get the test point of the JSON object `text` (a result, summary or
+/// failure)
+pub(crate) fn json_point(text: &str) -> Result<TestPoint, String> {
+    let mut parser = JsonParser { bytes: text.as_bytes(), pos: 0 };
+    let value = parser.value()?;
+    rows(vec![value]).first().map(point)
+        .ok_or_else(|| format!("`{}` is not an object", text))
+}
+
+/// Get the contents of the JSON string `text`, or of each element of the
+/// JSON array of strings `text`
+pub(crate) fn json_strings(text: &str) -> Result<Vec<String>, String> {
+    let mut parser = JsonParser { bytes: text.as_bytes(), pos: 0 };
+    let not_string = || format!("`{}` is not a string", text);
+    match parser.value()? {
+        Json::Other(value) => Ok(vec![value]),
+        Json::Array(elements) => elements.into_iter().map(|x| match x {
+            Json::Other(value) => Ok(value),
+            _ => Err(not_string()),
+        }).collect(),
+        _ => Err(not_string()),
+    }
+}
+
+/// Get the results of CSV output, named by the header row
+fn parse_csv(contents: &str) -> Result<Vec<Row>, String> {
+    let mut lines = contents.lines();
+    let header: Vec<&str> = lines.next().unwrap_or("").split(',').collect();
+
+    Ok(lines.filter(|line| !line.is_empty()).map(|line| {
+        // Unavailable event counts are empty and skipped
+        header.iter().zip(line.split(','))
+            .filter_map(|(name, value)| {
+                Some((name.to_string(), value.parse().ok()?))
+            }).collect()
+    }).collect())
+}
+
+/// Get the results of text output, skipping the summary comments and
+/// histograms. Outliers are marked by a trailing comment.
+fn parse_text(contents: &str) -> Result<Vec<Row>, String> {
+    Ok(contents.lines().filter_map(|line| {
+        let values = line.split_whitespace()
+            .map(|x| x.parse::<f64>().ok())
+            .take(TEXT_COLUMNS.len())
+            .collect::<Option<Vec<_>>>()?;
+        if values.len() < TEXT_COLUMNS.len() {
+            return None;
+        }
+
+        let mut row: Row = TEXT_COLUMNS.iter().map(|x| x.to_string())
+            .zip(values).collect();
+        row.insert("outlier".into(),
+                   if line.ends_with("# outlier") { 1. } else { 0. });
+        Some(row)
+    }).collect())
+}
+
+/// Get the test point a result was measured at. Dimensions which weren't
+/// swept by older versions default to 0.
+fn point(row: &Row) -> TestPoint {
+    let get = |name: &str| row.get(name).copied().unwrap_or(0.) as u64;
+    TestPoint {
+        threads:      get("threads"),
+        workload:     get("effective_workload_insts") / INSTS_PER_WORKLOAD,
+        memory_mib:   get("prefork_memory_mib"),
+        vmas:         get("vmas"),
+        fds:          get("fds"),
+        idle_threads: get("idle_threads"),
+    }
+}
+
+/// Load the results of a results file in any output format, by test point.
+/// Dimensions which weren't swept by older versions default to 0.
+pub fn load(path: &str) -> Result<Results, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|err| format!("Failed to read `{}`: {}", path, err))?;
+
+    // Every line of JSON lines output starts with its type
+    let rows = if contents.trim_start().starts_with("{\"type\"") {
+        parse_jsonl(&contents)
+    } else if contents.trim_start().starts_with('{') {
+        parse_json(&contents)
+    } else if contents.starts_with("threads,") {
+        parse_csv(&contents)
+    } else {
+        parse_text(&contents)
+    }.map_err(|err| format!("Failed to parse `{}`: {}", path, err))?;
+
+    let mut ret = Results::new();
+    for row in rows {
+        ret.entry(point(&row)).or_default().push(row);
+    }
+
+    if ret.is_empty() {
+        return Err(format!("No results in `{}`", path));
+    }
+
+    Ok(ret)
+}
+
+/// Get the compared metrics of results measured by this run
+pub fn from_results(results: &[TestResult]) -> Results {
+    let mut ret = Results::new();
+    for result in results {
+        let row = [
+            ("efficiency_ratio", result.efficiency()),
+            ("forks_per_second", result.forks_per_second()),
+            ("ns_per_fork",      result.ns_per_fork()),
+            ("creation_ns",      result.creation_ns()),
+            ("p99_ns",           result.latency_percentile_ns(0.99)),
+        ].iter().map(|&(name, value)| (name.to_string(), value)).collect();
+        ret.entry(result.point).or_default().push(row);
+    }
+    ret
+}
+
+/// Average `metric` over all repetitions of a test point which report it,
+/// leaving out the ones flagged as outliers
+fn mean(rows: &[Row], metric: &str) -> Option<f64> {
+    let values: Vec<f64> = rows.iter()
+        .filter(|row| row.get("outlier") != Some(&1.))
+        .filter_map(|row| row.get(metric).copied()).collect();
+    if values.is_empty() {
+        return None;
+    }
+
+    Some(values.iter().sum::<f64>() / values.len() as f64)
+}
+
+/// Compare the results files named on the command line (not including the
+/// program name and subcommand), printing the change of every metric of
+/// every test point
+pub fn run(args: impl IntoIterator<Item = String>) -> Result<(), String> {
+    let mut threshold = 5.0;
+    let mut paths = Vec::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--threshold" => {
+                threshold = args.next()
+                    .ok_or("`--threshold` requires a value")?
+                    .parse::<f64>()
+                    .map_err(|_| "Invalid value for `--threshold`")?;
+            }
+            "--help" | "-h" => {
+                print!("{}", USAGE);
+                std::process::exit(0);
+            }
+            _ if arg.starts_with("--") =>
+                return Err(format!("Unknown argument `{}`", arg)),
+            _ => paths.push(arg),
+        }
+    }
+
+    if paths.len() != 2 {
+        return Err(String::from("Expected an old and a new results file"));
+    }
+    if !threshold.is_finite() || threshold < 0.0 {
+        return Err(String::from("`--threshold` must not be negative"));
+    }
+
+    let old = load(&paths[0])?;
+    let new = load(&paths[1])?;
+
+    // Only highlight changes when someone is looking
+    report(&mut io::stdout(), &old, &new, threshold,
+           io::stdout().is_terminal())
+        .map_err(|err| format!("Failed to write comparison: {}", err))?;
+
+    Ok(())
+}
+
+/// Write the change of every metric of every test point from `old` to `new`
+/// to `writer`, highlighting changes beyond `threshold` percent with colors
+/// if `color` is set. Returns the number of test points which regressed.
+pub fn report(writer: &mut dyn Write, old: &Results, new: &Results,
+              threshold: f64, color: bool) -> io::Result<usize> {
+    write!(writer, "{:>10} {:>14} {:>8} {:>8} {:>8} {:>8}", "threads",
+           "workload", "memory", "vmas", "fds", "idle")?;
+    for (name, _) in METRICS {
+        write!(writer, " {:>17}", name)?;
+    }
+    write!(writer, "\n")?;
+
+    let mut points: Vec<&TestPoint> = old.keys().chain(new.keys()).collect();
+    points.sort();
+    points.dedup();
+
+    let mut compared  = 0;
+    let mut regressed = 0;
+    for &point in &points {
+        write!(writer, "{:10} {:14} {:8} {:8} {:8} {:8}", point.threads,
+               point.workload * INSTS_PER_WORKLOAD, point.memory_mib,
+               point.vmas, point.fds, point.idle_threads)?;
+
+        let mut regression = false;
+        for &(metric, higher_is_better) in METRICS {
+            let delta = old.get(point).and_then(|x| mean(x, metric))
+                .zip(new.get(point).and_then(|x| mean(x, metric)))
+                .map(|(old, new)| (new - old) / old * 100.0)
+                .filter(|x| x.is_finite());
+            let delta = match delta {
+                Some(delta) => delta,
+                None => {
+                    write!(writer, " {:>17}", "-")?;
+                    continue;
+                }
+            };
+
+            // Regressions are marked such that they can be grepped for
+            let worse = if higher_is_better { -delta } else { delta };
+            let cell = format!("{:+.2}%{}", delta,
+                               if worse > threshold { "!" } else { " " });
+            regression |= worse > threshold;
+            let (start, end) = match (worse > threshold, -worse > threshold) {
+                _ if !color  => ("", ""),
+                (true, _)    => ("\x1b[1;31m", "\x1b[0m"),
+                (_, true)    => ("\x1b[32m", "\x1b[0m"),
+                _            => ("", ""),
+            };
+            write!(writer, " {}{:>17}{}", start, cell, end)?;
+        }
+        write!(writer, "\n")?;
+
+        if old.contains_key(point) && new.contains_key(point) {
+            compared += 1;
+        }
+        regressed += regression as usize;
+    }
+
+    // Commented out like the text output summaries
+    write!(writer, "# {} test points compared ({} only in one file), {} \
+        regressed by more than {}%\n", compared, points.len() - compared,
+        regressed, threshold)?;
+
+    Ok(regressed)
+}
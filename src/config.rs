@@ -0,0 +1,1569 @@
+//! Command line configuration of the benchmark sweep
+
+use std::io::IsTerminal;
+
+use crate::threading::{LogicalProcessor, SchedPolicy};
+use crate::topology::{Topology, PinStrategy, CoreSelection, StatsPlacement};
+use crate::output::{self, OutputFormat};
+use crate::backend::{self, BackendKind, Ipc, PidfdWait, Ptrace, Reaper};
+use crate::backend::SnapshotReset;
+use crate::memory::{HugePages, ForkAdvice};
+use crate::clock::ClockSource;
+use crate::workload::WorkloadKind;
+use crate::replay::Replay;
+use crate::sweep::{Axis, Spacing, TestPoint, ThreadCounts};
+
+/// Usage string printed for `--help` and on argument errors
+pub const USAGE: &str = r#"usage: forkbench [run] [options]
+       forkbench report [options] <results>
+       forkbench plot [options] <results> <script>
+       forkbench compare [options] <old> <new>
+       forkbench watch [options] [<pid>]
+       forkbench coordinator [options] -- [<run options>]
+       forkbench agent [options] <url>
+
+`run` (the default) runs a sweep, `report`, `plot` and `compare` work on the
+results files of earlier runs and `watch` follows a running sweep.
+`coordinator` has the `agent`s joining it run a sweep on their machines and
+combines their results. Run `forkbench <command> --help` for the options of
+the other commands.
+
+options of `run`:
+    --config           <path>   Load options from a TOML benchmark definition,
+                                whose keys are options without the `--`.
+                                Options after it override the file.
+    --thread-samples   <n>      Number of samples of thread counts
+    --workload-samples <n>      Number of samples of workloads
+    --spacing          <mode>   Spacing of the samples of every sampled
+                                axis: `log` (the default) or `linear`
+    --threads          <list>   Test exactly these comma separated thread
+                                counts, `all` counts up to the maximum, or
+                                `physical` ones up to the number of physical
+                                cores, rather than samples
+    --adaptive         <rounds> Start from a coarse grid of thread counts
+                                (`--thread-samples` samples and the maximum)
+                                and add a thread count around the knee of
+                                each curve, where efficiency drops sharpest,
+                                for <rounds> rounds
+    --search-efficiency <ratio> Binary search the most threads which keep an
+                                efficiency of at least <ratio> (eg. 0.8),
+                                with every other axis at a single value
+    --workloads        <list>   Test exactly these comma separated
+                                workloads, rather than samples
+    --max-threads      <n>      Maximum number of threads to test
+                                (defaults to the number of selected logical
+                                processors)
+    --numa-node        <node>   Only use logical processors of a NUMA node
+    --cores            <cores>  Classes of cores of hybrid CPUs to use:
+                                `mixed` (the default), `p-only` or `e-only`
+    --pinning          <mode>   Order workers are assigned to logical
+                                processors: `compact`, `physical-first` or
+                                `scatter` (across NUMA nodes)
+    --cpus             <list>   Pin workers to exactly these logical
+                                processors in this order (eg. `0-15,32-47`),
+                                rather than selecting and ordering them
+                                automatically
+    --sched-policy     <policy> Scheduling policy the workers and their fuzz
+                                cases run under: `other`, `batch`, `idle`,
+                                or real-time `fifo` or `rr` (Linux)
+    --sched-priority   <n>      Priority of the real-time policies, 1 to 99
+    --nice             <n>      Nice level of the `other` and `batch`
+                                policies, -20 to 19 (Linux)
+    --mbind-node       <node>   Bind the statistics and pre-fork memory to a
+                                NUMA node
+    --stats-placement  <mode>   NUMA node the statistics are placed on:
+                                `default`, `local` (the node of the first
+                                worker) or `remote` (a node none of the
+                                workers run on), overriding `--mbind-node`
+                                for them (Linux)
+    --shm-dir          <dir>    Back the statistics with a file in <dir> (eg.
+                                a tmpfs mount) rather than anonymous shared
+                                memory
+    --cgroup           <dir>    Run the workers of each test point in a fresh
+                                cgroup created below <dir>, a delegated
+                                cgroup v2 directory (Linux)
+    --cgroup-depth     <n>      Nest the cgroup of each test point <n> levels
+                                below <dir> (default: 1)
+    --cgroup-cpus      <n>      Limit the cgroup to <n> CPUs worth of time
+                                (`cpu.max`)
+    --cgroup-memory    <MiB>    Limit the memory of the cgroup (`memory.max`)
+    --max-workload     <n>      Maximum workload to sample to
+    --duration         <ticks>  Number of clock ticks each worker runs for
+    --duration-secs    <secs>   Number of seconds each worker runs for,
+                                overriding `--duration`
+    --iterations       <n>      Run exactly <n> fuzz cases per worker rather
+                                than for a duration
+    --point            <point>  Test only this test point, given as axis
+                                values (eg. `threads=64,workload=10000`,
+                                also `memory`, `vmas`, `fds` and
+                                `idle_threads`), rather than a sweep
+    --soak             <time>   Run the test point of `--point` for <time>
+                                (eg. `600`, `10m` or `2h`), sampling its
+                                throughput on the way
+    --soak-interval    <secs>   Interval `--soak` samples the throughput at
+                                (defaults to 10)
+    --replay           <path>   Test the test points of the results file
+                                <path> (`--output json` or `jsonl`) again,
+                                merging the new results into it
+    --filter           <conds>  Replay only the test points matching all of
+                                these conditions on axes or metrics (eg.
+                                `threads>=64,efficiency_ratio<0.5`)
+    --warmup           <ticks>  Number of clock ticks each worker runs for
+                                before the measurement of each test point
+    --reps             <n>      Number of repetitions of each test point,
+                                summarized by mean, stddev and 95% CI
+    --outliers         <mads>   Flag repetitions whose efficiency or fork
+                                rate is more than <mads> (eg. 3.5) median
+                                absolute deviations from the median of their
+                                test point as outliers, leaving them out of
+                                the summary. Results are then reported once
+                                all repetitions of a test point are done.
+    --noise-threshold  <pct>    Flag test points whose efficiency varies by
+                                more than <pct> percent (its coefficient of
+                                variation) across repetitions as noisy
+                                (defaults to 5)
+    --timeout          <secs>   Kill the workers of a test which hasn't
+                                finished after <secs> seconds, failing the
+                                test point rather than hanging the sweep
+    --shuffle                   Run the test points in a random order, such
+                                that drift over the sweep (eg. thermal
+                                throttling) doesn't bias the last ones.
+                                Results are still reported in order, once
+                                the sweep is done.
+    --shuffle-seed     <n>      Shuffle with a given seed, eg. the one
+                                recorded by an earlier run (implies
+                                `--shuffle`)
+    --clock            <source> Clock all timing is done with: `tsc` (the
+                                cycle counter), `monotonic-raw` or `auto`
+                                (the TSC only if it is invariant)
+    --overhead                  Benchmark the overhead of fork() over the
+                                (threads, workload) matrix rather than only
+                                the scaling of fork() with cores
+    --memory-samples   <n>      Number of samples (spaced per `--spacing`)
+                                of the MiB of memory each worker dirties
+                                before forking (0 to not sweep pre-fork
+                                memory)
+    --max-memory       <MiB>    Maximum pre-fork memory to sample to
+    --vma-samples      <n>      Number of samples (spaced per `--spacing`)
+                                of the number of distinct memory mappings
+                                each worker creates before forking (0 to
+                                not sweep mappings)
+    --max-vmas         <n>      Maximum number of mappings to sample to
+    --fd-samples       <n>      Number of samples (spaced per `--spacing`)
+                                of the number of file descriptors each
+                                worker holds open before forking (0 to not
+                                sweep them, Linux)
+    --max-fds          <n>      Maximum number of descriptors to sample to
+    --idle-thread-samples <n>   Number of samples (spaced per `--spacing`)
+                                of the number of idle threads each worker
+                                starts before forking (0 to not sweep them)
+    --max-idle-threads <n>      Maximum number of idle threads to sample to
+    --huge-pages       <mode>   Huge page backing of the pre-fork memory:
+                                `default`, `hugetlb`, `thp` or `no-thp`
+    --fork-advice      <mode>   Fork behavior applied to the pre-fork memory
+                                with madvise(): `none`, `wipeonfork` or
+                                `dontfork`
+    --fork-advice-mib  <MiB>    MiB at the end of the pre-fork memory the fork
+                                advice applies to (defaults to all of it)
+    --dirty-memory     <bytes>  Number of bytes of memory to dirty per fuzz
+                                case (one write per page)
+    --cow-pages        <n>      Number of pages inherited from the worker the
+                                fuzz case writes to, forcing copy-on-write
+                                faults
+    --workload-kind    <kind>   Kernel run by each fuzz case: `stack` (loads
+                                from the stack), `compute` (integer and
+                                floating-point dependency chains touching
+                                no memory), reads and writes of a
+                                buffer inherited from the worker which are
+                                `sequential`, `strided` or `random`, or
+                                system calls bypassing the vDSO, `getpid` or
+                                `clock_gettime` (one per iteration)
+    --working-set      <bytes>  Size of the buffer of the buffer kernels
+                                (defaults to 1 MiB)
+    --stride           <bytes>  Distance between the accesses of the
+                                `strided` kernel (defaults to 4096)
+    --backend          <name>   Process-creation backend to benchmark: `fork`,
+                                `vfork`, `posix_spawn`, `fork_exec`,
+                                `shell_spawn`, `clone`, `clone3`, `pdfork`
+                                (FreeBSD), `criu_restore`, `thread`, `pool`,
+                                `snapshot` or `create_process` (the only one on
+                                Windows)
+    --exec-path        <path>   Target launched by the `posix_spawn` and
+                                `fork_exec` backends instead of a helper
+                                instance of this binary running the workload,
+                                and by `shell_spawn` instead of /bin/true
+    --clone-flags      <flags>  Comma separated flags for the `clone` backend
+                                (vm, fs, files, sighand, sysvsem, io, newipc,
+                                newnet, newns, newpid, newuts, newuser,
+                                newcgroup)
+    --unshare          <ns>     Comma separated namespaces each fuzz case of
+                                the `fork`, `clone` and `clone3` backends
+                                `unshare()`s first (ipc, net, mount, pid,
+                                uts, user, cgroup)
+    --pidfd-wait       <mode>   How the `clone3` backend waits for children
+                                through their pidfd: `waitid` or `poll`
+    --criu-images      <dir>    Directory of the process image restored by
+                                the `criu_restore` backend, dumped with
+                                `criu dump` from a process which exits on
+                                its own once restored
+    --criu-path        <path>   `criu` executable used by the `criu_restore`
+                                backend (defaults to /usr/sbin/criu)
+    --pool-size        <n>      Number of children each worker of the `pool`
+                                backend forks up front and dispatches fuzz
+                                cases to (defaults to 4)
+    --snapshot-reset   <mode>   How the `snapshot` backend resets the
+                                pre-fork memory after each fuzz case: `copy`
+                                all of it, or only the `dirty` pages found
+                                through soft-dirty bits (Linux)
+    --reaper           <mode>   How the `fork` backend reaps children:
+                                `serial`ly after each fork, or as `SIGCHLD`
+                                arrives on a `signalfd` while forking the
+                                next one (Linux)
+    --in-flight        <n>      Most children alive at a time per worker
+                                with `--reaper signalfd` (defaults to 4)
+    --ptrace           <mode>   Supervise every child of the `fork` backend
+                                with ptrace() like a debugger-based fuzzer:
+                                `off` (the default), `traceme` (the child
+                                asks to be traced) or `attach` (the worker
+                                attaches to it). Its exit is reported as a
+                                ptrace event (Linux)
+    --ptrace-syscalls           Also stop traced children at every system
+                                call
+    --pipe-handshake            Make a round trip over a pipe between the
+                                worker and every child of the `fork` backend
+                                (write a token, read an acknowledgement)
+                                before it exits, like fork-based fuzzers do
+    --ipc              <mode>   Have every child of the `fork` backend send
+                                a message back to its worker after the fuzz
+                                case: `off` (the default), `socketpair` or
+                                `ring` (a buffer in shared memory)
+    --ipc-size         <bytes>  Size of the message of `--ipc` (defaults to
+                                65536)
+    --output           <fmt>    Format to report results in: `text`, `csv`,
+                                `json` or `jsonl` (a JSON object per line,
+                                written as each test completes)
+    --output-file      <path>   File to write results to (defaults to stdout)
+    --out              <path>   Also append results to <path>, synced to disk
+                                after every test such that it survives a
+                                lost terminal and can be followed with
+                                `tail -f` (only `--output jsonl` with
+                                `--shuffle`)
+    --histogram                 Print the log2 histogram of iteration
+                                latencies after each result (text output,
+                                JSON output always includes it)
+    --perf                      Count context switches, page faults, cache
+                                misses and instructions of the workers and
+                                fuzz cases with perf_event_open() (Linux)
+    --trace-fork                Time the fork path in the kernel and the
+                                delay until new processes first run with
+                                ftrace tracepoints, separately from the
+                                latency the workers observe (Linux, root)
+    --perf-record      <dir>    Record each test with `perf record -g` on the
+                                logical processors of its workers, saving a
+                                perf.data file per test point and repetition
+                                in <dir> (Linux)
+    --cpu-frequency             Measure the min, average and max frequency of
+                                the logical processors of the workers
+                                during each test, with APERF/MPERF if the
+                                MSRs are readable, or by sampling cpufreq
+                                (Linux)
+    --gnuplot          <path>   Write a gnuplot script rendering a heatmap of
+                                the results to <path>
+    --heatmap                   Draw the efficiency heatmap on stderr once
+                                the sweep is done
+    --usl                       Fit the Universal Scalability Law to the fork
+                                rate of each scaling curve and print its
+                                contention and coherency cost on stderr once
+                                the sweep is done
+    --checkpoint       <path>   Record the results of each completed test
+                                point in <path>
+    --resume                    Skip test points already completed in the
+                                `--checkpoint` file, reporting their recorded
+                                results instead
+    --save-baseline    <name>   Store the results as the baseline <name>
+    --gate-against     <name>   Compare the results against the baseline
+                                <name>, exiting with status 2 if any test
+                                point regressed
+    --gate-threshold   <pct>    Change for the worse in percent a test point
+                                may have before it counts as regressed
+                                (defaults to 5)
+    --baseline-dir     <path>   Directory baselines are stored in (defaults
+                                to `forkbench-baselines`)
+    --strict                    Refuse to run if the machine is set up in a
+                                way which makes results unreliable (turbo,
+                                a governor other than `performance`, SMT
+                                with `physical-first` pinning, mixed core
+                                classes, deep C-states or swap), rather
+                                than warning
+    --no-progress               Don't report the progress of the sweep on
+                                stderr
+    --metrics          <addr>   Serve the progress of the sweep, the fork
+                                rate of each worker and the most recent
+                                result as Prometheus metrics over HTTP on
+                                <addr> (eg. `0.0.0.0:9100`)
+    --stream           <url>    Send every result, summary and failed test
+                                point and live statistics of the running one
+                                to the collector at <url>
+                                (`tcp://host:port`) as newline-delimited JSON
+    --tui                       Show a live dashboard of the fork rate of
+                                each worker and the efficiency of completed
+                                test points on stderr instead
+    --dry-run                   Print the test points, the logical
+                                processors the workers of each thread count
+                                are pinned to and the estimated duration of
+                                the sweep rather than running it
+    --help                      Print this message
+"#;
+
+/// Configuration of a benchmark sweep
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Number of samples to have over the thread range
+    pub thread_samples: usize,
+
+    /// Number of samples to have over the workload range
+    pub workload_samples: usize,
+
+    /// Spacing of the samples of every sampled axis
+    pub spacing: Spacing,
+
+    /// Thread counts tested instead of samples of the range
+    pub thread_counts: Option<ThreadCounts>,
+
+    /// Number of rounds of sampling the thread counts around the knee of
+    /// the scaling curve, 0 to not refine the grid
+    pub adaptive: usize,
+
+    /// Efficiency to search the most threads which keep it for, rather than
+    /// sweeping the thread counts
+    pub search_efficiency: Option<f64>,
+
+    /// Workloads tested instead of samples of the range
+    pub workloads: Option<Vec<u64>>,
+
+    /// Maximum number of threads to test
+    pub max_threads: usize,
+
+    /// Maximum workload to sample to
+    pub max_workload: usize,
+
+    /// Only use logical processors of this NUMA node
+    pub numa_node: Option<u16>,
+
+    /// Classes of cores of hybrid CPUs to use
+    pub cores: CoreSelection,
+
+    /// Order workers are assigned to logical processors
+    pub pinning: PinStrategy,
+
+    /// Logical processors to pin workers to in order, by OS index, rather
+    /// than selecting them automatically
+    pub cpus: Option<Vec<usize>>,
+
+    /// Scheduling policy the workers and their fuzz cases run under
+    pub sched_policy: SchedPolicy,
+
+    /// Priority of the real-time scheduling policies
+    pub sched_priority: u32,
+
+    /// Nice level of the time-sharing scheduling policies
+    pub nice: i32,
+
+    /// NUMA node to bind the statistics and pre-fork memory to
+    pub mbind_node: Option<u16>,
+
+    /// NUMA node the statistics are placed on relative to the workers
+    pub stats_placement: StatsPlacement,
+
+    /// Directory to create the file backing the statistics in, anonymous
+    /// shared memory is used if `None`
+    pub shm_dir: Option<String>,
+
+    /// Cgroup v2 directory to create a cgroup for each test point below,
+    /// workers stay in the cgroup of the benchmark if `None`
+    pub cgroup: Option<String>,
+
+    /// Number of levels the cgroup of each test point is nested below
+    /// `cgroup`
+    pub cgroup_depth: u32,
+
+    /// Number of CPUs worth of time the cgroup of each test point is limited
+    /// to
+    pub cgroup_cpus: Option<f64>,
+
+    /// Memory limit of the cgroup of each test point, in MiB
+    pub cgroup_memory: Option<u64>,
+
+    /// Topology of the system the benchmark runs on
+    pub topology: Topology,
+
+    /// Logical processors workers are pinned to, worker `n` is pinned to the
+    /// `n`th processor
+    pub processors: Vec<LogicalProcessor>,
+
+    /// Number of clock ticks each worker spends forking per test
+    pub duration: u64,
+
+    /// Number of seconds each worker spends forking per test, overriding
+    /// `duration` if set
+    pub duration_secs: Option<f64>,
+
+    /// Number of fuzz cases each worker runs per test, rather than running
+    /// for a duration
+    pub iterations: Option<u64>,
+
+    /// Clock all timing is done with
+    pub clock: ClockSource,
+
+    /// Number of clock ticks each worker warms up for before measuring
+    pub warmup: u64,
+
+    /// Number of repetitions of each test point
+    pub reps: usize,
+
+    /// Number of scaled median absolute deviations from the median of its
+    /// test point beyond which a repetition is flagged as an outlier
+    pub outliers: Option<f64>,
+
+    /// Coefficient of variation in percent of the efficiency of a test point
+    /// across its repetitions beyond which it's flagged as noisy
+    pub noise_threshold: f64,
+
+    /// Single test point tested instead of a sweep
+    pub point: Option<TestPoint>,
+
+    /// Number of seconds the test point runs for while its throughput is
+    /// sampled, overriding the duration if set
+    pub soak_secs: Option<f64>,
+
+    /// Number of seconds between throughput samples of `soak_secs`
+    pub soak_interval: f64,
+
+    /// Test points of an earlier run tested again instead of a sweep
+    pub replay: Option<Replay>,
+
+    /// Number of seconds after which the workers of a test which hasn't
+    /// finished are killed, they're waited for indefinitely if `None`
+    pub timeout_secs: Option<f64>,
+
+    /// Seed of the random order the test points are run in, they are run
+    /// in order if `None`
+    pub shuffle_seed: Option<u64>,
+
+    /// Benchmark the overhead of fork()
+    /// If `false`, this tool will instead benchmark the scaling of fork() with
+    /// cores
+    pub bench_overhead: bool,
+
+    /// Number of samples (spaced per `--spacing`) to have over the pre-fork
+    /// memory range, zero to not sweep pre-fork memory
+    pub memory_samples: usize,
+
+    /// Maximum amount of memory (in MiB) each worker dirties before forking
+    pub max_memory_mib: usize,
+
+    /// Number of samples (spaced per `--spacing`) to have over the mapping
+    /// count range, zero to not sweep extra mappings
+    pub vma_samples: usize,
+
+    /// Maximum number of extra mappings each worker creates before forking
+    pub max_vmas: usize,
+
+    /// Number of samples (spaced per `--spacing`) to have over the open
+    /// descriptor count range, zero to not sweep extra descriptors
+    pub fd_samples: usize,
+
+    /// Maximum number of extra descriptors each worker holds open before
+    /// forking
+    pub max_fds: usize,
+
+    /// Number of samples (spaced per `--spacing`) to have over the idle
+    /// thread count range, zero to not sweep idle threads
+    pub idle_thread_samples: usize,
+
+    /// Maximum number of idle threads each worker starts before forking
+    pub max_idle_threads: usize,
+
+    /// Huge page backing of the pre-fork memory
+    pub huge_pages: HugePages,
+
+    /// Fork behavior applied to the pre-fork memory
+    pub fork_advice: ForkAdvice,
+
+    /// MiB at the end of the pre-fork memory the fork advice applies to, all
+    /// of it if `None`
+    pub fork_advice_mib: Option<usize>,
+
+    /// If set, the `Some(bytes)` will tell how many bytes of memory should
+    /// be dirtied (written to) per fuzz case. Only one write will occur per
+    /// page, thus it will be minimal CPU traffic, it's just stressing OS
+    /// paging.
+    pub dirty_memory: Option<usize>,
+
+    /// Number of pages inherited from the worker which are written to per
+    /// fuzz case, forcing copy-on-write faults in the child
+    pub cow_pages: usize,
+
+    /// Kernel run by each fuzz case
+    pub workload_kind: WorkloadKind,
+
+    /// Size in bytes of the buffer accessed by the buffer kernels
+    pub working_set: usize,
+
+    /// Distance in bytes between the accesses of the strided kernel
+    pub stride: usize,
+
+    /// Process-creation backend to benchmark
+    pub backend: BackendKind,
+
+    /// Target to launch for the `posix_spawn` and `fork_exec` backends, a
+    /// helper instance of ourselves if `None` (`/bin/true` for
+    /// `shell_spawn`)
+    pub exec_path: Option<String>,
+
+    /// `CLONE_*` flags used by the `clone` backend
+    pub clone_flags: libc::c_int,
+
+    /// `CLONE_NEW*` flags of the namespaces each fuzz case `unshare()`s
+    pub unshare: libc::c_int,
+
+    /// How the `clone3` backend waits for children
+    pub pidfd_wait: PidfdWait,
+
+    /// Directory of the process image restored by the `criu_restore`
+    /// backend
+    pub criu_images: Option<String>,
+
+    /// `criu` executable used by the `criu_restore` backend
+    pub criu_path: String,
+
+    /// Number of children each worker of the `pool` backend keeps
+    pub pool_size: usize,
+
+    /// How the `snapshot` backend resets the pre-fork memory
+    pub snapshot_reset: SnapshotReset,
+
+    /// How the `fork` backend reaps children
+    pub reaper: Reaper,
+
+    /// Most children alive at a time per worker when reaping asynchronously
+    pub in_flight: usize,
+
+    /// How the `fork` backend supervises children with `ptrace()`
+    pub ptrace: Ptrace,
+
+    /// Stop traced children at every system call
+    pub ptrace_syscalls: bool,
+
+    /// Make a round trip over a pipe with every child of the `fork` backend
+    pub pipe_handshake: bool,
+
+    /// How children of the `fork` backend send a message back to the worker
+    pub ipc: Ipc,
+
+    /// Size of the message of `ipc` in bytes
+    pub ipc_size: usize,
+
+    /// Format to report results in
+    pub output_format: OutputFormat,
+
+    /// File to write results to, stdout if `None`
+    pub output_file: Option<String>,
+
+    /// File results are also appended to, synced after every result
+    pub out_file: Option<String>,
+
+    /// Print the iteration latency histogram of each result in text output
+    pub histogram: bool,
+
+    /// Collect the event counters of each test with `perf_event_open()`
+    pub perf: bool,
+
+    /// Measure the frequency of the logical processors of the workers during
+    /// each test
+    pub cpu_frequency: bool,
+
+    /// Time process creation in the kernel with ftrace tracepoints
+    pub trace_fork: bool,
+
+    /// Directory to save a `perf record` of each test in
+    pub perf_record: Option<String>,
+
+    /// Path to write a gnuplot heatmap script of the results to
+    pub gnuplot: Option<String>,
+
+    /// Draw the efficiency heatmap on stderr once the sweep is done
+    pub heatmap: bool,
+
+    /// Print the Universal Scalability Law fits of the scaling curves on
+    /// stderr once the sweep is done
+    pub usl: bool,
+
+    /// Address to serve Prometheus metrics of the sweep on
+    pub metrics: Option<String>,
+
+    /// URL of the collector to stream results to (`tcp://host:port`)
+    pub stream: Option<String>,
+
+    /// Path of the file the results of completed test points are recorded
+    /// in
+    pub checkpoint: Option<String>,
+
+    /// Skip test points already completed in the checkpoint
+    pub resume: bool,
+
+    /// Name to store the results of the sweep as a baseline under
+    pub save_baseline: Option<String>,
+
+    /// Name of the baseline the results are checked for regressions against
+    pub gate_against: Option<String>,
+
+    /// Change for the worse in percent of any metric of a test point which
+    /// counts as a regression
+    pub gate_threshold: f64,
+
+    /// Directory baselines are stored in
+    pub baseline_dir: String,
+
+    /// Refuse to run on a machine set up such that results are unreliable
+    pub strict: bool,
+
+    /// Report the progress of the sweep on stderr
+    pub progress: bool,
+
+    /// Show the live dashboard on stderr
+    pub tui: bool,
+
+    /// Print the plan of the sweep rather than running it
+    pub dry_run: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let topology   = Topology::detect();
+        let processors = topology.allowed().ordered(PinStrategy::Compact);
+
+        Config {
+            thread_samples:   32,
+            workload_samples: 100,
+            spacing:          Spacing::Log,
+            thread_counts:    None,
+            adaptive:         0,
+            search_efficiency: None,
+            workloads:        None,
+            max_threads:      processors.len(),
+            max_workload:     1000000,
+            numa_node:        None,
+            cores:            CoreSelection::Mixed,
+            pinning:          PinStrategy::Compact,
+            cpus:             None,
+            sched_policy:     SchedPolicy::Other,
+            sched_priority:   0,
+            nice:             0,
+            mbind_node:       None,
+            stats_placement:  StatsPlacement::Default,
+            shm_dir:          None,
+            cgroup:           None,
+            cgroup_depth:     1,
+            cgroup_cpus:      None,
+            cgroup_memory:    None,
+            topology,
+            processors,
+            duration:         1_000_000_000,
+            duration_secs:    None,
+            iterations:       None,
+            clock:            ClockSource::Auto,
+            warmup:           0,
+            reps:             1,
+            outliers:         None,
+            noise_threshold:  output::NOISE_THRESHOLD,
+            point:            None,
+            soak_secs:        None,
+            soak_interval:    10.,
+            replay:           None,
+            timeout_secs:     None,
+            shuffle_seed:     None,
+            bench_overhead:   false,
+            memory_samples:   0,
+            max_memory_mib:   4096,
+            vma_samples:      0,
+            max_vmas:         16384,
+            fd_samples:       0,
+            max_fds:          65536,
+            idle_thread_samples: 0,
+            max_idle_threads: 1024,
+            huge_pages:       HugePages::Default,
+            fork_advice:      ForkAdvice::None,
+            fork_advice_mib:  None,
+            dirty_memory:     None,
+            cow_pages:        0,
+            workload_kind:    WorkloadKind::Stack,
+            working_set:      1024 * 1024,
+            stride:           4096,
+            backend:          if cfg!(windows) {
+                BackendKind::CreateProcess
+            } else {
+                BackendKind::Fork
+            },
+            exec_path:        None,
+            clone_flags:      0,
+            unshare:          0,
+            pidfd_wait:       PidfdWait::Waitid,
+            criu_images:      None,
+            criu_path:        "/usr/sbin/criu".into(),
+            pool_size:        4,
+            snapshot_reset:   SnapshotReset::Copy,
+            reaper:           Reaper::Serial,
+            in_flight:        4,
+            ptrace:           Ptrace::Off,
+            ptrace_syscalls:  false,
+            pipe_handshake:   false,
+            ipc:              Ipc::Off,
+            ipc_size:         65536,
+            output_format:    OutputFormat::Text,
+            output_file:      None,
+            out_file:         None,
+            histogram:        false,
+            perf:             false,
+            cpu_frequency:    false,
+            trace_fork:       false,
+            perf_record:      None,
+            gnuplot:          None,
+            heatmap:          false,
+            usl:              false,
+            metrics:          None,
+            stream:           None,
+            checkpoint:       None,
+            resume:           false,
+            save_baseline:    None,
+            gate_against:     None,
+            gate_threshold:   5.0,
+            baseline_dir:     "forkbench-baselines".into(),
+            strict:           false,
+            progress:         true,
+            tui:              false,
+            dry_run:          false,
+        }
+    }
+}
+
+/// Parse a numeric argument, allowing `_` digit separators
+fn parse_num<T: std::str::FromStr>(flag: &str, val: Option<String>)
+        -> Result<T, String> {
+    let val = val.ok_or_else(|| format!("`{}` requires a value", flag))?;
+    val.replace('_', "").parse()
+        .map_err(|_| format!("Invalid value `{}` for `{}`", val, flag))
+}
+
+/// Parse a length of time in seconds, or with an `s`, `m` or `h` suffix
+fn parse_time(flag: &str, val: Option<String>) -> Result<f64, String> {
+    let val = val.ok_or_else(|| format!("`{}` requires a value", flag))?;
+    let (num, unit) = match val.char_indices().last() {
+        Some((pos, 's')) => (&val[..pos], 1.),
+        Some((pos, 'm')) => (&val[..pos], 60.),
+        Some((pos, 'h')) => (&val[..pos], 3600.),
+        _ => (val.as_str(), 1.),
+    };
+    num.parse::<f64>().map(|x| x * unit)
+        .map_err(|_| format!("Invalid value `{}` for `{}`", val, flag))
+}
+
+impl Config {
+    /// Parse the configuration from the arguments of the `run` command (not
+    /// including the program name and command), printing usage and exiting
+    /// on error
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Self {
+        match Self::parse(args) {
+            Ok(config) => config,
+            Err(err) => {
+                eprint!("error: {}\n\n{}", err, USAGE);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    /// Parse the configuration from a list of arguments (not including the
+    /// program name)
+    pub fn parse(args: impl IntoIterator<Item = String>)
+            -> Result<Self, String> {
+        let mut config = Config::default();
+        let mut max_threads = None;
+        let mut shuffle = false;
+        let mut replay = None;
+        let mut filter = None;
+
+        // Splice in the options of definition files where they're given
+        let mut expanded = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "--config" {
+                let path = args.next().ok_or("`--config` requires a value")?;
+                expanded.extend(crate::definition::load(&path)?);
+            } else {
+                expanded.push(arg);
+            }
+        }
+
+        let mut args = expanded.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--thread-samples" =>
+                    config.thread_samples = parse_num(&arg, args.next())?,
+                "--workload-samples" =>
+                    config.workload_samples = parse_num(&arg, args.next())?,
+                "--max-threads" =>
+                    max_threads = Some(parse_num(&arg, args.next())?),
+                "--spacing" => {
+                    config.spacing = args.next()
+                        .ok_or("`--spacing` requires a value")?.parse()?;
+                }
+                "--threads" => {
+                    config.thread_counts = Some(args.next()
+                        .ok_or("`--threads` requires a value")?.parse()?);
+                }
+                "--adaptive" =>
+                    config.adaptive = parse_num(&arg, args.next())?,
+                "--search-efficiency" => {
+                    config.search_efficiency =
+                        Some(parse_num(&arg, args.next())?);
+                }
+                "--workloads" => {
+                    let list = args.next()
+                        .ok_or("`--workloads` requires a value")?;
+                    config.workloads = Some(list.split(',')
+                        .map(|x| x.trim().parse::<u64>())
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(|_| format!("Invalid workloads `{}`",
+                                             list))?);
+                }
+                "--max-workload" =>
+                    config.max_workload = parse_num(&arg, args.next())?,
+                "--numa-node" =>
+                    config.numa_node = Some(parse_num(&arg, args.next())?),
+                "--cores" => {
+                    config.cores = args.next()
+                        .ok_or("`--cores` requires a value")?.parse()?;
+                }
+                "--pinning" => {
+                    config.pinning = args.next()
+                        .ok_or("`--pinning` requires a value")?.parse()?;
+                }
+                "--cpus" => {
+                    let list = args.next()
+                        .ok_or("`--cpus` requires a value")?;
+                    config.cpus = Some(crate::threading::parse_cpu_list(&list)
+                        .ok_or_else(|| format!("Invalid CPU list `{}`",
+                                               list))?);
+                }
+                "--sched-policy" => {
+                    config.sched_policy = args.next()
+                        .ok_or("`--sched-policy` requires a value")?
+                        .parse()?;
+                }
+                "--sched-priority" =>
+                    config.sched_priority = parse_num(&arg, args.next())?,
+                "--nice" =>
+                    config.nice = parse_num(&arg, args.next())?,
+                "--mbind-node" =>
+                    config.mbind_node = Some(parse_num(&arg, args.next())?),
+                "--stats-placement" => {
+                    config.stats_placement = args.next()
+                        .ok_or("`--stats-placement` requires a value")?
+                        .parse()?;
+                }
+                "--shm-dir" => {
+                    config.shm_dir = Some(args.next()
+                        .ok_or("`--shm-dir` requires a value")?);
+                }
+                "--cgroup" => {
+                    config.cgroup = Some(args.next()
+                        .ok_or("`--cgroup` requires a value")?);
+                }
+                "--cgroup-depth" =>
+                    config.cgroup_depth = parse_num(&arg, args.next())?,
+                "--cgroup-cpus" =>
+                    config.cgroup_cpus = Some(parse_num(&arg, args.next())?),
+                "--cgroup-memory" =>
+                    config.cgroup_memory =
+                        Some(parse_num(&arg, args.next())?),
+                "--duration" =>
+                    config.duration = parse_num(&arg, args.next())?,
+                "--duration-secs" =>
+                    config.duration_secs =
+                        Some(parse_num(&arg, args.next())?),
+                "--point" => {
+                    config.point = Some(args.next()
+                        .ok_or("`--point` requires a value")?.parse()?);
+                }
+                "--soak" =>
+                    config.soak_secs = Some(parse_time(&arg, args.next())?),
+                "--soak-interval" =>
+                    config.soak_interval = parse_num(&arg, args.next())?,
+                "--replay" => {
+                    replay = Some(args.next()
+                        .ok_or("`--replay` requires a value")?);
+                }
+                "--filter" => {
+                    filter = Some(args.next()
+                        .ok_or("`--filter` requires a value")?.parse()?);
+                }
+                "--outliers" =>
+                    config.outliers = Some(parse_num(&arg, args.next())?),
+                "--noise-threshold" =>
+                    config.noise_threshold = parse_num(&arg, args.next())?,
+                "--iterations" =>
+                    config.iterations = Some(parse_num(&arg, args.next())?),
+                "--warmup" =>
+                    config.warmup = parse_num(&arg, args.next())?,
+                "--reps" =>
+                    config.reps = parse_num(&arg, args.next())?,
+                "--timeout" =>
+                    config.timeout_secs = Some(parse_num(&arg, args.next())?),
+                "--shuffle" => shuffle = true,
+                "--shuffle-seed" =>
+                    config.shuffle_seed = Some(parse_num(&arg, args.next())?),
+                "--clock" => {
+                    config.clock = args.next()
+                        .ok_or("`--clock` requires a value")?.parse()?;
+                }
+                "--overhead" => config.bench_overhead = true,
+                "--memory-samples" =>
+                    config.memory_samples = parse_num(&arg, args.next())?,
+                "--max-memory" =>
+                    config.max_memory_mib = parse_num(&arg, args.next())?,
+                "--vma-samples" =>
+                    config.vma_samples = parse_num(&arg, args.next())?,
+                "--max-vmas" =>
+                    config.max_vmas = parse_num(&arg, args.next())?,
+                "--fd-samples" =>
+                    config.fd_samples = parse_num(&arg, args.next())?,
+                "--max-fds" =>
+                    config.max_fds = parse_num(&arg, args.next())?,
+                "--idle-thread-samples" => config.idle_thread_samples =
+                    parse_num(&arg, args.next())?,
+                "--max-idle-threads" =>
+                    config.max_idle_threads = parse_num(&arg, args.next())?,
+                "--huge-pages" => {
+                    config.huge_pages = args.next()
+                        .ok_or("`--huge-pages` requires a value")?.parse()?;
+                }
+                "--fork-advice" => {
+                    config.fork_advice = args.next()
+                        .ok_or("`--fork-advice` requires a value")?.parse()?;
+                }
+                "--fork-advice-mib" =>
+                    config.fork_advice_mib =
+                        Some(parse_num(&arg, args.next())?),
+                "--dirty-memory" =>
+                    config.dirty_memory = Some(parse_num(&arg, args.next())?),
+                "--backend" => {
+                    config.backend = args.next()
+                        .ok_or("`--backend` requires a value")?.parse()?;
+                }
+                "--exec-path" => {
+                    config.exec_path = Some(args.next()
+                        .ok_or("`--exec-path` requires a value")?);
+                }
+                "--clone-flags" => {
+                    config.clone_flags = backend::parse_clone_flags(&args
+                        .next().ok_or("`--clone-flags` requires a value")?)?;
+                }
+                "--unshare" => {
+                    config.unshare = backend::parse_namespaces(&args
+                        .next().ok_or("`--unshare` requires a value")?)?;
+                }
+                "--pidfd-wait" => {
+                    config.pidfd_wait = args.next()
+                        .ok_or("`--pidfd-wait` requires a value")?.parse()?;
+                }
+                "--criu-images" => {
+                    config.criu_images = Some(args.next()
+                        .ok_or("`--criu-images` requires a value")?);
+                }
+                "--criu-path" => {
+                    config.criu_path = args.next()
+                        .ok_or("`--criu-path` requires a value")?;
+                }
+                "--pool-size" =>
+                    config.pool_size = parse_num(&arg, args.next())?,
+                "--snapshot-reset" => {
+                    config.snapshot_reset = args.next()
+                        .ok_or("`--snapshot-reset` requires a value")?
+                        .parse()?;
+                }
+                "--reaper" => {
+                    config.reaper = args.next()
+                        .ok_or("`--reaper` requires a value")?.parse()?;
+                }
+                "--in-flight" =>
+                    config.in_flight = parse_num(&arg, args.next())?,
+                "--ptrace" => {
+                    config.ptrace = args.next()
+                        .ok_or("`--ptrace` requires a value")?.parse()?;
+                }
+                "--ptrace-syscalls" => config.ptrace_syscalls = true,
+                "--pipe-handshake" => config.pipe_handshake = true,
+                "--ipc" => {
+                    config.ipc = args.next()
+                        .ok_or("`--ipc` requires a value")?.parse()?;
+                }
+                "--ipc-size" =>
+                    config.ipc_size = parse_num(&arg, args.next())?,
+                "--cow-pages" =>
+                    config.cow_pages = parse_num(&arg, args.next())?,
+                "--workload-kind" => {
+                    config.workload_kind = args.next()
+                        .ok_or("`--workload-kind` requires a value")?
+                        .parse()?;
+                }
+                "--working-set" =>
+                    config.working_set = parse_num(&arg, args.next())?,
+                "--stride" =>
+                    config.stride = parse_num(&arg, args.next())?,
+                "--output" => {
+                    config.output_format = args.next()
+                        .ok_or("`--output` requires a value")?.parse()?;
+                }
+                "--output-file" => {
+                    config.output_file = Some(args.next()
+                        .ok_or("`--output-file` requires a value")?);
+                }
+                "--out" => {
+                    config.out_file = Some(args.next()
+                        .ok_or("`--out` requires a value")?);
+                }
+                "--histogram" => config.histogram = true,
+                "--perf" => config.perf = true,
+                "--cpu-frequency" => config.cpu_frequency = true,
+                "--trace-fork" => config.trace_fork = true,
+                "--perf-record" => {
+                    config.perf_record = Some(args.next()
+                        .ok_or("`--perf-record` requires a value")?);
+                }
+                "--gnuplot" => {
+                    config.gnuplot = Some(args.next()
+                        .ok_or("`--gnuplot` requires a value")?);
+                }
+                "--heatmap" => config.heatmap = true,
+                "--usl" => config.usl = true,
+                "--metrics" => {
+                    config.metrics = Some(args.next()
+                        .ok_or("`--metrics` requires a value")?);
+                }
+                "--stream" => {
+                    config.stream = Some(args.next()
+                        .ok_or("`--stream` requires a value")?);
+                }
+                "--checkpoint" => {
+                    config.checkpoint = Some(args.next()
+                        .ok_or("`--checkpoint` requires a value")?);
+                }
+                "--resume" => config.resume = true,
+                "--save-baseline" => {
+                    config.save_baseline = Some(args.next()
+                        .ok_or("`--save-baseline` requires a value")?);
+                }
+                "--gate-against" => {
+                    config.gate_against = Some(args.next()
+                        .ok_or("`--gate-against` requires a value")?);
+                }
+                "--gate-threshold" =>
+                    config.gate_threshold = parse_num(&arg, args.next())?,
+                "--baseline-dir" => {
+                    config.baseline_dir = args.next()
+                        .ok_or("`--baseline-dir` requires a value")?;
+                }
+                "--strict" => config.strict = true,
+                "--no-progress" => config.progress = false,
+                "--tui" => config.tui = true,
+                "--dry-run" => config.dry_run = true,
+                "--help" | "-h" => {
+                    print!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                _ => return Err(format!("Unknown argument `{}`", arg)),
+            }
+        }
+
+        // Pick a seed to shuffle with, which is recorded in the results such
+        // that the order can be reproduced. It's kept to 32 bits such that
+        // it's easily copied, and survives JSON parsers using doubles.
+        if shuffle && config.shuffle_seed.is_none() {
+            config.shuffle_seed = Some(std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|x| x.as_nanos() as u32 as u64).unwrap_or(0));
+        }
+
+        // Select the test points to replay, the filter may have come after
+        // the results file
+        match (replay, filter) {
+            (Some(path), filter) =>
+                config.replay = Some(Replay::load(&path, filter)?),
+            (None, Some(_)) =>
+                return Err("`--filter` requires `--replay`".into()),
+            (None, None) => {}
+        }
+
+        config.finish(max_threads)
+    }
+
+    /// Select the logical processors to run workers on and validate the
+    /// configuration, testing up to `max_threads` threads or as many as
+    /// there are processors
+    pub(crate) fn finish(mut self, max_threads: Option<usize>)
+            -> Result<Self, String> {
+        // Only processors in our affinity mask can be pinned to
+        let allowed = self.topology.allowed();
+
+        // An explicit list is used as is, in its order
+        if let Some(cpus) = &self.cpus {
+            if self.numa_node.is_some() || self.cores != CoreSelection::Mixed ||
+                    self.pinning != PinStrategy::Compact {
+                return Err("`--cpus` selects the logical processors and \
+                    their order itself, it can't be combined with \
+                    `--numa-node`, `--cores` or `--pinning`".into());
+            }
+            if cpus.is_empty() {
+                return Err("`--cpus` must list at least one logical \
+                    processor".into());
+            }
+
+            let mut processors: Vec<LogicalProcessor> = Vec::new();
+            for &cpu in cpus {
+                if processors.iter().any(|x| x.cpu() == cpu) {
+                    return Err(format!("`--cpus` lists logical processor \
+                        {} more than once", cpu));
+                }
+                processors.push(allowed.processors().iter()
+                    .find(|x| x.cpu() == cpu).copied()
+                    .ok_or_else(|| format!("Logical processor {} doesn't \
+                        exist or isn't in the CPU affinity mask", cpu))?);
+            }
+            self.processors = processors;
+            self.max_threads = max_threads.unwrap_or(self.processors.len());
+
+            self.validate()?;
+            return Ok(self);
+        }
+
+        let topology = match self.numa_node {
+            Some(node) => allowed.numa_node(node),
+            None       => allowed,
+        };
+        if topology.logical_processors() == 0 {
+            return Err(format!("NUMA node {} has no logical processors \
+                in the CPU affinity mask", self.numa_node.unwrap_or(0)));
+        }
+        let topology = match self.cores.core_type() {
+            Some(core_type) => topology.core_type(core_type),
+            None            => topology,
+        };
+        if topology.logical_processors() == 0 {
+            return Err(format!("`--cores {}` leaves no logical processors \
+                to run on", self.cores.name()));
+        }
+        self.processors = topology.ordered(self.pinning);
+        self.max_threads = max_threads.unwrap_or(self.processors.len());
+
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// Number of clock ticks each worker runs for per test. Must only be
+    /// used once the clock has been selected.
+    pub fn duration_ticks(&self) -> u64 {
+        match self.soak_secs.or(self.duration_secs) {
+            Some(secs) => (secs * crate::clock::calibration().frequency as f64)
+                .max(1.) as u64,
+            None => self.duration,
+        }
+    }
+
+    /// NUMA node the statistics are bound to, `None` to leave them
+    /// wherever the kernel allocates them
+    pub fn stats_node(&self) -> Option<u16> {
+        let workers = &self.processors[..self.max_threads];
+        match self.stats_placement {
+            StatsPlacement::Default => self.mbind_node,
+            StatsPlacement::Local   => workers.first().map(|x| x.numa_id()),
+            StatsPlacement::Remote  => self.topology.remote_node(workers),
+        }
+    }
+
+    /// Path of the results file of the baseline `name`
+    pub fn baseline_path(&self, name: &str) -> std::path::PathBuf {
+        std::path::Path::new(&self.baseline_dir).join(format!("{}.json", name))
+    }
+
+    /// Make sure the configuration describes a sweep we can actually run
+    fn validate(&self) -> Result<(), String> {
+        let procs = self.processors.len();
+
+        if self.max_threads == 0 {
+            return Err("`--max-threads` must be at least 1".into());
+        }
+        if self.max_threads > crate::MAX_WORKERS {
+            return Err(format!("`--max-threads` is limited to {}",
+                crate::MAX_WORKERS));
+        }
+        if self.max_threads > procs {
+            return Err(format!("`--max-threads` of {} exceeds the {} \
+                selected logical processors (restricted by the NUMA node \
+                and CPU affinity mask)", self.max_threads, procs));
+        }
+        if let Some(ThreadCounts::List(counts)) = &self.thread_counts {
+            if counts.iter().any(|&x| x == 0 || x > self.max_threads as u64) {
+                return Err(format!("`--threads` must be between 1 and the \
+                    maximum of {} threads", self.max_threads));
+            }
+        }
+        if self.adaptive > 0 &&
+                matches!(self.thread_counts, Some(ThreadCounts::All) |
+                                             Some(ThreadCounts::Physical)) {
+            return Err("`--adaptive` refines sampled thread counts, every \
+                count is tested with `--threads all` or `physical`".into());
+        }
+        if let Some(ratio) = self.search_efficiency {
+            if !(ratio > 0. && ratio <= 1.) {
+                return Err("`--search-efficiency` must be greater than 0 \
+                    and at most 1".into());
+            }
+            if self.thread_counts.is_some() || self.adaptive > 0 ||
+                    self.bench_overhead {
+                return Err("`--search-efficiency` picks the thread counts, \
+                    it can't be combined with `--threads`, `--adaptive` or \
+                    `--overhead`".into());
+            }
+            if Axis::ALL[1..].iter().any(|x| x.values(self).len() > 1) {
+                return Err("`--search-efficiency` requires a single value \
+                    of every axis but the thread count (eg. a single \
+                    `--workloads`)".into());
+            }
+        }
+        if self.mbind_node.is_some() && !cfg!(target_os = "linux") {
+            return Err("`--mbind-node` is only supported on Linux".into());
+        }
+        if self.stats_placement != StatsPlacement::Default {
+            if !cfg!(target_os = "linux") {
+                return Err("`--stats-placement` is only supported on \
+                    Linux".into());
+            }
+            if self.stats_node().is_none() {
+                return Err(format!("`--stats-placement remote` needs a \
+                    NUMA node none of the workers run on, but they use all \
+                    {} of them", self.topology.numa_nodes()));
+            }
+        }
+        if self.shm_dir.is_some() && !cfg!(unix) {
+            return Err("`--shm-dir` is not supported on this platform".into());
+        }
+        if let Some(dir) = &self.cgroup {
+            if !cfg!(target_os = "linux") {
+                return Err("`--cgroup` is only supported on Linux".into());
+            }
+            if !std::path::Path::new(dir).join("cgroup.controllers")
+                    .exists() {
+                return Err(format!("`--cgroup` directory `{}` is not a \
+                    cgroup v2 directory", dir));
+            }
+        } else if self.cgroup_depth != 1 || self.cgroup_cpus.is_some() ||
+                self.cgroup_memory.is_some() {
+            return Err("`--cgroup-depth`, `--cgroup-cpus` and \
+                `--cgroup-memory` require `--cgroup`".into());
+        }
+        if self.cgroup_depth == 0 {
+            return Err("`--cgroup-depth` must be at least 1".into());
+        }
+        if self.cgroup_cpus.is_some_and(|x| !(x > 0. && x.is_finite())) {
+            return Err("`--cgroup-cpus` must be positive".into());
+        }
+        if self.cgroup_memory == Some(0) {
+            return Err("`--cgroup-memory` must be at least 1 MiB".into());
+        }
+        if self.perf && !cfg!(target_os = "linux") {
+            return Err("`--perf` is only supported on Linux".into());
+        }
+        if self.cpu_frequency && !cfg!(target_os = "linux") {
+            return Err("`--cpu-frequency` is only supported on Linux".into());
+        }
+        if self.trace_fork && !cfg!(target_os = "linux") {
+            return Err("`--trace-fork` is only supported on Linux".into());
+        }
+        if self.perf_record.is_some() && !cfg!(target_os = "linux") {
+            return Err("`--perf-record` is only supported on Linux".into());
+        }
+        if self.out_file.is_some() && self.shuffle_seed.is_some() &&
+                self.output_format != OutputFormat::Jsonl {
+            return Err("`--shuffle` holds back results until the sweep is \
+                done, defeating `--out`, combine them with `--output jsonl` \
+                which is written as each test completes".into());
+        }
+        if self.tui && !std::io::stderr().is_terminal() {
+            return Err("`--tui` requires stderr to be a terminal".into());
+        }
+        if self.tui && self.output_file.is_none() &&
+                std::io::stdout().is_terminal() {
+            return Err("`--tui` takes over the terminal, redirect the \
+                results with `--output-file`".into());
+        }
+        if let Some(url) = &self.stream {
+            let addr = url.strip_prefix("tcp://").unwrap_or("");
+            if !addr.rsplit_once(':')
+                    .is_some_and(|(host, port)| {
+                        !host.is_empty() && port.parse::<u16>().is_ok()
+                    }) {
+                return Err(format!("`--stream` URL `{}` is not of the form \
+                    `tcp://host:port`", url));
+            }
+        }
+        if self.bench_overhead {
+            if self.thread_samples == 0 || self.workload_samples == 0 {
+                return Err("Sample counts must be at least 1".into());
+            }
+            if self.max_workload == 0 {
+                return Err("`--max-workload` must be at least 1".into());
+            }
+        }
+        if self.memory_samples > 0 && self.max_memory_mib == 0 {
+            return Err("`--max-memory` must be at least 1".into());
+        }
+        if self.vma_samples > 0 && self.max_vmas == 0 {
+            return Err("`--max-vmas` must be at least 1".into());
+        }
+        if self.vma_samples > 0 && cfg!(windows) {
+            return Err("`--vma-samples` is not supported on this platform"
+                .into());
+        }
+        if self.fd_samples > 0 && self.max_fds == 0 {
+            return Err("`--max-fds` must be at least 1".into());
+        }
+        if self.fd_samples > 0 && !cfg!(target_os = "linux") {
+            return Err("`--fd-samples` is not supported on this platform"
+                .into());
+        }
+        if self.idle_thread_samples > 0 && self.max_idle_threads == 0 {
+            return Err("`--max-idle-threads` must be at least 1".into());
+        }
+        if self.idle_thread_samples > 0 && cfg!(windows) {
+            return Err("`--idle-thread-samples` is not supported on this \
+                platform".into());
+        }
+        if self.cow_pages > 0 && self.dirty_memory.is_some() {
+            return Err("`--cow-pages` and `--dirty-memory` are mutually \
+                exclusive".into());
+        }
+        if self.cow_pages > 0 && cfg!(windows) {
+            return Err("`--cow-pages` requires fork()".into());
+        }
+        if !self.workload_kind.is_supported() {
+            return Err(format!("Workload kind `{}` is not supported on this \
+                platform", self.workload_kind.name()));
+        }
+        if self.workload_kind != WorkloadKind::Stack &&
+                !self.backend.runs_in_process() {
+            return Err(format!("Workload kind `{}` requires a backend \
+                which runs fuzz cases in a copy of the process (`fork`, \
+                `vfork`, `clone`, `clone3`, `pdfork`, `thread`, `pool` or \
+                `snapshot`)",
+                self.workload_kind.name()));
+        }
+        if self.workload_kind.uses_buffer() {
+            if self.working_set < 8 {
+                return Err("`--working-set` must be at least 8 bytes"
+                    .into());
+            }
+            if self.stride == 0 || !self.stride.is_multiple_of(8) {
+                return Err("`--stride` must be a non-zero multiple of 8 \
+                    bytes".into());
+            }
+        }
+        if !self.huge_pages.is_supported() {
+            return Err(format!("Huge page mode `{}` is not supported on \
+                this platform", self.huge_pages.name()));
+        }
+        if !self.sched_policy.is_supported() {
+            return Err(format!("Scheduling policy `{}` is not supported on \
+                this platform", self.sched_policy.name()));
+        }
+        if self.sched_policy.is_realtime() {
+            if !(1..=99).contains(&self.sched_priority) {
+                return Err(format!("Scheduling policy `{}` requires a \
+                    `--sched-priority` from 1 to 99",
+                    self.sched_policy.name()));
+            }
+            if self.nice != 0 {
+                return Err(format!("`--nice` doesn't apply to scheduling \
+                    policy `{}`", self.sched_policy.name()));
+            }
+        } else if self.sched_priority != 0 {
+            return Err(format!("`--sched-priority` doesn't apply to \
+                scheduling policy `{}`", self.sched_policy.name()));
+        }
+        if !(-20..=19).contains(&self.nice) {
+            return Err("`--nice` must be from -20 to 19".into());
+        }
+        if self.nice != 0 && !cfg!(target_os = "linux") {
+            return Err("`--nice` is not supported on this platform".into());
+        }
+        if !self.fork_advice.is_supported() {
+            return Err(format!("Fork advice `{}` is not supported on this \
+                platform", self.fork_advice.name()));
+        }
+        if !self.backend.is_supported() {
+            return Err(format!("Backend `{}` is not supported on this \
+                platform", self.backend.name()));
+        }
+        if self.backend == BackendKind::CriuRestore &&
+                self.criu_images.is_none() {
+            return Err("Backend `criu_restore` requires `--criu-images`"
+                .into());
+        }
+        if self.unshare != 0 && !matches!(self.backend,
+                BackendKind::Fork | BackendKind::Clone | BackendKind::Clone3) {
+            return Err("`--unshare` requires the `fork`, `clone` or \
+                `clone3` backend".into());
+        }
+        if !self.snapshot_reset.is_supported() {
+            return Err(format!("Snapshot reset `{}` is not supported on this \
+                platform", self.snapshot_reset.name()));
+        }
+        if self.pool_size == 0 {
+            return Err("`--pool-size` must be at least 1".into());
+        }
+        if !self.reaper.is_supported() {
+            return Err(format!("Reaper `{}` is not supported on this \
+                platform", self.reaper.name()));
+        }
+        if self.reaper != Reaper::Serial &&
+                self.backend != BackendKind::Fork {
+            return Err(format!("Reaper `{}` requires the `fork` backend",
+                self.reaper.name()));
+        }
+        if self.in_flight == 0 {
+            return Err("`--in-flight` must be at least 1".into());
+        }
+        if !self.ptrace.is_supported() {
+            return Err(format!("Ptrace mode `{}` is not supported on this \
+                platform", self.ptrace.name()));
+        }
+        if self.ptrace != Ptrace::Off &&
+                (self.backend != BackendKind::Fork ||
+                 self.reaper != Reaper::Serial) {
+            return Err("`--ptrace` requires the `fork` backend with the \
+                `serial` reaper".into());
+        }
+        if self.ptrace_syscalls && self.ptrace == Ptrace::Off {
+            return Err("`--ptrace-syscalls` requires `--ptrace`".into());
+        }
+        if self.pipe_handshake &&
+                (self.backend != BackendKind::Fork ||
+                 self.reaper != Reaper::Serial ||
+                 self.ptrace != Ptrace::Off) {
+            return Err("`--pipe-handshake` requires the `fork` backend with \
+                the `serial` reaper, without `--ptrace`".into());
+        }
+        if self.ipc != Ipc::Off &&
+                (self.backend != BackendKind::Fork ||
+                 self.reaper != Reaper::Serial ||
+                 self.ptrace != Ptrace::Off || self.pipe_handshake) {
+            return Err("`--ipc` requires the `fork` backend with the `serial` \
+                reaper, without `--ptrace` or `--pipe-handshake`".into());
+        }
+        if self.ipc_size == 0 {
+            return Err("`--ipc-size` must be at least 1".into());
+        }
+        if !self.clock.is_supported() {
+            return Err(format!("Clock `{}` is not supported on this \
+                platform", self.clock.name()));
+        }
+        if self.reps == 0 {
+            return Err("`--reps` must be at least 1".into());
+        }
+        if self.duration == 0 {
+            return Err("`--duration` must be at least 1 tick".into());
+        }
+        if let Some(secs) = self.duration_secs {
+            if !(secs > 0. && secs.is_finite()) {
+                return Err("`--duration-secs` must be positive".into());
+            }
+            if self.iterations.is_some() {
+                return Err("`--duration-secs` and `--iterations` are \
+                    mutually exclusive".into());
+            }
+        }
+        if self.iterations == Some(0) {
+            return Err("`--iterations` must be at least 1".into());
+        }
+        if let Some(point) = self.point {
+            if point.threads > self.max_threads as u64 {
+                return Err(format!("`--point` must have between 1 and the \
+                    maximum of {} threads", self.max_threads));
+            }
+            if self.adaptive > 0 || self.search_efficiency.is_some() {
+                return Err("`--point` tests a single test point, it can't \
+                    be combined with `--adaptive` or `--search-efficiency`"
+                    .into());
+            }
+        }
+        if let Some(threshold) = self.outliers {
+            if !(threshold > 0. && threshold.is_finite()) {
+                return Err("`--outliers` must be positive".into());
+            }
+            if self.reps < 3 {
+                return Err("`--outliers` needs at least 3 `--reps` to tell \
+                    outliers apart".into());
+            }
+        }
+        if !(self.noise_threshold >= 0. && self.noise_threshold.is_finite()) {
+            return Err("`--noise-threshold` must not be negative".into());
+        }
+        if let Some(replay) = &self.replay {
+            let threads = replay.points.keys().map(|x| x.threads).max();
+            if threads > Some(self.max_threads as u64) {
+                return Err(format!("`--replay` has test points of {} \
+                    threads, more than the maximum of {}",
+                    threads.unwrap_or(0), self.max_threads));
+            }
+            if self.point.is_some() || self.adaptive > 0 ||
+                    self.search_efficiency.is_some() {
+                return Err("`--replay` tests the test points of the results \
+                    file, it can't be combined with `--point`, `--adaptive` \
+                    or `--search-efficiency`".into());
+            }
+        }
+        if let Some(secs) = self.soak_secs {
+            if !(secs > 0. && secs.is_finite()) {
+                return Err("`--soak` must be positive".into());
+            }
+            if self.point.is_none() {
+                return Err("`--soak` requires `--point`".into());
+            }
+            if self.iterations.is_some() || self.tui {
+                return Err("`--soak` can't be combined with `--iterations` \
+                    or `--tui`".into());
+            }
+        }
+        if !(self.soak_interval > 0. && self.soak_interval.is_finite()) {
+            return Err("`--soak-interval` must be positive".into());
+        }
+        if let Some(secs) = self.timeout_secs {
+            if !(secs > 0. && secs.is_finite()) {
+                return Err("`--timeout` must be positive".into());
+            }
+        }
+        if self.resume && self.checkpoint.is_none() {
+            return Err("`--resume` requires `--checkpoint`".into());
+        }
+        for name in self.save_baseline.iter().chain(&self.gate_against) {
+            if name.is_empty() || name.starts_with('.') ||
+                    name.contains(['/', '\\']) {
+                return Err(format!("Invalid baseline name `{}`", name));
+            }
+        }
+        if !self.gate_threshold.is_finite() || self.gate_threshold < 0.0 {
+            return Err("`--gate-threshold` must not be negative".into());
+        }
+
+        Ok(())
+    }
+}
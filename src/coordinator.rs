@@ -0,0 +1,345 @@
+//! Sweeps of a pool of machines, coordinated by one of them
+//!
+//! `forkbench coordinator --agents <n> -- [<options>]` waits for `n` agents
+//! (see `agent`) to register, has each of them run the sweep described by
+//! the options of `run` and collects the results they stream back into a
+//! single JSON document keyed by hostname. Options like `--threads physical`
+//! adapt to each machine, such that fork scaling can be compared across a
+//! heterogeneous pool.
+
+use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Sender};
+
+use crate::compare;
+use crate::output::json_string;
+
+/// Usage string printed for `coordinator --help` and on argument errors
+pub const USAGE: &str = r#"usage: forkbench coordinator [options] -- [<options>]
+
+Waits for agents (`forkbench agent`) to join, has each of them run the sweep
+described by the options of `run` given after `--` and writes the results
+of all of them as a single JSON document, keyed by the hostname of each
+agent, once they're done.
+
+options:
+    --agents <n>                Number of agents to wait for
+    --listen <addr>             Address to accept agents on
+                                (default 0.0.0.0:5000)
+    --output-file <path>        Write the combined results to <path> rather
+                                than stdout
+    --help                      Print this message
+"#;
+
+/// Something that happened on a connection, by the ID of the connection
+enum Event {
+    /// A connection was accepted, with a handle to answer on
+    Connected(usize, TcpStream),
+
+    /// A message was received
+    Message(usize, String),
+
+    /// The connection was closed
+    Closed(usize),
+}
+
+/// An agent and what it reported so far
+#[derive(Default)]
+struct Agent {
+    /// Number of connections of the agent which are open
+    connections: usize,
+
+    /// Number of test points of its sweep, once it started
+    test_points: Option<usize>,
+
+    /// Metadata of its machine as a JSON object, once it started
+    metadata: Option<String>,
+
+    /// Results reported as JSON objects
+    results: Vec<String>,
+
+    /// Summaries reported as JSON objects
+    summaries: Vec<String>,
+
+    /// Failed test points reported as JSON objects
+    failures: Vec<String>,
+
+    /// How its sweep ended: `done`, `interrupted`, `error` (with the error)
+    /// or `lost`. `None` while it's running.
+    status: Option<(&'static str, Option<String>)>,
+}
+
+/// Accept connections on `listener`, sending everything happening on them
+/// to `events`
+fn accept(listener: TcpListener, events: Sender<Event>) {
+    for (id, stream) in listener.incoming().enumerate() {
+        let (stream, writer) = match stream.and_then(|x| {
+            let writer = x.try_clone()?;
+            Ok((x, writer))
+        }) {
+            Ok(streams) => streams,
+            Err(_)      => continue,
+        };
+        if events.send(Event::Connected(id, writer)).is_err() {
+            return;
+        }
+
+        let events = events.clone();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stream).lines() {
+                match line {
+                    Ok(line) => if events.send(Event::Message(id, line))
+                            .is_err() {
+                        return;
+                    },
+                    Err(_) => break,
+                }
+            }
+            let _ = events.send(Event::Closed(id));
+        });
+    }
+}
+
+/// Write the results of `agents` which ran the sweep of `args` to `writer`
+fn write(writer: &mut dyn Write, args: &[String],
+         agents: &BTreeMap<String, Agent>) -> io::Result<()> {
+    let list = |items: &[String]| {
+        if items.is_empty() {
+            String::from("[]")
+        } else {
+            format!("[\n        {}\n      ]", items.join(",\n        "))
+        }
+    };
+
+    write!(writer, "{{\n  \"args\": [{}],\n  \"hosts\": {{",
+           args.iter().map(|x| json_string(x)).collect::<Vec<_>>()
+               .join(", "))?;
+    for (ii, (host, agent)) in agents.iter().enumerate() {
+        let (status, error) = agent.status.clone()
+            .unwrap_or(("lost", None));
+        write!(writer, "{}\n    {}: {{\n      \"status\": {},\n      \
+            \"error\": {},\n      \"test_points\": {},\n      \
+            \"metadata\": {},\n      \"results\": {},\n      \
+            \"summaries\": {},\n      \"failures\": {}\n    }}",
+            if ii > 0 { "," } else { "" }, json_string(host),
+            json_string(status),
+            error.map(|x| json_string(&x)).unwrap_or_else(|| "null".into()),
+            agent.test_points.map(|x| x.to_string())
+                .unwrap_or_else(|| "null".into()),
+            agent.metadata.as_deref().unwrap_or("null"),
+            list(&agent.results), list(&agent.summaries),
+            list(&agent.failures))?;
+    }
+    write!(writer, "\n  }}\n}}\n")?;
+    writer.flush()
+}
+
+/// Coordinate the sweep described on the command line (not including the
+/// program name and command), writing the combined results once all agents
+/// are done
+pub fn run(args: impl IntoIterator<Item = String>) -> Result<(), String> {
+    let mut expected = None;
+    let mut listen = String::from("0.0.0.0:5000");
+    let mut output_file = None;
+    let mut sweep = Vec::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--agents" => {
+                expected = Some(args.next()
+                    .ok_or("`--agents` requires a value")?
+                    .parse::<usize>()
+                    .map_err(|_| "Invalid value for `--agents`")?);
+            }
+            "--listen" =>
+                listen = args.next().ok_or("`--listen` requires a value")?,
+            "--output-file" => {
+                output_file = Some(args.next()
+                    .ok_or("`--output-file` requires a value")?);
+            }
+            "--" => sweep.extend(args.by_ref()),
+            "--help" | "-h" => {
+                print!("{}", USAGE);
+                std::process::exit(0);
+            }
+            _ => return Err(format!("Unknown argument `{}`", arg)),
+        }
+    }
+
+    let expected = match expected {
+        Some(0) => return Err(String::from("`--agents` must be at least 1")),
+        Some(expected) => expected,
+        None => return Err(String::from("`--agents` is required")),
+    };
+    if sweep.iter().any(|x| x == "--stream") {
+        return Err(String::from("Agents stream to the coordinator, \
+            `--stream` can't be given"));
+    }
+
+    // Open the output up front, rather than finding out it fails after the
+    // whole sweep
+    let mut writer: Box<dyn Write> = match &output_file {
+        Some(path) => Box::new(BufWriter::new(File::create(path)
+            .map_err(|err| format!("Failed to create `{}`: {}", path, err))?)),
+        None => Box::new(io::stdout()),
+    };
+
+    let listener = TcpListener::bind(&listen)
+        .map_err(|err| format!("Failed to listen on `{}`: {}", listen, err))?;
+    let (sender, events) = mpsc::channel();
+    std::thread::spawn(move || accept(listener, sender));
+    eprint!("waiting for {} agents on {}\n", expected, listen);
+
+    // The run command, as each agent is told to run it
+    let reply = format!("{{\"type\": \"run\", \"args\": [{}]}}\n",
+        sweep.iter().map(|x| json_string(x)).collect::<Vec<_>>()
+            .join(", "));
+
+    let mut agents: BTreeMap<String, Agent> = BTreeMap::new();
+    let mut writers = BTreeMap::new();
+    let mut owners  = BTreeMap::new();
+    while agents.len() < expected ||
+            agents.values().any(|x| x.status.is_none()) {
+        let (id, message) = match events.recv() {
+            Ok(Event::Connected(id, writer)) => {
+                writers.insert(id, writer);
+                continue;
+            }
+            Ok(Event::Message(id, message)) => (id, message),
+            Ok(Event::Closed(id)) => {
+                // An agent is lost once all its connections are closed
+                // without it finishing, the results stream may outlive
+                // the registration
+                writers.remove(&id);
+                if let Some(host) = owners.remove(&id) {
+                    let agent: &mut Agent = agents.get_mut(&host).unwrap();
+                    agent.connections -= 1;
+                    if agent.connections == 0 && agent.status.is_none() {
+                        eprint!("error: agent `{}` exited before finishing \
+                                 its sweep\n", host);
+                        agent.status = Some(("lost", None));
+                    }
+                }
+                continue;
+            }
+            Err(_) => return Err(String::from("Stopped accepting agents")),
+        };
+
+        let members = match compare::json_members(&message) {
+            Ok(members) => members,
+            Err(err) => {
+                eprint!("warning: ignoring invalid message: {}\n", err);
+                continue;
+            }
+        };
+        let field = |name: &str| {
+            members.iter().find(|x| x.0 == name).map(|x| x.1.clone())
+        };
+        let string = |name: &str| {
+            field(name).and_then(|x| compare::json_strings(&x).ok())
+                .and_then(|x| x.into_iter().next())
+        };
+        let (kind, host) = match (string("type"), string("host")) {
+            (Some(kind), Some(host)) => (kind, host),
+            _ => {
+                eprint!("warning: ignoring message without a type and \
+                         host\n");
+                continue;
+            }
+        };
+
+        // Register new agents, each host may only join once such that
+        // results can be keyed by it
+        if kind == "agent" {
+            let reason = if agents.contains_key(&host) {
+                Some(format!("an agent on `{}` already joined", host))
+            } else if agents.len() >= expected {
+                Some(format!("all {} agents already joined", expected))
+            } else {
+                None
+            };
+            let answer = match &reason {
+                Some(reason) => format!("{{\"type\": \"reject\", \
+                    \"reason\": {}}}\n", json_string(reason)),
+                None => reply.clone(),
+            };
+            let sent = writers.get_mut(&id)
+                .map(|x: &mut TcpStream| x.write_all(answer.as_bytes()));
+            match (reason, sent) {
+                (None, Some(Ok(()))) => {
+                    agents.insert(host.clone(),
+                                  Agent { connections: 1, ..Agent::default() });
+                    owners.insert(id, host.clone());
+                    eprint!("agent `{}` joined ({}/{})\n", host, agents.len(),
+                            expected);
+                }
+                (Some(reason), _) => {
+                    eprint!("warning: rejected agent: {}\n", reason);
+                }
+                _ => {}
+            }
+            continue;
+        }
+
+        let agent = match agents.get_mut(&host) {
+            Some(agent) => agent,
+            None => {
+                eprint!("warning: ignoring message from unknown agent \
+                         `{}`\n", host);
+                continue;
+            }
+        };
+        if let Entry::Vacant(owner) = owners.entry(id) {
+            owner.insert(host.clone());
+            agent.connections += 1;
+        }
+
+        match kind.as_str() {
+            "error" => {
+                let error = string("error").unwrap_or_default();
+                eprint!("error: agent `{}` failed: {}\n", host, error);
+                agent.status = Some(("error", Some(error)));
+            }
+            "start" => {
+                agent.test_points = field("test_points")
+                    .and_then(|x| x.parse().ok());
+                agent.metadata = field("metadata");
+                eprint!("agent `{}` started a sweep of {} test points\n",
+                        host, agent.test_points.unwrap_or(0));
+            }
+            "result" => agent.results.extend(field("result")),
+            "summary" | "failure" => {
+                if kind == "summary" {
+                    agent.summaries.extend(field("summary"));
+                } else {
+                    agent.failures.extend(field("failure"));
+                }
+                eprint!("agent `{}`: {} of {} test points done\n", host,
+                        agent.summaries.len() + agent.failures.len(),
+                        agent.test_points.unwrap_or(0));
+            }
+            "done" => {
+                let status = if field("interrupted").as_deref() ==
+                        Some("true") {
+                    "interrupted"
+                } else {
+                    "done"
+                };
+                eprint!("agent `{}` is {}\n", host, status);
+                agent.status = Some((status, None));
+            }
+            "live" => {
+                // Only of interest while the sweep is running
+            }
+            _ => eprint!("warning: ignoring message of unknown type \
+                          `{}`\n", kind),
+        }
+    }
+
+    write(&mut writer, &sweep, &agents)
+        .map_err(|err| format!("Failed to write results: {}", err))
+}
@@ -0,0 +1,332 @@
+//! Benchmark definitions loaded from TOML files with `--config`
+//!
+//! Every key of the file is a command line option without the leading `--`
+//! (`_` and `-` are interchangeable), such that a definition describes
+//! exactly what its command line would:
+//!
+//! ```toml
+//! backend = "vfork"
+//! reps = 5
+//!
+//! [sweep]
+//! threads = [1, 2, 4, 8]
+//! memory_samples = 8
+//! max_memory = 1024
+//!
+//! [output]
+//! output = "json"
+//! output_file = "results.json"
+//! perf = true
+//! ```
+//!
+//! Tables only group keys, their names are ignored. Boolean `true` passes an
+//! option taking no value and `false` leaves it out, arrays are passed as
+//! comma separated lists. Only this subset of TOML is supported: bare or
+//! quoted keys, basic and literal strings, numbers, booleans and arrays of
+//! them.
+
+/// A value of a key
+enum Value {
+    /// A boolean, selecting whether an option without a value is passed
+    Bool(bool),
+
+    /// Anything else, as the value of the option
+    Arg(String),
+}
+
+/// Parser of a definition file
+struct Parser<'a> {
+    /// Contents of the file
+    bytes: &'a [u8],
+
+    /// Offset of the next byte to parse
+    pos: usize,
+
+    /// Line of the next byte to parse, starting at 1
+    line: usize,
+}
+
+impl Parser<'_> {
+    /// Create an error at the current line
+    fn error(&self, message: &str) -> String {
+        format!("line {}: {}", self.line, message)
+    }
+
+    /// Skip spaces and tabs, plus newlines and comments if `newlines` is
+    /// set, and get the next byte without consuming it
+    fn skip(&mut self, newlines: bool) -> Option<u8> {
+        while let Some(&byte) = self.bytes.get(self.pos) {
+            match byte {
+                b' ' | b'\t' | b'\r' => {}
+                b'\n' if newlines => self.line += 1,
+                b'#' if newlines => {
+                    while self.bytes.get(self.pos)
+                            .is_some_and(|&x| x != b'\n') {
+                        self.pos += 1;
+                    }
+                    continue;
+                }
+                _ => return Some(byte),
+            }
+            self.pos += 1;
+        }
+        None
+    }
+
+    /// Make sure nothing but a comment follows on the current line
+    fn end_of_line(&mut self) -> Result<(), String> {
+        match self.skip(false) {
+            None | Some(b'\n') | Some(b'#') => Ok(()),
+            Some(_) => Err(self.error("expected the end of the line")),
+        }
+    }
+
+    /// Parse a bare or quoted key
+    fn key(&mut self) -> Result<String, String> {
+        match self.skip(false) {
+            Some(b'"') | Some(b'\'') => self.string(),
+            _ => {
+                let start = self.pos;
+                while self.bytes.get(self.pos).is_some_and(|&x| {
+                    x.is_ascii_alphanumeric() || x == b'_' || x == b'-' ||
+                        x == b'.'
+                }) {
+                    self.pos += 1;
+                }
+                if start == self.pos {
+                    return Err(self.error("expected a key"));
+                }
+                Ok(String::from_utf8_lossy(&self.bytes[start..self.pos])
+                    .into_owned())
+            }
+        }
+    }
+
+    /// Parse a basic (`"`) or literal (`'`) string
+    fn string(&mut self) -> Result<String, String> {
+        let quote = self.bytes[self.pos];
+        self.pos += 1;
+
+        let mut ret = Vec::new();
+        loop {
+            let byte = match self.bytes.get(self.pos) {
+                Some(b'\n') | None =>
+                    return Err(self.error("unterminated string")),
+                Some(&byte) => byte,
+            };
+            self.pos += 1;
+
+            if byte == quote {
+                break;
+            }
+            if byte != b'\\' || quote == b'\'' {
+                ret.push(byte);
+                continue;
+            }
+
+            let escape = self.bytes.get(self.pos).copied().unwrap_or(b'\n');
+            self.pos += 1;
+            let chr = match escape {
+                b'n'  => '\n',
+                b't'  => '\t',
+                b'r'  => '\r',
+                b'"'  => '"',
+                b'\\' => '\\',
+                b'u' => {
+                    let code = self.bytes.get(self.pos..self.pos + 4)
+                        .and_then(|x| std::str::from_utf8(x).ok())
+                        .and_then(|x| u32::from_str_radix(x, 16).ok())
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| self.error("invalid unicode escape"))?;
+                    self.pos += 4;
+                    code
+                }
+                _ => return Err(self.error("invalid escape")),
+            };
+            let mut buf = [0u8; 4];
+            ret.extend_from_slice(chr.encode_utf8(&mut buf).as_bytes());
+        }
+
+        String::from_utf8(ret).map_err(|_| self.error("invalid UTF-8"))
+    }
+
+    /// Parse a value
+    fn value(&mut self) -> Result<Value, String> {
+        match self.skip(false) {
+            Some(b'"') | Some(b'\'') => Ok(Value::Arg(self.string()?)),
+            Some(b'[') => {
+                // Arrays may span lines, and are passed as a comma separated
+                // list of their elements
+                self.pos += 1;
+                let mut elements = Vec::new();
+                loop {
+                    if self.skip(true) == Some(b']') {
+                        self.pos += 1;
+                        break;
+                    }
+                    match self.value()? {
+                        Value::Arg(arg) => elements.push(arg),
+                        Value::Bool(_) =>
+                            return Err(self.error("arrays of booleans are \
+                                not supported")),
+                    }
+                    match self.skip(true) {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {}
+                        _ => return Err(self.error("expected `,` or `]`")),
+                    }
+                }
+                Ok(Value::Arg(elements.join(",")))
+            }
+            _ => {
+                // Numbers and booleans run until the next delimiter
+                let start = self.pos;
+                while self.bytes.get(self.pos).is_some_and(|x| {
+                    !b",]# \t\r\n".contains(x)
+                }) {
+                    self.pos += 1;
+                }
+                match &self.bytes[start..self.pos] {
+                    b"true"  => Ok(Value::Bool(true)),
+                    b"false" => Ok(Value::Bool(false)),
+                    b"" => Err(self.error("expected a value")),
+                    token => Ok(Value::Arg(String::from_utf8_lossy(token)
+                        .into_owned())),
+                }
+            }
+        }
+    }
+}
+
+/// Parse the `contents` of a definition file as the command line arguments
+/// they stand for
+fn parse(contents: &str) -> Result<Vec<String>, String> {
+    let mut parser = Parser { bytes: contents.as_bytes(), pos: 0, line: 1 };
+    let mut args = Vec::new();
+    while let Some(byte) = parser.skip(true) {
+        // Tables only group keys
+        if byte == b'[' {
+            parser.pos += 1;
+            parser.key()?;
+            if parser.skip(false) != Some(b']') {
+                return Err(parser.error("expected `]`"));
+            }
+            parser.pos += 1;
+            parser.end_of_line()?;
+            continue;
+        }
+
+        let key = parser.key()?.replace('_', "-");
+        if parser.skip(false) != Some(b'=') {
+            return Err(parser.error("expected `=`"));
+        }
+        parser.pos += 1;
+        let value = parser.value()?;
+        parser.end_of_line()?;
+
+        if key == "config" {
+            return Err(parser.error("definitions can't load other \
+                definitions"));
+        }
+        match value {
+            Value::Bool(true)  => args.push(format!("--{}", key)),
+            Value::Bool(false) => {}
+            Value::Arg(arg) => {
+                args.push(format!("--{}", key));
+                args.push(arg);
+            }
+        }
+    }
+
+    Ok(args)
+}
+
+/// Load the definition file `path` as the command line arguments it stands
+/// for
+pub fn load(path: &str) -> Result<Vec<String>, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|err| format!("Failed to read `{}`: {}", path, err))?;
+    parse(&contents)
+        .map_err(|err| format!("Failed to parse `{}`: {}", path, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Split `args` on spaces, for comparing to parsed arguments
+    fn args(args: &str) -> Vec<String> {
+        args.split(' ').map(String::from).collect()
+    }
+
+    #[test]
+    fn readme_example() {
+        // The definition shown in the README, loaded from a file
+        let readme = include_str!("../README.md");
+        let start = readme.find("```toml\n").unwrap() + "```toml\n".len();
+        let end = start + readme[start..].find("```").unwrap();
+        let path = std::env::temp_dir().join(format!(
+            "forkbench-definition-{}.toml", std::process::id()));
+        std::fs::write(&path, &readme[start..end]).unwrap();
+        let loaded = load(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap(), args("--backend vfork --reps 5 --pinning \
+            physical-first --threads 1,2,4,8 --memory-samples 8 \
+            --max-memory 1024 --output json --output-file results.json"));
+    }
+
+    #[test]
+    fn strings() {
+        let parsed = parse(r#"
+            basic = "a\tb \"c\" d\\e \u00e9"
+            literal = 'C:\temp\n "x"'  # no escapes
+            "quoted_key" = ''
+        "#).unwrap();
+        assert_eq!(parsed, [
+            "--basic", "a\tb \"c\" d\\e é",
+            "--literal", "C:\\temp\\n \"x\"",
+            "--quoted-key", "",
+        ]);
+    }
+
+    #[test]
+    fn arrays_tables_and_booleans() {
+        let parsed = parse("
+            # Comments and blank lines are skipped
+
+            [sweep]
+            threads = [
+                1, 2,  # comments end lines inside arrays too
+                4,
+            ]
+            [ output ]
+            perf = true
+            tui = false
+            out-file = [\"a b\", 'c']
+        ").unwrap();
+        assert_eq!(parsed, [
+            "--threads", "1,2,4", "--perf", "--out-file", "a b,c",
+        ]);
+    }
+
+    #[test]
+    fn rejects_nested_definitions() {
+        assert_eq!(parse("reps = 1\n[x]\nconfig = 'other.toml'\n"),
+                   Err("line 3: definitions can't load other definitions"
+                       .into()));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse("reps = 1\n\nbackend = \"vfork\n"),
+                   Err("line 3: unterminated string".into()));
+        assert_eq!(parse("reps 1\n"), Err("line 1: expected `=`".into()));
+        assert_eq!(parse("threads = [1,\n  true]\n"),
+                   Err("line 2: arrays of booleans are not supported"
+                       .into()));
+        assert_eq!(parse("[sweep\n"), Err("line 1: expected `]`".into()));
+        assert_eq!(parse("reps = 1 2\n"),
+                   Err("line 1: expected the end of the line".into()));
+    }
+}
@@ -0,0 +1,63 @@
+//! Open file descriptors which workers hold before they start forking
+//!
+//! Every `fork()` duplicates the descriptor table of the worker, taking a
+//! reference to each open file, so its cost grows with the number of open
+//! descriptors like it does for targets with huge descriptor tables.
+
+#[cfg(target_os = "linux")] use libc::*;
+
+#[cfg(target_os = "linux")] use crate::error::{self, Error, Syscall};
+
+/// Number of descriptors kept free on top of the held ones for everything
+/// else the worker and its fuzz cases open
+#[cfg(target_os = "linux")]
+const SPARE_DESCRIPTORS: u64 = 64;
+
+/// A set of `eventfd()`s, each a distinct open file, inherited by every
+/// fuzz case
+#[cfg(target_os = "linux")]
+pub struct Descriptors {
+    /// The held descriptors
+    fds: Vec<c_int>,
+}
+
+#[cfg(target_os = "linux")]
+impl Descriptors {
+    /// Open `count` descriptors, raising the soft descriptor limit of the
+    /// worker up to its hard limit if they wouldn't fit
+    pub fn new(count: usize) -> error::Result<Self> {
+        if count == 0 {
+            return Ok(Descriptors { fds: Vec::new() });
+        }
+
+        // Make room for the descriptors
+        let mut limit: rlimit = unsafe { core::mem::zeroed() };
+        Error::check(Syscall::Getrlimit,
+            unsafe { getrlimit(RLIMIT_NOFILE, &mut limit) })?;
+        let wanted = count as u64 + SPARE_DESCRIPTORS;
+        if limit.rlim_cur < wanted {
+            limit.rlim_cur = wanted.min(limit.rlim_max);
+            Error::check(Syscall::Setrlimit,
+                unsafe { setrlimit(RLIMIT_NOFILE, &limit) })?;
+        }
+
+        // From here on the descriptors are closed on failure, which fails
+        // with `EMFILE` if the hard limit is too low
+        let mut ret = Descriptors { fds: Vec::with_capacity(count) };
+        for _ in 0..count {
+            ret.fds.push(Error::check(Syscall::Eventfd,
+                unsafe { eventfd(0, 0) })?);
+        }
+
+        Ok(ret)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Descriptors {
+    fn drop(&mut self) {
+        for &fd in &self.fds {
+            unsafe { close(fd); }
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! Checks of the state of the machine before a sweep, catching settings
+//! which routinely make results noisy or misleading
+//!
+//! Each check which fails produces a warning, which `--strict` turns into an
+//! error. Checks whose settings can't be read (eg. no cpufreq in a VM) pass.
+
+use crate::config::Config;
+
+/// Get the problems with the machine which may invalidate the results of the
+/// sweep described by `config`
+#[cfg(target_os = "linux")]
+pub fn check(config: &Config) -> Vec<String> {
+    use crate::topology::PinStrategy;
+
+    let read = |path: &str| {
+        std::fs::read_to_string(path).ok().map(|x| x.trim().to_string())
+    };
+
+    let mut ret = Vec::new();
+
+    // Turbo makes the frequency depend on how many cores are busy, and on
+    // how hot the package got during earlier test points
+    let turbo = match read("/sys/devices/system/cpu/intel_pstate/no_turbo") {
+        Some(no_turbo) => no_turbo == "0",
+        None => read("/sys/devices/system/cpu/cpufreq/boost")
+            .is_some_and(|x| x == "1"),
+    };
+    if turbo {
+        ret.push(String::from("turbo is enabled, the frequency will vary \
+            with the number of busy cores and temperature"));
+    }
+
+    // Any governor other than `performance` ramps the frequency up and down
+    // with the load
+    let governors: Vec<String> = config.processors.iter()
+        .filter_map(|x| read(&format!(
+            "/sys/devices/system/cpu/cpu{}/cpufreq/scaling_governor",
+            x.cpu())))
+        .filter(|x| x != "performance")
+        .collect();
+    if let Some(governor) = governors.first() {
+        ret.push(format!("{} of the selected logical processors use the \
+            `{}` frequency governor rather than `performance`",
+            governors.len(), governor));
+    }
+
+    // Physical cores are only used on their own until the sweep runs out
+    // of them, after which workers share cores
+    if config.pinning == PinStrategy::PhysicalFirst &&
+            config.max_threads > config.topology.physical_cores() &&
+            read("/sys/devices/system/cpu/smt/active")
+                .is_some_and(|x| x == "1") {
+        ret.push(format!("SMT is enabled, test points of more than {} \
+            threads will run workers on sibling threads of the same core",
+            config.topology.physical_cores()));
+    }
+
+    // The fork rate of a hybrid CPU depends on which class of cores the
+    // workers happen to land on
+    let workers = &config.processors[..config.max_threads];
+    if workers.iter().any(|x| x.core_type() != workers[0].core_type()) {
+        ret.push(String::from("the selected logical processors mix \
+            performance and efficiency cores, select one class with \
+            `--cores`"));
+    }
+
+    // Waking up from deep idle states adds latency whenever a worker blocks
+    // (eg. waiting for a fuzz case to exit)
+    let deep = (0..).map_while(|state| {
+        let dir = format!("/sys/devices/system/cpu/cpu{}/cpuidle/state{}",
+                          config.processors.first().map_or(0, |x| x.cpu()),
+                          state);
+        Some((read(&format!("{}/name", dir))?,
+              read(&format!("{}/latency", dir))?,
+              read(&format!("{}/disable", dir))?))
+    }).filter(|(_, latency, disable)| {
+        disable == "0" && latency.parse::<u64>().is_ok_and(|x| x > 10)
+    }).map(|(name, _, _)| name).collect::<Vec<_>>();
+    if !deep.is_empty() {
+        ret.push(format!("deep C-states are enabled ({}), waking up from \
+            them adds latency", deep.join(", ")));
+    }
+
+    // Swapping out the memory of workers makes fork() times depend on
+    // memory pressure
+    let swaps = read("/proc/swaps").map_or(0, |x| x.lines().count());
+    if swaps > 1 {
+        ret.push(String::from("swap is active, memory of the workers may be \
+            swapped out"));
+    }
+
+    ret
+}
+
+/// Get the problems with the machine which may invalidate the results of the
+/// sweep described by `config`, none of which are checked on this platform
+#[cfg(not(target_os = "linux"))]
+pub fn check(_config: &Config) -> Vec<String> {
+    Vec::new()
+}
@@ -0,0 +1,247 @@
+//! Errors which can cause a test point (or the whole benchmark) to fail
+
+use std::fmt;
+use std::io;
+
+/// Result type used throughout the benchmark
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// System calls whose failure is reported as an error. Numbered such that
+/// workers can pass failures to the parent through shared memory.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum Syscall {
+    Fork = 1,
+    Vfork,
+    Waitpid,
+    PosixSpawn,
+    Clone,
+    Mmap,
+    Madvise,
+    Mbind,
+    Prctl,
+    Ftruncate,
+    ShmOpen,
+    SchedSetaffinity,
+    MemfdCreate,
+    CreateFileMapping,
+    OpenFileMapping,
+    MapViewOfFile,
+    ShmUnlink,
+    Fcntl,
+    Wait4,
+    Pipe,
+    Read,
+    Write,
+    Open,
+    Clone3,
+    Poll,
+    Waitid,
+    Signalfd,
+    PthreadSigmask,
+    SchedSetattr,
+    Unshare,
+    Munmap,
+    Getrlimit,
+    Setrlimit,
+    Eventfd,
+    PthreadCreate,
+    Pdfork,
+    CpusetSetaffinity,
+    SetThreadGroupAffinity,
+    Ptrace,
+    Socketpair,
+}
+
+impl Syscall {
+    /// All system calls, in numbering order
+    const ALL: &'static [Syscall] = &[
+        Syscall::Fork, Syscall::Vfork, Syscall::Waitpid, Syscall::PosixSpawn,
+        Syscall::Clone, Syscall::Mmap, Syscall::Madvise, Syscall::Mbind,
+        Syscall::Prctl, Syscall::Ftruncate, Syscall::ShmOpen,
+        Syscall::SchedSetaffinity, Syscall::MemfdCreate,
+        Syscall::CreateFileMapping, Syscall::OpenFileMapping,
+        Syscall::MapViewOfFile, Syscall::ShmUnlink, Syscall::Fcntl,
+        Syscall::Wait4, Syscall::Pipe, Syscall::Read, Syscall::Write,
+        Syscall::Open, Syscall::Clone3, Syscall::Poll, Syscall::Waitid,
+        Syscall::Signalfd, Syscall::PthreadSigmask, Syscall::SchedSetattr,
+        Syscall::Unshare, Syscall::Munmap, Syscall::Getrlimit,
+        Syscall::Setrlimit, Syscall::Eventfd, Syscall::PthreadCreate,
+        Syscall::Pdfork, Syscall::CpusetSetaffinity,
+        Syscall::SetThreadGroupAffinity, Syscall::Ptrace, Syscall::Socketpair,
+    ];
+
+    /// Name of the system call
+    pub fn name(&self) -> &'static str {
+        match self {
+            Syscall::Fork                   => "fork",
+            Syscall::Vfork                  => "vfork",
+            Syscall::Waitpid                => "waitpid",
+            Syscall::PosixSpawn             => "posix_spawn",
+            Syscall::Clone                  => "clone",
+            Syscall::Mmap                   => "mmap",
+            Syscall::Madvise                => "madvise",
+            Syscall::Mbind                  => "mbind",
+            Syscall::Prctl                  => "prctl",
+            Syscall::Ftruncate              => "ftruncate",
+            Syscall::ShmOpen                => "shm_open",
+            Syscall::SchedSetaffinity       => "sched_setaffinity",
+            Syscall::MemfdCreate            => "memfd_create",
+            Syscall::CreateFileMapping      => "CreateFileMappingW",
+            Syscall::OpenFileMapping        => "OpenFileMappingW",
+            Syscall::MapViewOfFile          => "MapViewOfFile",
+            Syscall::ShmUnlink              => "shm_unlink",
+            Syscall::Fcntl                  => "fcntl",
+            Syscall::Wait4                  => "wait4",
+            Syscall::Pipe                   => "pipe",
+            Syscall::Read                   => "read",
+            Syscall::Write                  => "write",
+            Syscall::Open                   => "open",
+            Syscall::Clone3                 => "clone3",
+            Syscall::Poll                   => "poll",
+            Syscall::Waitid                 => "waitid",
+            Syscall::Signalfd               => "signalfd",
+            Syscall::PthreadSigmask         => "pthread_sigmask",
+            Syscall::SchedSetattr           => "sched_setattr",
+            Syscall::Unshare                => "unshare",
+            Syscall::Munmap                 => "munmap",
+            Syscall::Getrlimit              => "getrlimit",
+            Syscall::Setrlimit              => "setrlimit",
+            Syscall::Eventfd                => "eventfd",
+            Syscall::PthreadCreate          => "pthread_create",
+            Syscall::Pdfork                 => "pdfork",
+            Syscall::CpusetSetaffinity      => "cpuset_setaffinity",
+            Syscall::SetThreadGroupAffinity => "SetThreadGroupAffinity",
+            Syscall::Ptrace                 => "ptrace",
+            Syscall::Socketpair             => "socketpair",
+        }
+    }
+
+    /// Get the system call numbered `number`
+    fn from_number(number: u32) -> Option<Self> {
+        Syscall::ALL.iter().copied().find(|&x| x as u32 == number)
+    }
+}
+
+/// An error which occurred while running the benchmark
+#[derive(Debug)]
+pub enum Error {
+    /// A system call failed with `errno`
+    Os { call: Syscall, errno: i32 },
+
+    /// Worker `thr_id` reported `error`
+    Worker { thr_id: usize, error: Box<Error> },
+
+    /// Worker `thr_id` exited with a non-zero exit code
+    WorkerExited { thr_id: usize, code: i32 },
+
+    /// Worker `thr_id` was killed by `signal`
+    WorkerKilled { thr_id: usize, signal: i32 },
+
+    /// The worker gave up because another worker of the test point failed
+    Aborted,
+
+    /// The workers hadn't finished after the timeout of `secs` seconds and
+    /// were killed
+    Timeout { secs: f64 },
+
+    /// `workers` workers were still counted as running after they all
+    /// exited, the worker accounting is broken
+    WorkersRunning { workers: u64 },
+
+    /// Reading or writing a file failed
+    Io(io::Error),
+
+    /// The configuration describes a sweep which can't be run
+    Config(String),
+}
+
+impl Error {
+    /// Create an error for a failure of `call`, taking the error code from
+    /// `errno`
+    pub fn last_os_error(call: Syscall) -> Self {
+        Error::Os {
+            call,
+            errno: io::Error::last_os_error().raw_os_error().unwrap_or(0),
+        }
+    }
+
+    /// Check the return value `ret` of `call`, which signals failure by
+    /// returning -1 and setting `errno`
+    pub fn check<T>(call: Syscall, ret: T) -> Result<T>
+            where T: PartialEq + From<i8> {
+        if ret == T::from(-1) {
+            Err(Error::last_os_error(call))
+        } else {
+            Ok(ret)
+        }
+    }
+
+    /// Get the error code of a transient failure to create a process or
+    /// thread (`EAGAIN`, eg. hitting `RLIMIT_NPROC` or the PID limit), which
+    /// may succeed when retried
+    #[cfg(unix)]
+    pub fn transient_errno(&self) -> Option<i32> {
+        match self {
+            Error::Os { call: Syscall::Fork | Syscall::Vfork |
+                        Syscall::Clone | Syscall::Clone3 |
+                        Syscall::PosixSpawn | Syscall::Pdfork |
+                        Syscall::PthreadCreate,
+                        errno: libc::EAGAIN } => Some(libc::EAGAIN),
+            _ => None,
+        }
+    }
+
+    /// Encode the error as a non-zero value which can be stored in shared
+    /// memory. Only system call failures can be encoded, as they are the
+    /// only errors which workers report.
+    pub fn encode(&self) -> Option<u64> {
+        match self {
+            Error::Os { call, errno } =>
+                Some((*call as u64) << 32 | *errno as u32 as u64),
+            _ => None,
+        }
+    }
+
+    /// Decode an error previously encoded with `encode()`
+    pub fn decode(value: u64) -> Option<Self> {
+        Some(Error::Os {
+            call:  Syscall::from_number((value >> 32) as u32)?,
+            errno: value as u32 as i32,
+        })
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Os { call, errno } => write!(f, "{}() failed: {}",
+                call.name(), io::Error::from_raw_os_error(*errno)),
+            Error::Worker { thr_id, error } =>
+                write!(f, "worker {}: {}", thr_id, error),
+            Error::WorkerExited { thr_id, code } =>
+                write!(f, "worker {} exited with code {}", thr_id, code),
+            Error::WorkerKilled { thr_id, signal } =>
+                write!(f, "worker {} was killed by signal {}", thr_id,
+                       signal),
+            Error::Aborted =>
+                write!(f, "aborted as another worker failed"),
+            Error::Timeout { secs } =>
+                write!(f, "timed out after {} seconds, workers were killed",
+                       secs),
+            Error::WorkersRunning { workers } =>
+                write!(f, "{} workers still counted as running after all \
+                       exited", workers),
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Config(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
@@ -0,0 +1,234 @@
+//! Effective frequency of the logical processors the workers run on, measured
+//! during each test such that turbo and throttling effects can be told apart
+//! from the scaling of process creation
+//!
+//! Where the `APERF` and `MPERF` MSRs can be read through `/dev/cpu/<n>/msr`
+//! (x86, as root with the `msr` module loaded) they give the average
+//! frequency of each processor while it wasn't idle. Otherwise the
+//! `scaling_cur_freq` of cpufreq is sampled periodically from a thread of the
+//! parent, which is only as accurate as the driver reporting it.
+
+/// Number of fields of `Frequency`
+pub const FIELDS: usize = 3;
+
+/// Names of the fields of `Frequency`, as reported in the results
+pub const NAMES: [&str; FIELDS] = [
+    "freq_min_mhz",
+    "freq_avg_mhz",
+    "freq_max_mhz",
+];
+
+/// Frequencies of the logical processors of the workers during a test
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Frequency {
+    /// Lowest frequency measured, in MHz
+    pub min_mhz: f64,
+
+    /// Average of all frequencies measured, in MHz
+    pub avg_mhz: f64,
+
+    /// Highest frequency measured, in MHz
+    pub max_mhz: f64,
+}
+
+impl Frequency {
+    /// All fields, in the order of `NAMES`
+    pub fn fields(&self) -> [f64; FIELDS] {
+        [self.min_mhz, self.avg_mhz, self.max_mhz]
+    }
+
+    /// Create from all fields, in the order of `NAMES`
+    pub fn from_fields(fields: [f64; FIELDS]) -> Self {
+        Frequency {
+            min_mhz: fields[0],
+            avg_mhz: fields[1],
+            max_mhz: fields[2],
+        }
+    }
+
+    /// Summarize frequency samples in MHz, `None` if there are none
+    fn of(samples: &[f64]) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+
+        Some(Frequency {
+            min_mhz: samples.iter().copied().fold(f64::INFINITY, f64::min),
+            avg_mhz: samples.iter().sum::<f64>() / samples.len() as f64,
+            max_mhz: samples.iter().copied()
+                .fold(f64::NEG_INFINITY, f64::max),
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::fs::File;
+    use std::os::unix::fs::FileExt;
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread::JoinHandle;
+    use std::time::{Duration, Instant};
+
+    use super::Frequency;
+
+    /// Address of the `IA32_TIME_STAMP_COUNTER` MSR
+    const MSR_TSC: u64 = 0x10;
+
+    /// Address of the `IA32_MPERF` MSR, ticking at the TSC rate while the
+    /// processor isn't idle
+    const MSR_MPERF: u64 = 0xe7;
+
+    /// Address of the `IA32_APERF` MSR, ticking at the actual frequency
+    /// while the processor isn't idle
+    const MSR_APERF: u64 = 0xe8;
+
+    /// Interval `scaling_cur_freq` is sampled at
+    const SAMPLE_INTERVAL: Duration = Duration::from_millis(10);
+
+    /// Values of the MSRs of a processor
+    #[derive(Clone, Copy)]
+    struct Msrs {
+        /// Time stamp counter
+        tsc: u64,
+
+        /// Cycles at the TSC rate while not idle
+        mperf: u64,
+
+        /// Actual cycles while not idle
+        aperf: u64,
+    }
+
+    /// Read the MSRs of a processor
+    fn read_msrs(msr: &File) -> Option<Msrs> {
+        let read = |address| {
+            let mut buf = [0u8; 8];
+            msr.read_exact_at(&mut buf, address).ok()?;
+            Some(u64::from_ne_bytes(buf))
+        };
+        Some(Msrs {
+            tsc:   read(MSR_TSC)?,
+            mperf: read(MSR_MPERF)?,
+            aperf: read(MSR_APERF)?,
+        })
+    }
+
+    /// Read the current frequency of a processor from cpufreq, in MHz
+    fn read_cur_freq(cpu: usize) -> Option<f64> {
+        let khz: f64 = std::fs::read_to_string(format!(
+            "/sys/devices/system/cpu/cpu{}/cpufreq/scaling_cur_freq", cpu))
+            .ok()?.trim().parse().ok()?;
+        Some(khz / 1000.)
+    }
+
+    /// Measurement of the frequencies of a set of logical processors over
+    /// the tests they run
+    pub struct Monitor {
+        /// Logical processors measured
+        cpus: Vec<usize>,
+
+        /// Frequencies measured during the current test, in MHz
+        samples: Arc<Mutex<Vec<f64>>>,
+    }
+
+    /// A running measurement, which ends when dropped
+    pub struct Sampling {
+        /// MSR file, wall-clock time and MSR values at the start of each
+        /// processor, if they could all be read
+        msrs: Option<Vec<(File, Instant, Msrs)>>,
+
+        /// Frequencies measured, shared with the `Monitor`
+        samples: Arc<Mutex<Vec<f64>>>,
+
+        /// Flag asking the sampling thread to stop
+        stop: Arc<AtomicBool>,
+
+        /// Thread sampling `scaling_cur_freq` without MSRs
+        thread: Option<JoinHandle<()>>,
+    }
+
+    impl Monitor {
+        /// Measure the frequencies of the logical processors `cpus`
+        pub fn new(cpus: Vec<usize>) -> Self {
+            Monitor { cpus, samples: Arc::new(Mutex::new(Vec::new())) }
+        }
+
+        /// Start measuring. Must only be called once all workers were
+        /// created, as forking while the sampling thread is running isn't
+        /// safe.
+        pub fn start(&self) -> Sampling {
+            self.samples.lock().unwrap().clear();
+            let stop = Arc::new(AtomicBool::new(false));
+
+            // Prefer the MSRs, if all of them are readable
+            let msrs = self.cpus.iter().map(|&cpu| {
+                let msr = File::open(format!("/dev/cpu/{}/msr", cpu)).ok()?;
+                let start = read_msrs(&msr)?;
+                Some((msr, Instant::now(), start))
+            }).collect::<Option<Vec<_>>>();
+            if msrs.is_some() {
+                return Sampling {
+                    msrs,
+                    samples: self.samples.clone(),
+                    stop,
+                    thread: None,
+                };
+            }
+
+            let cpus    = self.cpus.clone();
+            let samples = self.samples.clone();
+            let flag    = stop.clone();
+            let thread = std::thread::spawn(move || {
+                while !flag.load(Ordering::SeqCst) {
+                    let freqs: Vec<f64> = cpus.iter()
+                        .filter_map(|&cpu| read_cur_freq(cpu)).collect();
+                    samples.lock().unwrap().extend(freqs);
+                    std::thread::sleep(SAMPLE_INTERVAL);
+                }
+            });
+
+            Sampling {
+                msrs:    None,
+                samples: self.samples.clone(),
+                stop,
+                thread:  Some(thread),
+            }
+        }
+
+        /// Get the frequencies measured during the last test, `None` if
+        /// they couldn't be measured
+        pub fn frequency(&self) -> Option<Frequency> {
+            Frequency::of(&self.samples.lock().unwrap())
+        }
+    }
+
+    impl Drop for Sampling {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::SeqCst);
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
+
+            // The average frequency of each processor while it wasn't idle,
+            // scaled from the TSC rate measured against the wall clock
+            for (msr, start_time, start) in self.msrs.iter().flatten() {
+                let end  = read_msrs(msr);
+                let secs = start_time.elapsed().as_secs_f64();
+                if let Some(end) = end {
+                    let mperf = end.mperf.wrapping_sub(start.mperf);
+                    if mperf == 0 || secs <= 0. {
+                        continue;
+                    }
+
+                    let tsc_hz = end.tsc.wrapping_sub(start.tsc) as f64 / secs;
+                    let hz = end.aperf.wrapping_sub(start.aperf) as f64 /
+                        mperf as f64 * tsc_hz;
+                    self.samples.lock().unwrap().push(hz / 1e6);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub use linux::{Monitor, Sampling};
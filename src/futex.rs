@@ -0,0 +1,51 @@
+//! Sleeping on a word of the shared memory until another process changes
+//! it, such that waiting workers don't burn their processors (and the cache
+//! line everyone else is about to read) right before a measurement
+//!
+//! Linux uses a `futex()` and FreeBSD `_umtx_op()`, both in their variants
+//! shared across processes. Elsewhere (including Windows, whose
+//! `WaitOnAddress()` only works within a process) waiting spins.
+
+use std::sync::atomic::AtomicU32;
+
+/// Sleep as long as `word` is `expected`. May return early (eg. when
+/// interrupted by a signal), thus callers check their condition again.
+pub fn wait(word: &AtomicU32, expected: u32) {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        libc::syscall(libc::SYS_futex, word.as_ptr(), libc::FUTEX_WAIT,
+                      expected, core::ptr::null::<libc::timespec>());
+    }
+
+    #[cfg(target_os = "freebsd")]
+    unsafe {
+        libc::_umtx_op(word.as_ptr() as *mut libc::c_void,
+                       libc::UMTX_OP_WAIT_UINT, expected as libc::c_ulong,
+                       core::ptr::null_mut(), core::ptr::null_mut());
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "freebsd")))]
+    {
+        let _ = (word, expected);
+        std::hint::spin_loop();
+    }
+}
+
+/// Wake everyone sleeping on `word`, which must have been changed first
+pub fn wake_all(word: &AtomicU32) {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        libc::syscall(libc::SYS_futex, word.as_ptr(), libc::FUTEX_WAKE,
+                      libc::c_int::MAX);
+    }
+
+    #[cfg(target_os = "freebsd")]
+    unsafe {
+        libc::_umtx_op(word.as_ptr() as *mut libc::c_void,
+                       libc::UMTX_OP_WAKE, libc::c_int::MAX as libc::c_ulong,
+                       core::ptr::null_mut(), core::ptr::null_mut());
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "freebsd")))]
+    let _ = word;
+}
@@ -0,0 +1,89 @@
+//! Fixed-bucket log2 histograms of per-iteration latencies
+
+/// Number of buckets, enough for any `u64` latency
+pub const BUCKETS: usize = 64;
+
+/// Get the bucket `value` falls into. Bucket `n` holds values in
+/// `[2^n, 2^(n+1))`, with 0 also landing in bucket 0.
+pub fn bucket(value: u64) -> usize {
+    63 - value.max(1).leading_zeros() as usize
+}
+
+/// Histogram of latencies in clock ticks
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    /// Number of samples in each bucket
+    counts: [u64; BUCKETS],
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram { counts: [0; BUCKETS] }
+    }
+}
+
+impl Histogram {
+    /// Add `count` samples to bucket `bucket`
+    pub fn add(&mut self, bucket: usize, count: u64) {
+        self.counts[bucket] += count;
+    }
+
+    /// Number of samples in each bucket, up to the highest non-empty one
+    pub fn counts(&self) -> &[u64] {
+        let used = self.counts.iter().rposition(|&x| x != 0)
+            .map(|x| x + 1).unwrap_or(0);
+        &self.counts[..used]
+    }
+
+    /// Total number of samples
+    pub fn samples(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Estimate the `quantile` (0.0 to 1.0) of the samples. Samples are
+    /// assumed to be spread evenly within their bucket, so the result is only
+    /// exact up to the bucket resolution.
+    pub fn percentile(&self, quantile: f64) -> u64 {
+        let samples = self.samples();
+        if samples == 0 {
+            return 0;
+        }
+
+        // Rank of the sample we're looking for, 1-based
+        let rank = ((quantile * samples as f64).ceil() as u64)
+            .clamp(1, samples);
+
+        let mut seen = 0;
+        for (ii, &count) in self.counts.iter().enumerate() {
+            if seen + count >= rank {
+                // Interpolate within the bucket
+                let low  = if ii == 0 { 0 } else { 1u64 << ii };
+                let high = 1u128 << (ii + 1);
+                let frac = (rank - seen) as f64 / count as f64;
+                return low + ((high - low as u128) as f64 * frac) as u64;
+            }
+            seen += count;
+        }
+
+        unreachable!()
+    }
+
+    /// Render the histogram as one bar per non-empty bucket, each line
+    /// prefixed with `indent`
+    pub fn render(&self, indent: &str) -> String {
+        let mut ret = String::new();
+
+        let max = self.counts.iter().copied().max().unwrap_or(0).max(1);
+        for (ii, &count) in self.counts.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+
+            let bar = "#".repeat((count * 50).div_ceil(max) as usize);
+            ret += &format!("{}[2^{:<2}, 2^{:<2}) {:12} {}\n", indent, ii,
+                            ii + 1, count, bar);
+        }
+
+        ret
+    }
+}
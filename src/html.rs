@@ -0,0 +1,353 @@
+//! Self-contained HTML reports of sweep results
+//!
+//! `forkbench report --html <path> <results>` writes a single file with no
+//! external resources, holding the efficiency heatmap, the scaling of the
+//! fork rate per worker with the number of workers, its Universal
+//! Scalability Law fits, the latency percentiles and the metadata and
+//! configuration of the run (JSON results only), such that it can be
+//! attached to a bug report or shared as is. Charts are inline SVG.
+
+use std::collections::BTreeMap;
+use std::io;
+
+use crate::compare::{Results, Row};
+use crate::output::{PointSummary, INSTS_PER_WORKLOAD};
+use crate::sweep::TestPoint;
+
+/// Width of the charts in pixels
+const WIDTH: f64 = 900.;
+
+/// Height of the charts in pixels
+const HEIGHT: f64 = 480.;
+
+/// Space left of the plotting area of a chart for its axis labels, in
+/// pixels
+const MARGIN_LEFT: f64 = 80.;
+
+/// Space below the plotting area of a chart for its axis labels, in pixels
+const MARGIN_BOTTOM: f64 = 50.;
+
+/// Colors of the lines of the scaling chart, cycled through
+const COLORS: [&str; 8] = [
+    "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728",
+    "#9467bd", "#8c564b", "#e377c2", "#17becf",
+];
+
+/// Styling of the report
+const STYLE: &str = "body { font-family: sans-serif; margin: 2em; } \
+    table { border-collapse: collapse; font-size: 13px; } \
+    th, td { border: 1px solid #ccc; padding: 2px 8px; text-align: right; } \
+    th { background: #eee; } td.key { text-align: left; } \
+    svg text { font-size: 11px; }";
+
+/// Escape `text` for use in HTML text and attribute values
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Average `metric` over all repetitions of a test point which report it
+fn mean(rows: &[Row], metric: &str) -> Option<f64> {
+    let values: Vec<f64> = rows.iter()
+        .filter_map(|row| row.get(metric).copied()).collect();
+    if values.is_empty() {
+        return None;
+    }
+
+    Some(values.iter().sum::<f64>() / values.len() as f64)
+}
+
+/// Render a table with a header row, `rows` are already escaped cells. The
+/// first column is aligned left, as it holds names.
+fn table(header: &[&str], rows: &[Vec<String>]) -> String {
+    let mut ret = String::from("<table>\n<tr>");
+    for name in header {
+        ret += &format!("<th>{}</th>", escape(name));
+    }
+    ret += "</tr>\n";
+    for row in rows {
+        ret += "<tr>";
+        for (column, cell) in row.iter().enumerate() {
+            let class = if column == 0 { " class=\"key\"" } else { "" };
+            ret += &format!("<td{}>{}</td>", class, cell);
+        }
+        ret += "</tr>\n";
+    }
+    ret + "</table>\n"
+}
+
+/// Get `count + 1` evenly spaced ticks of an axis from 0 to `max`
+fn ticks(max: f64, count: usize) -> Vec<f64> {
+    (0..=count).map(|x| max * x as f64 / count as f64).collect()
+}
+
+/// Render the mean efficiency over the (threads, workload) grid as an SVG
+/// heatmap, on a log scale from 0.01 to 1 like the gnuplot heatmap. Test
+/// points differing in other dimensions are averaged, cells with a noisy
+/// test point are faded.
+fn heatmap(summaries: &[PointSummary]) -> String {
+    let mut grid: BTreeMap<(u64, u64), Vec<(f64, bool)>> = BTreeMap::new();
+    for summary in summaries {
+        grid.entry((summary.point.threads, summary.effective_workload()))
+            .or_default().push((summary.efficiency.mean, summary.noisy));
+    }
+    let mut threads: Vec<u64> = grid.keys().map(|x| x.0).collect();
+    let mut workloads: Vec<u64> = grid.keys().map(|x| x.1).collect();
+    threads.sort();
+    threads.dedup();
+    workloads.sort();
+    workloads.dedup();
+
+    let cell_width  = (WIDTH - MARGIN_LEFT) / workloads.len() as f64;
+    let cell_height = (HEIGHT - MARGIN_BOTTOM) / threads.len() as f64;
+    let mut ret = format!("<svg width=\"{}\" height=\"{}\" \
+        xmlns=\"http://www.w3.org/2000/svg\">\n", WIDTH, HEIGHT + 20.);
+
+    // Most threads at the top, like the gnuplot heatmap
+    for ((thread_count, workload), values) in &grid {
+        let efficiency = values.iter().map(|x| x.0).sum::<f64>() /
+            values.len() as f64;
+        let noisy = values.iter().any(|x| x.1);
+        let row    = threads.iter().position(|x| x == thread_count).unwrap();
+        let column = workloads.iter().position(|x| x == workload).unwrap();
+
+        // Red for no efficiency, green for no overhead
+        let color = if efficiency.is_finite() {
+            format!("hsl({:.0}, 70%, 45%)",
+                    crate::plot::level(efficiency) * 120.)
+        } else {
+            String::from("#ccc")
+        };
+        ret += &format!("<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" \
+            height=\"{:.1}\" fill=\"{}\" fill-opacity=\"{}\"><title>{} \
+            threads, {} instructions: efficiency {:.4}{}</title></rect>\n",
+            MARGIN_LEFT + column as f64 * cell_width,
+            (threads.len() - 1 - row) as f64 * cell_height,
+            cell_width, cell_height, color, if noisy { 0.35 } else { 1. },
+            thread_count, workload, efficiency,
+            if noisy { " (noisy)" } else { "" });
+    }
+
+    // Label at most 16 rows and columns
+    let step = |count: usize| count.div_ceil(16).max(1);
+    for (row, thread_count) in threads.iter().enumerate()
+            .step_by(step(threads.len())) {
+        ret += &format!("<text x=\"{:.1}\" y=\"{:.1}\" \
+            text-anchor=\"end\">{}</text>\n", MARGIN_LEFT - 6.,
+            (threads.len() - row) as f64 * cell_height - cell_height / 2. + 4.,
+            thread_count);
+    }
+    for (column, workload) in workloads.iter().enumerate()
+            .step_by(step(workloads.len())) {
+        ret += &format!("<text x=\"{:.1}\" y=\"{:.1}\" \
+            text-anchor=\"middle\">{}</text>\n",
+            MARGIN_LEFT + (column as f64 + 0.5) * cell_width,
+            HEIGHT - MARGIN_BOTTOM + 16., workload);
+    }
+    ret += &format!("<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">\
+        instructions per fuzz case (rows: threads, red 0.01 to green 1.0 \
+        efficiency, faded if noisy)</text>\n",
+        MARGIN_LEFT + (WIDTH - MARGIN_LEFT) / 2., HEIGHT - MARGIN_BOTTOM + 40.);
+
+    ret + "</svg>\n"
+}
+
+/// Render the mean fork rate per worker against the number of workers as
+/// an SVG line chart, with a line for each combination of the other
+/// dimensions
+fn scaling(summaries: &[PointSummary]) -> String {
+    // Lines are keyed by the test point without its thread count
+    let mut lines: BTreeMap<TestPoint, Vec<(u64, f64)>> = BTreeMap::new();
+    for summary in summaries {
+        let point = summary.point;
+        lines.entry(TestPoint { threads: 0, ..point })
+            .or_default()
+            .push((point.threads, summary.forks_per_second_per_thread.mean));
+    }
+
+    let max_threads = summaries.iter().map(|x| x.point.threads).max()
+        .unwrap_or(1).max(1) as f64;
+    let max_rate = summaries.iter()
+        .map(|x| x.forks_per_second_per_thread.mean)
+        .filter(|x| x.is_finite()).fold(1., f64::max) * 1.05;
+    let plot_width  = WIDTH - MARGIN_LEFT - 20.;
+    let plot_height = HEIGHT - MARGIN_BOTTOM - 10.;
+    let x = |threads: f64| MARGIN_LEFT + threads / max_threads * plot_width;
+    let y = |rate: f64| 10. + plot_height - rate / max_rate * plot_height;
+
+    let mut ret = format!("<svg width=\"{}\" height=\"{}\" \
+        xmlns=\"http://www.w3.org/2000/svg\">\n", WIDTH,
+        HEIGHT + 20. * lines.len() as f64);
+
+    // Axes with evenly spaced ticks
+    for rate in ticks(max_rate, 5) {
+        ret += &format!("<line x1=\"{:.1}\" x2=\"{:.1}\" y1=\"{:.1}\" \
+            y2=\"{:.1}\" stroke=\"#ddd\"/><text x=\"{:.1}\" y=\"{:.1}\" \
+            text-anchor=\"end\">{:.0}</text>\n", x(0.), x(max_threads),
+            y(rate), y(rate), MARGIN_LEFT - 6., y(rate) + 4., rate);
+    }
+    for threads in ticks(max_threads, (max_threads as usize).min(8)) {
+        ret += &format!("<text x=\"{:.1}\" y=\"{:.1}\" \
+            text-anchor=\"middle\">{:.0}</text>\n", x(threads),
+            HEIGHT - MARGIN_BOTTOM + 16., threads);
+    }
+    ret += &format!("<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">\
+        threads (y: forks per second per thread)</text>\n",
+        MARGIN_LEFT + plot_width / 2., HEIGHT - MARGIN_BOTTOM + 40.);
+
+    // A line with a marker on each test point and a legend entry for each
+    // combination
+    for (index, (key, points)) in lines.iter().enumerate() {
+        let color  = COLORS[index % COLORS.len()];
+        let points: Vec<(f64, f64)> = points.iter()
+            .filter(|x| x.1.is_finite())
+            .map(|&(threads, rate)| (x(threads as f64), y(rate)))
+            .collect();
+        ret += &format!("<polyline points=\"{}\" fill=\"none\" \
+            stroke=\"{}\" stroke-width=\"2\"/>\n", points.iter()
+                .map(|(x, y)| format!("{:.1},{:.1}", x, y))
+                .collect::<Vec<_>>().join(" "), color);
+        for (x, y) in &points {
+            ret += &format!("<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"3\" \
+                fill=\"{}\"/>\n", x, y, color);
+        }
+        ret += &format!("<text x=\"{:.1}\" y=\"{:.1}\" fill=\"{}\">workload \
+            {} memory {} MiB vmas {} fds {} idle threads {}</text>\n",
+            MARGIN_LEFT, HEIGHT + 16. + 20. * index as f64, color,
+            key.workload * INSTS_PER_WORKLOAD, key.memory_mib, key.vmas,
+            key.fds, key.idle_threads);
+    }
+
+    ret + "</svg>\n"
+}
+
+/// Render the cells of a test point as a table row
+fn point_cells(summary: &PointSummary) -> Vec<String> {
+    let point = &summary.point;
+    vec![
+        point.threads.to_string(),
+        summary.effective_workload().to_string(),
+        point.memory_mib.to_string(),
+        point.vmas.to_string(),
+        point.fds.to_string(),
+        point.idle_threads.to_string(),
+        summary.reps.to_string(),
+    ]
+}
+
+/// Columns of `point_cells()`
+const POINT_COLUMNS: [&str; 7] = [
+    "threads", "workload", "memory (MiB)", "vmas", "fds", "idle threads",
+    "reps",
+];
+
+/// Write an HTML report of `results`, read from `source`, to `path`,
+/// flagging test points whose efficiency varies by more than
+/// `noise_threshold` percent as noisy. `metadata` and `config` are the
+/// flattened sections of JSON results, empty for other formats.
+pub fn write_report(path: &str, source: &str, results: &Results,
+                    noise_threshold: f64, metadata: &[(String, String)],
+                    config: &[(String, String)]) -> io::Result<()> {
+    let summaries = crate::report::summarize(results, noise_threshold);
+
+    let mut html = format!("<!DOCTYPE html>\n<html>\n<head>\n\
+        <meta charset=\"utf-8\">\n<title>forkbench report of {}</title>\n\
+        <style>{}</style>\n</head>\n<body>\n\
+        <h1>forkbench report</h1>\n<p>Results of <code>{}</code>, {} test \
+        points.</p>\n", escape(source), STYLE, escape(source),
+        summaries.len());
+
+    html += "<h2>Efficiency</h2>\n<p>Ratio of CPU time spent inside the \
+        fuzz case (1.0 means no overhead).</p>\n";
+    html += &heatmap(&summaries);
+
+    html += "<h2>Scaling</h2>\n<p>Fuzz cases created per second by each \
+        worker, which stays flat as long as process creation scales.</p>\n";
+    html += &scaling(&summaries);
+
+    // Compact characterization of every scaling curve
+    html += "<h2>Universal Scalability Law</h2>\n<p>Fits of the fork rate \
+        to <code>X(N) = &lambda;N / (1 + &sigma;(N - 1) + &kappa;N(N - \
+        1))</code>: &sigma; is the contention (the serialized fraction) and \
+        &kappa; the coherency cost between every pair of workers. The fork \
+        rate peaks at <code>sqrt((1 - &sigma;) / &kappa;)</code> \
+        workers.</p>\n";
+    let rows: Vec<Vec<String>> = crate::usl::fit_curves(&summaries).iter()
+        .map(|(curve, fit)| {
+            let number = |value: Option<f64>, precision: usize| {
+                value.map_or_else(|| String::from("-"),
+                                  |x| format!("{:.*}", precision, x))
+            };
+            vec![
+                (curve.workload * INSTS_PER_WORKLOAD).to_string(),
+                curve.memory_mib.to_string(),
+                curve.vmas.to_string(),
+                curve.fds.to_string(),
+                curve.idle_threads.to_string(),
+                number(fit.map(|x| x.lambda), 2),
+                number(fit.map(|x| x.sigma), 6),
+                number(fit.map(|x| x.kappa), 8),
+                number(fit.map(|x| x.r2), 4),
+                number(fit.and_then(|x| x.peak()), 1),
+            ]
+        }).collect();
+    html += &table(&["workload", "memory (MiB)", "vmas", "fds",
+                     "idle threads", "λ", "σ", "κ", "R²", "peak threads"],
+                   &rows);
+
+    // Summaries of every test point
+    html += "<h2>Summaries</h2>\n";
+    let mut header = POINT_COLUMNS.to_vec();
+    header.extend(["efficiency", "+-", "cv %", "forks/s", "+-", "ns/fork",
+                   "+-", "crashes"]);
+    let rows: Vec<Vec<String>> = summaries.iter().map(|summary| {
+        let mut row = point_cells(summary);
+        row.extend([
+            format!("{:.6}", summary.efficiency.mean),
+            format!("{:.6}", summary.efficiency.ci95),
+            format!("{:.2}{}", summary.efficiency.cv() * 100.,
+                    if summary.noisy { " (noisy)" } else { "" }),
+            format!("{:.2}", summary.forks_per_second.mean),
+            format!("{:.2}", summary.forks_per_second.ci95),
+            format!("{:.1}", summary.ns_per_fork.mean),
+            format!("{:.1}", summary.ns_per_fork.ci95),
+            summary.crashes.to_string(),
+        ]);
+        row
+    }).collect();
+    html += &table(&header, &rows);
+
+    // Latency percentiles, averaged over the repetitions
+    html += "<h2>Latency percentiles</h2>\n<p>Latency of creating and \
+        running a fuzz case in nanoseconds, averaged over the \
+        repetitions.</p>\n";
+    let mut header = POINT_COLUMNS.to_vec();
+    header.extend(["p50", "p90", "p99", "p99.9"]);
+    let rows: Vec<Vec<String>> = summaries.iter().map(|summary| {
+        let mut row = point_cells(summary);
+        for metric in ["p50_ns", "p90_ns", "p99_ns", "p999_ns"] {
+            row.push(mean(&results[&summary.point], metric)
+                .map_or_else(|| String::from("-"), |x| format!("{:.1}", x)));
+        }
+        row
+    }).collect();
+    html += &table(&header, &rows);
+
+    // Only JSON results describe the run
+    html += "<h2>Run</h2>\n";
+    if metadata.is_empty() && config.is_empty() {
+        html += "<p>Only results written with <code>--output json</code> \
+            record the metadata and configuration of the run.</p>\n";
+    }
+    for (name, section) in [("Metadata", metadata), ("Configuration", config)]
+            .iter().filter(|x| !x.1.is_empty()) {
+        html += &format!("<h3>{}</h3>\n", name);
+        let rows: Vec<Vec<String>> = section.iter()
+            .map(|(key, value)| vec![escape(key), escape(value)])
+            .collect();
+        html += &table(&["name", "value"], &rows);
+    }
+
+    html += "</body>\n</html>\n";
+    std::fs::write(path, html)
+}
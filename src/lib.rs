@@ -0,0 +1,856 @@
+//! Benchmark of the scalability of `fork()` (and other ways of creating
+//! processes) based resets, as used by fuzzers
+//!
+//! Sweeps are configured with `BenchmarkConfig` and run with
+//! `Benchmark::run()`, the `forkbench` binary is a command line interface
+//! over them.
+
+// Output is written with explicit `\n` terminators throughout
+#![allow(clippy::write_with_newline, clippy::print_with_newline)]
+
+#[cfg(unix)] use libc::*;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub mod threading;
+pub mod futex;
+pub mod sync;
+pub mod config;
+pub mod definition;
+pub mod output;
+pub mod metadata;
+pub mod environment;
+pub mod plot;
+pub mod backend;
+pub mod sweep;
+pub mod memory;
+pub mod descriptors;
+pub mod topology;
+pub mod clock;
+pub mod histogram;
+pub mod stats;
+pub mod error;
+pub mod shmem;
+pub mod cgroup;
+pub mod checkpoint;
+pub mod compare;
+pub mod report;
+pub mod html;
+pub mod watch;
+pub mod progress;
+pub mod sampler;
+pub mod plan;
+pub mod tui;
+pub mod metrics;
+pub mod stream;
+pub mod soak;
+pub mod replay;
+pub mod usl;
+pub mod coordinator;
+pub mod agent;
+pub mod benchmark;
+pub mod workload;
+pub mod perf;
+pub mod frequency;
+pub mod trace;
+pub mod usage;
+
+#[cfg(unix)]
+pub mod signals;
+
+#[cfg(target_os = "linux")]
+pub mod record;
+
+#[cfg(windows)]
+pub mod windows;
+
+pub use benchmark::{Benchmark, BenchmarkConfig, Report};
+pub use config::Config;
+pub use output::{TestResult, PointSummary};
+pub use sweep::TestPoint;
+pub use error::{Error, Result};
+pub use workload::Workload;
+
+use histogram::Histogram;
+use sync::CachePadded;
+#[cfg(unix)] use error::Syscall;
+
+/// Maximum number of workers statistics can be recorded for
+const MAX_WORKERS: usize = 1024;
+
+/// Identifies the statistics of a sweep to external readers, "FORKBNCH"
+const LAYOUT_MAGIC: u64 = u64::from_le_bytes(*b"FORKBNCH");
+
+/// Version of `Layout`, bumped whenever its fields or the meaning of the
+/// fields it locates change
+const LAYOUT_VERSION: u64 = 1;
+
+/// Exit code of a worker which failed and recorded why in its statistics
+const EXIT_FAILED: i32 = 1;
+
+/// Exit code of a worker which gave up because another worker failed
+const EXIT_ABORTED: i32 = 2;
+
+/// Statistics of a single worker, padded to a cache line such that workers
+/// never contend on updating them. The fields the fuzz cases update come
+/// first, the ones the worker updates start on a cache line of their own.
+#[derive(Debug)]
+#[repr(C, align(64))]
+struct WorkerStatistics {
+    fuzz_cases: AtomicU64,
+    vm_cycles: AtomicU64,
+
+    /// Clock ticks from the worker starting to create each fuzz case until
+    /// the fuzz case was running, summed
+    creation_cycles: AtomicU64,
+
+    /// Clock reading when the worker started creating the most recent fuzz
+    /// case, for fuzz cases which can't be handed it: those launched as a
+    /// fresh executable, and those run by long-lived children (eg. of the
+    /// `pool` backend). Only one of them is in flight at a time.
+    iteration_start: CachePadded<AtomicU64>,
+
+    /// Number of fuzz cases the worker created and saw complete (eg. reaped
+    /// them), counted by the worker rather than the fuzz case itself
+    forks: AtomicU64,
+
+    /// Number of fuzz cases the worker attempted to create, including the
+    /// ones which failed to be created
+    attempts: AtomicU64,
+
+    /// Number of fuzz cases which failed to be created, with a transient
+    /// error the worker carried on after
+    fork_failures: AtomicU64,
+
+    /// Error code of the most recent fuzz case which failed to be created,
+    /// 0 if none did
+    fork_errno: AtomicU64,
+
+    /// Lowest and highest latency of an iteration in clock ticks, 0 if
+    /// there were none. Only the worker writes them, the parent reads them
+    /// together.
+    latency_range: sync::SeqLock<[u64; 2]>,
+
+    /// Clock ticks the worker spent blocked waiting for fuzz cases to exit,
+    /// summed
+    reap_cycles: AtomicU64,
+
+    /// Number of fuzz cases which were killed by a signal or exited with a
+    /// non-zero status
+    crashes: AtomicU64,
+
+    /// log2 histogram of the latency of each iteration (process creation
+    /// and fuzz case) in clock ticks
+    latency: [AtomicU64; histogram::BUCKETS],
+
+    /// Error which made the worker fail, encoded with `Error::encode()`, 0
+    /// if the worker hasn't failed
+    error: AtomicU64,
+
+    /// Event counts of the worker and its descendants, if requested
+    perf: [AtomicU64; perf::COUNTERS],
+
+    /// Resource usage of all fuzz cases reaped, fields in the order of
+    /// `usage::NAMES`
+    usage: [AtomicU64; usage::FIELDS],
+}
+
+impl WorkerStatistics {
+    /// Discard everything recorded so far
+    fn reset(&self) {
+        self.fuzz_cases.store(0, Ordering::SeqCst);
+        self.forks.store(0, Ordering::SeqCst);
+        self.attempts.store(0, Ordering::SeqCst);
+        self.fork_failures.store(0, Ordering::SeqCst);
+        self.fork_errno.store(0, Ordering::SeqCst);
+        self.latency_range.write([0, 0]);
+        self.vm_cycles.store(0, Ordering::SeqCst);
+        self.creation_cycles.store(0, Ordering::SeqCst);
+        self.reap_cycles.store(0, Ordering::SeqCst);
+        self.crashes.store(0, Ordering::SeqCst);
+        for bucket in self.latency.iter() {
+            bucket.store(0, Ordering::SeqCst);
+        }
+        for field in self.usage.iter() {
+            field.store(0, Ordering::SeqCst);
+        }
+    }
+
+    /// Record that the worker is about to create a fuzz case, returning the
+    /// clock reading recorded
+    fn start_iteration(&self) -> u64 {
+        let it = clock::now();
+        self.iteration_start.store(it, Ordering::Relaxed);
+        self.attempts.fetch_add(1, Ordering::Relaxed);
+        it
+    }
+
+    /// Record the latency of a single iteration
+    fn record_latency(&self, ticks: u64) {
+        self.latency[histogram::bucket(ticks)]
+            .fetch_add(1, Ordering::Relaxed);
+
+        // Only the worker updates these, no need for atomic updates
+        let [min, max] = self.latency_range.read();
+        if min == 0 || ticks < min || ticks > max {
+            self.latency_range.write([
+                if min == 0 { ticks } else { min.min(ticks) },
+                max.max(ticks),
+            ]);
+        }
+    }
+
+    /// Record that a fuzz case failed to be created with `errno`
+    #[cfg(unix)]
+    fn record_failed_fork(&self, errno: i32) {
+        self.fork_failures.fetch_add(1, Ordering::Relaxed);
+        self.fork_errno.store(errno as u32 as u64, Ordering::Relaxed);
+    }
+
+    /// Record what was observed when reaping a single fuzz case
+    #[cfg(unix)]
+    fn record_reaped(&self, reaped: &backend::Reaped) {
+        self.reap_cycles.fetch_add(reaped.wait_cycles, Ordering::Relaxed);
+        self.crashes.fetch_add(reaped.crashes, Ordering::Relaxed);
+        for (field, value) in self.usage.iter().zip(reaped.usage.fields()) {
+            field.fetch_add(value, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Description of the statistics at their very start, such that external
+/// readers (`watch`) find what they need by offset rather than sharing the
+/// build of the sweep. Fields are only ever appended, and everything located
+/// by them is a little-endian `u64` updated atomically. It's written once
+/// when the statistics are created and not reset between tests.
+#[derive(Debug)]
+#[repr(C)]
+struct Layout {
+    /// `LAYOUT_MAGIC`
+    magic: u64,
+
+    /// `LAYOUT_VERSION`
+    version: u64,
+
+    /// Size of the statistics in bytes
+    size: u64,
+
+    /// Offset of the number of workers fuzzing
+    workers: u64,
+
+    /// Offset of the number of workers of the running test point
+    threads: u64,
+
+    /// Offset of the slot of the first worker
+    slots: u64,
+
+    /// Distance between the slots of consecutive workers
+    slot_size: u64,
+
+    /// Number of slots
+    max_workers: u64,
+
+    /// Offset of the number of fuzz cases created within a slot
+    forks: u64,
+
+    /// Offset of the number of fuzz cases which failed to be created
+    /// within a slot
+    fork_failures: u64,
+}
+
+impl Layout {
+    /// Describe the statistics of this build
+    fn new() -> Self {
+        use core::mem::{offset_of, size_of};
+
+        Layout {
+            magic:         LAYOUT_MAGIC,
+            version:       LAYOUT_VERSION,
+            size:          size_of::<Statistics>() as u64,
+            workers:       offset_of!(Statistics, workers) as u64,
+            threads:       offset_of!(Statistics, threads) as u64,
+            slots:         offset_of!(Statistics, slots) as u64,
+            slot_size:     size_of::<WorkerStatistics>() as u64,
+            max_workers:   MAX_WORKERS as u64,
+            forks:         offset_of!(WorkerStatistics, forks) as u64,
+            fork_failures: offset_of!(WorkerStatistics, fork_failures) as u64,
+        }
+    }
+}
+
+/// Statistics for syncing between children in shared memory. All zeros is
+/// the initial state, apart from the `layout`.
+///
+/// Every field shared by all workers is on a cache line of its own, such
+/// that eg. a worker leaving doesn't invalidate the `failed` flag all other
+/// workers check after each fuzz case.
+#[derive(Debug)]
+#[repr(C)]
+struct Statistics {
+    /// Where external readers find the fields they need
+    layout: CachePadded<Layout>,
+
+    /// Barrier all workers wait on before they start fuzzing
+    start: CachePadded<sync::Barrier>,
+
+    /// Number of "workers" currently "fuzzing"
+    workers: CachePadded<AtomicU64>,
+
+    /// Number of workers of the running test point, for external readers
+    threads: CachePadded<AtomicU64>,
+
+    /// Barrier all workers wait on once they finished warming up
+    warm: CachePadded<sync::Barrier>,
+
+    /// Set once any worker of the test point failed, releasing all workers
+    /// waiting on the others
+    failed: CachePadded<AtomicU64>,
+
+    /// Clock reading at the start of the measured window, only set if there
+    /// was a warmup
+    measure_start: CachePadded<AtomicU64>,
+
+    /// Bitmask of the event counters which were unavailable to any worker
+    perf_missing: CachePadded<AtomicU64>,
+
+    /// Statistics of each worker, indexed by worker ID
+    slots: [WorkerStatistics; MAX_WORKERS],
+}
+
+impl Statistics {
+    /// Wait for all `num_threads` workers to be started, counting the
+    /// calling worker as fuzzing once they are. Fails if another worker
+    /// failed instead, as it will never arrive.
+    fn start(&self, num_threads: u64) -> Result<()> {
+        self.start.wait(num_threads)?;
+        self.workers.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Mark the test point as failed, releasing all workers waiting on the
+    /// others
+    fn abort(&self) {
+        self.failed.store(1, Ordering::SeqCst);
+        self.start.abort();
+        self.warm.abort();
+    }
+
+    /// Called by worker `thr_id` once it's done warming up. Discards the
+    /// statistics of the warmup and waits for all `num_threads` workers to
+    /// be warm such that they start the measured window together.
+    fn finish_warmup(&self, thr_id: usize, num_threads: u64) -> Result<()> {
+        self.slot(thr_id).reset();
+
+        self.warm.wait(num_threads)?;
+
+        if thr_id == 0 {
+            self.measure_start.store(clock::now(), Ordering::SeqCst);
+        }
+
+        Ok(())
+    }
+
+    /// Record that worker `thr_id` failed with `error`, releasing all
+    /// workers waiting on it
+    #[cfg(unix)]
+    fn fail(&self, thr_id: usize, error: &Error) {
+        if let Some(error) = error.encode() {
+            self.slot(thr_id).error.store(error, Ordering::SeqCst);
+        }
+        self.abort();
+    }
+
+    /// Get the error worker `thr_id` failed with, if any
+    #[cfg(unix)]
+    fn worker_error(&self, thr_id: usize) -> Option<Error> {
+        Error::decode(self.slot(thr_id).error.load(Ordering::SeqCst))
+    }
+
+    /// Get the statistics slot of worker `thr_id`
+    fn slot(&self, thr_id: usize) -> &WorkerStatistics {
+        &self.slots[thr_id]
+    }
+
+    /// Total number of fuzz cases run by all workers
+    fn fuzz_cases(&self) -> u64 {
+        self.slots.iter().map(|x| x.fuzz_cases.load(Ordering::SeqCst)).sum()
+    }
+
+    /// Total number of fuzz cases created by all workers
+    fn forks(&self) -> u64 {
+        self.slots.iter().map(|x| x.forks.load(Ordering::SeqCst)).sum()
+    }
+
+    /// Total number of cycles spent inside fuzz cases by all workers
+    fn vm_cycles(&self) -> u64 {
+        self.slots.iter().map(|x| x.vm_cycles.load(Ordering::SeqCst)).sum()
+    }
+
+    /// Total number of cycles all workers spent waiting for fuzz cases to
+    /// exit
+    fn reap_cycles(&self) -> u64 {
+        self.slots.iter().map(|x| x.reap_cycles.load(Ordering::SeqCst)).sum()
+    }
+
+    /// Total number of fuzz cases all workers attempted to create
+    fn attempts(&self) -> u64 {
+        self.slots.iter().map(|x| x.attempts.load(Ordering::SeqCst)).sum()
+    }
+
+    /// Total number of fuzz cases which failed to be created
+    fn fork_failures(&self) -> u64 {
+        self.slots.iter()
+            .map(|x| x.fork_failures.load(Ordering::SeqCst)).sum()
+    }
+
+    /// Error code of a fuzz case which failed to be created, if any
+    fn fork_errno(&self) -> Option<i32> {
+        self.slots.iter().map(|x| x.fork_errno.load(Ordering::SeqCst))
+            .find(|&x| x != 0).map(|x| x as u32 as i32)
+    }
+
+    /// Lowest and highest iteration latency of each of the first `threads`
+    /// workers, in clock ticks
+    fn worker_latency(&self, threads: usize) -> Vec<(u64, u64)> {
+        self.slots[..threads].iter().map(|x| {
+            let [min, max] = x.latency_range.read();
+            (min, max)
+        }).collect()
+    }
+
+    /// Total number of fuzz cases of all workers which didn't complete
+    fn crashes(&self) -> u64 {
+        self.slots.iter().map(|x| x.crashes.load(Ordering::SeqCst)).sum()
+    }
+
+    /// Total number of cycles all fuzz cases took to start running
+    fn creation_cycles(&self) -> u64 {
+        self.slots.iter()
+            .map(|x| x.creation_cycles.load(Ordering::SeqCst)).sum()
+    }
+
+    /// Record the event counts of worker `thr_id`
+    #[cfg(target_os = "linux")]
+    fn record_perf(&self, thr_id: usize, counts: &perf::Counts) {
+        let slot = self.slot(thr_id);
+        for (ii, count) in counts.iter().enumerate() {
+            match count {
+                Some(count) => slot.perf[ii].store(*count, Ordering::SeqCst),
+                None => {
+                    self.perf_missing.fetch_or(1 << ii, Ordering::SeqCst);
+                }
+            }
+        }
+    }
+
+    /// Resource usage of the fuzz cases of all workers combined
+    fn usage(&self) -> usage::Usage {
+        let mut fields = [0; usage::FIELDS];
+        for (ii, field) in fields.iter_mut().enumerate() {
+            *field = self.slots.iter()
+                .map(|x| x.usage[ii].load(Ordering::SeqCst)).sum();
+        }
+        usage::Usage::from_fields(fields)
+    }
+
+    /// Event counts of all workers combined
+    fn perf(&self) -> perf::Counts {
+        let missing = self.perf_missing.load(Ordering::SeqCst);
+        let mut ret = [None; perf::COUNTERS];
+        for (ii, count) in ret.iter_mut().enumerate() {
+            if missing & (1 << ii) == 0 {
+                *count = Some(self.slots.iter()
+                    .map(|x| x.perf[ii].load(Ordering::SeqCst)).sum());
+            }
+        }
+        ret
+    }
+
+    /// Iteration latency histogram of all workers combined
+    fn latency(&self) -> Histogram {
+        let mut ret = Histogram::default();
+        for slot in self.slots.iter() {
+            for (ii, count) in slot.latency.iter().enumerate() {
+                ret.add(ii, count.load(Ordering::SeqCst));
+            }
+        }
+        ret
+    }
+}
+
+/// Run a single fuzz case of `workload`, recording statistics in the shared
+/// memory slot `stats` of the worker. `start` is the clock reading when the
+/// worker started creating the fuzz case, `None` to take it from `stats`.
+fn fuzz_case(stats: &WorkerStatistics, start: Option<u64>,
+             workload: &dyn Workload, dirtyme: &mut [u8]) {
+    let it = clock::now();
+
+    // Time since the worker started creating us, the clock is synchronized
+    // across processors but don't trust it to be perfectly so. Backends
+    // with several fuzz cases in flight start the next before this one
+    // runs, so the shared start may already belong to another.
+    let start = start
+        .unwrap_or_else(|| stats.iteration_start.load(Ordering::Relaxed));
+    stats.creation_cycles.fetch_add(it.saturating_sub(start),
+                                    Ordering::Relaxed);
+
+    // Dirty memory as requested
+    for ii in (0..dirtyme.len()).step_by(4096) {
+        unsafe {
+            core::ptr::write_volatile(&mut dirtyme[ii], 5);
+        }
+    }
+
+    std::hint::black_box(workload.run());
+    let elapsed = clock::now() - it;
+
+    stats.fuzz_cases.fetch_add(1, Ordering::SeqCst);
+
+    stats.vm_cycles.fetch_add(elapsed, Ordering::Relaxed);
+}
+
+/// Body of worker `thr_id` of a test point, which benchmarks the configured
+/// backend running `workload` (or the configured kernel) until the duration
+/// or iteration count is reached
+#[cfg(unix)]
+fn run_worker(config: &Config, shmem: &Statistics, point: TestPoint,
+              workload: Option<&dyn Workload>, thr_id: usize,
+              cgroup: Option<&cgroup::Cgroup>,
+              dirtyme: &mut [u8]) -> Result<()> {
+    let num_threads = point.threads;
+
+    // Join the cgroup of the test point, taking every fuzz case with us
+    if let Some(cgroup) = cgroup {
+        cgroup.join()?;
+    }
+
+    // Pin to a specific processor
+    threading::pin_to_logical_processor(config.processors[thr_id])?;
+
+    // Run under the requested scheduling policy, inherited by every fuzz
+    // case we create
+    #[cfg(target_os = "linux")]
+    if config.sched_policy != threading::SchedPolicy::Other ||
+            config.nice != 0 {
+        threading::set_scheduling(config.sched_policy, config.sched_priority,
+                                  config.nice)?;
+    }
+
+    // Make the requested amount of memory resident before forking, making
+    // sure there's enough to satisfy the copy-on-write writes
+    let mut prefork = memory::PreforkMemory::with_size(
+        (point.memory_mib as usize * 1024 * 1024)
+            .max(config.cow_pages * memory::PAGE_SIZE),
+        config.huge_pages, config.mbind_node)?;
+
+    // Exclude memory from duplication if requested
+    prefork.advise(config.fork_advice,
+                   config.fork_advice_mib
+                       .map(|x| x * 1024 * 1024).unwrap_or(!0),
+                   config.cow_pages * memory::PAGE_SIZE)?;
+
+    // Split up the address space as requested, every mapping is duplicated
+    // by each fork
+    let _mappings = memory::Mappings::new(point.vmas as usize)?;
+
+    // Hold open descriptors as requested, every one is duplicated by each
+    // fork
+    #[cfg(target_os = "linux")]
+    let _descriptors = descriptors::Descriptors::new(point.fds as usize)?;
+
+    // Fork from a multi-threaded process if requested
+    let _idle_threads =
+        threading::IdleThreads::new(point.idle_threads as usize)?;
+
+    // The snapshot backend resets the memory a fork would have duplicated,
+    // snapshot it before any fuzz case gets to write to it
+    let snapshot = if config.backend == backend::BackendKind::Snapshot {
+        Some(unsafe {
+            backend::Snapshot::new(prefork.as_mut_slice(),
+                                   config.snapshot_reset)?
+        })
+    } else {
+        None
+    };
+
+    // If copy-on-write faults were requested, the fuzz case dirties pages
+    // inherited from us rather than fresh memory
+    let dirtyme = if config.cow_pages > 0 {
+        &mut prefork.as_mut_slice()[..config.cow_pages * memory::PAGE_SIZE]
+    } else {
+        dirtyme
+    };
+
+    // Set up the work done by each fuzz case, making any memory it accesses
+    // resident such that fuzz cases inherit it
+    let kernel;
+    let workload = match workload {
+        Some(workload) => workload,
+        None => {
+            kernel = config.workload_kind.workload(config, point.workload);
+            &*kernel
+        }
+    };
+
+    // Open the event counters before anything is created, such that every
+    // fuzz case inherits them
+    #[cfg(target_os = "linux")]
+    let counters = config.perf.then(perf::Counters::open);
+
+    // Wait for all worker threads to be started, this ensures all threads
+    // start forking rnougly at the same time (within the time that the
+    // `workers` variable gets cache-coherencied across all cores. This will
+    // make sure that any expensive jitter caused by forking in the kernel
+    // will not be part of the benchmark. This also ensures that the threads
+    // are all running at the same time rather than straddled
+    shmem.start(num_threads)?;
+
+    // Count the whole run unless the warmup is excluded below
+    #[cfg(target_os = "linux")]
+    if config.warmup == 0 {
+        counters.iter().for_each(perf::Counters::enable);
+    }
+
+    // Get the process-creation backend to benchmark
+    let backend: Box<dyn backend::Backend> = match snapshot {
+        Some(snapshot) => Box::new(snapshot),
+        None => config.backend.backend(config, thr_id, point.workload),
+    };
+    let stats = shmem.slot(thr_id);
+    let reruns = backend.reruns_fuzz_case();
+    let mut iteration = || -> Result<()> {
+        let it = stats.start_iteration();
+        let reaped = match backend.run(&mut || {
+            // Enter fresh namespaces first, such that creating them counts
+            // towards creating the fuzz case
+            #[cfg(target_os = "linux")]
+            if config.unshare != 0 {
+                if let Err(err) = Error::check(Syscall::Unshare, unsafe {
+                    unshare(config.unshare)
+                }) {
+                    shmem.fail(thr_id, &err);
+                    return;
+                }
+            }
+
+            // Our copy of the start of this iteration, forked children
+            // have their own. Children running later fuzz cases too would
+            // still have the one they were forked in.
+            fuzz_case(stats, (!reruns).then_some(it), workload, dirtyme);
+        }) {
+            Ok(reaped) => reaped,
+            Err(err) => match err.transient_errno() {
+                // Count transient failures (eg. hitting the process limit)
+                // and move on to the next fuzz case
+                Some(errno) => {
+                    stats.record_failed_fork(errno);
+                    return Ok(());
+                }
+                None => return Err(err),
+            },
+        };
+        stats.record_latency(clock::now() - it);
+        stats.record_reaped(&reaped);
+        stats.forks.fetch_add(1, Ordering::Relaxed);
+
+        // Stop if a fuzz case failed to set itself up, it recorded why
+        if shmem.failed.load(Ordering::Relaxed) != 0 {
+            return Err(Error::Aborted);
+        }
+        Ok(())
+    };
+
+    // Warm up everything involved in the iteration, without keeping any
+    // statistics
+    if config.warmup > 0 {
+        let timeout = clock::now() + config.warmup;
+        while clock::now() < timeout {
+            iteration()?;
+        }
+        shmem.finish_warmup(thr_id, num_threads)?;
+
+        // Only count the measured window
+        #[cfg(target_os = "linux")]
+        counters.iter().for_each(perf::Counters::enable);
+    }
+
+    match config.iterations {
+        Some(iterations) => {
+            for _ in 0..iterations {
+                iteration()?;
+            }
+        }
+        None => {
+            let timeout = clock::now() + config.duration_ticks();
+            while clock::now() < timeout {
+                iteration()?;
+            }
+        }
+    }
+
+    // Reap anything the backend kept around such that the statistics and
+    // counts include it, then tear it down
+    stats.record_reaped(&backend.finish()?);
+    drop(backend);
+
+    // Report the counts, all fuzz cases have been reaped so they include
+    // everything they did
+    #[cfg(target_os = "linux")]
+    if let Some(counters) = &counters {
+        shmem.record_perf(thr_id, &counters.read());
+    }
+
+    // We're done working
+    shmem.workers.fetch_sub(1, Ordering::SeqCst);
+
+    Ok(())
+}
+
+/// Fork the workers which each benchmark the configured backend for a single
+/// test point, running `workload` if given rather than the configured kernel,
+/// and wait for them all to exit while `monitor` (called with the number of
+/// workers) watches them. Fails with the first failure of any worker.
+#[cfg(unix)]
+fn run_workers<T>(config: &Config, shmem: &Statistics, point: TestPoint,
+                  workload: Option<&dyn Workload>,
+                  monitor: impl FnOnce(usize) -> T) -> Result<()> {
+    // PIDs of the children, indexed by worker ID
+    let mut children = Vec::new();
+
+    let mut dirtyme = vec![0u8; config.dirty_memory.unwrap_or(0)];
+
+    // Give the test point a fresh cgroup, removed once all workers exited
+    let cgroup = config.cgroup.as_deref()
+        .map(|dir| cgroup::Cgroup::create(dir, config)).transpose()?;
+
+    // Create children while we're not at our target number of
+    // children
+    let mut result = Ok(());
+    for thr_id in 0..point.threads as usize {
+        // Fork to make a child
+        let child = match Error::check(Syscall::Fork, unsafe { fork() }) {
+            Ok(child) => child,
+            Err(err) => {
+                // Release the children waiting for the missing workers
+                shmem.abort();
+                result = Err(err);
+                break;
+            }
+        };
+
+        // Put the worker in the process group of the workers, such that all
+        // of them can be killed if we're interrupted
+        signals::join_worker_group(if child == 0 { unsafe { getpid() } }
+                                   else { child }, thr_id == 0);
+
+        if child == 0 {
+            // We're the child, record why we failed for the parent
+            let code = match run_worker(config, shmem, point, workload,
+                                        thr_id, cgroup.as_ref(),
+                                        &mut dirtyme) {
+                Ok(())              => 0,
+                Err(Error::Aborted) => EXIT_ABORTED,
+                Err(err) => {
+                    shmem.fail(thr_id, &err);
+                    EXIT_FAILED
+                }
+            };
+
+            // Done entirely on this thread
+            unsafe { exit(code); }
+        } else {
+            // Log the PID of the child we just spawned
+            children.push(child);
+        }
+    }
+
+    // Watch the workers as they run, killing them if they don't finish in
+    // time
+    let watchdog = config.timeout_secs.map(|secs| {
+        signals::Watchdog::start(std::time::Duration::from_secs_f64(secs))
+    });
+    let watch = monitor(children.len());
+
+    // Wait for all children to exit in whatever order they do, such that a
+    // failed child releases the others right away. Only the process group
+    // of the workers is waited on, leaving other children of the process
+    // alone. As the subreaper we may also reap orphans of workers which
+    // died, those are skipped.
+    let group = children.first().copied().unwrap_or(0);
+    let mut statuses = vec![0; children.len()];
+    let mut running = children.len();
+    while running > 0 {
+        let mut status = 0;
+        let pid = Error::check(Syscall::Waitpid, unsafe {
+            waitpid(-group, &mut status, 0)
+        })?;
+
+        let thr_id = match children.iter().position(|&x| x == pid) {
+            Some(thr_id) => thr_id,
+            None         => continue,
+        };
+        statuses[thr_id] = status;
+        running -= 1;
+
+        // A child which didn't exit cleanly (eg. panicked) may not have
+        // been able to record why
+        if !WIFEXITED(status) || WEXITSTATUS(status) != 0 {
+            shmem.abort();
+        }
+    }
+    drop(watch);
+    let timed_out = watchdog.is_some_and(signals::Watchdog::stop);
+    signals::clear_worker_group();
+    result?;
+
+    // Workers killed by the watchdog fail however they were interrupted
+    if timed_out {
+        return Err(Error::Timeout {
+            secs: config.timeout_secs.unwrap_or(0.),
+        });
+    }
+
+    // Report the first failure, preferring the errors workers recorded over
+    // ones only known from their exit status
+    for thr_id in 0..children.len() {
+        if let Some(error) = shmem.worker_error(thr_id) {
+            return Err(Error::Worker { thr_id, error: Box::new(error) });
+        }
+    }
+    for (thr_id, &status) in statuses.iter().enumerate() {
+        if WIFSIGNALED(status) {
+            return Err(Error::WorkerKilled {
+                thr_id,
+                signal: WTERMSIG(status),
+            });
+        } else if WEXITSTATUS(status) != 0 &&
+                WEXITSTATUS(status) != EXIT_ABORTED {
+            return Err(Error::WorkerExited {
+                thr_id,
+                code: WEXITSTATUS(status),
+            });
+        }
+    }
+
+    // Workers only abort because another worker failed
+    if shmem.failed.load(Ordering::SeqCst) != 0 {
+        return Err(Error::Aborted);
+    }
+
+    Ok(())
+}
+
+/// If this process was launched by the benchmark as a helper (or a Windows
+/// stub), run it and exit, otherwise return. Must be called first thing in
+/// `main()` of any binary running benchmarks, as some backends (and all
+/// Windows workers) launch the running executable again.
+pub fn dispatch() {
+    // If we were spawned as a helper binary, run a single fuzz case and exit
+    #[cfg(unix)]
+    if std::env::args().nth(1).as_deref() == Some(backend::HELPER_ARG) {
+        backend::helper_main();
+    }
+
+    // On Windows workers are stub processes rather than forks of ourselves
+    #[cfg(windows)]
+    windows::dispatch_stub();
+}
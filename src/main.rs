@@ -1,271 +1,422 @@
-#![feature(llvm_asm)]
+//! Command line interface of the benchmark, runs the sweep described by the
+//! command line and reports the results, or runs one of the commands
+//! working on results of earlier (or running) sweeps
 
-use std::fs::{File, OpenOptions};
-use std::os::unix::io::IntoRawFd;
-use std::time::Instant;
-use std::sync::atomic::{AtomicU64, AtomicPtr, Ordering};
-use std::collections::{BTreeSet, HashSet};
-use libc::*;
+// Output is written with explicit `\n` terminators throughout
+#![allow(clippy::print_with_newline)]
 
-pub mod threading;
+use std::io::IsTerminal;
 
-/// Statistics for syncing between children in shared memory
-#[derive(Default, Debug)]
-struct Statistics {
-    fuzz_cases: AtomicU64,
-    vm_cycles: AtomicU64,
+use forkbench::{Benchmark, Config, PointSummary};
+use forkbench::{checkpoint, compare, environment, plot};
+use forkbench::output::{self, Output};
+use forkbench::{agent, config, coordinator, metrics, progress, report};
+use forkbench::{plan, soak, stream, sweep, tui, usl, watch};
+#[cfg(unix)] use forkbench::signals;
 
-    /// Number of "workers" currently "fuzzing"
-    workers: AtomicU64,
-}
-
-/// Location where shared memory was mapped
-static SHARED_MEMORY: AtomicPtr<Statistics> =
-    AtomicPtr::new(core::ptr::null_mut());
-
-/// Create shared memory to be used for communication of statistics between
-/// children and the parent threads
-unsafe fn create_shared_memory() {
-    // Create a new file to use for the shared memory backing
-    let fd = OpenOptions::new().create(true).read(true).write(true)
-        .truncate(true).open("shared_memory").unwrap();
-    fd.set_len(core::mem::size_of::<Statistics>() as u64).unwrap();
-
-    // Map in the shared memory
-    let ret = mmap(core::ptr::null_mut(), core::mem::size_of::<Statistics>(),
-        PROT_READ | PROT_WRITE, MAP_SHARED, File::into_raw_fd(fd), 0);
-    assert!(ret != MAP_FAILED);
-
-    // Initialize the memory to default values
-    core::ptr::write_volatile(ret as *mut Statistics, Statistics::default());
-
-    // Store the address of the shared memory allocation
-    SHARED_MEMORY.store(ret as *mut Statistics, Ordering::SeqCst);
+fn main() {
+    // Run as a helper or stub if that's what we were launched as
+    forkbench::dispatch();
+
+    // The command is the first argument, running a sweep if there is none
+    // such that plain `forkbench [options]` keeps working
+    let mut args = std::env::args().skip(1).peekable();
+    let command = match args.peek() {
+        Some(arg) if !arg.starts_with('-') => args.next().unwrap(),
+        _ => String::from("run"),
+    };
+
+    let (result, usage) = match command.as_str() {
+        "run"     => return run(Config::from_args(args)),
+        "report"  => (report::run(args),  report::USAGE),
+        "plot"    => (plot::run(args),    plot::USAGE),
+        "compare" => (compare::run(args), compare::USAGE),
+        "watch"   => (watch::run(args),   watch::USAGE),
+        "coordinator" => (coordinator::run(args), coordinator::USAGE),
+        "agent" => match agent::join(args) {
+            // Stay registered with the coordinator until the sweep is done
+            Ok(agent) => return run(agent.config.clone()),
+            Err(err)  => (Err(err), agent::USAGE),
+        },
+        "help" => {
+            print!("{}", config::USAGE);
+            return;
+        }
+        _ => (Err(format!("Unknown command `{}`", command)), config::USAGE),
+    };
+    if let Err(err) = result {
+        eprint!("error: {}\n\n{}", err, usage);
+        std::process::exit(1);
+    }
 }
 
-/// Get access to the shared memory structure
-/// Technically not safe cause it could be !Sync (eg. contains a `Cell`)
-unsafe fn shared_memory() -> &'static Statistics {
-    let sm = SHARED_MEMORY.load(Ordering::SeqCst);
-    assert!(!sm.is_null());
-    &*sm
-}
+/// Run the sweep described by `config`, reporting its results
+fn run(config: Config) {
+    // Only print what would run if asked to
+    if config.dry_run {
+        plan::write(&mut std::io::stdout(), &config)
+            .expect("Failed to write plan");
+        return;
+    }
 
-/// Reset shared memory to the default values
-unsafe fn reset_shared_memory() {
-    let sm = SHARED_MEMORY.load(Ordering::SeqCst);
-    assert!(!sm.is_null());
-    *sm = Statistics::default();
-}
+    // Warn about settings of the machine which make the results unreliable,
+    // refusing to run with them if asked to
+    let problems = environment::check(&config);
+    for problem in &problems {
+        eprint!("warning: {}\n", problem);
+    }
+    if config.strict && !problems.is_empty() {
+        eprint!("error: refusing to run with {} environment problems \
+                 (`--strict`)\n", problems.len());
+        std::process::exit(1);
+    }
 
-fn rdtsc() -> u64 {
-    unsafe { core::arch::x86_64::_rdtsc() }
-}
+    // Tear down the workers and write out what we have on Ctrl-C
+    #[cfg(unix)]
+    signals::install();
+
+    // Set up the clock and shared memory
+    let benchmark = Benchmark::new(config).unwrap_or_else(|err| {
+        eprint!("error: failed to create shared memory: {}\n", err);
+        std::process::exit(1);
+    });
+    let config = benchmark.config();
+
+    // Load the baseline to gate against up front, rather than finding out
+    // it's missing after the whole sweep
+    let gate = config.gate_against.as_ref().map(|name| {
+        let path = config.baseline_path(name);
+        compare::load(&path.to_string_lossy()).unwrap_or_else(|err| {
+            eprint!("error: failed to load baseline `{}`: {}\n", name, err);
+            std::process::exit(1);
+        })
+    });
+
+    // Open the results output
+    let mut output = Output::new(config)
+        .expect("Failed to open results output");
+
+    // Determine all the tests we should run
+    let mut tests = benchmark.run_order();
+
+    // Open the checkpoint, loading the results of an earlier run to resume
+    let mut checkpoint = config.checkpoint.as_ref().map(|path| {
+        checkpoint::Checkpoint::open(config, path, config.resume)
+            .unwrap_or_else(|err| {
+                eprint!("error: failed to open checkpoint: {}\n", err);
+                std::process::exit(1);
+            })
+    });
+
+    // Summaries of all test points of the sweep
+    let mut summaries = Vec::new();
+
+    // All results of the sweep, kept for the baseline
+    let mut all_results = Vec::new();
+
+    // Number of test points which failed
+    let mut failed = 0;
+
+    // Test points which failed with their rendered errors, merged into the
+    // results file of a replay
+    let mut failures = Vec::new();
+
+    // Report progress on stderr as we go, unless the dashboard or the
+    // samples of a soak show it
+    let mut progress = progress::Progress::new(tests.len(),
+        config.progress && !config.tui && config.soak_secs.is_none());
+
+    // Show the live dashboard if requested
+    let mut dashboard = tui::Dashboard::new(config);
+
+    // Serve metrics if requested
+    let exporter = metrics::Exporter::new(config).unwrap_or_else(|err| {
+        eprint!("error: failed to serve metrics on `{}`: {}\n",
+                config.metrics.as_deref().unwrap_or(""), err);
+        std::process::exit(1);
+    });
+    exporter.progress(0, 0, tests.len());
+
+    // Stream to the collector if requested
+    let stream = stream::Stream::new(config).unwrap_or_else(|err| {
+        eprint!("error: failed to connect to collector `{}`: {}\n",
+                config.stream.as_deref().unwrap_or(""), err);
+        std::process::exit(1);
+    });
+    stream.start(config, tests.len());
+
+    // Results are held back until all repetitions of their test point are
+    // in when flagging outliers
+    let hold = config.outliers.is_some();
+
+    // Sample the throughput while soaking if requested
+    let soak = soak::Soak::new(config);
+
+    // Run all the tests! Once they all ran, `--adaptive` adds test points
+    // around the knee of the scaling curves for each of its rounds, and
+    // `--search-efficiency` bisects the thread counts until it's found.
+    let mut rounds = 0;
+    let mut next   = 0;
+    'points: loop {
+        if next == tests.len() {
+            let refined = benchmark.next_round(rounds, &summaries, &tests);
+            if refined.is_empty() {
+                break;
+            }
+            rounds += 1;
+            progress.extend(refined.len());
+            tests.extend(refined);
+        }
+        let point = tests[next];
+        next += 1;
+        let mut results = Vec::new();
+        progress.start_point();
+
+        // Report the results of a test point completed by an earlier run
+        // rather than measuring it again
+        let resumed = checkpoint.as_ref().and_then(|x| x.completed(&point));
+        for result in resumed.iter().flatten() {
+            if !hold {
+                output.result(result).expect("Failed to write results");
+                stream.result(result, config.cpu_frequency);
+            }
+            results.push(result.clone());
+        }
 
-fn main() {
-    /// Number of samples to have over the thread range (logscale)
-    const THREAD_SAMPLES: usize = 32;
+        for rep in results.len()..config.reps {
+            progress.report(&point, rep, config.reps);
+            dashboard.status(progress.status(&point, rep, config.reps));
+            exporter.running(point, rep);
+
+            // Run all the workers for this test, showing them on the
+            // dashboard, in the metrics and to the collector, and sampling
+            // them while soaking
+            let status = benchmark.run_point_monitored(point, rep,
+                |threads| (dashboard.watch(threads), exporter.watch(threads),
+                           stream.watch(point, rep),
+                           soak.watch(threads, rep)));
+            progress.clear();
+            output.samples(&soak.take()).expect("Failed to write results");
+
+            // Stop the sweep if we were interrupted, discarding the test
+            // point which was running
+            #[cfg(unix)]
+            if signals::interrupted().is_some() {
+                break 'points;
+            }
 
-    /// Number of samples to have over the workload range (logscale)
-    const WORKLOAD_SAMPLES: usize = 100;
+            // Report a failed test point and move on to the next one
+            let result = match status {
+                Ok(result) => result,
+                Err(err) => {
+                    dashboard.log(format!("error: test point threads {} \
+                        workload {} memory {} vmas {} fds {} idle threads {} \
+                        failed: {}", point.threads, point.workload,
+                        point.memory_mib, point.vmas, point.fds,
+                        point.idle_threads, err));
+                    output.failure(&point, &err)
+                        .expect("Failed to write results");
+                    stream.failure(&point, &err);
+                    failures.push((point, err.to_string()));
+                    failed += 1;
+                    exporter.progress(summaries.len() + failed, failed,
+                                      tests.len());
+                    progress.finish_point(true);
+                    continue 'points;
+                }
+            };
+
+            // Fuzz cases which crashed are still counted, make sure they
+            // don't go unnoticed
+            if result.crashes > 0 {
+                dashboard.log(format!("warning: {} fuzz cases of test point \
+                    threads {} workload {} memory {} vmas {} fds {} idle \
+                    threads {} were killed or exited with a non-zero \
+                    status", result.crashes, point.threads, point.workload,
+                    point.memory_mib, point.vmas, point.fds,
+                    point.idle_threads));
+            }
 
-    /// Maximum number of threads to test
-    const MAX_THREADS: usize = 192;
+            // Likewise fuzz cases which failed to be created are skipped
+            if result.fork_failures > 0 {
+                dashboard.log(format!("warning: {} of {} fuzz cases of test \
+                    point threads {} workload {} memory {} vmas {} fds {} \
+                    idle threads {} failed to be created ({})",
+                    result.fork_failures, result.attempts, point.threads,
+                    point.workload, point.memory_mib, point.vmas, point.fds,
+                    point.idle_threads, std::io::Error::from_raw_os_error(
+                        result.fork_errno.unwrap_or(0))));
+            }
 
-    /// Maximum workload to sample to
-    const MAX_WORKLOAD: usize = 1000000;
+            if !hold {
+                output.result(&result).expect("Failed to write results");
+                stream.result(&result, config.cpu_frequency);
+            }
+            exporter.result(&result);
+            results.push(result);
+        }
 
-    /// Benchmark the overhead of fork()
-    /// If `false`, this tool will instead benchmark the scaling of fork() with
-    /// cores
-    const BENCH_OVERHEAD: bool = false;
+        // Flag outliers once all repetitions are in, reporting the results
+        // held back for it
+        if let Some(threshold) = config.outliers {
+            output::flag_outliers(&mut results, threshold);
+            for result in &results {
+                output.result(result).expect("Failed to write results");
+                stream.result(result, config.cpu_frequency);
+            }
+        }
 
-    /// If set, the `Some(bytes)` will tell how many bytes of memory should
-    /// be dirtied (written to) per fuzz case. Only one write will occur per
-    /// page, thus it will be minimal CPU traffic, it's just stressing OS
-    /// paging.
-    const DIRTY_MEMORY: Option<usize> = None; //Some(128 * 1024);
+        // Persist the newly completed test point
+        if let (Some(checkpoint), None) = (&mut checkpoint, &resumed) {
+            checkpoint.record(&results)
+                .expect("Failed to write checkpoint");
+        }
 
-    // Create shared memory
-    unsafe { create_shared_memory(); }
+        // Summarize all repetitions of the test point
+        let summary = PointSummary::new(&results, config.noise_threshold);
+        output.summary(&summary).expect("Failed to write results");
+        stream.summary(&summary);
+        dashboard.finish_point(summary.efficiency.mean);
+        summaries.push(summary);
+        exporter.progress(summaries.len() + failed, failed, tests.len());
+        all_results.extend(results);
+        progress.finish_point(resumed.is_none());
+    }
+    dashboard.finish();
+    progress.finish();
+
+    // Answer the search on stderr, as the results may be going to stdout
+    if let Some(ratio) = config.search_efficiency {
+        match sweep::search_bounds(ratio, &summaries, &tests) {
+            (0, _) => eprint!("no thread count keeps an efficiency of at \
+                least {}\n", ratio),
+            (below, Some(above)) if above - below > 1 => eprint!("the most \
+                threads keeping an efficiency of at least {} are between {} \
+                and {} (search incomplete)\n", ratio, below, above - 1),
+            (below, _) => eprint!("{} threads are the most which keep an \
+                efficiency of at least {}\n", below, ratio),
+        }
+    }
 
-    // Get access to shared memory
-    let shmem = unsafe { shared_memory() };
+    // Point out the test points whose means can't be trusted
+    let noisy: Vec<&PointSummary> = summaries.iter().filter(|x| x.noisy)
+        .collect();
+    if !noisy.is_empty() {
+        eprint!("warning: {} of {} test points are noisy, their efficiency \
+            varies by more than {}% across repetitions:\n", noisy.len(),
+            summaries.len(), config.noise_threshold);
+        for summary in noisy {
+            eprint!("    threads {} workload {} memory {} vmas {} fds {} \
+                idle threads {}: cv {:.2}%\n", summary.point.threads,
+                summary.effective_workload(), summary.point.memory_mib,
+                summary.point.vmas, summary.point.fds,
+                summary.point.idle_threads,
+                summary.efficiency.cv() * 100.);
+        }
+    }
 
-    // Map for children
-    let mut children = HashSet::new();
+    // Make sure nothing outlives us if we were interrupted
+    #[cfg(unix)]
+    let interrupted = signals::interrupted();
+    #[cfg(unix)]
+    if let Some(signal) = interrupted {
+        signals::reap_all();
+        eprint!("error: interrupted by signal {}, results are partial\n",
+                signal);
+    }
 
-    // Determine the scaling multipliers to hit the max values using the
-    // number of samples requested
-    let thrscale = (MAX_THREADS as f64 ).powf(1. / THREAD_SAMPLES as f64);
-    let wlscale  = (MAX_WORKLOAD as f64).powf(1. / WORKLOAD_SAMPLES as f64);
+    output.finish().expect("Failed to write results");
 
-    let mut tests = BTreeSet::new();
+    // Merge the replayed test points into the results file they came from
+    if let Some(replay) = &config.replay {
+        replay.merge(&all_results, &summaries, &failures,
+                     config.cpu_frequency).unwrap_or_else(|err| {
+            eprint!("error: {}\n", err);
+            std::process::exit(1);
+        });
+    }
 
-    if BENCH_OVERHEAD {
-        // Determine all the tests we should run. This will dedup any duplicate
-        // tests
-        let mut threads = 1.0;
+    // Tell the collector nothing follows
+    #[cfg(unix)]
+    stream.done(summaries.len() + failed, failed, interrupted.is_some());
+    #[cfg(not(unix))]
+    stream.done(summaries.len() + failed, failed, false);
 
-        while (threads as usize) < MAX_THREADS {
-            // Capture the number of threads to use this test
-            let num_threads = threads as u64;
+    // Generate a heatmap if requested
+    if let Some(path) = &config.gnuplot {
+        plot::write_gnuplot(path, &summaries)
+            .expect("Failed to write gnuplot script");
+    }
 
-            // Update the threads by the multiplier
-            threads *= thrscale;
-        
-            let mut target_workload = 1.0;
-            while (target_workload as usize) < MAX_WORKLOAD {
-                // Capture the workload
-                let workload = target_workload as u64;
+    // Draw the heatmap on stderr, as the results may be going to stdout
+    if config.heatmap {
+        let mut stderr = std::io::stderr();
+        let color = stderr.is_terminal();
+        plot::write_terminal(&mut stderr, &summaries, color)
+            .expect("Failed to draw heatmap");
+    }
 
-                // Update the workload by the multiplier
-                target_workload *= wlscale;
+    // Characterize the scaling curves on stderr, as the results may be
+    // going to stdout
+    if config.usl {
+        usl::write(&mut std::io::stderr(), &summaries, false)
+            .expect("Failed to write the scalability fits");
+    }
 
-                // Log that we want to run a test with this number of threads
-                // and the supplied workload
-                tests.insert((num_threads, workload));
-            }
-        }
-    } else {
-        // Just benchmark the scaling of fork WRT cores and fuzz cases
-        for thrs in 1..=MAX_THREADS as u64 {
-            tests.insert((thrs, 0));
-        }
+    // Exit like the signal would have, for the sake of the shell
+    #[cfg(unix)]
+    if let Some(signal) = interrupted {
+        std::process::exit(128 + signal);
     }
 
-    let mut dirtyme = vec![0u8; DIRTY_MEMORY.unwrap_or(0)];
-
-    // Run all the tests!
-    for &(num_threads, workload) in tests.iter() {
-        // No children should be running at this point
-        assert!(children.len() == 0);
-
-        // Reset statistics
-        unsafe { reset_shared_memory(); }
-
-        // Start a wall-clock timer
-        let start_time = Instant::now();
-
-        // Start a rdtsc-based timer too
-        let start_cycles = rdtsc();
-
-        // Create children while we're not at our target number of
-        // children
-        for thr_id in 0..num_threads {
-            // Fork to make a child
-            let child = unsafe { fork() };
-            assert!(child != -1);
-
-            if child == 0 {
-                // We're the child
-
-                // Pin to a specific processor
-                threading::pin_to_logical_processor(thr_id as usize);
-              
-                // Wait for all worker threads to be started, this ensures
-                // all threads start forking rnougly at the same time
-                // (within the time that the `workers` variable gets
-                // cache-coherencied across all cores. This will make sure
-                // that any expensive jitter caused by forking in the
-                // kernel will not be part of the benchmark. This also
-                // ensures that the threads are all running at the same
-                // time rather than straddled
-                shmem.workers.fetch_add(1, Ordering::SeqCst);
-                while shmem.workers.load(Ordering::SeqCst) !=
-                    num_threads {}
-                
-                let timeout = rdtsc() + 1_000_000_000;
-
-                while rdtsc() < timeout {
-                    let subchild = unsafe { fork() };
-                    assert!(subchild != 1);
-
-                    if subchild == 0 {
-                        let it = rdtsc();
-
-                        // Dirty memory as requested
-                        for ii in
-                                (0..DIRTY_MEMORY.unwrap_or(0)).step_by(4096) {
-                            unsafe {
-                                core::ptr::write_volatile(&mut dirtyme[ii], 5);
-                            }
-                        }
-
-                        unsafe {
-                            llvm_asm!(r#"
-
-                                test rcx, rcx
-                                jz   3f
-
-                                mov rax, rcx
-                            2:
-                            .rept 16
-                                mov rdx, [rsp]
-                            .endr
-
-                                dec rax
-                                jnz 2b
-
-                            3:
-
-                            "# :: "{rcx}"(workload) : "rax", "rdx" :
-                            "intel", "volatile");
-                        }
-                        let elapsed = rdtsc() - it;
-
-                        shmem.fuzz_cases.fetch_add(1, Ordering::SeqCst);
-                                                   
-                        shmem.vm_cycles.fetch_add(elapsed,
-                                                  Ordering::Relaxed);
-                
-                        // Done
-                        unsafe { exit(0); }
-                    } else {
-                        // Wait for the subchild to exit
-                        assert!(unsafe {
-                            waitpid(subchild, core::ptr::null_mut(), 0)
-                        } == subchild);
-                    }
+    // Store the complete sweep as a baseline, written to a temporary file
+    // first such that a failure doesn't clobber an earlier one
+    if let Some(name) = &config.save_baseline {
+        let path = config.baseline_path(name);
+        let tmp  = path.with_extension("json.tmp");
+        std::fs::create_dir_all(&config.baseline_dir)
+            .and_then(|_| {
+                let mut baseline = Output::new(&Config {
+                    output_format: forkbench::output::OutputFormat::Json,
+                    output_file:   Some(tmp.to_string_lossy().into_owned()),
+                    out_file:      None,
+                    ..config.clone()
+                })?;
+                for result in &all_results {
+                    baseline.result(result)?;
                 }
+                for summary in &summaries {
+                    baseline.summary(summary)?;
+                }
+                baseline.finish()?;
+                std::fs::rename(&tmp, &path)
+            })
+            .expect("Failed to write baseline");
+    }
 
-                // We're done working
-                shmem.workers.fetch_sub(1, Ordering::SeqCst);
-                
-                // Done entirely on this thread
-                unsafe { exit(0); }
-            } else {
-                // Log the PID of the child we just spawned
-                children.insert(child);
-            }
-        }
-
-        // Wait for all children to exit
-        children.retain(|&pid| {
-            unsafe {
-                waitpid(pid, core::ptr::null_mut(), 0) != pid
-            }
-        });
-
-        // All children are done, log number of cycles
-        let elapsed_cycles = rdtsc() - start_cycles;
-
-        // Get elapsed time in seconds
-        let elapsed = (Instant::now() - start_time).as_secs_f64();
-
-        // Compute fuzz cases/second
-        let fcps = shmem.fuzz_cases.load(Ordering::SeqCst) as f64 / elapsed;
-
-        // Just make sure all workers are "done", this should never happen
-        // unless we broke something
-        assert!(shmem.workers.load(Ordering::SeqCst) == 0);
+    // Check for regressions against the baseline, reported on stderr as the
+    // results may be going to stdout
+    let regressed = gate.map(|baseline| {
+        let mut stderr = std::io::stderr();
+        let color = stderr.is_terminal();
+        compare::report(&mut stderr, &baseline,
+                        &compare::from_results(&all_results),
+                        config.gate_threshold, color)
+            .expect("Failed to write comparison")
+    }).unwrap_or(0);
+
+    // Results of the other test points are still valid, but make sure
+    // failures don't go unnoticed by scripts
+    if failed > 0 {
+        eprint!("error: {} of {} test points failed\n", failed, tests.len());
+        std::process::exit(1);
+    }
 
-        print!("{:10} {:14} {:12.6} {:12.6}\n",
-               num_threads,
-               workload * (16 + 2),
-               shmem.vm_cycles.load(Ordering::Relaxed) as f64 /
-               (elapsed_cycles as f64 * num_threads as f64),
-               fcps / num_threads as f64);
-    }    
+    // Fail gated runs which regressed
+    if regressed > 0 {
+        eprint!("error: {} test points regressed by more than {}% against \
+                 baseline `{}`\n", regressed, config.gate_threshold,
+                config.gate_against.as_deref().unwrap_or(""));
+        std::process::exit(2);
+    }
 }
-
@@ -0,0 +1,289 @@
+//! Memory which workers dirty before they start forking
+
+#[cfg(unix)] use libc::*;
+
+#[cfg(unix)] use crate::error::{self, Error, Syscall};
+
+/// Size of a page which gets dirtied
+pub const PAGE_SIZE: usize = 4096;
+
+/// Size of a `MAP_HUGETLB` page
+#[cfg(target_os = "linux")]
+const HUGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+
+/// Huge page backing of the pre-fork memory
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HugePages {
+    /// Leave it up to the system configuration
+    Default,
+
+    /// Back the memory with `MAP_HUGETLB` pages from the hugetlbfs pool
+    HugeTlb,
+
+    /// Request transparent huge pages with `MADV_HUGEPAGE`
+    Thp,
+
+    /// Forbid transparent huge pages for the worker with
+    /// `PR_SET_THP_DISABLE`
+    NoThp,
+}
+
+impl std::str::FromStr for HugePages {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(HugePages::Default),
+            "hugetlb" => Ok(HugePages::HugeTlb),
+            "thp"     => Ok(HugePages::Thp),
+            "no-thp"  => Ok(HugePages::NoThp),
+            _ => Err(format!("Unknown huge page mode `{}`", s)),
+        }
+    }
+}
+
+impl HugePages {
+    /// Name of the mode, as selected on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            HugePages::Default => "default",
+            HugePages::HugeTlb => "hugetlb",
+            HugePages::Thp     => "thp",
+            HugePages::NoThp   => "no-thp",
+        }
+    }
+
+    /// Whether this mode can be used on the current platform
+    pub fn is_supported(&self) -> bool {
+        *self == HugePages::Default || cfg!(target_os = "linux")
+    }
+}
+
+/// `madvise()` fork behavior applied to part of the pre-fork memory
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ForkAdvice {
+    /// Memory is duplicated into children as usual
+    None,
+
+    /// `MADV_WIPEONFORK`, children get zero-filled memory in its place
+    WipeOnFork,
+
+    /// `MADV_DONTFORK`, the memory is not mapped in children at all
+    DontFork,
+}
+
+impl std::str::FromStr for ForkAdvice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none"       => Ok(ForkAdvice::None),
+            "wipeonfork" => Ok(ForkAdvice::WipeOnFork),
+            "dontfork"   => Ok(ForkAdvice::DontFork),
+            _ => Err(format!("Unknown fork advice `{}`", s)),
+        }
+    }
+}
+
+impl ForkAdvice {
+    /// Name of the advice, as selected on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            ForkAdvice::None       => "none",
+            ForkAdvice::WipeOnFork => "wipeonfork",
+            ForkAdvice::DontFork   => "dontfork",
+        }
+    }
+
+    /// Whether this advice can be used on the current platform
+    pub fn is_supported(&self) -> bool {
+        *self == ForkAdvice::None || cfg!(target_os = "linux")
+    }
+}
+
+/// An anonymous mapping which is entirely dirtied (made resident) on
+/// creation, such that every `fork()` has to duplicate its page tables and
+/// set up copy-on-write for it
+#[cfg(unix)]
+pub struct PreforkMemory {
+    /// Base address of the mapping
+    base: *mut u8,
+
+    /// Size of the mapping in bytes
+    size: usize,
+}
+
+#[cfg(unix)]
+impl PreforkMemory {
+    /// Map and dirty `size` bytes of anonymous memory using the `huge`
+    /// huge page mode, optionally bound to NUMA node `node`
+    pub fn with_size(size: usize, huge: HugePages, node: Option<u16>)
+            -> error::Result<Self> {
+        if size == 0 {
+            return Ok(PreforkMemory { base: core::ptr::null_mut(), size });
+        }
+
+        // hugetlb mappings must be a multiple of the huge page size
+        let (size, flags) = match huge {
+            #[cfg(target_os = "linux")]
+            HugePages::HugeTlb => (
+                (size + HUGE_PAGE_SIZE - 1) & !(HUGE_PAGE_SIZE - 1),
+                MAP_PRIVATE | MAP_ANONYMOUS | MAP_HUGETLB,
+            ),
+            _ => (size, MAP_PRIVATE | MAP_ANONYMOUS),
+        };
+
+        // THP can only be disabled process-wide, which is inherited by the
+        // children we fork
+        #[cfg(target_os = "linux")]
+        if huge == HugePages::NoThp {
+            Error::check(Syscall::Prctl,
+                unsafe { prctl(PR_SET_THP_DISABLE, 1, 0, 0, 0) })?;
+        }
+
+        let base = unsafe {
+            mmap(core::ptr::null_mut(), size, PROT_READ | PROT_WRITE,
+                 flags, -1, 0)
+        };
+        if base == MAP_FAILED {
+            // An exhausted hugetlb pool also shows up as `ENOMEM`
+            return Err(Error::last_os_error(Syscall::Mmap));
+        }
+
+        // From here on the mapping is unmapped on failure
+        let ret = PreforkMemory { base: base as *mut u8, size };
+
+        // Bind to the requested node before the memory is touched
+        #[cfg(target_os = "linux")]
+        if let Some(node) = node {
+            unsafe {
+                crate::threading::bind_memory_to_node(ret.base, size, node)?;
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = node;
+
+        // Request THP before touching the memory so faults can use them
+        #[cfg(target_os = "linux")]
+        if huge == HugePages::Thp {
+            Error::check(Syscall::Madvise, unsafe {
+                madvise(ret.base as *mut c_void, size, MADV_HUGEPAGE)
+            })?;
+        }
+
+        // Dirty every page
+        for offset in (0..size).step_by(PAGE_SIZE) {
+            unsafe { core::ptr::write_volatile(ret.base.add(offset), 5); }
+        }
+
+        Ok(ret)
+    }
+}
+
+#[cfg(unix)]
+impl PreforkMemory {
+    /// Apply `advice` to up to `bytes` bytes at the end of the memory,
+    /// leaving at least the first `keep` bytes duplicated into children
+    /// normally (the fuzz case may be writing to them)
+    pub fn advise(&mut self, advice: ForkAdvice, bytes: usize, keep: usize)
+            -> error::Result<()> {
+        // Round the start up to a page boundary
+        let start = self.size.saturating_sub(bytes).max(keep);
+        let start = (start + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        if advice == ForkAdvice::None || start >= self.size {
+            return Ok(());
+        }
+
+        #[cfg(target_os = "linux")]
+        {
+            let advice = match advice {
+                ForkAdvice::WipeOnFork => MADV_WIPEONFORK,
+                _                      => MADV_DONTFORK,
+            };
+
+            Error::check(Syscall::Madvise, unsafe {
+                madvise(self.base.add(start) as *mut c_void,
+                        self.size - start, advice)
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Get the memory as a slice
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        if self.size == 0 {
+            return &mut [];
+        }
+
+        unsafe { core::slice::from_raw_parts_mut(self.base, self.size) }
+    }
+}
+
+/// Distinct single-page anonymous mappings, separated by unmapped gaps such
+/// that the kernel can't merge them, each with a resident page. Every
+/// `fork()` has to duplicate each of them as a VMA of its own.
+#[cfg(unix)]
+pub struct Mappings {
+    /// Base address of the range holding the mappings and the gaps
+    base: *mut u8,
+
+    /// Number of mappings
+    count: usize,
+}
+
+#[cfg(unix)]
+impl Mappings {
+    /// Create and dirty `count` mappings
+    pub fn new(count: usize) -> error::Result<Self> {
+        if count == 0 {
+            return Ok(Mappings { base: core::ptr::null_mut(), count });
+        }
+
+        // Map room for all the mappings and the gaps between them in one go
+        let base = unsafe {
+            mmap(core::ptr::null_mut(), count * 2 * PAGE_SIZE,
+                 PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0)
+        };
+        if base == MAP_FAILED {
+            return Err(Error::last_os_error(Syscall::Mmap));
+        }
+
+        // From here on the range is unmapped on failure
+        let ret = Mappings { base: base as *mut u8, count };
+
+        // Punch a gap after every page, each split fails with `ENOMEM` once
+        // `vm.max_map_count` is reached
+        for ii in 0..count {
+            unsafe {
+                let page = ret.base.add(ii * 2 * PAGE_SIZE);
+                Error::check(Syscall::Munmap,
+                    munmap(page.add(PAGE_SIZE) as *mut c_void, PAGE_SIZE))?;
+                core::ptr::write_volatile(page, 5);
+            }
+        }
+
+        Ok(ret)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mappings {
+    fn drop(&mut self) {
+        // Unmapping the gaps again is harmless
+        if self.count > 0 {
+            unsafe {
+                munmap(self.base as *mut c_void, self.count * 2 * PAGE_SIZE);
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for PreforkMemory {
+    fn drop(&mut self) {
+        if self.size > 0 {
+            unsafe { munmap(self.base as *mut c_void, self.size); }
+        }
+    }
+}
@@ -0,0 +1,239 @@
+//! Information about the machine a benchmark was run on
+
+use crate::clock;
+use crate::topology::Topology;
+
+/// Description of the system the benchmark ran on, making results files
+/// self-describing when comparing runs across machines
+#[derive(Clone, Debug)]
+pub struct RunMetadata {
+    /// Kernel name and release (eg. `Linux 5.4.0`)
+    pub kernel_version: String,
+
+    /// CPU model string
+    pub cpu_model: String,
+
+    /// Microcode revision of the CPU
+    pub microcode: String,
+
+    /// State of simultaneous multithreading (eg. `on`, `off`, `forceoff`)
+    pub smt: String,
+
+    /// Frequency scaling governor of the first logical processor
+    pub governor: String,
+
+    /// Transparent hugepage mode (eg. `always`, `madvise`, `never`)
+    pub transparent_hugepage: String,
+
+    /// Each CPU vulnerability known to the kernel and the state of its
+    /// mitigation, sorted by vulnerability
+    pub mitigations: Vec<(String, String)>,
+
+    /// Number of sockets detected
+    pub sockets: usize,
+
+    /// Number of NUMA nodes detected
+    pub numa_nodes: usize,
+
+    /// Number of physical cores detected
+    pub physical_cores: usize,
+
+    /// Number of logical processors detected
+    pub logical_processors: usize,
+
+    /// Number of logical processors of efficiency cores detected, 0 unless
+    /// the CPU is hybrid
+    pub efficiency_processors: usize,
+
+    /// Frequency of the benchmark clock in Hz
+    pub tsc_frequency: u64,
+
+    /// How the benchmark clock frequency was determined
+    pub tsc_source: &'static str,
+
+    /// Whether the cycle counter ticks at a constant rate
+    pub invariant_tsc: bool,
+}
+
+impl RunMetadata {
+    /// Collect metadata about the current system with the detected
+    /// `topology`. This may take a short amount of time if the benchmark
+    /// clock has not been calibrated yet.
+    pub fn collect(topology: &Topology) -> Self {
+        let calibration = clock::calibration();
+
+        RunMetadata {
+            kernel_version:     kernel_version(),
+            cpu_model:          cpu_model(),
+            microcode:          microcode(),
+            smt:                system_file("devices/system/cpu/smt/control"),
+            governor:           system_file(
+                "devices/system/cpu/cpu0/cpufreq/scaling_governor"),
+            transparent_hugepage: transparent_hugepage(),
+            mitigations:        mitigations(),
+            sockets:            topology.sockets(),
+            numa_nodes:         topology.numa_nodes(),
+            physical_cores:     topology.physical_cores(),
+            logical_processors: topology.logical_processors(),
+            efficiency_processors: topology.efficiency_processors(),
+            tsc_frequency:      calibration.frequency,
+            tsc_source:         calibration.source,
+            invariant_tsc:      clock::invariant_tsc(),
+        }
+    }
+}
+
+/// Get the kernel name and release from `uname()`
+#[cfg(unix)]
+fn kernel_version() -> String {
+    unsafe {
+        let mut uts: libc::utsname = core::mem::zeroed();
+        if libc::uname(&mut uts) != 0 {
+            return String::from("unknown");
+        }
+
+        let sysname = std::ffi::CStr::from_ptr(uts.sysname.as_ptr());
+        let release = std::ffi::CStr::from_ptr(uts.release.as_ptr());
+        format!("{} {}", sysname.to_string_lossy(), release.to_string_lossy())
+    }
+}
+
+/// Get the name of the machine from `uname()`, identifying it among the
+/// machines of a fleet
+#[cfg(unix)]
+pub fn hostname() -> String {
+    unsafe {
+        let mut uts: libc::utsname = core::mem::zeroed();
+        if libc::uname(&mut uts) != 0 {
+            return String::from("unknown");
+        }
+
+        std::ffi::CStr::from_ptr(uts.nodename.as_ptr()).to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// Get the name of the machine from the environment
+#[cfg(windows)]
+pub fn hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| "unknown".into())
+}
+
+/// Get the kernel name
+#[cfg(windows)]
+fn kernel_version() -> String {
+    String::from("Windows")
+}
+
+/// Get the model string of the CPU from `sysctl`
+#[cfg(any(target_os = "macos", target_os = "freebsd"))]
+fn cpu_model() -> String {
+    #[cfg(target_os = "macos")]
+    const NAME: &[u8] = b"machdep.cpu.brand_string\0";
+    #[cfg(target_os = "freebsd")]
+    const NAME: &[u8] = b"hw.model\0";
+
+    let mut buf = [0u8; 256];
+    let mut size = buf.len();
+
+    unsafe {
+        if libc::sysctlbyname(NAME.as_ptr() as *const _,
+                buf.as_mut_ptr() as *mut libc::c_void, &mut size,
+                core::ptr::null_mut(), 0) != 0 {
+            return String::from("unknown");
+        }
+
+        std::ffi::CStr::from_ptr(buf.as_ptr() as *const _)
+            .to_string_lossy().into_owned()
+    }
+}
+
+/// Get the model string of the CPU from `/proc/cpuinfo`. ARM systems have
+/// no model name, in which case the implementer and part numbers are used.
+#[cfg(not(any(target_os = "macos", target_os = "freebsd")))]
+fn cpu_model() -> String {
+    let cpuinfo = match std::fs::read_to_string("/proc/cpuinfo") {
+        Ok(cpuinfo) => cpuinfo,
+        Err(_)      => return String::from("unknown"),
+    };
+
+    let field = |name: &str| cpuinfo_field(&cpuinfo, name);
+
+    field("model name").or_else(|| {
+        Some(format!("implementer {} part {}", field("CPU implementer")?,
+                     field("CPU part")?))
+    }).unwrap_or_else(|| String::from("unknown"))
+}
+
+/// Get the value of the first `name` field of `/proc/cpuinfo`
+#[cfg(not(any(target_os = "macos", target_os = "freebsd")))]
+fn cpuinfo_field(cpuinfo: &str, name: &str) -> Option<String> {
+    cpuinfo.lines()
+        .find(|line| line.starts_with(name))
+        .and_then(|line| line.split_once(':').map(|x| x.1))
+        .map(|value| value.trim().to_string())
+}
+
+/// Get the microcode revision of the CPU from `/proc/cpuinfo`
+#[cfg(target_os = "linux")]
+fn microcode() -> String {
+    std::fs::read_to_string("/proc/cpuinfo").ok()
+        .and_then(|cpuinfo| cpuinfo_field(&cpuinfo, "microcode"))
+        .unwrap_or_else(|| String::from("unknown"))
+}
+
+/// Get the microcode revision of the CPU, which isn't exposed
+#[cfg(not(target_os = "linux"))]
+fn microcode() -> String {
+    String::from("unknown")
+}
+
+/// Get the trimmed contents of a file below `/sys`, `unknown` if it doesn't
+/// exist (eg. no SMT control or frequency scaling in a VM)
+#[cfg(target_os = "linux")]
+fn system_file(path: &str) -> String {
+    std::fs::read_to_string(format!("/sys/{}", path))
+        .map(|value| value.trim().to_string())
+        .unwrap_or_else(|_| String::from("unknown"))
+}
+
+/// Get the contents of a file below `/sys`, which only exists on Linux
+#[cfg(not(target_os = "linux"))]
+fn system_file(_path: &str) -> String {
+    String::from("unknown")
+}
+
+/// Get the selected transparent hugepage mode, which the kernel reports in
+/// brackets amongst all modes (eg. `always [madvise] never`)
+fn transparent_hugepage() -> String {
+    let modes = system_file("kernel/mm/transparent_hugepage/enabled");
+    modes.split_once('[')
+        .and_then(|(_, selected)| selected.split_once(']'))
+        .map(|(selected, _)| selected.to_string())
+        .unwrap_or(modes)
+}
+
+/// Get the mitigation state of every CPU vulnerability the kernel knows of
+/// from `/sys/devices/system/cpu/vulnerabilities`
+#[cfg(target_os = "linux")]
+fn mitigations() -> Vec<(String, String)> {
+    let dir = "devices/system/cpu/vulnerabilities";
+    let entries = match std::fs::read_dir(format!("/sys/{}", dir)) {
+        Ok(entries) => entries,
+        Err(_)      => return Vec::new(),
+    };
+
+    let mut ret: Vec<(String, String)> = entries.flatten().map(|entry| {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let state = system_file(&format!("{}/{}", dir, name));
+        (name, state)
+    }).collect();
+    ret.sort();
+    ret
+}
+
+/// Get the mitigation state of CPU vulnerabilities, which isn't exposed
+#[cfg(not(target_os = "linux"))]
+fn mitigations() -> Vec<(String, String)> {
+    Vec::new()
+}
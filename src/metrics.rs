@@ -0,0 +1,246 @@
+//! Prometheus metrics of the sweep, served over HTTP for monitoring long
+//! runs from existing dashboards
+//!
+//! With `--metrics <addr>` the progress of the sweep, the fork rate of each
+//! worker of the running test point (sampled from their statistics slots in
+//! shared memory) and the most recent result are served in the Prometheus
+//! text exposition format at `/metrics`. Like the dashboard, requests are
+//! only answered by a thread running while the workers of a test point are,
+//! see [`crate::sampler`] for why. The listening
+//! socket stays open in between, such that scrapes arriving then are
+//! answered once the next test point started.
+
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::output::TestResult;
+use crate::sampler::{self, Watch};
+use crate::sweep::TestPoint;
+
+/// Interval the fork rates are sampled at
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Longest a client may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// State of the sweep shared with the serving thread
+#[derive(Default)]
+struct State {
+    /// Number of test points of the sweep
+    total: usize,
+
+    /// Number of test points completed, including failed ones
+    completed: usize,
+
+    /// Number of test points which failed
+    failed: usize,
+
+    /// Test point and repetition running
+    running: Option<(TestPoint, usize)>,
+
+    /// Most recent result
+    last: Option<TestResult>,
+}
+
+/// Metrics endpoint of the sweep, does nothing unless `--metrics` was given
+pub struct Exporter {
+    /// Socket requests are accepted on, `None` if not serving
+    listener: Option<Arc<TcpListener>>,
+
+    /// Logical processor each worker is pinned to, indexed by worker ID
+    cpus: Vec<usize>,
+
+    /// State of the sweep
+    state: Arc<Mutex<State>>,
+}
+
+/// Render the labels of a test point
+fn point_labels(point: &TestPoint) -> String {
+    format!("threads=\"{}\",workload=\"{}\",memory_mib=\"{}\",vmas=\"{}\",\
+        fds=\"{}\",idle_threads=\"{}\"", point.threads, point.workload,
+        point.memory_mib, point.vmas, point.fds, point.idle_threads)
+}
+
+/// Render the metrics in the text exposition format, `forks` and `rates`
+/// are the fork counts and rates of the workers of the running test point
+fn render(state: &State, cpus: &[usize], forks: &[u64], rates: &[f64])
+        -> String {
+    let mut ret = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str,
+                      values: &[(String, String)]| {
+        let _ = write!(ret, "# HELP forkbench_{} {}\n# TYPE forkbench_{} \
+            {}\n", name, help, name, kind);
+        for (labels, value) in values {
+            let labels = if labels.is_empty() {
+                String::new()
+            } else {
+                format!("{{{}}}", labels)
+            };
+            let _ = write!(ret, "forkbench_{}{} {}\n", name, labels, value);
+        }
+    };
+    let single = |value: String| vec![(String::new(), value)];
+
+    // Progress of the sweep
+    metric("test_points", "gauge", "Number of test points of the sweep",
+           &single(state.total.to_string()));
+    metric("test_points_completed", "gauge",
+           "Number of test points completed, including failed ones",
+           &single(state.completed.to_string()));
+    metric("test_points_failed", "gauge",
+           "Number of test points which failed",
+           &single(state.failed.to_string()));
+    if let Some((point, rep)) = &state.running {
+        metric("running_rep", "gauge",
+               "Repetition of the running test point",
+               &[(point_labels(point), rep.to_string())]);
+    }
+
+    // Workers of the running test point
+    let worker = |thr_id: usize| {
+        format!("worker=\"{}\",cpu=\"{}\"", thr_id, cpus[thr_id])
+    };
+    metric("worker_forks", "gauge",
+           "Fuzz cases created by each worker during the running test",
+           &forks.iter().enumerate()
+               .map(|(thr_id, forks)| (worker(thr_id), forks.to_string()))
+               .collect::<Vec<_>>());
+    metric("worker_forks_per_second", "gauge",
+           "Fuzz cases created per second by each worker, sampled",
+           &rates.iter().enumerate()
+               .map(|(thr_id, rate)| (worker(thr_id), format!("{:.1}", rate)))
+               .collect::<Vec<_>>());
+
+    // Most recent result
+    if let Some(result) = &state.last {
+        let labels = point_labels(&result.point);
+        for (name, help, value) in [
+            ("efficiency_ratio",
+             "Ratio of CPU time spent inside the fuzz case",
+             result.efficiency()),
+            ("forks_per_second",
+             "Fuzz cases created per second over all workers",
+             result.forks_per_second()),
+            ("ns_per_fork",
+             "Wall-clock nanoseconds each worker spent per fuzz case",
+             result.ns_per_fork()),
+            ("p99_ns", "99th percentile of the iteration latency",
+             result.latency_percentile_ns(0.99)),
+        ] {
+            metric(&format!("last_{}", name), "gauge",
+                   &format!("{}, of the most recent result", help),
+                   &[(labels.clone(), value.to_string())]);
+        }
+    }
+
+    ret
+}
+
+/// Answer the request of `stream` with `metrics`, or a 404 for anything but
+/// `/metrics` (or `/`)
+fn respond(mut stream: TcpStream, metrics: &str) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+
+    // Read until the end of the headers, only the request line matters
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|x| x == b"\r\n\r\n") &&
+            request.len() < 16 * 1024 {
+        let bytes = stream.read(&mut buf)?;
+        if bytes == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..bytes]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let path = request.split_whitespace().nth(1).unwrap_or("");
+
+    let (status, body) = match path {
+        "/metrics" | "/" => ("200 OK", metrics),
+        _                => ("404 Not Found", "not found\n"),
+    };
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: text/plain; \
+        version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, body.len(), body)
+}
+
+impl Exporter {
+    /// Create the exporter, listening on the address of `--metrics` if it
+    /// was given
+    pub fn new(config: &Config) -> io::Result<Self> {
+        let listener = config.metrics.as_ref().map(|addr| {
+            let listener = TcpListener::bind(addr)?;
+            listener.set_nonblocking(true)?;
+            Ok::<_, io::Error>(Arc::new(listener))
+        }).transpose()?;
+
+        Ok(Exporter {
+            listener,
+            cpus:  config.processors.iter().map(|x| x.cpu()).collect(),
+            state: Arc::new(Mutex::new(State::default())),
+        })
+    }
+
+    /// Set the progress of the sweep, `completed` of `total` test points of
+    /// which `failed` failed
+    pub fn progress(&self, completed: usize, failed: usize, total: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.completed = completed;
+        state.failed    = failed;
+        state.total     = total;
+    }
+
+    /// Set the test point and repetition which is running
+    pub fn running(&self, point: TestPoint, rep: usize) {
+        self.state.lock().unwrap().running = Some((point, rep));
+    }
+
+    /// Record the most recent result
+    pub fn result(&self, result: &TestResult) {
+        self.state.lock().unwrap().last = Some(result.clone());
+    }
+
+    /// Start serving requests while `threads` workers are running, see
+    /// [`sampler`] for when that's allowed
+    pub fn watch(&self, threads: usize) -> Watch {
+        let listener = match &self.listener {
+            Some(listener) => listener.clone(),
+            None           => return Watch::none(),
+        };
+
+        let state = self.state.clone();
+        let cpus  = self.cpus[..threads].to_vec();
+        let shmem = unsafe { crate::shmem::get() };
+        let sample = move || -> Vec<u64> {
+            (0..threads).map(|thr_id| {
+                shmem.slot(thr_id).forks.load(Ordering::Relaxed)
+            }).collect()
+        };
+
+        let mut last  = sample();
+        let mut rates = vec![0.0; threads];
+        Watch::start(SAMPLE_INTERVAL, move |secs| {
+            if let Some(secs) = secs {
+                let now = sample();
+                for ((rate, &new), &old) in
+                        rates.iter_mut().zip(now.iter()).zip(last.iter()) {
+                    *rate = sampler::delta(new, old) as f64 / secs;
+                }
+                last = now;
+            }
+
+            // Answer everyone waiting, failing clients are dropped
+            while let Ok((stream, _)) = listener.accept() {
+                let metrics = render(&state.lock().unwrap(), &cpus,
+                                     &sample(), &rates);
+                let _ = respond(stream, &metrics);
+            }
+        })
+    }
+}
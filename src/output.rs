@@ -0,0 +1,1161 @@
+//! Reporting of benchmark results
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write, BufWriter};
+
+use crate::config::Config;
+use crate::metadata::RunMetadata;
+use crate::backend;
+use crate::clock;
+use crate::sweep::{self, TestPoint};
+use crate::topology::StatsPlacement;
+use crate::histogram::Histogram;
+use crate::stats::Summary;
+use crate::error::Error;
+use crate::perf;
+use crate::soak::Sample;
+use crate::frequency::{self, Frequency};
+use crate::trace;
+use crate::usage::{self, Usage};
+
+/// Number of instructions executed per iteration of the workload loop (16
+/// loads, a decrement and a branch)
+pub const INSTS_PER_WORKLOAD: u64 = 16 + 2;
+
+/// Default coefficient of variation in percent of the efficiency of a test
+/// point beyond which it's flagged as noisy
+pub const NOISE_THRESHOLD: f64 = 5.;
+
+/// Format to report results in
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OutputFormat {
+    /// Whitespace aligned columns, suitable for gnuplot
+    Text,
+
+    /// Comma separated values with a header row
+    Csv,
+
+    /// A single JSON document containing run metadata and all results
+    Json,
+
+    /// A JSON object per line for each result, summary and failed test
+    /// point, written as soon as it's known
+    Jsonl,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text"  => Ok(OutputFormat::Text),
+            "csv"   => Ok(OutputFormat::Csv),
+            "json"  => Ok(OutputFormat::Json),
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            _ => Err(format!("Unknown output format `{}`", s)),
+        }
+    }
+}
+
+/// Results of a single test point
+#[derive(Clone, Debug)]
+pub struct TestResult {
+    /// Test point these are the results of
+    pub point: TestPoint,
+
+    /// Repetition of the test point, starting at 0
+    pub rep: usize,
+
+    /// Number of fuzz cases run by all workers
+    pub fuzz_cases: u64,
+
+    /// Number of fuzz cases all workers created and saw complete, as
+    /// counted by the workers. Unlike `fuzz_cases` this includes fuzz cases
+    /// which crashed.
+    pub forks: u64,
+
+    /// Number of fuzz cases all workers attempted to create, including the
+    /// ones which failed to be created
+    pub attempts: u64,
+
+    /// Number of fuzz cases which failed to be created with a transient
+    /// error (eg. `EAGAIN` from hitting the process limit)
+    pub fork_failures: u64,
+
+    /// Error code of a fuzz case which failed to be created, if any did
+    pub fork_errno: Option<i32>,
+
+    /// Number of cycles all workers spent inside of fuzz cases
+    pub vm_cycles: u64,
+
+    /// Number of cycles from the workers starting to create each fuzz case
+    /// until it was running, summed over all fuzz cases
+    pub creation_cycles: u64,
+
+    /// Number of cycles the workers spent blocked waiting for fuzz cases to
+    /// exit, summed over all fuzz cases
+    pub reap_cycles: u64,
+
+    /// Number of fuzz cases which were killed by a signal or exited with a
+    /// non-zero status, rather than completing
+    pub crashes: u64,
+
+    /// Number of cycles from the start of the test until all workers exited
+    pub elapsed_cycles: u64,
+
+    /// Number of fuzz cases per second, per thread
+    pub fcps_per_thread: f64,
+
+    /// Histogram of the latency of each iteration in clock ticks
+    pub latency: Histogram,
+
+    /// Lowest and highest latency of an iteration of each worker in clock
+    /// ticks, indexed by worker ID
+    pub worker_latency: Vec<(u64, u64)>,
+
+    /// Resource usage of all fuzz cases, as reported when they were reaped
+    pub usage: Usage,
+
+    /// Event counts of the workers and fuzz cases, if they were collected
+    pub perf: Option<perf::Counts>,
+
+    /// Frequency of the logical processors of the workers, if it was
+    /// measured
+    pub frequency: Option<Frequency>,
+
+    /// Kernel-side timing of process creation, if it was traced
+    pub kernel: Option<trace::Timing>,
+
+    /// Whether the repetition is an outlier among the repetitions of its
+    /// test point, see `flag_outliers()`. Outliers are still reported, but
+    /// left out of the summary.
+    pub outlier: bool,
+}
+
+impl TestResult {
+    /// Number of instructions executed per fuzz case
+    pub fn effective_workload(&self) -> u64 {
+        self.point.workload * INSTS_PER_WORKLOAD
+    }
+
+    /// Ratio of CPU time spent inside the fuzz case (1.0 means no overhead)
+    pub fn efficiency(&self) -> f64 {
+        self.vm_cycles as f64 /
+            (self.elapsed_cycles as f64 * self.point.threads as f64)
+    }
+
+    /// Wall-clock duration of the test in nanoseconds
+    pub fn elapsed_ns(&self) -> f64 {
+        clock::cycles_to_ns(self.elapsed_cycles)
+    }
+
+    /// Number of fuzz cases created per second over all workers
+    pub fn forks_per_second(&self) -> f64 {
+        self.forks as f64 / (self.elapsed_ns() / 1e9)
+    }
+
+    /// Number of fuzz cases created per second by each worker
+    pub fn forks_per_second_per_thread(&self) -> f64 {
+        self.forks_per_second() / self.point.threads as f64
+    }
+
+    /// Estimated `quantile` (0.0 to 1.0) of the iteration latency in
+    /// nanoseconds
+    pub fn latency_percentile_ns(&self, quantile: f64) -> f64 {
+        clock::cycles_to_ns(self.latency.percentile(quantile))
+    }
+
+    /// Average wall-clock nanoseconds each worker spent per fuzz case,
+    /// including the process creation overhead
+    pub fn ns_per_fork(&self) -> f64 {
+        self.elapsed_ns() * self.point.threads as f64 /
+            self.forks.max(1) as f64
+    }
+
+    /// Lowest latency of any iteration of any worker in nanoseconds
+    pub fn latency_min_ns(&self) -> f64 {
+        clock::cycles_to_ns(self.worker_latency.iter().map(|x| x.0)
+            .filter(|&x| x > 0).min().unwrap_or(0))
+    }
+
+    /// Highest latency of any iteration of any worker in nanoseconds
+    pub fn latency_max_ns(&self) -> f64 {
+        clock::cycles_to_ns(self.worker_latency.iter().map(|x| x.1).max()
+            .unwrap_or(0))
+    }
+
+    /// Average nanoseconds from a worker starting to create a fuzz case
+    /// until it was running. With `vfork` this includes reaping the child,
+    /// as the fuzz case only runs once the worker resumes.
+    pub fn creation_ns(&self) -> f64 {
+        clock::cycles_to_ns(self.creation_cycles) /
+            self.fuzz_cases.max(1) as f64
+    }
+
+    /// Average nanoseconds a worker spent blocked waiting for a fuzz case to
+    /// exit and be reaped
+    pub fn reap_ns(&self) -> f64 {
+        clock::cycles_to_ns(self.reap_cycles) / self.fuzz_cases.max(1) as f64
+    }
+
+    /// Average nanoseconds spent running the workload of a fuzz case
+    pub fn workload_ns(&self) -> f64 {
+        clock::cycles_to_ns(self.vm_cycles) / self.fuzz_cases.max(1) as f64
+    }
+}
+
+/// Summary of all repetitions of a single test point
+#[derive(Clone, Debug)]
+pub struct PointSummary {
+    /// Test point this is a summary of
+    pub point: TestPoint,
+
+    /// Number of repetitions of the test point
+    pub reps: usize,
+
+    /// Number of repetitions left out of the summary as outliers
+    pub outliers: usize,
+
+    /// Ratio of CPU time spent inside the fuzz case
+    pub efficiency: Summary,
+
+    /// Number of fuzz cases created per second over all workers
+    pub forks_per_second: Summary,
+
+    /// Number of fuzz cases created per second by each worker
+    pub forks_per_second_per_thread: Summary,
+
+    /// Average wall-clock nanoseconds each worker spent per fuzz case
+    pub ns_per_fork: Summary,
+
+    /// Number of fuzz cases of all repetitions which didn't complete
+    pub crashes: u64,
+
+    /// Whether the efficiency varies by more than the noise threshold
+    /// across the repetitions, such that its mean isn't to be trusted
+    pub noisy: bool,
+}
+
+impl PointSummary {
+    /// Summarize the repetitions `results` of a test point, leaving out
+    /// the ones flagged as outliers. It's noisy if the coefficient of
+    /// variation of its efficiency exceeds `noise_threshold` percent.
+    pub fn new(results: &[TestResult], noise_threshold: f64) -> Self {
+        let summary = |f: fn(&TestResult) -> f64| {
+            Summary::of(&results.iter().filter(|x| !x.outlier).map(f)
+                .collect::<Vec<_>>())
+        };
+        let efficiency = summary(TestResult::efficiency);
+
+        PointSummary {
+            point:            results[0].point,
+            reps:             results.len(),
+            outliers:         results.iter().filter(|x| x.outlier).count(),
+            efficiency,
+            forks_per_second: summary(TestResult::forks_per_second),
+            forks_per_second_per_thread:
+                summary(TestResult::forks_per_second_per_thread),
+            ns_per_fork:      summary(TestResult::ns_per_fork),
+            crashes:          results.iter().map(|x| x.crashes).sum(),
+            noisy:            efficiency.cv() * 100. > noise_threshold,
+        }
+    }
+
+    /// Number of instructions executed per fuzz case
+    pub fn effective_workload(&self) -> u64 {
+        self.point.workload * INSTS_PER_WORKLOAD
+    }
+}
+
+/// Flag the repetitions `results` of a test point whose efficiency or fork
+/// rate is more than `threshold` scaled median absolute deviations away from
+/// the median of all of them as outliers, eg. one which ran into a cron job
+/// or an interrupt storm. Outliers are the exception, if half of the
+/// repetitions or more would be flagged the test point is just noisy and
+/// none are.
+pub fn flag_outliers(results: &mut [TestResult], threshold: f64) {
+    let mut flagged = vec![false; results.len()];
+    for metric in [TestResult::efficiency, TestResult::forks_per_second] {
+        let samples: Vec<f64> = results.iter().map(metric).collect();
+        let outliers = crate::stats::outliers(&samples, threshold);
+        for (flag, outlier) in flagged.iter_mut().zip(outliers) {
+            *flag |= outlier;
+        }
+    }
+
+    let noisy = flagged.iter().filter(|&&x| x).count() * 2 >= results.len();
+    for (result, flag) in results.iter_mut().zip(flagged) {
+        result.outlier = flag && !noisy;
+    }
+}
+
+/// Render a summary as a JSON object
+fn json_summary(summary: &Summary) -> String {
+    format!("{{\"mean\": {:.6}, \"stddev\": {:.6}, \"ci95\": {:.6}}}",
+            summary.mean, summary.stddev, summary.ci95)
+}
+
+/// Latency percentiles reported for each result, as names and quantiles
+pub const PERCENTILES: &[(&str, f64)] = &[
+    ("p50",  0.50),
+    ("p90",  0.90),
+    ("p99",  0.99),
+    ("p999", 0.999),
+];
+
+/// Render an event count, unavailable counters as `unavailable`
+fn perf_count(count: Option<u64>, unavailable: &str) -> String {
+    count.map(|x| x.to_string()).unwrap_or_else(|| unavailable.into())
+}
+
+/// Render the fields of a frequency measurement, all `unavailable` if it
+/// couldn't be measured
+fn frequency_fields(frequency: Option<Frequency>, unavailable: &str)
+        -> Vec<String> {
+    match frequency {
+        Some(frequency) => frequency.fields().iter()
+            .map(|x| format!("{:.0}", x)).collect(),
+        None => vec![unavailable.to_string(); frequency::FIELDS],
+    }
+}
+
+/// Render the fields of the kernel-side timing, all `unavailable` if it
+/// wasn't traced and the times `unavailable` if nothing was matched up
+fn timing_fields(timing: Option<trace::Timing>, unavailable: &str)
+        -> Vec<String> {
+    let time = |x: Option<f64>| {
+        x.map(|x| format!("{:.1}", x)).unwrap_or_else(|| unavailable.into())
+    };
+    match timing {
+        Some(timing) => vec![time(timing.fork_ns),
+                             time(timing.sched_delay_ns),
+                             timing.forks.to_string()],
+        None => vec![unavailable.to_string(); trace::FIELDS],
+    }
+}
+
+/// A file which is synced to disk whenever it's flushed
+struct SyncedFile(File);
+
+impl Write for SyncedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.sync_data()
+    }
+}
+
+/// Writer duplicating everything written to it into two writers
+struct Tee(Box<dyn Write>, Box<dyn Write>);
+
+impl Write for Tee {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write_all(buf)?;
+        self.1.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()?;
+        self.1.flush()
+    }
+}
+
+/// A report held back until the sweep is done
+enum Deferred {
+    /// Result of a single test, boxed as it's much larger than the others
+    Result(Box<TestResult>),
+
+    /// Summary of all repetitions of a test point
+    Summary(PointSummary),
+
+    /// Failed test point, and the rendered error it failed with
+    Failure(TestPoint, String),
+}
+
+impl Deferred {
+    /// Test point the report is about
+    fn point(&self) -> TestPoint {
+        match self {
+            Deferred::Result(result)   => result.point,
+            Deferred::Summary(summary) => summary.point,
+            Deferred::Failure(point, _) => *point,
+        }
+    }
+}
+
+/// Sink for test results in the requested format
+pub struct Output {
+    /// Format to write results in
+    format: OutputFormat,
+
+    /// Where the results are written
+    writer: Box<dyn Write>,
+
+    /// Number of results written so far
+    results: usize,
+
+    /// Print latency histograms in text output
+    histogram: bool,
+
+    /// Report the event counts of each result
+    perf: bool,
+
+    /// Report the processor frequency of each result
+    cpu_frequency: bool,
+
+    /// Report the kernel-side timing of each result
+    trace_fork: bool,
+
+    /// Rendered summaries of each test point, written at the end of JSON
+    /// output
+    summaries: Vec<String>,
+
+    /// Rendered failed test points, written at the end of JSON output
+    failures: Vec<String>,
+
+    /// Rendered throughput samples, written at the end of JSON output.
+    /// `None` if not soaking.
+    samples: Option<Vec<String>>,
+
+    /// Reports held back until the sweep is done, such that the test points
+    /// of a shuffled sweep are written in order. `None` if reports are
+    /// written as they come in.
+    deferred: Option<Vec<Deferred>>,
+}
+
+/// Escape a string as a JSON string literal
+pub fn json_string(s: &str) -> String {
+    let mut ret = String::from("\"");
+    for chr in s.chars() {
+        match chr {
+            '"'  => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            '\n' => ret.push_str("\\n"),
+            '\r' => ret.push_str("\\r"),
+            '\t' => ret.push_str("\\t"),
+            chr if (chr as u32) < 0x20 =>
+                ret.push_str(&format!("\\u{:04x}", chr as u32)),
+            chr => ret.push(chr),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+/// Write a named JSON object member of pre-rendered `fields` at the second
+/// nesting level of the results document
+fn write_json_object(writer: &mut dyn Write, name: &str,
+                     fields: &[(&str, String)]) -> io::Result<()> {
+    write!(writer, "  {}: {{\n", json_string(name))?;
+    for (ii, (key, value)) in fields.iter().enumerate() {
+        write!(writer, "    {}: {}{}\n", json_string(key), value,
+               if ii + 1 < fields.len() { "," } else { "" })?;
+    }
+    write!(writer, "  }},\n")
+}
+
+/// Render `result` as a JSON object, with the processor frequency if
+/// `cpu_frequency`
+pub fn json_result(result: &TestResult, cpu_frequency: bool) -> String {
+    let mut ret = format!("{{\"threads\": {}, \"rep\": {}, \
+        \"outlier\": {}, \"effective_workload_insts\": {}, \
+        \"prefork_memory_mib\": {}, \
+        \"vmas\": {}, \"fds\": {}, \"idle_threads\": {}, \
+        \"efficiency_ratio\": {:.6}, \"elapsed_cycles\": {}, \
+        \"vm_cycles\": {}, \"fcps_per_thread\": {:.6}, \
+        \"fuzz_cases\": {}, \"forks\": {}, \"elapsed_ns\": {:.0}, \
+        \"forks_per_second\": {:.2}, \
+        \"forks_per_second_per_thread\": {:.2}, \
+        \"ns_per_fork\": {:.1}, \"creation_cycles\": {}, \
+        \"creation_ns\": {:.1}, \"workload_ns\": {:.1}, \
+        \"reap_cycles\": {}, \"reap_ns\": {:.1}, \"crashes\": {}, \
+        \"fork_attempts\": {}, \"fork_failures\": {}, \
+        \"fork_errno\": {}, \"latency_min_ns\": {:.1}, \
+        \"latency_max_ns\": {:.1}, \"worker_latency_min_ns\": [{}], \
+        \"worker_latency_max_ns\": [{}], \
+        \"latency_log2_histogram\": [{}]",
+        result.point.threads,
+        result.rep,
+        result.outlier,
+        result.effective_workload(),
+        result.point.memory_mib,
+        result.point.vmas,
+        result.point.fds,
+        result.point.idle_threads,
+        result.efficiency(),
+        result.elapsed_cycles,
+        result.vm_cycles,
+        result.fcps_per_thread,
+        result.fuzz_cases,
+        result.forks,
+        result.elapsed_ns(),
+        result.forks_per_second(),
+        result.forks_per_second_per_thread(),
+        result.ns_per_fork(),
+        result.creation_cycles,
+        result.creation_ns(),
+        result.workload_ns(),
+        result.reap_cycles,
+        result.reap_ns(),
+        result.crashes,
+        result.attempts,
+        result.fork_failures,
+        result.fork_errno.map(|x| x.to_string())
+            .unwrap_or_else(|| "null".into()),
+        result.latency_min_ns(),
+        result.latency_max_ns(),
+        result.worker_latency.iter()
+            .map(|x| format!("{:.1}", clock::cycles_to_ns(x.0)))
+            .collect::<Vec<_>>().join(", "),
+        result.worker_latency.iter()
+            .map(|x| format!("{:.1}", clock::cycles_to_ns(x.1)))
+            .collect::<Vec<_>>().join(", "),
+        result.latency.counts().iter().map(|x| x.to_string())
+            .collect::<Vec<_>>().join(", "));
+    for (name, quantile) in PERCENTILES {
+        ret.push_str(&format!(", \"{}_ns\": {:.1}", name,
+                              result.latency_percentile_ns(*quantile)));
+    }
+    for (name, field) in usage::NAMES.iter().zip(result.usage.fields()) {
+        ret.push_str(&format!(", \"{}\": {}", name, field));
+    }
+    if cpu_frequency {
+        for (name, field) in frequency::NAMES.iter()
+                .zip(frequency_fields(result.frequency, "null")) {
+            ret.push_str(&format!(", \"{}\": {}", name, field));
+        }
+    }
+    if result.kernel.is_some() {
+        for (name, field) in trace::NAMES.iter()
+                .zip(timing_fields(result.kernel, "null")) {
+            ret.push_str(&format!(", \"{}\": {}", name, field));
+        }
+    }
+    if let Some(counts) = &result.perf {
+        let members: Vec<String> = perf::NAMES.iter()
+            .zip(counts.iter()).map(|(name, &count)| {
+                format!("{}: {}", json_string(name),
+                        perf_count(count, "null"))
+            }).collect();
+        ret.push_str(&format!(", \"perf\": {{{}}}", members.join(", ")));
+    }
+    ret.push('}');
+    ret
+}
+
+/// Render the summary of all repetitions of a test point as a JSON object
+pub fn json_point_summary(summary: &PointSummary) -> String {
+    format!("{{\"threads\": {}, \"effective_workload_insts\": {}, \
+        \"prefork_memory_mib\": {}, \"vmas\": {}, \"fds\": {}, \
+        \"idle_threads\": {}, \"reps\": {}, \"outliers\": {}, \
+        \"efficiency_ratio\": {}, \"efficiency_cv\": {:.6}, \
+        \"noisy\": {}, \
+        \"forks_per_second\": {}, \"forks_per_second_per_thread\": {}, \
+        \"ns_per_fork\": {}, \"crashes\": {}}}",
+        summary.point.threads,
+        summary.effective_workload(),
+        summary.point.memory_mib,
+        summary.point.vmas,
+        summary.point.fds,
+        summary.point.idle_threads,
+        summary.reps,
+        summary.outliers,
+        json_summary(&summary.efficiency),
+        summary.efficiency.cv(),
+        summary.noisy,
+        json_summary(&summary.forks_per_second),
+        json_summary(&summary.forks_per_second_per_thread),
+        json_summary(&summary.ns_per_fork),
+        summary.crashes)
+}
+
+/// Render test point `point` which failed with the rendered `error` as a
+/// JSON object
+pub fn json_failure(point: &TestPoint, error: &str) -> String {
+    format!("{{\"threads\": {}, \"effective_workload_insts\": {}, \
+        \"prefork_memory_mib\": {}, \"vmas\": {}, \"fds\": {}, \
+        \"idle_threads\": {}, \"error\": {}}}",
+        point.threads,
+        point.workload * INSTS_PER_WORKLOAD,
+        point.memory_mib,
+        point.vmas,
+        point.fds,
+        point.idle_threads,
+        json_string(error))
+}
+
+/// Render a throughput sample of `--soak` as a JSON object
+pub fn json_sample(sample: &Sample) -> String {
+    format!("{{\"rep\": {}, \"secs\": {}, \"forks\": {}, \
+        \"forks_per_second\": {}, \"forks_per_second_per_thread\": {}, \
+        \"efficiency_ratio\": {}, \"fork_failures\": {}}}",
+        sample.rep,
+        sample.secs,
+        sample.forks,
+        sample.forks_per_second,
+        sample.forks_per_second_per_thread,
+        sample.efficiency,
+        sample.fork_failures)
+}
+
+/// Render the members of the `metadata` object of JSON output
+pub fn json_metadata(metadata: &RunMetadata) -> Vec<(&'static str, String)> {
+    vec![
+        ("kernel_version", json_string(&metadata.kernel_version)),
+        ("cpu_model", json_string(&metadata.cpu_model)),
+        ("microcode", json_string(&metadata.microcode)),
+        ("smt", json_string(&metadata.smt)),
+        ("governor", json_string(&metadata.governor)),
+        ("transparent_hugepage",
+            json_string(&metadata.transparent_hugepage)),
+        ("mitigations", json_mitigations(metadata)),
+        ("sockets", metadata.sockets.to_string()),
+        ("numa_nodes", metadata.numa_nodes.to_string()),
+        ("physical_cores", metadata.physical_cores.to_string()),
+        ("efficiency_processors",
+            metadata.efficiency_processors.to_string()),
+        ("logical_processors", metadata.logical_processors.to_string()),
+        ("tsc_frequency", metadata.tsc_frequency.to_string()),
+        ("tsc_source", json_string(metadata.tsc_source)),
+        ("invariant_tsc", metadata.invariant_tsc.to_string()),
+    ]
+}
+
+/// Format the CPU vulnerability mitigations of `metadata` as a nested JSON
+/// object
+fn json_mitigations(metadata: &RunMetadata) -> String {
+    if metadata.mitigations.is_empty() {
+        return String::from("{}");
+    }
+
+    let mut ret = String::from("{\n");
+    for (ii, (name, state)) in metadata.mitigations.iter().enumerate() {
+        ret.push_str(&format!("      {}: {}{}\n", json_string(name),
+            json_string(state),
+            if ii + 1 < metadata.mitigations.len() { "," } else { "" }));
+    }
+    ret.push_str("    }");
+    ret
+}
+
+impl Output {
+    /// Create a new output based on the configuration, writing to stdout if
+    /// no output file was requested
+    pub fn new(config: &Config) -> io::Result<Self> {
+        let mut writer: Box<dyn Write> = match &config.output_file {
+            Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+            None       => Box::new(io::stdout()),
+        };
+
+        // Results are flushed after every test, which syncs them to disk
+        if let Some(path) = &config.out_file {
+            let file = OpenOptions::new().create(true).append(true)
+                .open(path)?;
+            writer = Box::new(Tee(writer,
+                Box::new(BufWriter::new(SyncedFile(file)))));
+        }
+
+        let mut ret = Output {
+            format: config.output_format,
+            writer,
+            results:   0,
+            histogram: config.histogram,
+            perf:      config.perf,
+            cpu_frequency: config.cpu_frequency,
+            trace_fork: config.trace_fork,
+            summaries: Vec::new(),
+            failures:  Vec::new(),
+            samples:   config.soak_secs.map(|_| Vec::new()),
+            deferred:  config.shuffle_seed
+                .filter(|_| config.output_format != OutputFormat::Jsonl)
+                .map(|_| Vec::new()),
+        };
+
+        // Every result carries the values of all axes, the header declares
+        // which of them vary
+        let axes = sweep::swept_axes(config).iter().map(|x| x.name())
+            .collect::<Vec<_>>();
+
+        match ret.format {
+            OutputFormat::Text => {
+                // Commented out such that the results stay plottable
+                write!(ret.writer, "# swept axes: {}\n", axes.join(" "))?;
+                if let Some(seed) = config.shuffle_seed {
+                    write!(ret.writer, "# shuffle seed: {}\n", seed)?;
+                }
+                if config.stats_placement != StatsPlacement::Default {
+                    write!(ret.writer, "# statistics on NUMA node {} ({})\n",
+                           config.stats_node().unwrap_or(0),
+                           config.stats_placement.name())?;
+                }
+            }
+            OutputFormat::Csv => {
+                write!(ret.writer, "threads,effective_workload_insts,\
+                    prefork_memory_mib,vmas,fds,idle_threads,efficiency_ratio,\
+                    elapsed_cycles,vm_cycles,fuzz_cases,forks,elapsed_ns,\
+                    forks_per_second,forks_per_second_per_thread,\
+                    ns_per_fork")?;
+                for (name, _) in PERCENTILES {
+                    write!(ret.writer, ",{}_ns", name)?;
+                }
+                write!(ret.writer, ",rep,outlier,creation_ns,workload_ns,\
+                    reap_ns,crashes,fork_attempts,fork_failures,fork_errno,\
+                    latency_min_ns,latency_max_ns,{}",
+                    usage::NAMES.join(","))?;
+                if ret.cpu_frequency {
+                    write!(ret.writer, ",{}", frequency::NAMES.join(","))?;
+                }
+                if ret.trace_fork {
+                    write!(ret.writer, ",{}", trace::NAMES.join(","))?;
+                }
+                if ret.perf {
+                    // Prefixed as some overlap with the resource usage
+                    for name in perf::NAMES.iter() {
+                        write!(ret.writer, ",perf_{}", name)?;
+                    }
+                }
+                write!(ret.writer, "\n")?;
+            }
+            OutputFormat::Json => {
+                let metadata = RunMetadata::collect(&config.topology);
+
+                write!(ret.writer, "{{\n")?;
+                write_json_object(&mut ret.writer, "metadata",
+                                  &json_metadata(&metadata))?;
+                write_json_object(&mut ret.writer, "config", &[
+                    ("swept_axes", format!("[{}]", axes.iter()
+                        .map(|x| json_string(x))
+                        .collect::<Vec<_>>().join(", "))),
+                    ("thread_samples", config.thread_samples.to_string()),
+                    ("workload_samples", config.workload_samples.to_string()),
+                    ("spacing", json_string(config.spacing.name())),
+                    ("threads", config.thread_counts.as_ref()
+                        .map(|x| json_string(&x.to_string()))
+                        .unwrap_or_else(|| "null".into())),
+                    ("adaptive", config.adaptive.to_string()),
+                    ("search_efficiency", config.search_efficiency
+                        .map(|x| x.to_string())
+                        .unwrap_or_else(|| "null".into())),
+                    ("point", config.point
+                        .map(|x| json_string(&x.to_string()))
+                        .unwrap_or_else(|| "null".into())),
+                    ("soak_secs", config.soak_secs.map(|x| x.to_string())
+                        .unwrap_or_else(|| "null".into())),
+                    ("soak_interval", config.soak_interval.to_string()),
+                    ("outliers", config.outliers.map(|x| x.to_string())
+                        .unwrap_or_else(|| "null".into())),
+                    ("noise_threshold", config.noise_threshold.to_string()),
+                    ("replay", config.replay.as_ref()
+                        .map(|x| json_string(&x.path))
+                        .unwrap_or_else(|| "null".into())),
+                    ("filter", config.replay.as_ref()
+                        .and_then(|x| x.filter.as_ref())
+                        .map(|x| json_string(&x.to_string()))
+                        .unwrap_or_else(|| "null".into())),
+                    ("workloads", config.workloads.as_ref()
+                        .map(|x| format!("[{}]", x.iter()
+                            .map(|x| x.to_string())
+                            .collect::<Vec<_>>().join(", ")))
+                        .unwrap_or_else(|| "null".into())),
+                    ("max_threads", config.max_threads.to_string()),
+                    ("max_workload", config.max_workload.to_string()),
+                    ("numa_node", config.numa_node.map(|x| x.to_string())
+                        .unwrap_or_else(|| "null".into())),
+                    ("cores", json_string(config.cores.name())),
+                    ("pinning", json_string(config.pinning.name())),
+                    ("cpus", config.cpus.as_ref()
+                        .map(|x| format!("[{}]", x.iter()
+                            .map(|x| x.to_string())
+                            .collect::<Vec<_>>().join(", ")))
+                        .unwrap_or_else(|| "null".into())),
+                    ("sched_policy",
+                        json_string(config.sched_policy.name())),
+                    ("sched_priority", config.sched_priority.to_string()),
+                    ("nice", config.nice.to_string()),
+                    ("mbind_node", config.mbind_node.map(|x| x.to_string())
+                        .unwrap_or_else(|| "null".into())),
+                    ("stats_placement",
+                        json_string(config.stats_placement.name())),
+                    ("stats_node", config.stats_node()
+                        .map(|x| x.to_string())
+                        .unwrap_or_else(|| "null".into())),
+                    ("shm_dir", config.shm_dir.as_deref()
+                        .map(json_string).unwrap_or_else(|| "null".into())),
+                    ("cgroup", config.cgroup.as_deref()
+                        .map(json_string).unwrap_or_else(|| "null".into())),
+                    ("cgroup_depth", config.cgroup_depth.to_string()),
+                    ("cgroup_cpus", config.cgroup_cpus.map(|x| x.to_string())
+                        .unwrap_or_else(|| "null".into())),
+                    ("cgroup_memory_mib", config.cgroup_memory
+                        .map(|x| x.to_string())
+                        .unwrap_or_else(|| "null".into())),
+                    ("duration_ticks", config.duration_ticks().to_string()),
+                    ("iterations", config.iterations.map(|x| x.to_string())
+                        .unwrap_or_else(|| "null".into())),
+                    ("warmup_ticks", config.warmup.to_string()),
+                    ("reps", config.reps.to_string()),
+                    ("timeout_secs", config.timeout_secs
+                        .map(|x| x.to_string())
+                        .unwrap_or_else(|| "null".into())),
+                    ("shuffle_seed", config.shuffle_seed
+                        .map(|x| x.to_string())
+                        .unwrap_or_else(|| "null".into())),
+                    ("clock", json_string(clock::clock().name())),
+                    ("bench_overhead", config.bench_overhead.to_string()),
+                    ("backend", json_string(config.backend.name())),
+                    ("exec_path", config.exec_path.as_deref()
+                        .map(json_string).unwrap_or_else(|| "null".into())),
+                    ("clone_flags", json_string(
+                        &backend::clone_flags_names(config.clone_flags))),
+                    ("unshare", json_string(
+                        &backend::namespaces_names(config.unshare))),
+                    ("pidfd_wait", json_string(config.pidfd_wait.name())),
+                    ("criu_images", config.criu_images.as_deref()
+                        .map(json_string).unwrap_or_else(|| "null".into())),
+                    ("criu_path", json_string(&config.criu_path)),
+                    ("pool_size", config.pool_size.to_string()),
+                    ("snapshot_reset",
+                        json_string(config.snapshot_reset.name())),
+                    ("reaper", json_string(config.reaper.name())),
+                    ("in_flight", config.in_flight.to_string()),
+                    ("ptrace", json_string(config.ptrace.name())),
+                    ("ptrace_syscalls", config.ptrace_syscalls.to_string()),
+                    ("pipe_handshake", config.pipe_handshake.to_string()),
+                    ("ipc", json_string(config.ipc.name())),
+                    ("ipc_size", config.ipc_size.to_string()),
+                    ("dirty_memory", config.dirty_memory
+                        .map(|x| x.to_string())
+                        .unwrap_or_else(|| "null".into())),
+                    ("cow_pages", config.cow_pages.to_string()),
+                    ("perf", config.perf.to_string()),
+                    ("cpu_frequency", config.cpu_frequency.to_string()),
+                    ("trace_fork", config.trace_fork.to_string()),
+                    ("perf_record", config.perf_record.as_deref()
+                        .map(json_string)
+                        .unwrap_or_else(|| "null".into())),
+                    ("workload_kind",
+                        json_string(config.workload_kind.name())),
+                    ("working_set", config.working_set.to_string()),
+                    ("stride", config.stride.to_string()),
+                    ("huge_pages", json_string(config.huge_pages.name())),
+                    ("fork_advice", json_string(config.fork_advice.name())),
+                    ("fork_advice_mib", config.fork_advice_mib
+                        .map(|x| x.to_string())
+                        .unwrap_or_else(|| "null".into())),
+                    ("memory_samples", config.memory_samples.to_string()),
+                    ("max_memory_mib", config.max_memory_mib.to_string()),
+                    ("vma_samples", config.vma_samples.to_string()),
+                    ("max_vmas", config.max_vmas.to_string()),
+                    ("fd_samples", config.fd_samples.to_string()),
+                    ("max_fds", config.max_fds.to_string()),
+                    ("idle_thread_samples",
+                        config.idle_thread_samples.to_string()),
+                    ("max_idle_threads", config.max_idle_threads.to_string()),
+                ])?;
+                write!(ret.writer, "  \"results\": [")?;
+            }
+            OutputFormat::Jsonl => {
+                // Every line stands on its own, and each of them carries
+                // its test point
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// Report the result of a single test
+    pub fn result(&mut self, result: &TestResult) -> io::Result<()> {
+        if let Some(deferred) = &mut self.deferred {
+            deferred.push(Deferred::Result(Box::new(result.clone())));
+            return Ok(());
+        }
+
+        self.write_result(result)
+    }
+
+    /// Write the result of a single test
+    fn write_result(&mut self, result: &TestResult) -> io::Result<()> {
+        match self.format {
+            OutputFormat::Text => {
+                write!(self.writer, "{:10} {:14} {:12.6} {:12.6} {:8} \
+                    {:8} {:8} {:8} {:14.2} {:12.1}",
+                       result.point.threads,
+                       result.effective_workload(),
+                       result.efficiency(),
+                       result.fcps_per_thread,
+                       result.point.memory_mib,
+                       result.point.vmas,
+                       result.point.fds,
+                       result.point.idle_threads,
+                       result.forks_per_second(),
+                       result.ns_per_fork())?;
+                for (_, quantile) in PERCENTILES {
+                    write!(self.writer, " {:12.1}",
+                           result.latency_percentile_ns(*quantile))?;
+                }
+                write!(self.writer, " {:12.1} {:12.1} {:12.1} {:8} {:12} \
+                    {:14.2} {:12} {:8} {:12.1} {:12.1}",
+                       result.creation_ns(), result.workload_ns(),
+                       result.reap_ns(), result.crashes, result.forks,
+                       result.forks_per_second_per_thread(),
+                       result.attempts, result.fork_failures,
+                       result.latency_min_ns(), result.latency_max_ns())?;
+                for field in result.usage.fields() {
+                    write!(self.writer, " {:14}", field)?;
+                }
+                if self.cpu_frequency {
+                    for field in frequency_fields(result.frequency, "-") {
+                        write!(self.writer, " {:>8}", field)?;
+                    }
+                }
+                if self.trace_fork {
+                    for field in timing_fields(result.kernel, "-") {
+                        write!(self.writer, " {:>12}", field)?;
+                    }
+                }
+                if self.perf {
+                    for &count in result.perf.iter().flatten() {
+                        write!(self.writer, " {:>14}",
+                               perf_count(count, "-"))?;
+                    }
+                }
+                if result.outlier {
+                    // Commented out such that the results stay plottable
+                    write!(self.writer, " # outlier")?;
+                }
+                write!(self.writer, "\n")?;
+                if self.histogram {
+                    write!(self.writer, "{}", result.latency.render("    "))?;
+                }
+            }
+            OutputFormat::Csv => {
+                write!(self.writer, "{},{},{},{},{},{},{:.6},{},{},{},{},\
+                    {:.0},{:.2},{:.2},{:.1}",
+                       result.point.threads,
+                       result.effective_workload(),
+                       result.point.memory_mib,
+                       result.point.vmas,
+                       result.point.fds,
+                       result.point.idle_threads,
+                       result.efficiency(),
+                       result.elapsed_cycles,
+                       result.vm_cycles,
+                       result.fuzz_cases,
+                       result.forks,
+                       result.elapsed_ns(),
+                       result.forks_per_second(),
+                       result.forks_per_second_per_thread(),
+                       result.ns_per_fork())?;
+                for (_, quantile) in PERCENTILES {
+                    write!(self.writer, ",{:.1}",
+                           result.latency_percentile_ns(*quantile))?;
+                }
+                write!(self.writer, ",{},{},{:.1},{:.1},{:.1},{},{},{},{},\
+                    {:.1},{:.1}", result.rep, result.outlier as u8,
+                       result.creation_ns(), result.workload_ns(),
+                       result.reap_ns(), result.crashes, result.attempts,
+                       result.fork_failures,
+                       result.fork_errno.map(|x| x.to_string())
+                           .unwrap_or_default(),
+                       result.latency_min_ns(), result.latency_max_ns())?;
+                for field in result.usage.fields() {
+                    write!(self.writer, ",{}", field)?;
+                }
+                if self.cpu_frequency {
+                    for field in frequency_fields(result.frequency, "") {
+                        write!(self.writer, ",{}", field)?;
+                    }
+                }
+                if self.trace_fork {
+                    for field in timing_fields(result.kernel, "") {
+                        write!(self.writer, ",{}", field)?;
+                    }
+                }
+                if self.perf {
+                    for &count in result.perf.iter().flatten() {
+                        write!(self.writer, ",{}", perf_count(count, ""))?;
+                    }
+                }
+                write!(self.writer, "\n")?;
+            }
+            OutputFormat::Json => {
+                write!(self.writer, "{}\n    {}",
+                       if self.results > 0 { "," } else { "" },
+                       json_result(result, self.cpu_frequency))?;
+            }
+            OutputFormat::Jsonl => {
+                write!(self.writer, "{{\"type\": \"result\", \"result\": \
+                    {}}}\n", json_result(result, self.cpu_frequency))?;
+            }
+        }
+
+        self.results += 1;
+        self.writer.flush()
+    }
+
+    /// Report the summary of all repetitions of a test point
+    pub fn summary(&mut self, summary: &PointSummary) -> io::Result<()> {
+        if let Some(deferred) = &mut self.deferred {
+            deferred.push(Deferred::Summary(summary.clone()));
+            return Ok(());
+        }
+
+        self.write_summary(summary)
+    }
+
+    /// Write the summary of all repetitions of a test point
+    fn write_summary(&mut self, summary: &PointSummary) -> io::Result<()> {
+        match self.format {
+            OutputFormat::Text if summary.reps == 1 => {
+                // Nothing to summarize over a single repetition
+            }
+            OutputFormat::Text => {
+                // Commented out such that the results stay plottable
+                write!(self.writer, "# threads {} workload {} memory {} \
+                    vmas {} fds {} idle threads {} reps {} outliers {}: \
+                    efficiency {:.6} +- {:.6} (stddev {:.6}, cv {:.2}%{}), \
+                    forks/s {:.2} +- {:.2} (stddev {:.2}), crashes {}\n",
+                       summary.point.threads,
+                       summary.effective_workload(),
+                       summary.point.memory_mib,
+                       summary.point.vmas,
+                       summary.point.fds,
+                       summary.point.idle_threads,
+                       summary.reps,
+                       summary.outliers,
+                       summary.efficiency.mean,
+                       summary.efficiency.ci95,
+                       summary.efficiency.stddev,
+                       summary.efficiency.cv() * 100.,
+                       if summary.noisy { ", noisy" } else { "" },
+                       summary.forks_per_second.mean,
+                       summary.forks_per_second.ci95,
+                       summary.forks_per_second.stddev,
+                       summary.crashes)?;
+            }
+            OutputFormat::Csv => {
+                // Every repetition is in the results, the summary can be
+                // recomputed from them
+            }
+            OutputFormat::Json => {
+                self.summaries.push(json_point_summary(summary));
+            }
+            OutputFormat::Jsonl => {
+                write!(self.writer, "{{\"type\": \"summary\", \
+                    \"summary\": {}}}\n", json_point_summary(summary))?;
+            }
+        }
+
+        self.writer.flush()
+    }
+
+    /// Report that a test point failed with `error`. Text and CSV output
+    /// leave it to the error printed by the caller, such that the results
+    /// stay machine readable.
+    pub fn failure(&mut self, point: &TestPoint, error: &Error)
+            -> io::Result<()> {
+        match &mut self.deferred {
+            Some(deferred) => {
+                deferred.push(Deferred::Failure(*point, error.to_string()));
+                Ok(())
+            }
+            None => self.write_failure(point, &error.to_string()),
+        }
+    }
+
+    /// Write that a test point failed with the rendered `error`
+    fn write_failure(&mut self, point: &TestPoint, error: &str)
+            -> io::Result<()> {
+        match self.format {
+            OutputFormat::Json => {
+                self.failures.push(json_failure(point, error));
+            }
+            OutputFormat::Jsonl => {
+                write!(self.writer, "{{\"type\": \"failure\", \
+                    \"failure\": {}}}\n", json_failure(point, error))?;
+                self.writer.flush()?;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Report the throughput samples taken while soaking. They're only
+    /// taken of a single test point, so they're never held back.
+    pub fn samples(&mut self, samples: &[Sample]) -> io::Result<()> {
+        for sample in samples {
+            match self.format {
+                OutputFormat::Text => {
+                    // Commented out such that the results stay plottable
+                    write!(self.writer, "# sample rep {} secs {:.1}: forks \
+                        {} forks/s {:.2} forks/s/worker {:.2} efficiency \
+                        {:.6} failures {}\n", sample.rep, sample.secs,
+                        sample.forks, sample.forks_per_second,
+                        sample.forks_per_second_per_thread, sample.efficiency,
+                        sample.fork_failures)?;
+                }
+                OutputFormat::Csv => {
+                    // Rows are results, samples don't fit in with them
+                }
+                OutputFormat::Json => {
+                    if let Some(samples) = &mut self.samples {
+                        samples.push(json_sample(sample));
+                    }
+                }
+                OutputFormat::Jsonl => {
+                    write!(self.writer, "{{\"type\": \"sample\", \
+                        \"sample\": {}}}\n", json_sample(sample))?;
+                }
+            }
+        }
+
+        self.writer.flush()
+    }
+
+    /// Finish writing results, closing out any open structure
+    pub fn finish(mut self) -> io::Result<()> {
+        // Write out the held back reports in the order of their test points,
+        // the sort is stable so results stay ahead of their summaries
+        if let Some(mut deferred) = self.deferred.take() {
+            deferred.sort_by_key(Deferred::point);
+            for report in &deferred {
+                match report {
+                    Deferred::Result(result)   => self.write_result(result)?,
+                    Deferred::Summary(summary) =>
+                        self.write_summary(summary)?,
+                    Deferred::Failure(point, error) =>
+                        self.write_failure(point, error)?,
+                }
+            }
+        }
+
+        if self.format == OutputFormat::Json {
+            write!(self.writer, "\n  ],\n  \"summaries\": [")?;
+            for (ii, summary) in self.summaries.iter().enumerate() {
+                write!(self.writer, "{}\n    {}",
+                       if ii > 0 { "," } else { "" }, summary)?;
+            }
+            write!(self.writer, "\n  ],\n  \"failures\": [")?;
+            for (ii, failure) in self.failures.iter().enumerate() {
+                write!(self.writer, "{}\n    {}",
+                       if ii > 0 { "," } else { "" }, failure)?;
+            }
+            write!(self.writer, "\n  ]")?;
+            if let Some(samples) = &self.samples {
+                write!(self.writer, ",\n  \"samples\": [")?;
+                for (ii, sample) in samples.iter().enumerate() {
+                    write!(self.writer, "{}\n    {}",
+                           if ii > 0 { "," } else { "" }, sample)?;
+                }
+                write!(self.writer, "\n  ]")?;
+            }
+            write!(self.writer, "\n}}\n")?;
+        }
+
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,145 @@
+//! Hardware and software event counters of the workers and everything they
+//! create, collected with `perf_event_open()` on Linux
+//!
+//! Each worker opens its counters with `inherit` set before creating any
+//! fuzz cases, such that the counts of every (exited) descendant are folded
+//! into them. Counters which can't be opened (eg. no PMU in a VM, or a
+//! restrictive `perf_event_paranoid`) are reported as unavailable.
+
+/// Number of counters collected
+pub const COUNTERS: usize = 5;
+
+/// Names of the counters, as reported in the results
+pub const NAMES: [&str; COUNTERS] = [
+    "context_switches",
+    "minor_faults",
+    "major_faults",
+    "cache_misses",
+    "instructions",
+];
+
+/// Counts of all counters of a test, `None` for counters which were
+/// unavailable
+pub type Counts = [Option<u64>; COUNTERS];
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use libc::*;
+
+    use super::COUNTERS;
+
+    /// `perf_event_attr.type` of generalized hardware events
+    const PERF_TYPE_HARDWARE: u32 = 0;
+
+    /// `perf_event_attr.type` of software events
+    const PERF_TYPE_SOFTWARE: u32 = 1;
+
+    /// `perf_event_attr.flags` bit to create the counter disabled
+    const FLAG_DISABLED: u64 = 1 << 0;
+
+    /// `perf_event_attr.flags` bit to also count descendants created later
+    const FLAG_INHERIT: u64 = 1 << 1;
+
+    /// `ioctl()` enabling a counter
+    const PERF_EVENT_IOC_ENABLE: c_ulong = 0x2400;
+
+    /// `perf_event_open()` flag to create the file descriptor close-on-exec
+    const PERF_FLAG_FD_CLOEXEC: c_ulong = 1 << 3;
+
+    /// Type and config of each counter, in the order of `NAMES`
+    const EVENTS: [(u32, u64); COUNTERS] = [
+        (PERF_TYPE_SOFTWARE, 3), // PERF_COUNT_SW_CONTEXT_SWITCHES
+        (PERF_TYPE_SOFTWARE, 5), // PERF_COUNT_SW_PAGE_FAULTS_MIN
+        (PERF_TYPE_SOFTWARE, 6), // PERF_COUNT_SW_PAGE_FAULTS_MAJ
+        (PERF_TYPE_HARDWARE, 3), // PERF_COUNT_HW_CACHE_MISSES
+        (PERF_TYPE_HARDWARE, 1), // PERF_COUNT_HW_INSTRUCTIONS
+    ];
+
+    /// First version of `struct perf_event_attr`, which has everything we
+    /// need. Not exposed by the `libc` crate.
+    #[repr(C)]
+    #[derive(Default)]
+    struct PerfEventAttr {
+        typ:            u32,
+        size:           u32,
+        config:         u64,
+        sample_period:  u64,
+        sample_type:    u64,
+        read_format:    u64,
+        flags:          u64,
+        wakeup_events:  u32,
+        bp_type:        u32,
+        config1:        u64,
+    }
+
+    /// Counters of the calling worker and its descendants
+    pub struct Counters {
+        /// File descriptor of each counter, `None` if it's unavailable
+        fds: [Option<c_int>; COUNTERS],
+    }
+
+    impl Counters {
+        /// Open all counters of the calling thread, disabled until
+        /// `enable()` is called
+        pub fn open() -> Self {
+            let mut fds = [None; COUNTERS];
+            for (fd, &(typ, config)) in fds.iter_mut().zip(EVENTS.iter()) {
+                let attr = PerfEventAttr {
+                    typ,
+                    size:  core::mem::size_of::<PerfEventAttr>() as u32,
+                    config,
+                    flags: FLAG_DISABLED | FLAG_INHERIT,
+                    ..Default::default()
+                };
+
+                let ret = unsafe {
+                    syscall(SYS_perf_event_open, &attr, 0 as pid_t,
+                            -1 as c_int, -1 as c_int, PERF_FLAG_FD_CLOEXEC)
+                };
+                if ret >= 0 {
+                    *fd = Some(ret as c_int);
+                }
+            }
+
+            Counters { fds }
+        }
+
+        /// Start counting
+        pub fn enable(&self) {
+            for fd in self.fds.iter().flatten() {
+                unsafe { ioctl(*fd, PERF_EVENT_IOC_ENABLE, 0); }
+            }
+        }
+
+        /// Read the counts so far, `None` for unavailable counters
+        pub fn read(&self) -> super::Counts {
+            let mut ret = [None; COUNTERS];
+            for (count, fd) in ret.iter_mut().zip(self.fds.iter()) {
+                let fd = match fd {
+                    Some(fd) => *fd,
+                    None     => continue,
+                };
+
+                let mut value = 0u64;
+                let bytes = unsafe {
+                    read(fd, &mut value as *mut u64 as *mut c_void, 8)
+                };
+                if bytes == 8 {
+                    *count = Some(value);
+                }
+            }
+            ret
+        }
+    }
+
+    impl Drop for Counters {
+        fn drop(&mut self) {
+            for fd in self.fds.iter().flatten() {
+                unsafe { close(*fd); }
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub use linux::Counters;
@@ -0,0 +1,114 @@
+//! Plan of a sweep printed by `--dry-run` instead of running it
+//!
+//! The test matrix is expanded like for a run, and for each thread count the
+//! logical processors its workers would be pinned to are listed. The
+//! duration is estimated from the duration and warmup of each test, bounded
+//! by `--timeout`, without forking anything.
+
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+use std::time::Duration;
+
+use crate::clock;
+use crate::config::Config;
+use crate::output::INSTS_PER_WORKLOAD;
+use crate::progress::hms;
+use crate::sweep;
+
+/// Format logical processors as a Linux style CPU list (eg. `0-3,8`),
+/// collapsing runs of consecutive processors
+fn cpu_list(cpus: &[usize]) -> String {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &cpu in cpus {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == cpu => *end = cpu,
+            _ => ranges.push((cpu, cpu)),
+        }
+    }
+
+    ranges.iter().map(|&(start, end)| if start == end {
+        start.to_string()
+    } else {
+        format!("{}-{}", start, end)
+    }).collect::<Vec<_>>().join(",")
+}
+
+/// Write the plan of the sweep described by `config` to `writer`
+pub fn write(writer: &mut dyn Write, config: &Config) -> io::Result<()> {
+    // Durations are given in ticks of the clock, which has to be calibrated
+    // to know how long they take
+    if !clock::is_selected() {
+        clock::select(config.clock);
+    }
+    let secs = |ticks: u64| clock::cycles_to_ns(ticks) / 1e9;
+
+    let tests = sweep::run_order(config, &sweep::test_points(config));
+    let axes = sweep::swept_axes(config).iter().map(|x| x.name())
+        .collect::<Vec<_>>();
+
+    write!(writer, "# dry run of {} test points, {} repetitions each ({} \
+        tests)\n", tests.len(), config.reps, tests.len() * config.reps)?;
+    write!(writer, "# swept axes: {}\n", axes.join(" "))?;
+    if let Some(seed) = config.shuffle_seed {
+        write!(writer, "# shuffle seed: {}\n", seed)?;
+    }
+    if let Some(ratio) = config.search_efficiency {
+        write!(writer, "# the thread counts between these are bisected \
+            until the most keeping an efficiency of {} are found\n",
+            ratio)?;
+    }
+    if let Some(replay) = &config.replay {
+        write!(writer, "# replaying test points of `{}`, the results are \
+            merged into it\n", replay.path)?;
+    }
+    if config.adaptive > 0 {
+        write!(writer, "# up to {} rounds of test points around the knee \
+            follow, not included below\n", config.adaptive)?;
+    }
+
+    // Test points in the order they would run
+    write!(writer, "{:>10} {:>14} {:>8} {:>8} {:>8} {:>8}\n", "threads",
+           "workload", "memory", "vmas", "fds", "idle")?;
+    for point in &tests {
+        write!(writer, "{:10} {:14} {:8} {:8} {:8} {:8}\n", point.threads,
+               point.workload * INSTS_PER_WORKLOAD, point.memory_mib,
+               point.vmas, point.fds, point.idle_threads)?;
+    }
+
+    // Worker `n` is pinned to the `n`th selected logical processor
+    write!(writer, "\n{:>10} {:>8}  cpus\n", "threads", "nodes")?;
+    let threads: BTreeSet<usize> =
+        tests.iter().map(|x| x.threads as usize).collect();
+    for &threads in &threads {
+        let workers = &config.processors[..threads];
+        let nodes: BTreeSet<u16> = workers.iter().map(|x| x.numa_id())
+            .collect();
+        write!(writer, "{:10} {:8}  {}\n", threads, nodes.len(),
+               cpu_list(&workers.iter().map(|x| x.cpu())
+                   .collect::<Vec<_>>()))?;
+    }
+
+    // Each test runs for its warmup and duration, unless it runs a number
+    // of iterations, and never for longer than the timeout
+    let count = (tests.len() * config.reps) as f64;
+    let test = config.iterations.is_none()
+        .then(|| secs(config.warmup) + secs(config.duration_ticks()));
+    let test = match (test, config.timeout_secs) {
+        (Some(test), Some(timeout)) => Some(test.min(timeout)),
+        (test, _) => test,
+    };
+    write!(writer, "\n")?;
+    match test {
+        Some(test) => write!(writer, "# estimated duration: {} ({:.3} s \
+            per test, not including setup)\n",
+            hms(Duration::from_secs_f64(test * count)), test)?,
+        None => write!(writer, "# estimated duration: unknown, tests run \
+            a number of iterations\n")?,
+    }
+    if let Some(timeout) = config.timeout_secs {
+        write!(writer, "# at most: {} ({:.3} s timeout per test)\n",
+               hms(Duration::from_secs_f64(timeout * count)), timeout)?;
+    }
+
+    writer.flush()
+}
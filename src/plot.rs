@@ -0,0 +1,253 @@
+//! Generation of ready-to-run gnuplot heatmaps of sweep results, and of
+//! heatmaps drawn right in the terminal
+//!
+//! Sweeps write them with `--gnuplot` and draw them with `--heatmap`,
+//! `forkbench plot` renders the results file of an earlier run the same
+//! ways.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, IsTerminal, Write, BufWriter};
+use std::path::Path;
+
+use crate::output::PointSummary;
+
+/// Usage string printed for `plot --help` and on argument errors
+pub const USAGE: &str = r#"usage: forkbench plot [options] <results> <script>
+       forkbench plot --terminal <results>
+
+Writes a gnuplot script to <script> which renders the efficiency heatmap of
+a results file written with `--output text`, `csv`, `json` or `jsonl`, like
+`--gnuplot` does for a sweep. `gnuplot <script>` renders it to a PNG next to
+the script.
+
+options:
+    --terminal                  Draw the heatmap on stdout instead, like
+                                `--heatmap` does for a sweep
+    --noise-threshold <pct>     Mark test points whose efficiency varies by
+                                more than <pct> percent across repetitions
+                                as noisy (default 5)
+    --help                      Print this message
+"#;
+
+/// Most columns of a heatmap drawn in the terminal, more workloads are
+/// binned together
+const TERMINAL_COLUMNS: usize = 72;
+
+/// Shades of a heatmap drawn without colors, from lowest to highest
+/// efficiency
+const SHADES: [char; 5] = [' ', '░', '▒', '▓', '█'];
+
+/// Position of `efficiency` on the log scale of the heatmaps, from 0.0 (an
+/// efficiency of 0.01 or less) to 1.0 (no overhead)
+pub(crate) fn level(efficiency: f64) -> f64 {
+    ((efficiency.max(0.01).log10() + 2.) / 2.).clamp(0., 1.)
+}
+
+/// Glyph of a cell of a noisy test point drawn without colors, which has
+/// no shade to spare
+const NOISY: char = '?';
+
+/// Render a cell of a heatmap drawn in the terminal at `level` (0.0 to
+/// 1.0), a full block on the red to green ramp of the 256 color palette or a
+/// shade if `color` isn't set. Cells of `noisy` test points are a medium
+/// shade on the ramp, or `NOISY` without colors.
+fn cell(level: f64, color: bool, noisy: bool) -> String {
+    if !color {
+        if noisy {
+            return NOISY.to_string();
+        }
+        return SHADES[(level * (SHADES.len() - 1) as f64).round() as usize]
+            .to_string();
+    }
+
+    // Red through yellow to green
+    let (red, green) = if level < 0.5 {
+        (5, (level * 10.).round() as u8)
+    } else {
+        (((1. - level) * 10.).round() as u8, 5)
+    };
+    format!("\x1b[38;5;{}m{}\x1b[0m", 16 + 36 * red + 6 * green,
+            if noisy { '▒' } else { '█' })
+}
+
+/// Draw the mean efficiency over the (threads, workload) grid of `results`
+/// as a heatmap of unicode blocks, with colors if `color` is set. Test
+/// points differing in other dimensions are averaged, as are neighboring
+/// workloads if there are too many to fit. Cells with a noisy test point are
+/// marked as such.
+pub fn write_terminal(writer: &mut dyn Write, results: &[PointSummary],
+                      color: bool) -> io::Result<()> {
+    let mut grid: BTreeMap<(u64, u64), Vec<(f64, bool)>> = BTreeMap::new();
+    for result in results {
+        grid.entry((result.point.threads, result.effective_workload()))
+            .or_default().push((result.efficiency.mean, result.noisy));
+    }
+    let mut workloads: Vec<u64> = grid.keys().map(|x| x.1).collect();
+    workloads.sort();
+    workloads.dedup();
+    let mut threads: Vec<u64> = grid.keys().map(|x| x.0).collect();
+    threads.dedup();
+    if workloads.is_empty() {
+        return Ok(());
+    }
+
+    // Column each workload is drawn in
+    let columns = workloads.len().min(TERMINAL_COLUMNS);
+    let column = |workload: u64| {
+        workloads.iter().position(|&x| x == workload).unwrap() * columns /
+            workloads.len()
+    };
+    let width = if columns * 2 <= TERMINAL_COLUMNS { 2 } else { 1 };
+
+    write!(writer, "efficiency heatmap, rows are threads, columns are {} \
+        workloads from {} to {} instructions\n", workloads.len(),
+        workloads[0], workloads[workloads.len() - 1])?;
+
+    // Most threads at the top, like the gnuplot heatmap
+    for &thread_count in threads.iter().rev() {
+        let mut cells: Vec<Vec<(f64, bool)>> = vec![Vec::new(); columns];
+        for ((_, workload), values) in grid.range((thread_count, 0)..=
+                                                  (thread_count, !0)) {
+            cells[column(*workload)].extend(values);
+        }
+
+        let mut line = format!("{:>8} ", thread_count);
+        for values in &cells {
+            let glyph = if values.is_empty() {
+                String::from(" ")
+            } else {
+                let mean = values.iter().map(|x| x.0).sum::<f64>() /
+                    values.len() as f64;
+                cell(level(mean), color, values.iter().any(|x| x.1))
+            };
+            line += &glyph.repeat(width);
+        }
+        write!(writer, "{}\n", line)?;
+    }
+
+    // Legend of the scale
+    let ramp: String = (0..=10).map(|x| cell(x as f64 / 10., color, false))
+        .collect();
+    write!(writer, "{:>8} 0.01 {} 1.0 efficiency (log scale)\n", "", ramp)?;
+    if results.iter().any(|x| x.noisy) {
+        write!(writer, "{:>8} {} noisy, not to be trusted\n", "",
+               cell(0.5, color, true))?;
+    }
+    Ok(())
+}
+
+/// Write a gnuplot script to `path` which renders a heatmap of mean
+/// efficiency over the (threads, workload) grid of `results`, with noisy test
+/// points crossed out. The data is embedded in the script so it can be run on
+/// its own with `gnuplot <path>`, producing a PNG next to the script.
+pub fn write_gnuplot(path: &str, results: &[PointSummary]) -> io::Result<()> {
+    let mut fd = BufWriter::new(File::create(path)?);
+
+    // Render to a PNG with the same name as the script
+    let png = Path::new(path).with_extension("png");
+
+    // Determine the bounds of the grid
+    let max_workload = results.iter()
+        .map(|x| x.effective_workload()).max().unwrap_or(0);
+    let max_threads = results.iter()
+        .map(|x| x.point.threads).max().unwrap_or(0);
+
+    write!(fd, "set term png size 1440,900\n")?;
+    write!(fd, "set output \"{}\"\n", png.display())?;
+    write!(fd, "set logscale cb\n")?;
+    write!(fd, "set title \"Scaling and overhead properties of fork()\"\n")?;
+    write!(fd, "set xrange [0:{}]\n", max_workload.max(1))?;
+    write!(fd, "set yrange [0:{}]\n", max_threads + 1)?;
+    write!(fd, "set cbrange [0.01:1]\n")?;
+    write!(fd, "set xlabel \"Number of Instructions per fuzz case \
+        (loop of hot loads, 2 inst/cycle)\"\n")?;
+    write!(fd, "set ylabel \"Number of cores\"\n")?;
+    write!(fd, "set cblabel \"Ratio of CPU time spent inside the fuzz case \
+        (1.0 means no overhead)\"\n")?;
+    write!(fd, "set rmargin 5\n")?;
+    write!(fd, "set grid xtics ytics mxtics mytics\n")?;
+
+    // Embed the data
+    write!(fd, "$data << EOD\n")?;
+    for result in results {
+        write!(fd, "{} {} {:.6}\n", result.point.threads,
+               result.effective_workload(), result.efficiency.mean)?;
+    }
+    write!(fd, "EOD\n")?;
+    write!(fd, "$noisy << EOD\n")?;
+    for result in results.iter().filter(|x| x.noisy) {
+        write!(fd, "{} {} {:.6}\n", result.point.threads,
+               result.effective_workload(), result.efficiency.mean)?;
+    }
+    write!(fd, "EOD\n")?;
+    let any_noisy = results.iter().any(|x| x.noisy);
+    let noisy = |columns: &str| if any_noisy {
+        format!(", $noisy u {} w points pt 2 lc rgb \"black\" \
+            title \"noisy\"", columns)
+    } else {
+        String::new()
+    };
+
+    // If only one workload was sampled there is no second dimension to
+    // render an image with, fall back to a line plot of efficiency
+    if results.iter().all(|x| x.point.workload == results[0].point.workload) {
+        write!(fd, "set autoscale\nunset logscale cb\n")?;
+        write!(fd, "set xlabel \"Number of cores\"\n")?;
+        write!(fd, "set ylabel \"Ratio of CPU time spent inside the fuzz \
+            case\"\n")?;
+        write!(fd, "plot $data u 1:3 w linespoints title \"efficiency\"{}\n",
+               noisy("1:3"))?;
+    } else {
+        write!(fd, "plot $data u 2:1:3 w image notitle{}\n",
+               noisy("2:1"))?;
+    }
+
+    fd.flush()
+}
+
+/// Plot the results file named on the command line (not including the
+/// program name and command), writing the gnuplot script named after it
+pub fn run(args: impl IntoIterator<Item = String>) -> Result<(), String> {
+    let mut terminal = false;
+    let mut noise_threshold = crate::output::NOISE_THRESHOLD;
+    let mut paths = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--terminal" => terminal = true,
+            "--noise-threshold" => {
+                noise_threshold = args.next()
+                    .ok_or("`--noise-threshold` requires a value")?
+                    .parse::<f64>()
+                    .map_err(|_| "Invalid value for `--noise-threshold`")?;
+            }
+            "--help" | "-h" => {
+                print!("{}", USAGE);
+                std::process::exit(0);
+            }
+            _ if arg.starts_with("--") =>
+                return Err(format!("Unknown argument `{}`", arg)),
+            _ => paths.push(arg),
+        }
+    }
+
+    if paths.len() != 2 - terminal as usize {
+        return Err(String::from(if terminal {
+            "Expected a results file"
+        } else {
+            "Expected a results file and a script"
+        }));
+    }
+
+    let summaries = crate::report::summarize(&crate::compare::load(
+        &paths[0])?, noise_threshold);
+    if terminal {
+        let color = io::stdout().is_terminal();
+        return write_terminal(&mut io::stdout().lock(), &summaries, color)
+            .map_err(|err| format!("Failed to draw heatmap: {}", err));
+    }
+    write_gnuplot(&paths[1], &summaries).map_err(|err| {
+        format!("Failed to write `{}`: {}", paths[1], err)
+    })
+}
@@ -0,0 +1,138 @@
+//! Progress of the sweep reported on stderr, such that stdout only ever
+//! contains results
+
+use std::io::{IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+use crate::sweep::TestPoint;
+
+/// Format a duration as `hh:mm:ss`
+pub(crate) fn hms(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// Tracks and reports how far along the sweep is
+pub struct Progress {
+    /// Report progress at all
+    enabled: bool,
+
+    /// stderr is a terminal, progress is updated in place on a single line
+    tty: bool,
+
+    /// Number of test points in the sweep
+    total: usize,
+
+    /// Number of test points done (measured, resumed or failed)
+    done: usize,
+
+    /// Time the sweep started
+    start: Instant,
+
+    /// Time the current test point started
+    point_start: Instant,
+
+    /// Number of test points which were measured rather than resumed from a
+    /// checkpoint, the remaining time is estimated from these
+    measured: usize,
+
+    /// Time spent on measured test points
+    measured_time: Duration,
+}
+
+impl Progress {
+    /// Start tracking a sweep of `total` test points, only reporting
+    /// anything if `enabled`
+    pub fn new(total: usize, enabled: bool) -> Self {
+        Progress {
+            enabled,
+            tty:           std::io::stderr().is_terminal(),
+            total,
+            done:          0,
+            start:         Instant::now(),
+            point_start:   Instant::now(),
+            measured:      0,
+            measured_time: Duration::ZERO,
+        }
+    }
+
+    /// Estimated time until the sweep is done, `None` until the first test
+    /// point has been measured
+    fn remaining(&self) -> Option<Duration> {
+        if self.measured == 0 {
+            return None;
+        }
+
+        Some(self.measured_time / self.measured as u32 *
+             (self.total - self.done) as u32)
+    }
+
+    /// Add `points` test points to the sweep
+    pub fn extend(&mut self, points: usize) {
+        self.total += points;
+    }
+
+    /// Start timing the next test point
+    pub fn start_point(&mut self) {
+        self.point_start = Instant::now();
+    }
+
+    /// Describe the progress of the sweep while repetition `rep` of `reps`
+    /// of `point` is running
+    pub fn status(&self, point: &TestPoint, rep: usize, reps: usize)
+            -> String {
+        let remaining = self.remaining().map(hms)
+            .unwrap_or_else(|| "unknown".into());
+        format!("[{}/{}] threads {} workload {} memory {} vmas {} fds {} \
+            idle threads {} rep {}/{}, elapsed {}, remaining {}",
+            self.done + 1, self.total, point.threads, point.workload,
+            point.memory_mib, point.vmas, point.fds, point.idle_threads,
+            rep + 1, reps,
+            hms(self.start.elapsed()), remaining)
+    }
+
+    /// Report that repetition `rep` of `reps` of `point` is running
+    pub fn report(&self, point: &TestPoint, rep: usize, reps: usize) {
+        if !self.enabled {
+            return;
+        }
+
+        let line = self.status(point, rep, reps);
+
+        if self.tty {
+            eprint!("\r\x1b[K{}", line);
+            let _ = std::io::stderr().flush();
+        } else {
+            eprint!("{}\n", line);
+        }
+    }
+
+    /// Report that the current test point is done, `measured` is clear if
+    /// its results were taken from a checkpoint
+    pub fn finish_point(&mut self, measured: bool) {
+        self.done += 1;
+        if measured {
+            self.measured      += 1;
+            self.measured_time += self.point_start.elapsed();
+        }
+    }
+
+    /// Clear the progress line such that results and other messages can be
+    /// printed
+    pub fn clear(&self) {
+        if self.enabled && self.tty {
+            eprint!("\r\x1b[K");
+        }
+    }
+
+    /// Report that the sweep is done
+    pub fn finish(&self) {
+        if !self.enabled {
+            return;
+        }
+
+        self.clear();
+        eprint!("{} of {} test points done in {}\n", self.done, self.total,
+                hms(self.start.elapsed()));
+    }
+}
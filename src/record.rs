@@ -0,0 +1,106 @@
+//! `perf record` sessions wrapping each test, for profiling exactly the test
+//! point where scaling collapsed
+//!
+//! With `--perf-record <dir>` the parent starts `perf record -g` on the
+//! logical processors of the workers before forking them, and stops it once
+//! they all exited. Each test gets its own file in `<dir>`, named after its
+//! test point in the syntax of `--point` and its repetition, eg.
+//! `threads=64,workload=10,memory=0,vmas=0,fds=0,idle_threads=0,rep=2`
+//! followed by `.perf.data`, such that it can be matched to its result row
+//! and run again. Recording system-wide needs a `perf_event_paranoid` of at
+//! most 0 (or root), and samples of the kernel are only resolved with
+//! `kptr_restrict` lifted.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::sweep::TestPoint;
+
+/// Longest `perf record` may take to start recording
+const START_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Interval the output file is checked for whether `perf record` started at
+const POLL: Duration = Duration::from_millis(10);
+
+/// Get the path of the `perf.data` file of repetition `rep` of `point` in
+/// `dir`
+pub fn path(dir: &str, point: &TestPoint, rep: usize) -> PathBuf {
+    Path::new(dir).join(format!("{},rep={}.perf.data", point, rep))
+}
+
+/// A running `perf record`, stopped when dropped
+pub struct Session {
+    /// The `perf record` process
+    perf: Child,
+
+    /// File it records to
+    path: PathBuf,
+}
+
+impl Session {
+    /// Start recording repetition `rep` of `point` on the logical processors
+    /// of its workers, returning once `perf` is recording
+    pub fn start(config: &Config, point: &TestPoint, rep: usize)
+            -> io::Result<Self> {
+        let dir = config.perf_record.as_deref()
+            .expect("Recording requires `--perf-record`");
+        std::fs::create_dir_all(dir)?;
+
+        let path = path(dir, point, rep);
+        let cpus = config.processors[..point.threads as usize].iter()
+            .map(|x| x.cpu().to_string()).collect::<Vec<_>>().join(",");
+
+        // An earlier recording of the test would pass for this one starting
+        match std::fs::remove_file(&path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound =>
+                return Err(err),
+            _ => {}
+        }
+
+        let mut perf = Command::new("perf")
+            .args(["record", "-g", "--quiet", "-a", "-C", &cpus, "-o"])
+            .arg(&path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn()
+            .map_err(|err| io::Error::new(err.kind(),
+                format!("Failed to launch `perf record`: {}", err)))?;
+
+        // The header of the output file is written once the events are
+        // open, the workers mustn't start before that
+        let start = Instant::now();
+        loop {
+            if std::fs::metadata(&path).is_ok_and(|x| x.len() > 0) {
+                return Ok(Session { perf, path });
+            }
+            if let Some(status) = perf.try_wait()? {
+                return Err(io::Error::other(format!("`perf record` exited \
+                    with {} before recording", status)));
+            }
+            if start.elapsed() > START_TIMEOUT {
+                let _ = perf.kill();
+                let _ = perf.wait();
+                return Err(io::Error::new(io::ErrorKind::TimedOut,
+                    "`perf record` didn't start recording in time"));
+            }
+            std::thread::sleep(POLL);
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        // `perf record` finishes the file when interrupted
+        unsafe { libc::kill(self.perf.id() as libc::pid_t, libc::SIGINT); }
+        match self.perf.wait() {
+            Ok(status) if status.success() => {}
+            Ok(status) => eprint!("warning: `perf record` of `{}` exited \
+                with {}\n", self.path.display(), status),
+            Err(err) => eprint!("warning: failed to wait for `perf record` \
+                of `{}`: {}\n", self.path.display(), err),
+        }
+    }
+}
@@ -0,0 +1,295 @@
+//! Replay of test points of an earlier run, eg. to measure suspicious
+//! outliers again with more repetitions
+//!
+//! `--replay results.json` runs the test points of a results file written
+//! with `--output json` or `jsonl` again, only those matching `--filter` if
+//! given. The filter is a comma separated list of conditions which all have
+//! to hold, each comparing an axis of the test point (`threads`, `workload`,
+//! `memory`, `vmas`, `fds` or `idle_threads`, like `--point`) or the mean of
+//! a metric over its repetitions (eg. `efficiency_ratio`) to a number, eg.
+//! `threads>=64,efficiency_ratio<0.5`.
+//!
+//! The new results are merged back into the results file once the run is
+//! done: they're added to the earlier results of their test point, numbered
+//! after its earlier repetitions, and their summaries and failures replace
+//! the earlier ones of the test point.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+
+use crate::compare::{self, Row};
+use crate::output::{self, PointSummary, TestResult};
+use crate::sweep::TestPoint;
+
+/// Comparison of a condition of a filter
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Less,
+    LessEqual,
+    Equal,
+    NotEqual,
+    GreaterEqual,
+    Greater,
+}
+
+impl Op {
+    /// Operators as written, longer ones first such that `>=` isn't taken
+    /// for `>`
+    const ALL: &'static [(&'static str, Op)] = &[
+        ("<=", Op::LessEqual),
+        (">=", Op::GreaterEqual),
+        ("!=", Op::NotEqual),
+        ("==", Op::Equal),
+        ("<",  Op::Less),
+        (">",  Op::Greater),
+        ("=",  Op::Equal),
+    ];
+
+    /// Get the operator as it's usually written
+    fn name(&self) -> &'static str {
+        Self::ALL.iter().find(|x| x.1 == *self).map(|x| x.0).unwrap()
+    }
+
+    /// Compare `lhs` to `rhs`
+    fn test(&self, lhs: f64, rhs: f64) -> bool {
+        match self {
+            Op::Less         => lhs <  rhs,
+            Op::LessEqual    => lhs <= rhs,
+            Op::Equal        => lhs == rhs,
+            Op::NotEqual     => lhs != rhs,
+            Op::GreaterEqual => lhs >= rhs,
+            Op::Greater      => lhs >  rhs,
+        }
+    }
+}
+
+/// A single condition of a filter, `<name><op><value>`
+#[derive(Clone, Debug)]
+struct Condition {
+    /// Axis or metric compared
+    name: String,
+
+    /// How it's compared
+    op: Op,
+
+    /// What it's compared to
+    value: f64,
+}
+
+/// Test points of a results file to replay, all conditions have to hold
+#[derive(Clone, Debug)]
+pub struct Filter(Vec<Condition>);
+
+impl std::str::FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',').map(|condition| {
+            let (pos, written, op) = Op::ALL.iter()
+                .filter_map(|&(written, op)| {
+                    condition.find(written).map(|pos| (pos, written, op))
+                })
+                .min_by_key(|&(pos, written, _)| {
+                    (pos, std::cmp::Reverse(written.len()))
+                })
+                .ok_or_else(|| format!("Expected a condition like \
+                    `threads>=64`, got `{}`", condition))?;
+            let name  = condition[..pos].trim();
+            let value = condition[pos + written.len()..].trim();
+            if name.is_empty() {
+                return Err(format!("Condition `{}` has no name",
+                                   condition));
+            }
+
+            Ok(Condition {
+                name: name.to_ascii_lowercase(),
+                op,
+                value: value.replace('_', "").parse().map_err(|_| {
+                    format!("Invalid value `{}` of `{}`", value, name)
+                })?,
+            })
+        }).collect::<Result<_, _>>().map(Filter)
+    }
+}
+
+impl std::fmt::Display for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0.iter()
+            .map(|x| format!("{}{}{}", x.name, x.op.name(), x.value))
+            .collect::<Vec<_>>().join(","))
+    }
+}
+
+/// Get the axis `name` of `point`, in the terms of `--point`
+fn axis(point: &TestPoint, name: &str) -> Option<u64> {
+    Some(match name {
+        "threads"      => point.threads,
+        "workload"     => point.workload,
+        "memory"       => point.memory_mib,
+        "vmas"         => point.vmas,
+        "fds"          => point.fds,
+        "idle_threads" => point.idle_threads,
+        _ => return None,
+    })
+}
+
+impl Filter {
+    /// Check whether the test point `point` with the results `rows` of its
+    /// repetitions passes the filter. Metrics are averaged over the
+    /// repetitions reporting them, test points without them don't pass.
+    fn matches(&self, point: &TestPoint, rows: &[Row]) -> bool {
+        self.0.iter().all(|condition| {
+            let value = axis(point, &condition.name).map(|x| x as f64)
+                .or_else(|| {
+                    let values: Vec<f64> = rows.iter()
+                        .filter_map(|x| x.get(&condition.name).copied())
+                        .collect();
+                    (!values.is_empty()).then(|| {
+                        values.iter().sum::<f64>() / values.len() as f64
+                    })
+                });
+            value.is_some_and(|x| condition.op.test(x, condition.value))
+        })
+    }
+}
+
+/// Test points of an earlier run selected to run again
+#[derive(Clone, Debug)]
+pub struct Replay {
+    /// Results file of the earlier run, merged into once done
+    pub path: String,
+
+    /// Filter the test points were selected with, `None` for all of them
+    pub filter: Option<Filter>,
+
+    /// Test points to run again, with the number of repetitions they were
+    /// run for so far
+    pub points: BTreeMap<TestPoint, usize>,
+}
+
+impl Replay {
+    /// Select the test points of the results file `path` passing `filter`
+    pub fn load(path: &str, filter: Option<Filter>) -> Result<Self, String> {
+        // Results of the other formats can't be rendered again when merging
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read `{}`: {}", path, err))?;
+        if !contents.trim_start().starts_with('{') {
+            return Err(format!("`{}` isn't written with `--output json` or \
+                `jsonl`, which `--replay` needs to merge into", path));
+        }
+        let results = compare::load(path)?;
+
+        // Names which are neither an axis nor a metric would filter out
+        // everything, most likely they're misspelled
+        if let Some(filter) = &filter {
+            for condition in &filter.0 {
+                let known = axis(&TestPoint::default(), &condition.name)
+                    .is_some() || results.values().flatten()
+                    .any(|x| x.contains_key(&condition.name));
+                if !known {
+                    return Err(format!("`{}` is neither an axis nor a metric \
+                        of `{}`", condition.name, path));
+                }
+            }
+        }
+
+        let points: BTreeMap<TestPoint, usize> = results.iter()
+            .filter(|(point, rows)| {
+                filter.as_ref().is_none_or(|x| x.matches(point, rows))
+            })
+            .map(|(point, rows)| (*point, rows.len())).collect();
+        if points.is_empty() {
+            return Err(format!("No test points of `{}` match the filter",
+                               path));
+        }
+
+        Ok(Replay { path: path.to_string(), filter, points })
+    }
+
+    /// Merge the `results`, `summaries` and `failures` (as test points and
+    /// rendered errors) of the replay into the results file
+    pub fn merge(&self, results: &[TestResult], summaries: &[PointSummary],
+                 failures: &[(TestPoint, String)], cpu_frequency: bool)
+            -> Result<(), String> {
+        // Number the new repetitions after the earlier ones
+        let results: Vec<String> = results.iter().map(|result| {
+            let mut result = result.clone();
+            result.rep += self.points.get(&result.point).copied()
+                .unwrap_or(0);
+            output::json_result(&result, cpu_frequency)
+        }).collect();
+        let summaries: Vec<String> = summaries.iter()
+            .map(output::json_point_summary).collect();
+        let failures: Vec<String> = failures.iter()
+            .map(|(point, error)| output::json_failure(point, error))
+            .collect();
+
+        let failed = |err: String| {
+            format!("Failed to merge into `{}`: {}", self.path, err)
+        };
+        let contents = std::fs::read_to_string(&self.path)
+            .map_err(|err| failed(err.to_string()))?;
+
+        // JSON lines are simply appended, later lines of a test point take
+        // precedence
+        if contents.trim_start().starts_with("{\"type\"") {
+            let mut lines = String::new();
+            for (kind, items) in [("result", &results),
+                                  ("summary", &summaries),
+                                  ("failure", &failures)] {
+                for item in items {
+                    lines.push_str(&format!("{{\"type\": \"{}\", \"{}\": \
+                        {}}}\n", kind, kind, item));
+                }
+            }
+            return OpenOptions::new().append(true).open(&self.path)
+                .and_then(|mut file| {
+                    file.write_all(lines.as_bytes())?;
+                    file.sync_data()
+                }).map_err(|err| failed(err.to_string()));
+        }
+
+        // Summaries and failures of replayed test points are replaced, the
+        // new ones describe them now
+        let replayed: BTreeSet<TestPoint> = summaries.iter()
+            .chain(&failures).map(|x| compare::json_point(x))
+            .collect::<Result<_, _>>().map_err(failed)?;
+        let mut members = compare::json_members(&contents).map_err(failed)?;
+        for (key, value) in &mut members {
+            let (new, replace) = match key.as_str() {
+                "results"   => (&results, false),
+                "summaries" => (&summaries, true),
+                "failures"  => (&failures, true),
+                _ => continue,
+            };
+
+            let mut elements = compare::json_elements(value)
+                .map_err(failed)?;
+            if replace {
+                elements.retain(|x| {
+                    compare::json_point(x)
+                        .map_or(true, |x| !replayed.contains(&x))
+                });
+            }
+            elements.extend(new.iter().cloned());
+            *value = if elements.is_empty() {
+                String::from("[\n  ]")
+            } else {
+                format!("[\n    {}\n  ]", elements.join(",\n    "))
+            };
+        }
+
+        // Written to a temporary file first such that a failure doesn't
+        // clobber the earlier results
+        let tmp = format!("{}.tmp", self.path);
+        File::create(&tmp).and_then(|mut file| {
+            write!(file, "{{\n{}\n}}\n", members.iter()
+                .map(|(key, value)| {
+                    format!("  {}: {}", output::json_string(key), value)
+                }).collect::<Vec<_>>().join(",\n"))?;
+            file.sync_data()
+        }).and_then(|_| std::fs::rename(&tmp, &self.path))
+            .map_err(|err| failed(err.to_string()))
+    }
+}
@@ -0,0 +1,196 @@
+//! Summaries of the results of earlier runs, recomputed from their results
+//! files
+//!
+//! `forkbench report <results>` reads a results file written in any of the
+//! output formats and summarizes the repetitions of each test point like the
+//! sweep itself does, such that results without summaries (eg. CSV output,
+//! or the `--out` file of an interrupted run) can still be summarized.
+//! `--html` writes a self-contained HTML report with charts instead, and
+//! `--usl` the Universal Scalability Law fits of the scaling curves.
+
+use std::io::{self, Write};
+
+use crate::compare::{self, Results, Row};
+use crate::output::{OutputFormat, PointSummary, NOISE_THRESHOLD};
+use crate::stats::Summary;
+
+/// Usage string printed for `report --help` and on argument errors
+pub const USAGE: &str = r#"usage: forkbench report [options] <results>
+
+Summarizes the repetitions of each test point of a results file written with
+`--output text`, `csv`, `json` or `jsonl`, reporting the mean and 95%
+confidence interval of its efficiency, fork rate and time per fork.
+
+options:
+    --output <fmt>              Format to report the summaries in: `text` or
+                                `csv` (default text)
+    --html <path>               Write a self-contained HTML report with the
+                                efficiency heatmap, the scaling of the fork
+                                rate, latency percentiles and the metadata
+                                of the run to <path> instead
+    --usl                       Report the Universal Scalability Law fits of
+                                the fork rate of each scaling curve (its
+                                contention and coherency cost) instead
+    --noise-threshold <pct>     Flag test points whose efficiency varies by
+                                more than <pct> percent across repetitions
+                                as noisy (default 5)
+    --help                      Print this message
+"#;
+
+/// Summarize the repetitions of every test point of `results`, in test point
+/// order, flagging the ones whose efficiency varies by more than
+/// `noise_threshold` percent as noisy. Metrics which no repetition reports
+/// are NaN.
+pub fn summarize(results: &Results, noise_threshold: f64)
+        -> Vec<PointSummary> {
+    results.iter().map(|(point, rows)| {
+        let outlier = |row: &&Row| row.get("outlier") == Some(&1.);
+        let summary = |metric: &dyn Fn(&Row) -> Option<f64>| {
+            let samples: Vec<f64> = rows.iter().filter(|x| !outlier(x))
+                .filter_map(metric).collect();
+            if samples.is_empty() {
+                Summary { mean: f64::NAN, stddev: f64::NAN, ci95: f64::NAN }
+            } else {
+                Summary::of(&samples)
+            }
+        };
+        let column = |name: &'static str| {
+            move |row: &Row| row.get(name).copied()
+        };
+
+        let efficiency = summary(&column("efficiency_ratio"));
+
+        PointSummary {
+            point:            *point,
+            reps:             rows.len(),
+            outliers:         rows.iter().filter(outlier).count(),
+            efficiency,
+            forks_per_second: summary(&column("forks_per_second")),
+            forks_per_second_per_thread: summary(&|row| {
+                Some(row.get("forks_per_second")? / point.threads as f64)
+            }),
+            ns_per_fork:      summary(&column("ns_per_fork")),
+            crashes:          rows.iter()
+                .filter_map(|row| row.get("crashes")).sum::<f64>() as u64,
+            noisy:            efficiency.cv() * 100. > noise_threshold,
+        }
+    }).collect()
+}
+
+/// Write `summaries` to `writer` in `format`, which is text or CSV
+fn write(writer: &mut dyn Write, summaries: &[PointSummary],
+         format: OutputFormat) -> io::Result<()> {
+    match format {
+        OutputFormat::Csv => {
+            write!(writer, "threads,effective_workload_insts,\
+                prefork_memory_mib,vmas,fds,idle_threads,reps,\
+                efficiency_mean,efficiency_stddev,efficiency_ci95,\
+                forks_per_second_mean,forks_per_second_stddev,\
+                forks_per_second_ci95,ns_per_fork_mean,ns_per_fork_stddev,\
+                ns_per_fork_ci95,crashes,efficiency_cv,noisy\n")?;
+        }
+        _ => {
+            write!(writer, "{:>10} {:>14} {:>8} {:>8} {:>8} {:>8} {:>6} \
+                {:>12} {:>10} {:>14} {:>12} {:>12} {:>10} {:>8} {:>8}\n",
+                "threads", "workload", "memory", "vmas", "fds", "idle",
+                "reps", "efficiency", "+-", "forks/s", "+-", "ns/fork", "+-",
+                "crashes", "cv %")?;
+        }
+    }
+
+    for summary in summaries {
+        let point = &summary.point;
+        match format {
+            OutputFormat::Csv => {
+                write!(writer, "{},{},{},{},{},{},{},{:.6},{:.6},{:.6},\
+                    {:.2},{:.2},{:.2},{:.1},{:.1},{:.1},{},{:.6},{}\n",
+                    point.threads, summary.effective_workload(),
+                    point.memory_mib, point.vmas, point.fds,
+                    point.idle_threads, summary.reps,
+                    summary.efficiency.mean, summary.efficiency.stddev,
+                    summary.efficiency.ci95, summary.forks_per_second.mean,
+                    summary.forks_per_second.stddev,
+                    summary.forks_per_second.ci95, summary.ns_per_fork.mean,
+                    summary.ns_per_fork.stddev, summary.ns_per_fork.ci95,
+                    summary.crashes, summary.efficiency.cv(),
+                    summary.noisy as u8)?;
+            }
+            _ => {
+                write!(writer, "{:10} {:14} {:8} {:8} {:8} {:8} {:6} \
+                    {:12.6} {:10.6} {:14.2} {:12.2} {:12.1} {:10.1} {:8} \
+                    {:8.2}{}\n",
+                    point.threads, summary.effective_workload(),
+                    point.memory_mib, point.vmas, point.fds,
+                    point.idle_threads, summary.reps,
+                    summary.efficiency.mean, summary.efficiency.ci95,
+                    summary.forks_per_second.mean,
+                    summary.forks_per_second.ci95, summary.ns_per_fork.mean,
+                    summary.ns_per_fork.ci95, summary.crashes,
+                    summary.efficiency.cv() * 100.,
+                    if summary.noisy { " noisy" } else { "" })?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Summarize the results file named on the command line (not including the
+/// program name and command), printing the summary of every test point
+pub fn run(args: impl IntoIterator<Item = String>) -> Result<(), String> {
+    let mut format = OutputFormat::Text;
+    let mut html = None;
+    let mut usl = false;
+    let mut noise_threshold = NOISE_THRESHOLD;
+    let mut paths = Vec::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" => {
+                format = args.next()
+                    .ok_or("`--output` requires a value")?.parse()?;
+            }
+            "--html" =>
+                html = Some(args.next().ok_or("`--html` requires a value")?),
+            "--usl" => usl = true,
+            "--noise-threshold" => {
+                noise_threshold = args.next()
+                    .ok_or("`--noise-threshold` requires a value")?
+                    .parse::<f64>()
+                    .map_err(|_| "Invalid value for `--noise-threshold`")?;
+            }
+            "--help" | "-h" => {
+                print!("{}", USAGE);
+                std::process::exit(0);
+            }
+            _ if arg.starts_with("--") =>
+                return Err(format!("Unknown argument `{}`", arg)),
+            _ => paths.push(arg),
+        }
+    }
+
+    if paths.len() != 1 {
+        return Err(String::from("Expected a single results file"));
+    }
+    if format == OutputFormat::Json || format == OutputFormat::Jsonl {
+        return Err(String::from("Summaries are reported as `text` or `csv`"));
+    }
+
+    let results = compare::load(&paths[0])?;
+    if let Some(path) = html {
+        return crate::html::write_report(&path, &paths[0], &results,
+                noise_threshold, &compare::load_section(&paths[0], "metadata")?,
+                &compare::load_section(&paths[0], "config")?)
+            .map_err(|err| format!("Failed to write `{}`: {}", path, err));
+    }
+
+    let summaries = summarize(&results, noise_threshold);
+    if usl {
+        return crate::usl::write(&mut io::stdout().lock(), &summaries,
+                                 format == OutputFormat::Csv)
+            .map_err(|err| format!("Failed to write report: {}", err));
+    }
+    write(&mut io::stdout().lock(), &summaries, format)
+        .map_err(|err| format!("Failed to write report: {}", err))
+}
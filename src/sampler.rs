@@ -0,0 +1,74 @@
+//! Threads sampling the statistics of the running test point from shared
+//! memory, behind the dashboard, the metrics endpoint, the stream to a
+//! collector and the samples of a soak
+//!
+//! A sampling thread must only be started once all workers of the test
+//! point were created, and be stopped before the next ones are: forking
+//! while another thread is running isn't safe, as the child only gets a copy
+//! of the forking thread while locks held by the others stay locked forever.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Interval sampling threads check whether they should stop at
+pub const POLL: Duration = Duration::from_millis(20);
+
+/// Thread sampling while the workers of a test point are running, stopped
+/// when dropped
+pub struct Watch {
+    /// Set to ask the thread to stop
+    stop: Arc<AtomicBool>,
+
+    /// The sampling thread, `None` if nothing is sampled
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Watch {
+    /// A watch sampling nothing, for when the view it's behind is disabled
+    pub fn none() -> Self {
+        Watch { stop: Arc::new(AtomicBool::new(false)), thread: None }
+    }
+
+    /// Start a thread calling `poll` every `POLL` until the watch is
+    /// dropped, with the seconds since the previous sample each time
+    /// `interval` passed since it and `None` in between
+    pub fn start(interval: Duration,
+                 mut poll: impl FnMut(Option<f64>) + Send + 'static)
+            -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let flag = stop.clone();
+        let thread = std::thread::spawn(move || {
+            let mut last = Instant::now();
+            while !flag.load(Ordering::SeqCst) {
+                let now = Instant::now();
+                if now - last >= interval {
+                    poll(Some((now - last).as_secs_f64()));
+                    last = now;
+                } else {
+                    poll(None);
+                }
+                std::thread::sleep(POLL);
+            }
+        });
+
+        Watch { stop, thread: Some(thread) }
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Get how much a counter sampled as `old` and then `new` counted in
+/// between. Counts are reset after the warmup and between tests, a counter
+/// which went backwards was reset and only what it counted since is known.
+pub fn delta(new: u64, old: u64) -> u64 {
+    if new >= old { new - old } else { new }
+}
@@ -0,0 +1,253 @@
+//! Shared memory region holding the statistics of all workers
+//!
+//! The region is backed by an anonymous `memfd_create()` file on Linux, a
+//! POSIX shared memory object on other Unixes (or a file in a user-selected
+//! directory on either), and a pagefile-backed section on Windows. Forked
+//! workers inherit the mapping, processes which are launched fresh (helpers
+//! and Windows stubs) attach to it with the handle returned by `handle()`.
+//!
+//! The region starts with a `Layout` locating the fields external readers
+//! need, such that `watch` can follow a sweep run by another build.
+
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+use crate::{Layout, Statistics};
+use crate::error::{Error, Result, Syscall};
+
+/// Size of the shared memory region
+const SIZE: usize = core::mem::size_of::<Statistics>();
+
+/// Size of the layout at the start of the region, which is never reset
+const LAYOUT_SIZE: usize = core::mem::size_of::<crate::CachePadded<Layout>>();
+
+/// Location where shared memory was mapped
+static SHARED_MEMORY: AtomicPtr<Statistics> =
+    AtomicPtr::new(core::ptr::null_mut());
+
+/// Handle other processes attach to the shared memory with
+static HANDLE: OnceLock<String> = OnceLock::new();
+
+/// Map the shared memory backing `fd`
+#[cfg(unix)]
+unsafe fn map(fd: libc::c_int) -> Result<*mut Statistics> {
+    let ret = libc::mmap(core::ptr::null_mut(), SIZE,
+        libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED, fd, 0);
+    if ret == libc::MAP_FAILED {
+        return Err(Error::last_os_error(Syscall::Mmap));
+    }
+
+    Ok(ret as *mut Statistics)
+}
+
+/// Name for a backing which is unique to this run, even across PID reuse
+#[cfg(unix)]
+fn unique_name() -> String {
+    let time = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+    format!("forkbench_{}_{}", std::process::id(), time.as_nanos())
+}
+
+/// Clear close-on-exec on `fd` such that helpers inherit it
+#[cfg(unix)]
+unsafe fn make_inheritable(fd: libc::c_int) -> Result<()> {
+    Error::check(Syscall::Fcntl, libc::fcntl(fd, libc::F_SETFD, 0))?;
+    Ok(())
+}
+
+/// Create an anonymous file as the backing
+#[cfg(target_os = "linux")]
+unsafe fn create_anonymous() -> Result<libc::c_int> {
+    Error::check(Syscall::MemfdCreate,
+        libc::memfd_create(b"forkbench\0".as_ptr() as *const libc::c_char,
+                           0))
+}
+
+/// Create a POSIX shared memory object as the backing. They're global, thus
+/// it's named uniquely and removed as soon as it's open.
+#[cfg(all(unix, not(target_os = "linux")))]
+unsafe fn create_anonymous() -> Result<libc::c_int> {
+    let name = std::ffi::CString::new(format!("/{}", unique_name()))
+        .unwrap();
+
+    let fd = Error::check(Syscall::ShmOpen, libc::shm_open(name.as_ptr(),
+        libc::O_CREAT | libc::O_EXCL | libc::O_RDWR,
+        0o600 as libc::c_uint))?;
+    Error::check(Syscall::ShmUnlink, libc::shm_unlink(name.as_ptr()))?;
+
+    make_inheritable(fd)?;
+    Ok(fd)
+}
+
+/// Create a file uniquely named for this run in `dir` as the backing. It's
+/// removed as soon as it's open, such that it's gone no matter how we exit
+/// and concurrent runs can never open each other's.
+#[cfg(unix)]
+unsafe fn create_file(dir: &str) -> Result<libc::c_int> {
+    use std::os::unix::io::IntoRawFd;
+
+    let path = std::path::Path::new(dir).join(unique_name());
+    let fd = std::fs::OpenOptions::new().read(true).write(true)
+        .create_new(true).open(&path)?.into_raw_fd();
+    std::fs::remove_file(&path)?;
+
+    make_inheritable(fd)?;
+    Ok(fd)
+}
+
+/// Create the backing, a file in `dir` if given, and map it. The file is
+/// never visible to other runs, helpers inherit it and are handed the file
+/// descriptor number as the handle.
+#[cfg(unix)]
+unsafe fn create_backing(dir: Option<&str>)
+        -> Result<(*mut Statistics, String)> {
+    let fd = match dir {
+        Some(dir) => create_file(dir)?,
+        None      => create_anonymous()?,
+    };
+    Error::check(Syscall::Ftruncate,
+        libc::ftruncate(fd, SIZE as libc::off_t))?;
+
+    Ok((map(fd)?, fd.to_string()))
+}
+
+/// Attach to the backing inherited as file descriptor `handle`
+#[cfg(unix)]
+unsafe fn attach_backing(handle: &str) -> Result<*mut Statistics> {
+    map(handle.parse().expect("Invalid shared memory handle"))
+}
+
+#[cfg(windows)]
+const INVALID_HANDLE_VALUE: usize = !0;
+#[cfg(windows)]
+const PAGE_READWRITE: u32 = 0x04;
+#[cfg(windows)]
+const FILE_MAP_ALL_ACCESS: u32 = 0xf001f;
+
+#[cfg(windows)]
+extern "system" {
+    fn CreateFileMappingW(hFile: usize, lpAttributes: *mut std::ffi::c_void,
+                          flProtect: u32, dwMaximumSizeHigh: u32,
+                          dwMaximumSizeLow: u32, lpName: *const u16) -> usize;
+
+    fn OpenFileMappingW(dwDesiredAccess: u32, bInheritHandle: i32,
+                        lpName: *const u16) -> usize;
+
+    fn MapViewOfFile(hFileMappingObject: usize, dwDesiredAccess: u32,
+                     dwFileOffsetHigh: u32, dwFileOffsetLow: u32,
+                     dwNumberOfBytesToMap: usize) -> *mut std::ffi::c_void;
+}
+
+/// Map a view of the section `mapping`, as opened by `call`
+#[cfg(windows)]
+unsafe fn map_view(mapping: usize, call: Syscall) -> Result<*mut Statistics> {
+    if mapping == 0 {
+        return Err(Error::last_os_error(call));
+    }
+
+    let ret = MapViewOfFile(mapping, FILE_MAP_ALL_ACCESS, 0, 0, SIZE);
+    if ret.is_null() {
+        return Err(Error::last_os_error(Syscall::MapViewOfFile));
+    }
+
+    Ok(ret as *mut Statistics)
+}
+
+/// Create a pagefile-backed section named uniquely by our PID as the
+/// backing, the name is the handle. Sections can't be backed by a file in
+/// a directory of our choosing.
+#[cfg(windows)]
+unsafe fn create_backing(_dir: Option<&str>)
+        -> Result<(*mut Statistics, String)> {
+    let handle = format!("Local\\forkbench_{}", std::process::id());
+
+    let mapping = CreateFileMappingW(INVALID_HANDLE_VALUE,
+        core::ptr::null_mut(), PAGE_READWRITE, 0, SIZE as u32,
+        crate::windows::wide(&handle).as_ptr());
+
+    Ok((map_view(mapping, Syscall::CreateFileMapping)?, handle))
+}
+
+/// Attach to the section named `handle`
+#[cfg(windows)]
+unsafe fn attach_backing(handle: &str) -> Result<*mut Statistics> {
+    map_view(OpenFileMappingW(FILE_MAP_ALL_ACCESS, 0,
+                              crate::windows::wide(handle).as_ptr()),
+             Syscall::OpenFileMapping)
+}
+
+/// Create the shared memory used for communication of statistics between
+/// the workers and the parent, optionally backed by a file in `dir` and
+/// bound to NUMA node `node`. Nothing needs to be cleaned up afterwards.
+///
+/// # Safety
+///
+/// Must only be called once, before any use of the shared memory
+pub unsafe fn create(dir: Option<&str>, node: Option<u16>) -> Result<()> {
+    let (ret, handle) = create_backing(dir)?;
+    HANDLE.set(handle).unwrap();
+
+    // Bind the memory to the requested node before it's touched
+    #[cfg(target_os = "linux")]
+    if let Some(node) = node {
+        crate::threading::bind_memory_to_node(ret as *mut u8, SIZE, node)?;
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = node;
+
+    // Initialize the memory to zeros, apart from the description of its
+    // layout
+    core::ptr::write_bytes(ret, 0, 1);
+    core::ptr::write(core::ptr::addr_of_mut!((*ret).layout),
+                     crate::CachePadded::new(Layout::new()));
+
+    // Store the address of the shared memory allocation
+    SHARED_MEMORY.store(ret, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Attach to shared memory previously created by `create()` in a parent
+/// process, identified by its `handle()`. Used by processes which do not
+/// inherit our mappings.
+///
+/// # Safety
+///
+/// Must only be called once, before any use of the shared memory
+pub unsafe fn attach(handle: &str) -> Result<()> {
+    SHARED_MEMORY.store(attach_backing(handle)?, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Check whether the shared memory was created (or attached to) already
+pub fn is_created() -> bool {
+    !SHARED_MEMORY.load(Ordering::SeqCst).is_null()
+}
+
+/// Handle other processes can `attach()` to the shared memory with
+pub fn handle() -> &'static str {
+    HANDLE.get().expect("Shared memory not created")
+}
+
+/// Get access to the shared memory structure
+///
+/// # Safety
+///
+/// Technically not safe cause it could be !Sync (eg. contains a `Cell`)
+pub(crate) unsafe fn get() -> &'static Statistics {
+    let sm = SHARED_MEMORY.load(Ordering::SeqCst);
+    assert!(!sm.is_null());
+    &*sm
+}
+
+/// Reset shared memory to zeros, apart from the description of its layout
+/// which external readers may be looking at
+///
+/// # Safety
+///
+/// No workers may be running
+pub unsafe fn reset() {
+    let sm = SHARED_MEMORY.load(Ordering::SeqCst);
+    assert!(!sm.is_null());
+    core::ptr::write_bytes((sm as *mut u8).add(LAYOUT_SIZE), 0,
+                           SIZE - LAYOUT_SIZE);
+}
@@ -0,0 +1,157 @@
+//! Handling of `SIGINT` and `SIGTERM` such that an interrupted sweep tears
+//! down all of its workers and still reports the results it has so far
+//!
+//! Workers (and everything they create) are placed in their own process
+//! group, which the handler kills as a whole. The rest of the cleanup is
+//! left to the main loop, which notices the interruption once the workers
+//! of the running test point have been reaped.
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use libc::*;
+
+/// PID of the benchmark parent, forked children inherit the handler but
+/// must not act on it
+static PARENT: AtomicI32 = AtomicI32::new(0);
+
+/// Process group of the workers of the running test point, 0 if there are
+/// none
+static WORKER_GROUP: AtomicI32 = AtomicI32::new(0);
+
+/// Signal the benchmark was interrupted by, 0 if it wasn't
+static INTERRUPTED: AtomicI32 = AtomicI32::new(0);
+
+/// Signal handler of the parent, only does async-signal-safe work
+extern "C" fn handler(signal: c_int) {
+    unsafe {
+        // Children which haven't left our handler behind yet die as usual
+        if getpid() != PARENT.load(Ordering::SeqCst) {
+            libc::signal(signal, SIG_DFL);
+            raise(signal);
+            return;
+        }
+
+        INTERRUPTED.store(signal, Ordering::SeqCst);
+
+        let group = WORKER_GROUP.load(Ordering::SeqCst);
+        if group > 0 {
+            kill(-group, SIGKILL);
+        }
+    }
+}
+
+/// Install the `SIGINT` and `SIGTERM` handlers in the parent. On Linux we
+/// also become the subreaper of our descendants, such that children
+/// orphaned by killed workers can be reaped as well.
+pub fn install() {
+    PARENT.store(unsafe { getpid() }, Ordering::SeqCst);
+
+    unsafe {
+        let mut action: sigaction = core::mem::zeroed();
+        action.sa_sigaction = handler as extern "C" fn(c_int) as sighandler_t;
+        action.sa_flags = SA_RESTART;
+        sigemptyset(&mut action.sa_mask);
+
+        for &signal in &[SIGINT, SIGTERM] {
+            assert!(sigaction(signal, &action, core::ptr::null_mut()) == 0,
+                "Failed to install signal handler");
+        }
+
+        #[cfg(target_os = "linux")]
+        prctl(PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0);
+        #[cfg(target_os = "freebsd")]
+        procctl(P_PID, getpid() as id_t, PROC_REAP_ACQUIRE,
+                core::ptr::null_mut());
+    }
+}
+
+/// Move worker `pid` into the process group of the workers, creating it if
+/// `first` is set. Called in both the parent and the worker after the
+/// fork, as either may run first.
+pub fn join_worker_group(pid: pid_t, first: bool) {
+    let group = if first { pid } else { WORKER_GROUP.load(Ordering::SeqCst) };
+    unsafe { setpgid(pid, group); }
+
+    // Kill the worker right away if we were interrupted before the group
+    // was known to the handler. The worker only updates its own copy.
+    if first {
+        WORKER_GROUP.store(group, Ordering::SeqCst);
+        if interrupted().is_some() {
+            unsafe { kill(-group, SIGKILL); }
+        }
+    }
+}
+
+/// Forget the process group of the workers once they have all been reaped
+pub fn clear_worker_group() {
+    WORKER_GROUP.store(0, Ordering::SeqCst);
+}
+
+/// Get the signal the benchmark was interrupted by, if any
+pub fn interrupted() -> Option<i32> {
+    Some(INTERRUPTED.load(Ordering::SeqCst)).filter(|&x| x != 0)
+}
+
+/// Kill and reap every remaining descendant after an interruption
+pub fn reap_all() {
+    let group = WORKER_GROUP.swap(0, Ordering::SeqCst);
+    if group > 0 {
+        unsafe { kill(-group, SIGKILL); }
+    }
+
+    // Wait until there are no children left (`ECHILD`), the handler
+    // restarts interrupted waits
+    while unsafe { waitpid(-1, core::ptr::null_mut(), 0) } != -1 {}
+}
+
+/// Kills the workers of the running test point if they haven't all exited
+/// before a timeout, such that a wedged worker fails the test point rather
+/// than hanging the sweep
+pub struct Watchdog {
+    /// Set once the workers exited, and the condition variable the thread
+    /// waits on for it
+    done: Arc<(Mutex<bool>, Condvar)>,
+
+    /// Thread waiting for the timeout, returning whether it killed the
+    /// workers
+    thread: JoinHandle<bool>,
+}
+
+impl Watchdog {
+    /// Start watching the workers of the running test point. Must only be
+    /// called once all workers were created, as forking while the thread
+    /// is running isn't safe.
+    pub fn start(timeout: Duration) -> Self {
+        let done = Arc::new((Mutex::new(false), Condvar::new()));
+
+        let flag = done.clone();
+        let thread = std::thread::spawn(move || {
+            let (lock, cvar) = &*flag;
+            let (_done, wait) = cvar.wait_timeout_while(
+                lock.lock().unwrap(), timeout, |done| !*done).unwrap();
+            if !wait.timed_out() {
+                return false;
+            }
+
+            let group = WORKER_GROUP.load(Ordering::SeqCst);
+            if group > 0 {
+                unsafe { kill(-group, SIGKILL); }
+            }
+            true
+        });
+
+        Watchdog { done, thread }
+    }
+
+    /// Stop watching once the workers exited, returning whether they were
+    /// killed for timing out
+    pub fn stop(self) -> bool {
+        let (lock, cvar) = &*self.done;
+        *lock.lock().unwrap() = true;
+        cvar.notify_one();
+        self.thread.join().unwrap_or(false)
+    }
+}
@@ -0,0 +1,119 @@
+//! Throughput samples of a single test point run for a long time, for
+//! finding drift, throttling or kernel-side degradation which the short
+//! window of a sweep averages away
+//!
+//! With `--soak <duration>` the test point of `--point` runs for that long,
+//! and every `--soak-interval` the fork rate and efficiency of the interval
+//! are sampled from the statistics in shared memory. Samples are printed on
+//! stderr as they're taken and reported with the results once the test is
+//! done.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::clock;
+use crate::config::Config;
+use crate::sampler::{self, Watch};
+
+/// Throughput of a test over one interval
+#[derive(Clone, Debug)]
+pub struct Sample {
+    /// Repetition of the test point the sample was taken during
+    pub rep: usize,
+
+    /// Seconds since the workers started when the interval ended
+    pub secs: f64,
+
+    /// Number of fuzz cases created during the interval
+    pub forks: u64,
+
+    /// Number of fuzz cases created per second over all workers
+    pub forks_per_second: f64,
+
+    /// Number of fuzz cases created per second by each worker
+    pub forks_per_second_per_thread: f64,
+
+    /// Ratio of CPU time spent inside the fuzz case during the interval
+    pub efficiency: f64,
+
+    /// Number of fuzz cases which failed to be created during the interval
+    pub fork_failures: u64,
+}
+
+/// Sampler of the test which is running, does nothing unless `--soak` was
+/// given
+pub struct Soak {
+    /// Interval samples are taken at, `None` if not soaking
+    interval: Option<Duration>,
+
+    /// Samples taken so far
+    samples: Arc<Mutex<Vec<Sample>>>,
+}
+
+impl Soak {
+    /// Create the sampler for `--soak` if it was given
+    pub fn new(config: &Config) -> Self {
+        Soak {
+            interval: config.soak_secs
+                .map(|_| Duration::from_secs_f64(config.soak_interval)),
+            samples:  Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Take the samples taken so far
+    pub fn take(&self) -> Vec<Sample> {
+        std::mem::take(&mut self.samples.lock().unwrap())
+    }
+
+    /// Start sampling repetition `rep` of the test while `threads` workers
+    /// are running, see [`sampler`] for when that's allowed
+    pub fn watch(&self, threads: usize, rep: usize) -> Watch {
+        let interval = match self.interval {
+            Some(interval) => interval,
+            None           => return Watch::none(),
+        };
+
+        let samples = self.samples.clone();
+        let shmem   = unsafe { crate::shmem::get() };
+        let sample  = move || {
+            (shmem.forks(), shmem.vm_cycles(), shmem.fork_failures())
+        };
+
+        eprint!("{:>10} {:>12} {:>14} {:>14} {:>12} {:>10}\n", "secs",
+                "forks", "forks/s", "forks/s/worker", "efficiency",
+                "failures");
+
+        let start       = Instant::now();
+        let mut last    = sample();
+        let mut last_tc = clock::now();
+        Watch::start(interval, move |secs| {
+            let secs = match secs {
+                Some(secs) => secs,
+                None       => return,
+            };
+
+            let now    = sample();
+            let now_tc = clock::now();
+            let forks  = sampler::delta(now.0, last.0);
+            let sample = Sample {
+                rep,
+                secs: start.elapsed().as_secs_f64(),
+                forks,
+                forks_per_second: forks as f64 / secs,
+                forks_per_second_per_thread:
+                    forks as f64 / secs / threads as f64,
+                efficiency: sampler::delta(now.1, last.1) as f64 /
+                    ((now_tc - last_tc) as f64 * threads as f64),
+                fork_failures: sampler::delta(now.2, last.2),
+            };
+            last    = now;
+            last_tc = now_tc;
+
+            eprint!("{:10.1} {:12} {:14.1} {:14.1} {:12.6} {:10}\n",
+                    sample.secs, sample.forks, sample.forks_per_second,
+                    sample.forks_per_second_per_thread, sample.efficiency,
+                    sample.fork_failures);
+            samples.lock().unwrap().push(sample);
+        })
+    }
+}
@@ -0,0 +1,101 @@
+//! Summary statistics over repetitions of a test point
+
+/// Two-sided 95% critical values of Student's t-distribution for 1 to 30
+/// degrees of freedom
+const T_95: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228,
+     2.201, 2.179, 2.160, 2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086,
+     2.080, 2.074, 2.069, 2.064, 2.060, 2.056, 2.052, 2.048, 2.045, 2.042,
+];
+
+/// Critical value of the t-distribution for a 95% confidence interval with
+/// `dof` degrees of freedom
+fn t_95(dof: usize) -> f64 {
+    match dof {
+        0      => f64::NAN,
+        1..=30 => T_95[dof - 1],
+        _      => 1.960,
+    }
+}
+
+/// Scale of the median absolute deviation which makes it estimate the
+/// standard deviation of normally distributed samples
+const MAD_SCALE: f64 = 1.4826;
+
+/// Median of `samples`, which must not be empty
+fn median(samples: &[f64]) -> f64 {
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(f64::total_cmp);
+
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[mid - 1] + sorted[mid]) / 2.
+    } else {
+        sorted[mid]
+    }
+}
+
+/// Flag the samples which are more than `threshold` scaled median absolute
+/// deviations away from the median of `samples` as outliers (the modified
+/// z-score of Iglewicz and Hoaglin). Unlike the standard deviation, the
+/// median absolute deviation isn't inflated by the outliers themselves. Fewer
+/// than 3 samples, or samples most of which are equal, have no outliers.
+pub fn outliers(samples: &[f64], threshold: f64) -> Vec<bool> {
+    if samples.len() < 3 {
+        return vec![false; samples.len()];
+    }
+
+    let median = median(samples);
+    let mad = MAD_SCALE * self::median(&samples.iter()
+        .map(|x| (x - median).abs()).collect::<Vec<_>>());
+    samples.iter().map(|x| {
+        mad > 0. && (x - median).abs() > threshold * mad
+    }).collect()
+}
+
+/// Mean, spread and confidence interval of a set of samples
+#[derive(Clone, Copy, Debug)]
+pub struct Summary {
+    /// Arithmetic mean
+    pub mean: f64,
+
+    /// Sample standard deviation, 0 for a single sample
+    pub stddev: f64,
+
+    /// Half-width of the 95% confidence interval of the mean, 0 for a
+    /// single sample
+    pub ci95: f64,
+}
+
+impl Summary {
+    /// Summarize `samples`, which must not be empty
+    pub fn of(samples: &[f64]) -> Self {
+        assert!(!samples.is_empty(), "Cannot summarize zero samples");
+
+        let n    = samples.len() as f64;
+        let mean = samples.iter().sum::<f64>() / n;
+        if samples.len() == 1 {
+            return Summary { mean, stddev: 0., ci95: 0. };
+        }
+
+        let variance = samples.iter().map(|x| (x - mean) * (x - mean))
+            .sum::<f64>() / (n - 1.);
+        let stddev = variance.sqrt();
+
+        Summary {
+            mean,
+            stddev,
+            ci95: t_95(samples.len() - 1) * stddev / n.sqrt(),
+        }
+    }
+
+    /// Coefficient of variation, the standard deviation relative to the
+    /// mean. 0 for a single sample or a mean of 0.
+    pub fn cv(&self) -> f64 {
+        if self.mean == 0. {
+            0.
+        } else {
+            self.stddev / self.mean.abs()
+        }
+    }
+}
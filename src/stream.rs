@@ -0,0 +1,173 @@
+//! Results streamed to a remote collector as the sweep goes, for aggregating
+//! unattended sweeps of a fleet of machines centrally
+//!
+//! With `--stream tcp://host:port` the sweep connects to the collector up
+//! front and sends it newline-delimited JSON messages, each an object with
+//! the `type` of the message and the `host` it came from:
+//!
+//! - `start`: the sweep started, with its PID, number of test points and
+//!   the metadata of the machine like in the JSON output
+//! - `live`: statistics of the running test point, sent every second while
+//!   its workers are running
+//! - `result`, `summary` and `failure`: a result, summary or failed test
+//!   point, rendered like in the JSON output
+//! - `done`: the sweep finished (or was interrupted), nothing follows
+//!
+//! A collector going away doesn't stop the sweep, streaming stops with a
+//! warning and the results are still written locally.
+
+use std::io::{self, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::error::Error;
+use crate::metadata::{self, RunMetadata};
+use crate::output::{self, json_string, PointSummary, TestResult};
+use crate::sampler::{self, Watch};
+use crate::sweep::TestPoint;
+
+/// Interval live statistics are sent at
+const LIVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Longest a write to the collector may block before it's given up on
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connection to the collector shared with the live thread, `None` if not
+/// streaming or once the collector went away
+type Sink = Arc<Mutex<Option<TcpStream>>>;
+
+/// Stream of the sweep to a collector, does nothing unless `--stream` was
+/// given
+pub struct Stream {
+    /// Connection to the collector
+    sink: Sink,
+
+    /// Name of this machine, identifying it to the collector
+    host: String,
+}
+
+/// Send a message of `kind` from `host` with the rendered JSON `fields` to
+/// the collector of `sink`, giving up on it if it fails
+fn send(sink: &Sink, host: &str, kind: &str, fields: &str) {
+    let mut sink = sink.lock().unwrap();
+    let stream = match sink.as_mut() {
+        Some(stream) => stream,
+        None         => return,
+    };
+
+    let message = format!("{{\"type\": {}, \"host\": {}{}}}\n",
+                          json_string(kind), json_string(host), fields);
+    if let Err(err) = stream.write_all(message.as_bytes()) {
+        eprint!("warning: failed to stream to the collector, continuing \
+                 without it: {}\n", err);
+        *sink = None;
+    }
+}
+
+/// Render the test point fields of `point` as JSON object members
+fn point_fields(point: &TestPoint) -> String {
+    format!(", \"threads\": {}, \"effective_workload_insts\": {}, \
+        \"prefork_memory_mib\": {}, \"vmas\": {}, \"fds\": {}, \
+        \"idle_threads\": {}", point.threads,
+        point.workload * output::INSTS_PER_WORKLOAD, point.memory_mib,
+        point.vmas, point.fds, point.idle_threads)
+}
+
+impl Stream {
+    /// Connect to the collector of `--stream` if it was given
+    pub fn new(config: &Config) -> io::Result<Self> {
+        let stream = config.stream.as_ref().map(|url| {
+            // The URL was validated with the configuration
+            let stream = TcpStream::connect(&url["tcp://".len()..])?;
+            stream.set_nodelay(true)?;
+            stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+            Ok::<_, io::Error>(stream)
+        }).transpose()?;
+
+        Ok(Stream {
+            sink: Arc::new(Mutex::new(stream)),
+            host: metadata::hostname(),
+        })
+    }
+
+    /// Announce the start of a sweep of `test_points` test points with
+    /// `config`
+    pub fn start(&self, config: &Config, test_points: usize) {
+        if self.sink.lock().unwrap().is_none() {
+            return;
+        }
+
+        // Collecting the metadata takes a moment, and it's rendered over
+        // several lines which mustn't end the message
+        let metadata = output::json_metadata(
+            &RunMetadata::collect(&config.topology)).iter()
+            .map(|(key, value)| {
+                format!("{}: {}", json_string(key), value.lines()
+                    .map(str::trim).collect::<Vec<_>>().join(" "))
+            }).collect::<Vec<_>>().join(", ");
+        send(&self.sink, &self.host, "start", &format!(", \"pid\": {}, \
+            \"test_points\": {}, \"metadata\": {{{}}}", std::process::id(),
+            test_points, metadata));
+    }
+
+    /// Send the result of a single test
+    pub fn result(&self, result: &TestResult, cpu_frequency: bool) {
+        send(&self.sink, &self.host, "result", &format!(", \"result\": {}",
+             output::json_result(result, cpu_frequency)));
+    }
+
+    /// Send the summary of all repetitions of a test point
+    pub fn summary(&self, summary: &PointSummary) {
+        send(&self.sink, &self.host, "summary", &format!(", \"summary\": {}",
+             output::json_point_summary(summary)));
+    }
+
+    /// Send that test point `point` failed with `error`
+    pub fn failure(&self, point: &TestPoint, error: &Error) {
+        send(&self.sink, &self.host, "failure", &format!(", \"failure\": {}",
+             output::json_failure(point, &error.to_string())));
+    }
+
+    /// Announce the end of the sweep, after `completed` test points of
+    /// which `failed` failed
+    pub fn done(&self, completed: usize, failed: usize, interrupted: bool) {
+        send(&self.sink, &self.host, "done", &format!(", \"completed\": {}, \
+            \"failed\": {}, \"interrupted\": {}", completed, failed,
+            interrupted));
+    }
+
+    /// Start sending live statistics of repetition `rep` of `point` while
+    /// its workers are running, see [`sampler`] for when that's allowed
+    pub fn watch(&self, point: TestPoint, rep: usize) -> Watch {
+        if self.sink.lock().unwrap().is_none() {
+            return Watch::none();
+        }
+
+        let sink  = self.sink.clone();
+        let host  = self.host.clone();
+        let shmem = unsafe { crate::shmem::get() };
+
+        let start    = Instant::now();
+        let mut last = shmem.forks();
+        Watch::start(LIVE_INTERVAL, move |secs| {
+            let secs = match secs {
+                Some(secs) => secs,
+                None       => return,
+            };
+
+            let forks = shmem.forks();
+            let rate  = sampler::delta(forks, last) as f64 / secs;
+            last      = forks;
+
+            send(&sink, &host, "live", &format!("{}, \"rep\": {}, \
+                \"secs\": {:.1}, \"workers\": {}, \"forks\": {}, \
+                \"forks_per_second\": {:.1}, \"fork_failures\": {}",
+                point_fields(&point), rep, start.elapsed().as_secs_f64(),
+                shmem.workers.load(Ordering::SeqCst), forks, rate,
+                shmem.fork_failures()));
+        })
+    }
+}
@@ -0,0 +1,427 @@
+//! Generation of the test matrix swept by the benchmark
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::config::Config;
+use crate::output::PointSummary;
+
+/// A single point of the test matrix
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct TestPoint {
+    /// Number of worker threads
+    pub threads: u64,
+
+    /// Number of workload loop iterations per fuzz case
+    pub workload: u64,
+
+    /// MiB of anonymous memory each worker dirties before it starts forking
+    pub memory_mib: u64,
+
+    /// Number of extra memory mappings each worker creates before it starts
+    /// forking
+    pub vmas: u64,
+
+    /// Number of extra file descriptors each worker holds open before it
+    /// starts forking
+    pub fds: u64,
+
+    /// Number of idle threads each worker starts before it starts forking
+    pub idle_threads: u64,
+}
+
+impl std::str::FromStr for TestPoint {
+    type Err = String;
+
+    /// Parse comma separated `axis=value` pairs (eg. `threads=64,
+    /// workload=10000`), case insensitively. The thread count is required,
+    /// every other axis defaults to 0.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut ret = TestPoint::default();
+        for pair in s.split(',') {
+            let (axis, value) = pair.split_once('=')
+                .ok_or_else(|| format!("Expected `axis=value`, got `{}`",
+                                       pair))?;
+            let value = value.trim().replace('_', "").parse::<u64>()
+                .map_err(|_| format!("Invalid value `{}` of `{}`", value,
+                                     axis))?;
+            let field = match axis.trim().to_ascii_lowercase().as_str() {
+                "threads"      => &mut ret.threads,
+                "workload"     => &mut ret.workload,
+                "memory"       => &mut ret.memory_mib,
+                "vmas"         => &mut ret.vmas,
+                "fds"          => &mut ret.fds,
+                "idle_threads" => &mut ret.idle_threads,
+                _ => return Err(format!("Unknown axis `{}`", axis)),
+            };
+            *field = value;
+        }
+
+        if ret.threads == 0 {
+            return Err(format!("Test point `{}` needs a thread count", s));
+        }
+        Ok(ret)
+    }
+}
+
+impl std::fmt::Display for TestPoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "threads={},workload={},memory={},vmas={},fds={},\
+            idle_threads={}", self.threads, self.workload, self.memory_mib,
+            self.vmas, self.fds, self.idle_threads)
+    }
+}
+
+/// How the samples of an axis are spaced over its range
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Spacing {
+    /// Samples grow by a constant factor, in the range `[1, max)`
+    Log,
+
+    /// Samples grow by a constant step, in the range `[1, max]`
+    Linear,
+}
+
+impl std::str::FromStr for Spacing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "log"    => Ok(Spacing::Log),
+            "linear" => Ok(Spacing::Linear),
+            _ => Err(format!("Unknown spacing `{}`", s)),
+        }
+    }
+}
+
+impl Spacing {
+    /// Name of the spacing, as selected on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            Spacing::Log    => "log",
+            Spacing::Linear => "linear",
+        }
+    }
+
+    /// Get `samples` samples of the range of an axis up to `max`
+    fn samples(&self, max: usize, samples: usize) -> BTreeSet<u64> {
+        match self {
+            Spacing::Log    => logscale(max, samples),
+            Spacing::Linear => linear(max, samples),
+        }
+    }
+}
+
+/// Thread counts tested instead of samples of the range
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ThreadCounts {
+    /// Every count from 1 up to the maximum
+    All,
+
+    /// Every count from 1 up to the number of physical cores of the selected
+    /// logical processors
+    Physical,
+
+    /// An explicit list of counts
+    List(Vec<u64>),
+}
+
+impl std::str::FromStr for ThreadCounts {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all"      => Ok(ThreadCounts::All),
+            "physical" => Ok(ThreadCounts::Physical),
+            _ => s.split(',').map(|x| x.trim().parse::<u64>()).collect::<
+                    Result<Vec<_>, _>>().map(ThreadCounts::List)
+                .map_err(|_| format!("Invalid thread counts `{}`", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for ThreadCounts {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ThreadCounts::All      => write!(f, "all"),
+            ThreadCounts::Physical => write!(f, "physical"),
+            ThreadCounts::List(counts) => write!(f, "{}", counts.iter()
+                .map(|x| x.to_string()).collect::<Vec<_>>().join(",")),
+        }
+    }
+}
+
+/// Get `samples` linearly spaced samples in the range `[1, max]`. Samples
+/// which round to the same integer are deduped.
+fn linear(max: usize, samples: usize) -> BTreeSet<u64> {
+    if samples <= 1 || max <= 1 {
+        return std::iter::once(1).collect();
+    }
+
+    let step = (max - 1) as f64 / (samples - 1) as f64;
+    (0..samples).map(|ii| (1. + ii as f64 * step).round() as u64).collect()
+}
+
+/// Get logscale samples in the range `[1, max)` using `samples` samples.
+/// Samples which round to the same integer are deduped.
+fn logscale(max: usize, samples: usize) -> BTreeSet<u64> {
+    // Determine the scaling multiplier to hit the max value using the number
+    // of samples requested
+    let scale = (max as f64).powf(1. / samples as f64);
+
+    let mut ret = BTreeSet::new();
+    let mut value = 1.0;
+    while (value as usize) < max {
+        ret.insert(value as u64);
+        value *= scale;
+    }
+
+    ret
+}
+
+/// A dimension of the test matrix
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Axis {
+    /// Number of worker threads
+    Threads,
+
+    /// Workload per fuzz case
+    Workload,
+
+    /// MiB of pre-fork memory
+    Memory,
+
+    /// Number of extra memory mappings
+    Vmas,
+
+    /// Number of extra file descriptors
+    Fds,
+
+    /// Number of idle threads
+    IdleThreads,
+}
+
+impl Axis {
+    /// All axes, in the order their values are reported
+    pub const ALL: [Axis; 6] = [
+        Axis::Threads, Axis::Workload, Axis::Memory, Axis::Vmas, Axis::Fds,
+        Axis::IdleThreads,
+    ];
+
+    /// Name of the column the axis is reported in
+    pub fn name(&self) -> &'static str {
+        match self {
+            Axis::Threads     => "threads",
+            Axis::Workload    => "effective_workload_insts",
+            Axis::Memory      => "prefork_memory_mib",
+            Axis::Vmas        => "vmas",
+            Axis::Fds         => "fds",
+            Axis::IdleThreads => "idle_threads",
+        }
+    }
+
+    /// Whether the axis is swept by `config`, rather than held at a single
+    /// value
+    pub fn is_swept(&self, config: &Config) -> bool {
+        match self {
+            Axis::Threads     => true,
+            Axis::Workload    =>
+                config.bench_overhead || config.workloads.is_some(),
+            Axis::Memory      => config.memory_samples > 0,
+            Axis::Vmas        => config.vma_samples > 0,
+            Axis::Fds         => config.fd_samples > 0,
+            Axis::IdleThreads => config.idle_thread_samples > 0,
+        }
+    }
+
+    /// Values of the axis tested by `config`
+    pub fn values(&self, config: &Config) -> BTreeSet<u64> {
+        let spacing = config.spacing;
+        let max_threads = config.max_threads as u64;
+
+        match self {
+            Axis::Threads => match &config.thread_counts {
+                Some(ThreadCounts::List(counts)) =>
+                    counts.iter().copied().collect(),
+                Some(ThreadCounts::Physical) => {
+                    let cores: BTreeSet<usize> = config.processors.iter()
+                        .map(|x| x.core()).collect();
+                    (1..=max_threads.min(cores.len() as u64)).collect()
+                }
+                // The bounds of the search, bisected from there
+                None if config.search_efficiency.is_some() =>
+                    BTreeSet::from([1, max_threads]),
+                // A coarse grid to refine around the knee, which may be
+                // anywhere up to the maximum
+                None if config.adaptive > 0 => {
+                    let mut ret = spacing.samples(config.max_threads,
+                                                  config.thread_samples);
+                    ret.insert(max_threads);
+                    ret
+                }
+                None if config.bench_overhead =>
+                    spacing.samples(config.max_threads, config.thread_samples),
+                // Just benchmark the scaling of fork WRT cores by default
+                Some(ThreadCounts::All) | None => (1..=max_threads).collect(),
+            },
+            _ if !self.is_swept(config) => std::iter::once(0).collect(),
+            Axis::Workload => match &config.workloads {
+                Some(workloads) => workloads.iter().copied().collect(),
+                None => spacing.samples(config.max_workload,
+                                        config.workload_samples),
+            },
+            Axis::Memory =>
+                spacing.samples(config.max_memory_mib, config.memory_samples),
+            Axis::Vmas => spacing.samples(config.max_vmas, config.vma_samples),
+            Axis::Fds  => spacing.samples(config.max_fds, config.fd_samples),
+            Axis::IdleThreads => spacing.samples(config.max_idle_threads,
+                                                 config.idle_thread_samples),
+        }
+    }
+
+    /// Set the value of the axis of `point`
+    fn set(&self, point: &mut TestPoint, value: u64) {
+        match self {
+            Axis::Threads     => point.threads      = value,
+            Axis::Workload    => point.workload     = value,
+            Axis::Memory      => point.memory_mib   = value,
+            Axis::Vmas        => point.vmas         = value,
+            Axis::Fds         => point.fds          = value,
+            Axis::IdleThreads => point.idle_threads = value,
+        }
+    }
+}
+
+/// Get the axes swept by `config`, in the order of `Axis::ALL`
+pub fn swept_axes(config: &Config) -> Vec<Axis> {
+    Axis::ALL.iter().copied().filter(|x| x.is_swept(config)).collect()
+}
+
+/// Determine all the tests we should run, every combination of the values
+/// of all axes, or only the test points of `--point` or `--replay`. This will
+/// dedup any duplicate tests
+pub fn test_points(config: &Config) -> BTreeSet<TestPoint> {
+    if let Some(point) = config.point {
+        return BTreeSet::from([point]);
+    }
+    if let Some(replay) = &config.replay {
+        return replay.points.keys().copied().collect();
+    }
+
+    let mut tests = BTreeSet::from([TestPoint::default()]);
+    for axis in Axis::ALL {
+        let values = axis.values(config);
+        tests = tests.iter().flat_map(|&point| {
+            values.iter().map(move |&value| {
+                let mut point = point;
+                axis.set(&mut point, value);
+                point
+            })
+        }).collect();
+    }
+
+    tests
+}
+
+/// Get the test points sampling the knee of the scaling curve more finely,
+/// given the `summaries` of the test points which completed. For every
+/// combination of the other axes, the thread count halfway between the
+/// adjacent tested ones between which efficiency drops sharpest per added
+/// thread is sampled next, unless it's in `tested` already (eg. because it
+/// failed).
+pub fn refine(summaries: &[PointSummary], tested: &[TestPoint])
+        -> Vec<TestPoint> {
+    // Efficiency by thread count of each combination of the other axes
+    let mut curves: BTreeMap<TestPoint, BTreeMap<u64, f64>> = BTreeMap::new();
+    for summary in summaries {
+        let curve = TestPoint { threads: 0, ..summary.point };
+        curves.entry(curve).or_default()
+            .insert(summary.point.threads, summary.efficiency.mean);
+    }
+
+    let mut ret = Vec::new();
+    for (curve, efficiency) in curves {
+        let efficiency: Vec<(u64, f64)> = efficiency.into_iter().collect();
+        let knee = efficiency.windows(2)
+            .filter(|pair| pair[1].0 - pair[0].0 > 1)
+            .map(|pair| {
+                let slope = (pair[0].1 - pair[1].1) /
+                    (pair[1].0 - pair[0].0) as f64;
+                (slope, (pair[0].0 + pair[1].0) / 2)
+            })
+            .filter(|&(slope, _)| slope > 0.)
+            .max_by(|a, b| a.0.total_cmp(&b.0));
+
+        if let Some((_, threads)) = knee {
+            let point = TestPoint { threads, ..curve };
+            if !tested.contains(&point) {
+                ret.push(point);
+            }
+        }
+    }
+
+    ret
+}
+
+/// Get the bounds the search for the most threads which keep an efficiency
+/// of at least `threshold` narrowed down to, given the `summaries` of the
+/// test points which completed and all `tested` ones. The most threads
+/// tested which kept it (0 if none did), and the fewest tested above them
+/// which didn't (or failed), `None` if all of them kept it. The search is
+/// done once they're adjacent.
+pub fn search_bounds(threshold: f64, summaries: &[PointSummary],
+                     tested: &[TestPoint]) -> (u64, Option<u64>) {
+    let kept = |threads: u64| summaries.iter().any(|x| {
+        x.point.threads == threads && x.efficiency.mean >= threshold
+    });
+
+    // Efficiency is assumed to fall with the thread count, noise which has
+    // it rise again above a thread count which didn't keep it is ignored
+    let above = tested.iter().map(|x| x.threads).filter(|&x| !kept(x))
+        .min();
+    let below = tested.iter().map(|x| x.threads)
+        .filter(|&x| kept(x) && above.is_none_or(|above| x < above))
+        .max().unwrap_or(0);
+
+    (below, above)
+}
+
+/// Get the test point to run next in the search for the most threads which
+/// keep an efficiency of at least `threshold`, halfway between its
+/// `search_bounds()`. Empty once the search is done.
+pub fn search(threshold: f64, summaries: &[PointSummary],
+              tested: &[TestPoint]) -> Vec<TestPoint> {
+    match search_bounds(threshold, summaries, tested) {
+        (below, Some(above)) if above - below > 1 => {
+            // Every other axis is held at a single value
+            let point = tested.first().copied().unwrap_or_default();
+            vec![TestPoint { threads: (below + above) / 2, ..point }]
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Get the order `tests` are run in, sorted unless `config` shuffles them
+pub fn run_order(config: &Config, tests: &BTreeSet<TestPoint>)
+        -> Vec<TestPoint> {
+    let mut ret: Vec<TestPoint> = tests.iter().copied().collect();
+    let seed = match config.shuffle_seed {
+        Some(seed) => seed,
+        None       => return ret,
+    };
+
+    // Fisher-Yates shuffle driven by splitmix64, such that every seed
+    // (including 0) gives a well mixed order
+    let mut state = seed;
+    for ii in (1..ret.len()).rev() {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut rand = state;
+        rand = (rand ^ (rand >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        rand = (rand ^ (rand >> 27)).wrapping_mul(0x94d049bb133111eb);
+        rand ^= rand >> 31;
+
+        let jj = ((rand as u128 * (ii as u128 + 1)) >> 64) as usize;
+        ret.swap(ii, jj);
+    }
+
+    ret
+}
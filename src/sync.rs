@@ -0,0 +1,562 @@
+//! Synchronization primitives which work across forked processes, as they
+//! keep all of their state inline and are placed in shared memory
+//!
+//! Every primitive is valid in its initial state when all of its bytes are
+//! zero, such that it can live in freshly mapped shared memory (eg. the
+//! statistics of the harness, or a `Shared` mapping) created before forking.
+//! None of them hold pointers or anything else tied to the process which
+//! created them.
+//!
+//! The harness synchronizes its workers with `Barrier`, publishes the
+//! latency range of each worker with a `SeqLock` and streams the messages of
+//! the `ring` IPC mode through a `Ring` in a `Shared` mapping.
+
+use std::cell::UnsafeCell;
+use std::sync::atomic::{fence, AtomicU32, AtomicU64, Ordering};
+
+use crate::error::{Error, Result};
+use crate::futex;
+
+/// Types which are valid, in their initial state, when all of their bytes
+/// are zero
+///
+/// # Safety
+///
+/// All zero bytes must be a valid value of the type
+pub unsafe trait Zeroed {}
+
+/// Plain data, which is valid for any bytes and can thus be copied while
+/// another process is writing it (the torn copy is discarded)
+///
+/// # Safety
+///
+/// Any bytes must be a valid value of the type, which must not hold
+/// pointers
+pub unsafe trait Plain: Copy {}
+
+unsafe impl Plain for u8  {}
+unsafe impl Plain for u16 {}
+unsafe impl Plain for u32 {}
+unsafe impl Plain for u64 {}
+unsafe impl Plain for usize {}
+unsafe impl Plain for i8  {}
+unsafe impl Plain for i16 {}
+unsafe impl Plain for i32 {}
+unsafe impl Plain for i64 {}
+unsafe impl Plain for isize {}
+unsafe impl Plain for f32 {}
+unsafe impl Plain for f64 {}
+unsafe impl<T: Plain, const N: usize> Plain for [T; N] {}
+
+unsafe impl<T: Plain> Zeroed for T {}
+unsafe impl<A: Zeroed, B: Zeroed> Zeroed for (A, B) {}
+unsafe impl<A: Zeroed, B: Zeroed, C: Zeroed> Zeroed for (A, B, C) {}
+unsafe impl<A: Zeroed, B: Zeroed, C: Zeroed, D: Zeroed> Zeroed
+    for (A, B, C, D) {}
+
+/// A value alone on its cache line(s), such that writes to it don't
+/// invalidate the line of whatever is next to it
+#[derive(Debug, Default)]
+#[repr(C, align(64))]
+pub struct CachePadded<T>(T);
+
+unsafe impl<T: Zeroed> Zeroed for CachePadded<T> {}
+
+impl<T> CachePadded<T> {
+    /// Pad `value` to a cache line
+    pub const fn new(value: T) -> Self {
+        CachePadded(value)
+    }
+}
+
+impl<T> std::ops::Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> std::ops::DerefMut for CachePadded<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+/// A barrier for a fixed number of parties, which sleep on a futex until the
+/// last one arrives rather than spinning. It can be aborted, releasing
+/// everyone waiting on parties which will never arrive.
+///
+/// A barrier is used once, `reset()` it before the next round once nobody
+/// waits on it.
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct Barrier {
+    /// Number of parties which arrived
+    arrived: AtomicU64,
+
+    /// Bumped when the waiting parties are released, the futex they sleep
+    /// on
+    release: AtomicU32,
+
+    /// Set once the barrier was aborted
+    aborted: AtomicU32,
+}
+
+unsafe impl Zeroed for Barrier {}
+
+impl Barrier {
+    /// Create a barrier nobody arrived at yet
+    pub const fn new() -> Self {
+        Barrier {
+            arrived: AtomicU64::new(0),
+            release: AtomicU32::new(0),
+            aborted: AtomicU32::new(0),
+        }
+    }
+
+    /// Arrive at the barrier and wait for all `parties` to arrive. Fails
+    /// with `Error::Aborted` if the barrier was aborted first.
+    pub fn wait(&self, parties: u64) -> Result<()> {
+        // The last party to arrive releases the others
+        let release = self.release.load(Ordering::SeqCst);
+        if self.arrived.fetch_add(1, Ordering::SeqCst) + 1 == parties {
+            self.release.fetch_add(1, Ordering::SeqCst);
+            futex::wake_all(&self.release);
+            return Ok(());
+        }
+
+        loop {
+            if self.aborted.load(Ordering::SeqCst) != 0 {
+                return Err(Error::Aborted);
+            }
+            if self.release.load(Ordering::SeqCst) != release {
+                return Ok(());
+            }
+            futex::wait(&self.release, release);
+        }
+    }
+
+    /// Abort the barrier, failing everyone waiting on it and arriving later
+    pub fn abort(&self) {
+        self.aborted.store(1, Ordering::SeqCst);
+        self.release.fetch_add(1, Ordering::SeqCst);
+        futex::wake_all(&self.release);
+    }
+
+    /// Number of parties which arrived so far
+    pub fn arrived(&self) -> u64 {
+        self.arrived.load(Ordering::SeqCst)
+    }
+
+    /// Return to the initial state, for the next round
+    pub fn reset(&self) {
+        self.arrived.store(0, Ordering::SeqCst);
+        self.aborted.store(0, Ordering::SeqCst);
+    }
+}
+
+/// A value with a single writer which any number of readers can take
+/// consistent copies of without ever blocking the writer, eg. a snapshot of
+/// the progress of a worker
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct SeqLock<T> {
+    /// Sequence number, odd while a write is in progress
+    seq: AtomicU64,
+
+    /// Current value
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: Plain> Sync for SeqLock<T> {}
+unsafe impl<T: Plain> Zeroed for SeqLock<T> {}
+
+impl<T: Plain> SeqLock<T> {
+    /// Create a lock holding `value`
+    pub const fn new(value: T) -> Self {
+        SeqLock { seq: AtomicU64::new(0), value: UnsafeCell::new(value) }
+    }
+
+    /// Replace the value. Only one process may write at a time.
+    pub fn write(&self, value: T) {
+        let seq = self.seq.load(Ordering::Relaxed);
+        self.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        unsafe { core::ptr::write_volatile(self.value.get(), value); }
+
+        self.seq.store(seq.wrapping_add(2), Ordering::Release);
+    }
+
+    /// Get a copy of the value, retrying until it wasn't written to while
+    /// it was copied
+    pub fn read(&self) -> T {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq & 1 != 0 {
+                std::hint::spin_loop();
+                continue;
+            }
+
+            let value = unsafe { core::ptr::read_volatile(self.value.get()) };
+            fence(Ordering::Acquire);
+            if self.seq.load(Ordering::Relaxed) == seq {
+                return value;
+            }
+        }
+    }
+}
+
+/// A bounded queue of `N` values from a single producer to a single
+/// consumer, which never block each other. Values can be pushed and popped
+/// in batches of any size, making a ring of bytes a stream for messages
+/// larger than the ring.
+#[derive(Debug)]
+#[repr(C)]
+pub struct Ring<T, const N: usize> {
+    /// Number of values popped so far, only written by the consumer
+    head: CachePadded<AtomicU64>,
+
+    /// Number of values pushed so far, only written by the producer
+    tail: CachePadded<AtomicU64>,
+
+    /// The values, indexed by their position modulo `N`
+    slots: [UnsafeCell<T>; N],
+}
+
+unsafe impl<T: Plain, const N: usize> Sync for Ring<T, N> {}
+unsafe impl<T: Plain, const N: usize> Zeroed for Ring<T, N> {}
+
+impl<T: Plain, const N: usize> Default for Ring<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Plain, const N: usize> Ring<T, N> {
+    /// Create an empty ring
+    pub fn new() -> Self {
+        assert!(N > 0, "Rings must hold at least one value");
+
+        // All zero bytes are an empty ring
+        unsafe { core::mem::zeroed() }
+    }
+
+    /// Push `value`, giving it back if the ring is full. Only one process
+    /// may push.
+    pub fn push(&self, value: T) -> std::result::Result<(), T> {
+        let tail = self.tail.0.load(Ordering::Relaxed);
+        if tail - self.head.0.load(Ordering::Acquire) == N as u64 {
+            return Err(value);
+        }
+
+        unsafe { *self.slots[(tail % N as u64) as usize].get() = value; }
+        self.tail.0.store(tail + 1, Ordering::Release);
+        Ok(())
+    }
+
+    /// Pop the oldest value, `None` if the ring is empty. Only one process
+    /// may pop.
+    pub fn pop(&self) -> Option<T> {
+        let head = self.head.0.load(Ordering::Relaxed);
+        if head == self.tail.0.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let value = unsafe { *self.slots[(head % N as u64) as usize].get() };
+        self.head.0.store(head + 1, Ordering::Release);
+        Some(value)
+    }
+
+    /// Push as many of `values` as fit, returning how many did. Only one
+    /// process may push.
+    pub fn push_slice(&self, values: &[T]) -> usize {
+        let tail = self.tail.0.load(Ordering::Relaxed);
+        let free = N - (tail - self.head.0.load(Ordering::Acquire)) as usize;
+        let len  = values.len().min(free);
+
+        unsafe { self.copy(tail, values.as_ptr() as *mut T, len, true); }
+        self.tail.0.store(tail + len as u64, Ordering::Release);
+        len
+    }
+
+    /// Pop the oldest values into `buf` until it's full or the ring is
+    /// empty, returning how many were popped. Only one process may pop.
+    pub fn pop_slice(&self, buf: &mut [T]) -> usize {
+        let head = self.head.0.load(Ordering::Relaxed);
+        let used = (self.tail.0.load(Ordering::Acquire) - head) as usize;
+        let len  = buf.len().min(used);
+
+        unsafe { self.copy(head, buf.as_mut_ptr(), len, false); }
+        self.head.0.store(head + len as u64, Ordering::Release);
+        len
+    }
+
+    /// Copy `len` values between the slots starting at position `pos` and
+    /// `buf`, into the slots if `push`
+    ///
+    /// # Safety
+    ///
+    /// `buf` must be valid for `len` values, and the slots must be owned by
+    /// the caller
+    unsafe fn copy(&self, pos: u64, buf: *mut T, len: usize, push: bool) {
+        let slots = UnsafeCell::raw_get(self.slots.as_ptr());
+        let start = (pos % N as u64) as usize;
+        let first = len.min(N - start);
+        for (slot, buf, len) in [(slots.add(start), buf, first),
+                                 (slots, buf.add(first), len - first)] {
+            if push {
+                core::ptr::copy_nonoverlapping(buf, slot, len);
+            } else {
+                core::ptr::copy_nonoverlapping(slot, buf, len);
+            }
+        }
+    }
+
+    /// Number of values in the ring
+    pub fn len(&self) -> usize {
+        (self.tail.0.load(Ordering::Acquire) -
+            self.head.0.load(Ordering::Acquire)) as usize
+    }
+
+    /// Whether the ring is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A value in anonymous shared memory, which processes forked after it was
+/// created share with the creator. The value is never dropped, only unmapped.
+#[cfg(unix)]
+pub struct Shared<T> {
+    /// The mapping holding the value
+    ptr: *mut T,
+}
+
+#[cfg(unix)]
+unsafe impl<T: Sync> Sync for Shared<T> {}
+
+#[cfg(unix)]
+impl<T: Zeroed> Shared<T> {
+    /// Map shared memory holding `T` in its all zero initial state
+    pub fn new() -> Result<Self> {
+        let ptr = unsafe {
+            libc::mmap(core::ptr::null_mut(),
+                       core::mem::size_of::<T>().max(1),
+                       libc::PROT_READ | libc::PROT_WRITE,
+                       libc::MAP_SHARED | libc::MAP_ANONYMOUS, -1, 0)
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(Error::last_os_error(crate::error::Syscall::Mmap));
+        }
+
+        Ok(Shared { ptr: ptr as *mut T })
+    }
+}
+
+#[cfg(unix)]
+impl<T> std::ops::Deref for Shared<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.ptr }
+    }
+}
+
+#[cfg(unix)]
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void,
+                         core::mem::size_of::<T>().max(1));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn seqlock_reads_are_never_torn() {
+        let lock = Arc::new(SeqLock::new([0u64; 8]));
+        let writer = {
+            let lock = lock.clone();
+            std::thread::spawn(move || {
+                for ii in 1..=100_000 {
+                    lock.write([ii; 8]);
+                }
+            })
+        };
+
+        // Every copy is of a single write, and writes are seen in order
+        let mut last = 0;
+        while !writer.is_finished() {
+            let value = lock.read();
+            assert!(value.iter().all(|&x| x == value[0]), "{:?}", value);
+            assert!(value[0] >= last);
+            last = value[0];
+        }
+        writer.join().unwrap();
+        assert_eq!(lock.read(), [100_000; 8]);
+    }
+
+    #[test]
+    fn seqlock_retries_while_written() {
+        // Leave a write in progress, as a writer preempted halfway would
+        let lock = Arc::new(SeqLock::new(1u64));
+        lock.seq.store(1, Ordering::SeqCst);
+        let reader = {
+            let lock = lock.clone();
+            std::thread::spawn(move || lock.read())
+        };
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!reader.is_finished());
+
+        // Finish the write, the reader gets the new value
+        unsafe { core::ptr::write_volatile(lock.value.get(), 2); }
+        lock.seq.store(2, Ordering::Release);
+        assert_eq!(reader.join().unwrap(), 2);
+    }
+
+    #[test]
+    fn ring_full_and_empty() {
+        let ring: Ring<u32, 4> = Ring::new();
+        assert!(ring.is_empty());
+        assert_eq!(ring.pop(), None);
+
+        for ii in 0..4 {
+            ring.push(ii).unwrap();
+        }
+        assert_eq!(ring.len(), 4);
+        assert_eq!(ring.push(4), Err(4));
+
+        for ii in 0..4 {
+            assert_eq!(ring.pop(), Some(ii));
+        }
+        assert!(ring.is_empty());
+        assert_eq!(ring.pop(), None);
+    }
+
+    #[test]
+    fn ring_wraps() {
+        let ring: Ring<u64, 3> = Ring::new();
+
+        // Start off the first slot, then fill and drain it many times over
+        ring.push(0).unwrap();
+        assert_eq!(ring.pop(), Some(0));
+        for round in 0..10 {
+            for ii in 0..3 {
+                ring.push(round * 3 + ii).unwrap();
+            }
+            assert_eq!(ring.push(99), Err(99));
+            for ii in 0..3 {
+                assert_eq!(ring.pop(), Some(round * 3 + ii));
+            }
+            assert!(ring.is_empty());
+        }
+    }
+
+    #[test]
+    fn ring_slices_wrap() {
+        let ring: Ring<u8, 5> = Ring::new();
+
+        // Batches larger than the free space are cut short
+        assert_eq!(ring.push_slice(&[1, 2, 3]), 3);
+        assert_eq!(ring.push_slice(&[4, 5, 6]), 2);
+        assert_eq!(ring.push_slice(&[6]), 0);
+
+        let mut buf = [0; 4];
+        assert_eq!(ring.pop_slice(&mut buf), 4);
+        assert_eq!(buf, [1, 2, 3, 4]);
+
+        // Pushed across the end of the slots and popped back in order
+        assert_eq!(ring.push_slice(&[6, 7, 8]), 3);
+        assert_eq!(ring.len(), 4);
+        assert_eq!(ring.pop_slice(&mut buf[..1]), 1);
+        assert_eq!(buf[0], 5);
+        assert_eq!(ring.pop_slice(&mut buf), 3);
+        assert_eq!(buf[..3], [6, 7, 8]);
+        assert_eq!(ring.pop_slice(&mut buf), 0);
+        assert!(ring.is_empty());
+    }
+
+    #[test]
+    fn ring_across_threads() {
+        let ring: Arc<Ring<u64, 8>> = Arc::new(Ring::new());
+        let producer = {
+            let ring = ring.clone();
+            std::thread::spawn(move || {
+                for ii in 0..100_000 {
+                    while ring.push(ii).is_err() {
+                        std::thread::yield_now();
+                    }
+                }
+            })
+        };
+
+        // Every value arrives once, in order
+        let mut next = 0;
+        while next < 100_000 {
+            match ring.pop() {
+                Some(value) => {
+                    assert_eq!(value, next);
+                    next += 1;
+                }
+                None => std::thread::yield_now(),
+            }
+        }
+        producer.join().unwrap();
+        assert!(ring.is_empty());
+    }
+
+    #[test]
+    fn barrier_reused_across_rounds() {
+        let barrier = Arc::new(Barrier::new());
+        let passed  = Arc::new(AtomicU64::new(0));
+        for round in 1..=5 {
+            let threads: Vec<_> = (0..4).map(|_| {
+                let barrier = barrier.clone();
+                let passed  = passed.clone();
+                std::thread::spawn(move || {
+                    barrier.wait(4).unwrap();
+                    passed.fetch_add(1, Ordering::SeqCst);
+                })
+            }).collect();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            assert_eq!(barrier.arrived(), 4);
+            assert_eq!(passed.load(Ordering::SeqCst), round * 4);
+            barrier.reset();
+        }
+    }
+
+    #[test]
+    fn barrier_abort_then_reuse() {
+        let barrier = Arc::new(Barrier::new());
+
+        // A party waiting on one which never arrives is released
+        let waiter = {
+            let barrier = barrier.clone();
+            std::thread::spawn(move || barrier.wait(2))
+        };
+        while barrier.arrived() == 0 {
+            std::thread::yield_now();
+        }
+        barrier.abort();
+        assert!(matches!(waiter.join().unwrap(), Err(Error::Aborted)));
+
+        // Once reset it works again
+        barrier.reset();
+        let other = {
+            let barrier = barrier.clone();
+            std::thread::spawn(move || barrier.wait(2))
+        };
+        barrier.wait(2).unwrap();
+        other.join().unwrap().unwrap();
+    }
+}
@@ -1,5 +1,12 @@
 use std;
 
+use crate::error::Result;
+#[cfg(any(target_os="linux", target_os="freebsd", target_os="windows"))]
+use crate::error::{Error, Syscall};
+#[cfg(unix)] use std::sync::Arc;
+#[cfg(unix)] use std::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(target_os="windows")]
 #[derive(Clone, Copy, Default, Debug)]
 #[repr(C)]
 #[allow(non_snake_case)]
@@ -9,6 +16,7 @@ struct PROCESSOR_NUMBER {
     Reserved: u8,
 }
 
+#[cfg(target_os="windows")]
 #[derive(Clone, Copy, Default, Debug)]
 #[repr(C)]
 #[allow(non_snake_case)]
@@ -20,7 +28,7 @@ struct GROUP_AFFINITY {
 
 
 #[cfg(target_os="windows")]
-extern {
+extern "system" {
     fn GetNumaProcessorNodeEx(Processor: *const PROCESSOR_NUMBER,
                               NodeNumber: *mut u16) -> bool;
 
@@ -29,45 +37,246 @@ extern {
     fn SetThreadGroupAffinity(
         hThread: usize, GroupAffinity: GROUP_AFFINITY,
         PreviousGroupAffinity: *mut GROUP_AFFINITY) -> bool;
+
+    fn GetLogicalProcessorInformationEx(RelationshipType: u32,
+        Buffer: *mut u8, ReturnedLength: *mut u32) -> bool;
+}
+
+/// `LOGICAL_PROCESSOR_RELATIONSHIP` values
+#[cfg(target_os="windows")]
+const RELATION_PROCESSOR_CORE:    u32 = 0;
+#[cfg(target_os="windows")]
+const RELATION_PROCESSOR_PACKAGE: u32 = 3;
+
+/// Get the efficiency class and group affinities of every
+/// `SYSTEM_LOGICAL_PROCESSOR_INFORMATION_EX` entry of `relation`, which must
+/// be a core or package relationship
+#[cfg(target_os="windows")]
+fn processor_relationships(relation: u32) -> Vec<(u8, Vec<GROUP_AFFINITY>)> {
+    let mut ret = Vec::new();
+
+    // Get the required size of the buffer, then fetch the entries
+    let mut size = 0u32;
+    unsafe {
+        GetLogicalProcessorInformationEx(relation, std::ptr::null_mut(),
+                                         &mut size);
+    }
+    let mut buf = vec![0u8; size as usize];
+    unsafe {
+        if !GetLogicalProcessorInformationEx(relation, buf.as_mut_ptr(),
+                                             &mut size) {
+            return ret;
+        }
+    }
+
+    /* Entries are variable sized, each is a `Relationship` and `Size` header
+     * followed by a `PROCESSOR_RELATIONSHIP` whose `EfficiencyClass` lives
+     * at offset 9, `GroupCount` at offset 30 and `GroupMask` array at offset
+     * 32
+     */
+    let read_u16 = |off: usize| u16::from_le_bytes([buf[off], buf[off + 1]]);
+    let mut off = 0;
+    while off + 32 <= size as usize {
+        let entry_size = u32::from_le_bytes([buf[off + 4], buf[off + 5],
+            buf[off + 6], buf[off + 7]]) as usize;
+        if entry_size == 0 {
+            break;
+        }
+
+        let count = read_u16(off + 30) as usize;
+        ret.push((buf[off + 9], (0..count).map(|ii| {
+            let mask = off + 32 + ii * std::mem::size_of::<GROUP_AFFINITY>();
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&buf[mask..mask + 8]);
+
+            GROUP_AFFINITY {
+                Mask:     u64::from_le_bytes(bytes),
+                Group:    read_u16(mask + 8),
+                Reserved: [0; 3],
+            }
+        }).collect()));
+
+        off += entry_size;
+    }
+
+    ret
+}
+
+/// Class of the core a logical processor is part of, which differ on hybrid
+/// CPUs (eg. Alder Lake or big.LITTLE)
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum CoreType {
+    /// A performance core, or any core of a CPU which isn't hybrid
+    #[default]
+    Performance,
+
+    /// An efficiency core
+    Efficiency,
 }
 
+/// A logical processor of the system, as returned by
+/// `get_logical_processors()` and accepted by `pin_to_logical_processor()` on
+/// every platform
 #[derive(Clone, Copy, Default, Debug)]
-pub struct NumaInfo {
+pub struct LogicalProcessor {
+    /// Group and number of the logical processor within it
+    #[cfg(target_os="windows")]
     procnum: PROCESSOR_NUMBER,
+
+    /// NUMA node the logical processor belongs to
     numa_id: u16,
+
+    /// OS index of the logical processor
+    cpu: usize,
+
+    /// Physical package (socket) the logical processor is part of
+    socket: u16,
+
+    /// Identifier of the physical core the logical processor is part of,
+    /// unique across the whole system
+    core: usize,
+
+    /// Index of the logical processor among its SMT siblings
+    smt_index: usize,
+
+    /// Class of the core the logical processor is part of
+    core_type: CoreType,
+}
+
+impl LogicalProcessor {
+    /// OS index of the logical processor
+    pub fn cpu(&self) -> usize {
+        self.cpu
+    }
+
+    /// NUMA node the logical processor belongs to
+    pub fn numa_id(&self) -> u16 {
+        self.numa_id
+    }
+
+    /// Physical package (socket) the logical processor is part of
+    pub fn socket(&self) -> u16 {
+        self.socket
+    }
+
+    /// Identifier of the physical core the logical processor is part of
+    pub fn core(&self) -> usize {
+        self.core
+    }
+
+    /// Index of the logical processor among its SMT siblings, 0 for the
+    /// first thread of a core
+    pub fn smt_index(&self) -> usize {
+        self.smt_index
+    }
+
+    /// Class of the core the logical processor is part of
+    pub fn core_type(&self) -> CoreType {
+        self.core_type
+    }
+}
+
+/// Parse a Linux style CPU list (eg. `0-3,8,10-11`)
+pub fn parse_cpu_list(list: &str) -> Option<Vec<usize>> {
+    let mut ret = Vec::new();
+
+    for range in list.trim().split(',').filter(|x| !x.is_empty()) {
+        let mut bounds = range.splitn(2, '-');
+        let start: usize = bounds.next()?.trim().parse().ok()?;
+        let end: usize = match bounds.next() {
+            Some(end) => end.trim().parse().ok()?,
+            None      => start,
+        };
+        if end < start {
+            return None;
+        }
+
+        ret.extend(start..=end);
+    }
+
+    Some(ret)
+}
+
+/// Bind the memory at `addr` of `size` bytes to NUMA node `node`. Must be
+/// done before the memory is touched to have any effect.
+///
+/// # Safety
+///
+/// `addr` must be the start of a mapping of at least `size` bytes owned by
+/// the caller
+#[cfg(target_os="linux")]
+pub unsafe fn bind_memory_to_node(addr: *mut u8, size: usize, node: u16)
+        -> Result<()> {
+    // MPOL_BIND from `linux/mempolicy.h`
+    const MPOL_BIND: usize = 2;
+
+    let mut nodemask = [0usize; 1024 / (std::mem::size_of::<usize>() * 8)];
+    let bits = std::mem::size_of::<usize>() * 8;
+    nodemask[node as usize / bits] |= 1 << (node as usize % bits);
+
+    Error::check(Syscall::Mbind, libc::syscall(libc::SYS_mbind, addr, size,
+        MPOL_BIND, nodemask.as_ptr(), nodemask.len() * bits, 0usize))?;
+    Ok(())
 }
 
 /// Pin the current thread to a specific logical processor
 #[cfg(target_os="windows")]
-pub fn pin_to_logical_processor(numainfo: NumaInfo)
-{
+pub fn pin_to_logical_processor(processor: LogicalProcessor) -> Result<()> {
     let group = GROUP_AFFINITY {
-        Mask:     1u64 << numainfo.procnum.Number,
-        Group:    numainfo.procnum.Group,
+        Mask:     1u64 << processor.procnum.Number,
+        Group:    processor.procnum.Group,
         Reserved: [0; 3],
     };
 
     unsafe {
-        assert!(SetThreadGroupAffinity(GetCurrentThread(),
-                                       group, std::ptr::null_mut()));
+        if !SetThreadGroupAffinity(GetCurrentThread(), group,
+                                   std::ptr::null_mut()) {
+            return Err(Error::last_os_error(Syscall::SetThreadGroupAffinity));
+        }
     }
+
+    Ok(())
 }
 
 #[cfg(target_os="linux")]
-extern {
+extern "C" {
     fn sched_setaffinity(pid: usize, cpusetsize: usize,
         mask: *mut usize) -> i32;
 
     fn syscall(id: usize) -> i32;
 }
 
+/// Get the OS indices of the logical processors this process may run on, as
+/// restricted by `taskset` or a cgroup cpuset
+#[cfg(target_os="linux")]
+pub fn get_allowed_processors() -> Option<Vec<usize>> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of_val(&set),
+                                   &mut set) != 0 {
+            return None;
+        }
+
+        Some((0..libc::CPU_SETSIZE as usize)
+            .filter(|&cpu| libc::CPU_ISSET(cpu, &set)).collect())
+    }
+}
+
+/// Get the OS indices of the logical processors this process may run on,
+/// `None` if the platform places no restriction we can detect
+#[cfg(not(target_os="linux"))]
+pub fn get_allowed_processors() -> Option<Vec<usize>> {
+    None
+}
+
+/// Pin the current thread to a specific logical processor
 #[cfg(target_os="linux")]
-pub fn pin_to_logical_processor(core_id: usize) {
+pub fn pin_to_logical_processor(processor: LogicalProcessor) -> Result<()> {
     unsafe {
         let mut bitmask = [0usize; 1024 / (std::mem::size_of::<usize>() * 8)];
 
-        let usize_idx = core_id / (std::mem::size_of::<usize>() * 8);
-        let bit_idx   = core_id % (std::mem::size_of::<usize>() * 8);
+        let usize_idx = processor.cpu / (std::mem::size_of::<usize>() * 8);
+        let bit_idx   = processor.cpu % (std::mem::size_of::<usize>() * 8);
 
         // Set the affinity
         bitmask[usize_idx] |= 1 << bit_idx;
@@ -75,33 +284,389 @@ pub fn pin_to_logical_processor(core_id: usize) {
         let tid = syscall(186);
         assert!(tid > 0);
 
-        assert!(sched_setaffinity(tid as usize,
-            std::mem::size_of_val(&bitmask), bitmask.as_mut_ptr()) == 0,
-            "Failed to pin to core");
+        Error::check(Syscall::SchedSetaffinity, sched_setaffinity(
+            tid as usize, std::mem::size_of_val(&bitmask),
+            bitmask.as_mut_ptr()))?;
     }
+
+    Ok(())
+}
+
+/// Threads which do nothing but sleep until dropped, such that the worker
+/// forks from a multi-threaded process
+#[cfg(unix)]
+pub struct IdleThreads {
+    /// Set to make the threads exit
+    stop: Arc<AtomicBool>,
+
+    /// The sleeping threads
+    threads: Vec<std::thread::JoinHandle<()>>,
+}
+
+#[cfg(unix)]
+impl IdleThreads {
+    /// Stack size of each thread, they never do anything with it
+    const STACK_SIZE: usize = 64 * 1024;
+
+    /// Start `count` idle threads
+    pub fn new(count: usize) -> Result<Self> {
+        let mut ret = IdleThreads {
+            stop:    Arc::new(AtomicBool::new(false)),
+            threads: Vec::with_capacity(count),
+        };
+        if count == 0 {
+            return Ok(ret);
+        }
+
+        // Spawn the threads with all signals blocked, which they inherit,
+        // such that signals meant for the worker (eg. `SIGCHLD` read from a
+        // `signalfd`) are never delivered to them instead
+        let mut all: libc::sigset_t = unsafe { std::mem::zeroed() };
+        let mut old: libc::sigset_t = unsafe { std::mem::zeroed() };
+        unsafe {
+            libc::sigfillset(&mut all);
+            libc::pthread_sigmask(libc::SIG_SETMASK, &all, &mut old);
+        }
+
+        // From here on the spawned threads are stopped on failure
+        let mut result = Ok(());
+        for _ in 0..count {
+            let stop = ret.stop.clone();
+            let thread = std::thread::Builder::new()
+                .stack_size(Self::STACK_SIZE)
+                .spawn(move || {
+                    while !stop.load(Ordering::SeqCst) {
+                        std::thread::park();
+                    }
+                });
+            match thread {
+                Ok(thread) => ret.threads.push(thread),
+                Err(err) => {
+                    result = Err(crate::error::Error::Os {
+                        call:  crate::error::Syscall::PthreadCreate,
+                        errno: err.raw_os_error().unwrap_or(0),
+                    });
+                    break;
+                }
+            }
+        }
+
+        unsafe {
+            libc::pthread_sigmask(libc::SIG_SETMASK, &old,
+                                  std::ptr::null_mut());
+        }
+        result.map(|()| ret)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for IdleThreads {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        for thread in self.threads.drain(..) {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Scheduling policies workers (and everything they create) can run under
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SchedPolicy {
+    /// The default time-sharing policy, `SCHED_OTHER`
+    Other,
+
+    /// `SCHED_BATCH`, time-sharing for CPU-bound non-interactive work
+    Batch,
+
+    /// `SCHED_IDLE`, only run when nothing else wants to
+    Idle,
+
+    /// `SCHED_FIFO` real-time, run until blocking or preempted by a higher
+    /// priority
+    Fifo,
+
+    /// `SCHED_RR` real-time, like `Fifo` with a time slice
+    Rr,
+}
+
+impl std::str::FromStr for SchedPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "other" => Ok(SchedPolicy::Other),
+            "batch" => Ok(SchedPolicy::Batch),
+            "idle"  => Ok(SchedPolicy::Idle),
+            "fifo"  => Ok(SchedPolicy::Fifo),
+            "rr"    => Ok(SchedPolicy::Rr),
+            _ => Err(format!("Unknown scheduling policy `{}`", s)),
+        }
+    }
+}
+
+impl SchedPolicy {
+    /// Name of the policy, as selected on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            SchedPolicy::Other => "other",
+            SchedPolicy::Batch => "batch",
+            SchedPolicy::Idle  => "idle",
+            SchedPolicy::Fifo  => "fifo",
+            SchedPolicy::Rr    => "rr",
+        }
+    }
+
+    /// Whether this policy can be used on the current platform
+    pub fn is_supported(&self) -> bool {
+        *self == SchedPolicy::Other || cfg!(target_os = "linux")
+    }
+
+    /// Whether this is a real-time policy, which takes a priority rather
+    /// than a nice level
+    pub fn is_realtime(&self) -> bool {
+        matches!(self, SchedPolicy::Fifo | SchedPolicy::Rr)
+    }
+}
+
+/// Run the current thread under `policy`, with a real-time `priority` or a
+/// `nice` level depending on the policy. Inherited by any process or thread
+/// it creates from here on.
+#[cfg(target_os="linux")]
+pub fn set_scheduling(policy: SchedPolicy, priority: u32, nice: i32)
+        -> Result<()> {
+    let mut attr: libc::sched_attr = unsafe { std::mem::zeroed() };
+    attr.size           = std::mem::size_of::<libc::sched_attr>() as u32;
+    attr.sched_policy   = match policy {
+        SchedPolicy::Other => libc::SCHED_OTHER,
+        SchedPolicy::Batch => libc::SCHED_BATCH,
+        SchedPolicy::Idle  => libc::SCHED_IDLE,
+        SchedPolicy::Fifo  => libc::SCHED_FIFO,
+        SchedPolicy::Rr    => libc::SCHED_RR,
+    } as u32;
+    attr.sched_priority = priority;
+    attr.sched_nice     = nice;
+
+    Error::check(Syscall::SchedSetattr, unsafe {
+        libc::syscall(libc::SYS_sched_setattr, 0 as libc::pid_t, &attr,
+                      0 as libc::c_uint)
+    })?;
+    Ok(())
 }
 
 #[cfg(target_os="linux")]
-pub fn get_logical_processors() -> Vec<NumaInfo> {
+pub fn get_logical_processors() -> Vec<LogicalProcessor> {
     let cpuinfo = std::fs::read_to_string("/proc/cpuinfo")
         .expect("Failed to read CPU info");
 
     let mut ret = Vec::new();
     for line in cpuinfo.lines() {
         if line.starts_with("processor") {
-            ret.push(NumaInfo::default());
+            let cpu = line.split_once(':').map(|x| x.1)
+                .and_then(|x| x.trim().parse().ok())
+                .unwrap_or(ret.len());
+
+            ret.push(LogicalProcessor { cpu, core: cpu, ..Default::default() });
         }
     }
 
+    // Fill in socket and SMT sibling information
+    for info in ret.iter_mut() {
+        let topology = format!("/sys/devices/system/cpu/cpu{}/topology",
+                               info.cpu);
+
+        info.socket = std::fs::read_to_string(
+                format!("{}/physical_package_id", topology)).ok()
+            .and_then(|x| x.trim().parse().ok()).unwrap_or(0);
+
+        let siblings = std::fs::read_to_string(
+                format!("{}/thread_siblings_list", topology)).ok()
+            .and_then(|x| parse_cpu_list(&x));
+        if let Some(siblings) = siblings {
+            info.core = siblings.iter().copied().min().unwrap_or(info.cpu);
+            info.smt_index = siblings.iter()
+                .position(|&x| x == info.cpu).unwrap_or(0);
+        }
+    }
+
+    // Fill in NUMA node membership, if the system has no NUMA information
+    // everything stays on node 0
+    if let Ok(nodes) = std::fs::read_dir("/sys/devices/system/node") {
+        for node in nodes.filter_map(|x| x.ok()) {
+            let name = node.file_name();
+            let numa_id: u16 = match name.to_str()
+                    .and_then(|x| x.strip_prefix("node"))
+                    .and_then(|x| x.parse().ok()) {
+                Some(id) => id,
+                None     => continue,
+            };
+
+            let cpus = std::fs::read_to_string(node.path().join("cpulist"))
+                .ok().and_then(|x| parse_cpu_list(&x)).unwrap_or_default();
+            for info in ret.iter_mut().filter(|x| cpus.contains(&x.cpu)) {
+                info.numa_id = numa_id;
+            }
+        }
+    }
+
+    // Tell the efficiency cores of hybrid CPUs apart
+    let efficiency = efficiency_processors(&ret);
+    for info in ret.iter_mut().filter(|x| efficiency.contains(&x.cpu)) {
+        info.core_type = CoreType::Efficiency;
+    }
+
     ret
 }
 
+/// Get the OS indices of the logical processors among `processors` which
+/// are part of efficiency cores, none if the CPU isn't hybrid
+#[cfg(target_os="linux")]
+fn efficiency_processors(processors: &[LogicalProcessor]) -> Vec<usize> {
+    // Hybrid Intel CPUs have a PMU per core type
+    if let Some(atom) = std::fs::read_to_string("/sys/devices/cpu_atom/cpus")
+            .ok().and_then(|x| parse_cpu_list(&x)) {
+        return atom;
+    }
+
+    // The scheduler knows the relative capacity of the cores of big.LITTLE
+    // CPUs, anything below the biggest ones is an efficiency core
+    let capacities = processors.iter().map(|x| {
+        std::fs::read_to_string(format!(
+            "/sys/devices/system/cpu/cpu{}/cpu_capacity", x.cpu)).ok()?
+            .trim().parse::<u64>().ok().map(|capacity| (x.cpu, capacity))
+    }).collect::<Option<Vec<_>>>();
+    if let Some(capacities) = capacities {
+        let max = capacities.iter().map(|x| x.1).max().unwrap_or(0);
+        return capacities.iter().filter(|x| x.1 < max).map(|x| x.0)
+            .collect();
+    }
+
+    hybrid_cpuid_atoms(processors)
+}
+
+/// Get the OS indices of the logical processors among `processors` whose
+/// core type (CPUID leaf 0x1A) is Atom, running on each of them in turn from
+/// a thread of its own. Only hybrid CPUs (CPUID leaf 7 EDX bit 15) have
+/// any.
+#[cfg(all(target_os="linux", target_arch="x86_64"))]
+fn hybrid_cpuid_atoms(processors: &[LogicalProcessor]) -> Vec<usize> {
+    use core::arch::x86_64::{__cpuid, __cpuid_count};
+
+    /// Core type of the Atom cores, in bits 31:24 of EAX of leaf 0x1A
+    const CORE_TYPE_ATOM: u32 = 0x20;
+
+    if __cpuid(0).eax < 0x1a || __cpuid_count(7, 0).edx & (1 << 15) == 0 {
+        return Vec::new();
+    }
+
+    let processors = processors.to_vec();
+    std::thread::spawn(move || {
+        processors.iter().filter(|&&processor| {
+            pin_to_logical_processor(processor).is_ok() &&
+                __cpuid(0x1a).eax >> 24 == CORE_TYPE_ATOM
+        }).map(|x| x.cpu).collect()
+    }).join().unwrap_or_default()
+}
+
+/// Core types can only be read from CPUID on x86
+#[cfg(all(target_os="linux", not(target_arch="x86_64")))]
+fn hybrid_cpuid_atoms(_processors: &[LogicalProcessor]) -> Vec<usize> {
+    Vec::new()
+}
+
+/// Pin the current thread to a specific logical processor. macOS has no
+/// hard affinity, this only gives the scheduler a hint to keep threads with
+/// different affinity tags apart (and it's ignored on Apple Silicon)
+#[cfg(target_os="macos")]
+pub fn pin_to_logical_processor(processor: LogicalProcessor) -> Result<()> {
+    let mut policy = libc::thread_affinity_policy {
+        affinity_tag: processor.cpu as libc::integer_t + 1,
+    };
+
+    unsafe {
+        libc::thread_policy_set(libc::mach_thread_self(),
+            libc::THREAD_AFFINITY_POLICY as libc::thread_policy_flavor_t,
+            &mut policy as *mut _ as libc::thread_policy_t,
+            libc::THREAD_AFFINITY_POLICY_COUNT);
+    }
+
+    Ok(())
+}
+
+/// Pin the current thread to a specific logical processor
+#[cfg(target_os="freebsd")]
+pub fn pin_to_logical_processor(processor: LogicalProcessor) -> Result<()> {
+    unsafe {
+        let mut set: libc::cpuset_t = std::mem::zeroed();
+        libc::CPU_SET(processor.cpu, &mut set);
+
+        // An ID of -1 is the calling thread
+        Error::check(Syscall::CpusetSetaffinity, libc::cpuset_setaffinity(
+            libc::CPU_LEVEL_WHICH, libc::CPU_WHICH_TID, -1,
+            std::mem::size_of_val(&set), &set))?;
+    }
+
+    Ok(())
+}
+
+/// Read an integer `sysctl` by `name`, which must be null terminated
+#[cfg(any(target_os="macos", target_os="freebsd"))]
+fn sysctl_int(name: &[u8]) -> usize {
+    let mut value: libc::c_int = 0;
+    let mut size = std::mem::size_of_val(&value);
+
+    unsafe {
+        assert!(libc::sysctlbyname(name.as_ptr() as *const _,
+            &mut value as *mut _ as *mut libc::c_void, &mut size,
+            std::ptr::null_mut(), 0) == 0, "Failed to get CPU count");
+    }
+
+    value as usize
+}
+
+#[cfg(target_os="macos")]
+pub fn get_logical_processors() -> Vec<LogicalProcessor> {
+    let count    = sysctl_int(b"hw.logicalcpu\0");
+    let physical = sysctl_int(b"hw.physicalcpu\0").max(1);
+
+    // macOS does not expose which logical processors are SMT siblings, but
+    // Intel Macs number the siblings of a core next to each other
+    let per_core = (count / physical).max(1);
+
+    (0..count).map(|cpu| {
+        LogicalProcessor {
+            cpu,
+            core:      cpu / per_core,
+            smt_index: cpu % per_core,
+            ..Default::default()
+        }
+    }).collect()
+}
+
+/// Get a list of all logical processors on the system. FreeBSD only
+/// describes the topology as XML (`kern.sched.topology_spec`), each logical
+/// processor is treated as a core of its own.
+#[cfg(target_os="freebsd")]
+pub fn get_logical_processors() -> Vec<LogicalProcessor> {
+    (0..sysctl_int(b"hw.ncpu\0")).map(|cpu| {
+        LogicalProcessor {
+            cpu,
+            core: cpu,
+            ..Default::default()
+        }
+    }).collect()
+}
+
 /// Get a list of all logical processors on the system
 #[cfg(target_os="windows")]
-pub fn get_logical_processors() -> Vec<NumaInfo>
+pub fn get_logical_processors() -> Vec<LogicalProcessor>
 {
     let mut ret = Vec::new();
 
+    let cores    = processor_relationships(RELATION_PROCESSOR_CORE);
+    let packages = processor_relationships(RELATION_PROCESSOR_PACKAGE);
+    let max_class = cores.iter().map(|x| x.0).max().unwrap_or(0);
+
     /* Support up to 64 groups, each group contains up to 64 logical
      * processors
      */
@@ -114,9 +679,39 @@ pub fn get_logical_processors() -> Vec<NumaInfo>
             };
 
             if let Some(numa_id) = get_numa_node_id(procnum) {
-                let ent = NumaInfo {
+                // Find the core and package the processor is part of
+                let contains = |masks: &Vec<GROUP_AFFINITY>| {
+                    masks.iter().any(|x| x.Group == group &&
+                                     x.Mask & (1u64 << number) != 0)
+                };
+                let core = cores.iter().position(|x| contains(&x.1))
+                    .unwrap_or(cores.len() + ret.len());
+                let socket = packages.iter().position(|x| contains(&x.1))
+                    .unwrap_or(0) as u16;
+
+                // Siblings are ordered by their number within the group
+                let smt_index = cores.get(core).and_then(|x| {
+                    x.1.iter().find(|x| x.Group == group)
+                }).map(|x| {
+                    (x.Mask & ((1u64 << number) - 1)).count_ones() as usize
+                }).unwrap_or(0);
+
+                // Higher efficiency classes are more performant, anything
+                // below the highest is an efficiency core
+                let core_type = match cores.get(core) {
+                    Some(&(class, _)) if class < max_class =>
+                        CoreType::Efficiency,
+                    _ => CoreType::Performance,
+                };
+
+                let ent = LogicalProcessor {
                     procnum,
                     numa_id,
+                    cpu: ret.len(),
+                    socket,
+                    core,
+                    smt_index,
+                    core_type,
                 };
 
                 ret.push(ent);
@@ -0,0 +1,253 @@
+//! Cross-platform view of the CPU topology: sockets, NUMA nodes, physical
+//! cores and their SMT siblings
+
+use std::collections::BTreeSet;
+
+use crate::threading::{self, CoreType, LogicalProcessor};
+
+/// Order in which logical processors are assigned to workers
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PinStrategy {
+    /// Sequential OS indices, which often packs SMT siblings together
+    Compact,
+
+    /// Fill every physical core before using any SMT siblings
+    PhysicalFirst,
+
+    /// Round-robin over NUMA nodes, physical cores first within each node
+    Scatter,
+}
+
+impl std::str::FromStr for PinStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "compact"        => Ok(PinStrategy::Compact),
+            "physical-first" => Ok(PinStrategy::PhysicalFirst),
+            "scatter"        => Ok(PinStrategy::Scatter),
+            _ => Err(format!("Unknown pinning strategy `{}`", s)),
+        }
+    }
+}
+
+impl PinStrategy {
+    /// Name of the strategy, as selected on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            PinStrategy::Compact       => "compact",
+            PinStrategy::PhysicalFirst => "physical-first",
+            PinStrategy::Scatter       => "scatter",
+        }
+    }
+}
+
+/// Classes of cores of hybrid CPUs workers are run on
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CoreSelection {
+    /// Any core, mixing both classes on hybrid CPUs
+    Mixed,
+
+    /// Only performance cores
+    POnly,
+
+    /// Only efficiency cores
+    EOnly,
+}
+
+impl std::str::FromStr for CoreSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mixed"  => Ok(CoreSelection::Mixed),
+            "p-only" => Ok(CoreSelection::POnly),
+            "e-only" => Ok(CoreSelection::EOnly),
+            _ => Err(format!("Unknown core selection `{}`", s)),
+        }
+    }
+}
+
+impl CoreSelection {
+    /// Name of the selection, as selected on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            CoreSelection::Mixed => "mixed",
+            CoreSelection::POnly => "p-only",
+            CoreSelection::EOnly => "e-only",
+        }
+    }
+
+    /// Class of cores selected, `None` if both are
+    pub fn core_type(&self) -> Option<CoreType> {
+        match self {
+            CoreSelection::Mixed => None,
+            CoreSelection::POnly => Some(CoreType::Performance),
+            CoreSelection::EOnly => Some(CoreType::Efficiency),
+        }
+    }
+}
+
+/// NUMA node the statistics shared by the workers are placed on
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StatsPlacement {
+    /// Wherever the kernel allocates them (or `--mbind-node`)
+    Default,
+
+    /// The node of the first worker
+    Local,
+
+    /// A node none of the workers run on, as if the coordination state of
+    /// a fuzzer was set up by a thread on the wrong socket
+    Remote,
+}
+
+impl std::str::FromStr for StatsPlacement {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(StatsPlacement::Default),
+            "local"   => Ok(StatsPlacement::Local),
+            "remote"  => Ok(StatsPlacement::Remote),
+            _ => Err(format!("Unknown statistics placement `{}`", s)),
+        }
+    }
+}
+
+impl StatsPlacement {
+    /// Name of the placement, as selected on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            StatsPlacement::Default => "default",
+            StatsPlacement::Local   => "local",
+            StatsPlacement::Remote  => "remote",
+        }
+    }
+}
+
+/// Logical processors of the system along with where they sit in the
+/// socket, NUMA node, core and SMT hierarchy
+#[derive(Clone, Debug)]
+pub struct Topology {
+    /// All logical processors, ordered by OS index
+    processors: Vec<LogicalProcessor>,
+}
+
+impl Topology {
+    /// Detect the topology of the current system
+    pub fn detect() -> Self {
+        let mut processors = threading::get_logical_processors();
+        processors.sort_by_key(|x| x.cpu());
+
+        Topology { processors }
+    }
+
+    /// Restrict the topology to the logical processors allowed by the
+    /// affinity mask this process was started with
+    pub fn allowed(&self) -> Self {
+        let allowed = match threading::get_allowed_processors() {
+            Some(allowed) => allowed,
+            None          => return self.clone(),
+        };
+
+        Topology {
+            processors: self.processors.iter()
+                .filter(|x| allowed.contains(&x.cpu())).copied().collect(),
+        }
+    }
+
+    /// Restrict the topology to the logical processors of NUMA node `node`
+    pub fn numa_node(&self, node: u16) -> Self {
+        Topology {
+            processors: self.processors.iter()
+                .filter(|x| x.numa_id() == node).copied().collect(),
+        }
+    }
+
+    /// Restrict the topology to the logical processors of cores of class
+    /// `core_type`
+    pub fn core_type(&self, core_type: CoreType) -> Self {
+        Topology {
+            processors: self.processors.iter()
+                .filter(|x| x.core_type() == core_type).copied().collect(),
+        }
+    }
+
+    /// All logical processors, ordered by OS index
+    pub fn processors(&self) -> &[LogicalProcessor] {
+        &self.processors
+    }
+
+    /// Number of sockets
+    pub fn sockets(&self) -> usize {
+        self.processors.iter().map(|x| x.socket())
+            .collect::<BTreeSet<_>>().len()
+    }
+
+    /// Number of NUMA nodes
+    pub fn numa_nodes(&self) -> usize {
+        self.processors.iter().map(|x| x.numa_id())
+            .collect::<BTreeSet<_>>().len()
+    }
+
+    /// Lowest NUMA node none of `workers` belong to, if any
+    pub fn remote_node(&self, workers: &[LogicalProcessor]) -> Option<u16> {
+        let local: BTreeSet<u16> =
+            workers.iter().map(|x| x.numa_id()).collect();
+        self.processors.iter().map(|x| x.numa_id())
+            .filter(|x| !local.contains(x)).min()
+    }
+
+    /// Number of logical processors of efficiency cores, 0 unless the CPU
+    /// is hybrid
+    pub fn efficiency_processors(&self) -> usize {
+        self.processors.iter()
+            .filter(|x| x.core_type() == CoreType::Efficiency).count()
+    }
+
+    /// Number of physical cores
+    pub fn physical_cores(&self) -> usize {
+        self.processors.iter().map(|x| x.core())
+            .collect::<BTreeSet<_>>().len()
+    }
+
+    /// Number of logical processors
+    pub fn logical_processors(&self) -> usize {
+        self.processors.len()
+    }
+
+    /// Logical processors in the order they should be assigned to workers
+    /// according to `strategy`
+    pub fn ordered(&self, strategy: PinStrategy) -> Vec<LogicalProcessor> {
+        let mut ret = self.processors.clone();
+
+        match strategy {
+            PinStrategy::Compact => {}
+            PinStrategy::PhysicalFirst => {
+                ret.sort_by_key(|x| (x.smt_index(), x.cpu()));
+            }
+            PinStrategy::Scatter => {
+                // Order each node physical-first, then interleave the nodes
+                ret.sort_by_key(|x| (x.numa_id(), x.smt_index(), x.cpu()));
+
+                let mut nodes: Vec<Vec<LogicalProcessor>> = Vec::new();
+                for info in ret.drain(..) {
+                    match nodes.last_mut() {
+                        Some(node) if node[0].numa_id() == info.numa_id() =>
+                            node.push(info),
+                        _ => nodes.push(vec![info]),
+                    }
+                }
+
+                let longest = nodes.iter().map(|x| x.len()).max()
+                    .unwrap_or(0);
+                for ii in 0..longest {
+                    ret.extend(nodes.iter().filter_map(|x| x.get(ii)));
+                }
+            }
+        }
+
+        ret
+    }
+}
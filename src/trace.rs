@@ -0,0 +1,375 @@
+//! Kernel-side timing of process creation from scheduler and syscall
+//! tracepoints, telling the cost of the fork path in the kernel apart from
+//! the delay until the new process gets to run
+//!
+//! With `--trace-fork` each test is traced in an ftrace instance of its own,
+//! limited to the benchmark and everything it creates (`set_event_pid` with
+//! `event-fork`). Once the workers exited, the trace is matched up:
+//!
+//! - the kernel fork time is from entering `clone()`, `clone3()`, `fork()`
+//!   or `vfork()` to `sched_process_fork`, which fires once the child is
+//!   fully set up by `copy_process()`
+//! - the scheduling delay is from `sched_wakeup_new` of the child to the
+//!   first `sched_switch` to it
+//!
+//! What's left of the `creation_ns` the workers observe is returning to user
+//! space and the rest of the backend. The trace buffer keeps the latest
+//! events, so long tests are sampled by their end. Timestamps are only
+//! precise to a microsecond, averaged over many forks that's plenty. Tracing
+//! needs a writable tracefs, ie. root.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+
+/// Number of fields of `Timing`
+pub const FIELDS: usize = 3;
+
+/// Names of the fields of `Timing`, as reported in the results
+pub const NAMES: [&str; FIELDS] = [
+    "kernel_fork_ns",
+    "sched_delay_ns",
+    "traced_forks",
+];
+
+/// Size of the trace buffer of each logical processor in KiB
+const BUFFER_KIB: usize = 4096;
+
+/// Syscalls which create a process, whichever of them the kernel has entry
+/// tracepoints for are traced
+const SYSCALLS: [&str; 4] = ["clone", "clone3", "fork", "vfork"];
+
+/// Scheduler tracepoints which are traced
+const SCHED_EVENTS: [&str; 3] =
+    ["sched_process_fork", "sched_wakeup_new", "sched_switch"];
+
+/// Kernel-side timing of the processes created during a test
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Timing {
+    /// Average nanoseconds from entering the syscall to the child being set
+    /// up, `None` if no syscall entries were traced
+    pub fork_ns: Option<f64>,
+
+    /// Average nanoseconds from waking up a new child until it first ran,
+    /// `None` if none were seen running
+    pub sched_delay_ns: Option<f64>,
+
+    /// Number of process creations traced
+    pub forks: u64,
+}
+
+impl Timing {
+    /// All fields, in the order of `NAMES`
+    pub fn fields(&self) -> [Option<f64>; FIELDS] {
+        [self.fork_ns, self.sched_delay_ns, Some(self.forks as f64)]
+    }
+
+    /// Create from all fields, in the order of `NAMES`
+    pub fn from_fields(fields: [Option<f64>; FIELDS]) -> Self {
+        Timing {
+            fork_ns:        fields[0],
+            sched_delay_ns: fields[1],
+            forks:          fields[2].unwrap_or(0.) as u64,
+        }
+    }
+}
+
+/// Get the tracefs mount, preferring the standalone one
+fn tracefs() -> io::Result<PathBuf> {
+    ["/sys/kernel/tracing", "/sys/kernel/debug/tracing"].iter()
+        .map(PathBuf::from)
+        .find(|x| x.join("instances").is_dir())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound,
+            "tracefs isn't mounted at /sys/kernel/tracing"))
+}
+
+/// Split a line of the text trace into the PID of the task it was recorded
+/// in, its timestamp in seconds and the event with its fields
+fn parse_line(line: &str) -> Option<(u32, f64, &str)> {
+    // `<comm>-<pid> [<cpu>] <flags> <secs>: <event>`, where the name of the
+    // task may contain anything, even something which looks like the CPU
+    let (pid, rest) = line.match_indices(" [").find_map(|(pos, _)| {
+        let task = line[..pos].trim_end();
+        let pid  = task[task.rfind('-')? + 1..].parse().ok()?;
+
+        let rest = &line[pos + 2..];
+        let end  = rest.find(']')?;
+        (end > 0 && rest[..end].bytes().all(|x| x.is_ascii_digit()))
+            .then(|| (pid, &rest[end + 1..]))
+    })?;
+
+    let (secs, event) = rest.split_once(": ")?;
+    let secs = secs.split_whitespace().last()?.parse().ok()?;
+    Some((pid, secs, event))
+}
+
+/// Get the numeric field `key` of the fields of an event
+fn field(fields: &str, key: &str) -> Option<u32> {
+    let start = fields.match_indices(key)
+        .map(|(pos, _)| pos)
+        .find(|&pos| pos == 0 || fields[..pos].ends_with(' '))? + key.len();
+    let value = fields[start..].strip_prefix('=')?;
+    let end = value.find(|x: char| !x.is_ascii_digit())
+        .unwrap_or(value.len());
+    value[..end].parse().ok()
+}
+
+/// Match up the events of the text trace `trace`, leaving out processes
+/// created and woken up by `ignore` (the benchmark itself creating the
+/// workers)
+fn analyze(trace: &str, ignore: u32) -> Timing {
+    // Pending syscall entries and wakeups, by the task they're waiting on
+    let mut entered: HashMap<u32, f64> = HashMap::new();
+    let mut woken:   HashMap<u32, f64> = HashMap::new();
+
+    let mut fork  = (0., 0u64);
+    let mut delay = (0., 0u64);
+    let mut forks = 0;
+    for (pid, secs, event) in trace.lines().filter_map(parse_line) {
+        if let Some(call) = event.strip_prefix("sys_") {
+            let call = &call[..call.find('(').unwrap_or(call.len())];
+            if SYSCALLS.contains(&call) {
+                entered.insert(pid, secs);
+            }
+        } else if event.starts_with("sched_process_fork: ") {
+            let start = entered.remove(&pid);
+            if pid == ignore {
+                continue;
+            }
+            forks += 1;
+            if let Some(start) = start {
+                fork.0 += secs - start;
+                fork.1 += 1;
+            }
+        } else if let Some(fields) = event.strip_prefix("sched_wakeup_new: ") {
+            // The new task is woken up by the task which created it
+            if pid == ignore {
+                continue;
+            }
+            if let Some(child) = field(fields, "pid") {
+                woken.insert(child, secs);
+            }
+        } else if let Some(fields) = event.strip_prefix("sched_switch: ") {
+            let next = fields.rfind("==>")
+                .and_then(|pos| field(&fields[pos + 3..], "next_pid"));
+            if let Some(start) = next.and_then(|x| woken.remove(&x)) {
+                delay.0 += secs - start;
+                delay.1 += 1;
+            }
+        }
+    }
+
+    let mean = |(sum, count): (f64, u64)| {
+        (count > 0).then(|| sum * 1e9 / count as f64)
+    };
+    Timing { fork_ns: mean(fork), sched_delay_ns: mean(delay), forks }
+}
+
+/// An ftrace instance tracing a test, removed when dropped
+pub struct Session {
+    /// Directory of the instance
+    dir: PathBuf,
+}
+
+impl Session {
+    /// Create an instance tracing process creation by the benchmark and
+    /// everything it creates from now on
+    pub fn start() -> io::Result<Self> {
+        let root = tracefs()?;
+        let dir = root.join("instances")
+            .join(format!("forkbench-{}", std::process::id()));
+
+        // A previous test may have been interrupted before cleaning up
+        if dir.exists() {
+            std::fs::remove_dir(&dir)?;
+        }
+        std::fs::create_dir(&dir).map_err(|err| io::Error::new(err.kind(),
+            format!("Failed to create ftrace instance `{}`: {}",
+                    dir.display(), err)))?;
+        let session = Session { dir };
+
+        let write = |file: &str, value: &str| {
+            std::fs::write(session.dir.join(file), value)
+                .map_err(|err| io::Error::new(err.kind(),
+                    format!("Failed to write `{}` of the ftrace instance: \
+                             {}", file, err)))
+        };
+        write("tracing_on", "0")?;
+        write("buffer_size_kb", &BUFFER_KIB.to_string())?;
+        write("set_event_pid", &std::process::id().to_string())?;
+        write("options/event-fork", "1")?;
+        for event in SCHED_EVENTS {
+            write(&format!("events/sched/{}/enable", event), "1")?;
+        }
+
+        // Syscall tracepoints need `CONFIG_FTRACE_SYSCALLS`, without them
+        // the kernel fork time is unavailable
+        for call in SYSCALLS {
+            let enable = session.dir.join(format!(
+                "events/syscalls/sys_enter_{}/enable", call));
+            if enable.exists() {
+                std::fs::write(enable, "1")?;
+            }
+        }
+
+        write("tracing_on", "1")?;
+        Ok(session)
+    }
+
+    /// Stop tracing and get the timing of what was traced
+    pub fn finish(self) -> io::Result<Timing> {
+        std::fs::write(self.dir.join("tracing_on"), "0")?;
+        let trace = std::fs::read_to_string(self.dir.join("trace"))?;
+        Ok(analyze(&trace, std::process::id()))
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        // Events have to be disabled before the instance can be removed
+        let _ = std::fs::write(self.dir.join("events/enable"), "0");
+        if let Err(err) = std::fs::remove_dir(&self.dir) {
+            eprint!("warning: failed to remove ftrace instance `{}`: {}\n",
+                    self.dir.display(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Trace of a task with an awkward name forking `/bin/true` twice, as
+    /// captured from an instance set up like `Session::start()` does (less
+    /// the padding in front of the task names)
+    const CAPTURED: &str = "\
+    # tracer: nop\n\
+    #\n\
+    # entries-in-buffer/entries-written: 10/10   #P:1\n\
+    #\n\
+    #                                _-----=> irqs-off/BH-disabled\n\
+    #                               / _----=> need-resched\n\
+    #                              | / _---=> hardirq/softirq\n\
+    #                              || / _--=> preempt-depth\n\
+    #                              ||| / _-=> migrate-disable\n\
+    #                              |||| /     delay\n\
+    #           TASK-PID     CPU#  |||||  TIMESTAMP  FUNCTION\n\
+    #              | |         |   |||||     |         |\n\
+    a b-[c] d-14479   [000] ..... 11032.771133: sys_clone(clone_flags: \
+        0x1200011, newsp: 0, parent_tidptr: 0, child_tidptr: 0x7f6f7c0bba10, \
+        tls: 0)\n\
+    a b-[c] d-14479   [000] ..... 11032.771189: sched_process_fork: comm=a \
+        b-[c] d pid=14479 child_comm=a b-[c] d child_pid=14480\n\
+    a b-[c] d-14479   [000] dN.2. 11032.771193: sched_wakeup_new: comm=a \
+        b-[c] d pid=14480 prio=120 target_cpu=000\n\
+    a b-[c] d-14479   [000] d..2. 11032.771197: sched_switch: prev_comm=a \
+        b-[c] d prev_pid=14479 prev_prio=120 prev_state=R ==> next_comm=a \
+        b-[c] d next_pid=14480 next_prio=120\n\
+    true-14480   [000] d..2. 11032.771708: sched_switch: prev_comm=true \
+        prev_pid=14480 prev_prio=120 prev_state=Z ==> next_comm=a b-[c] d \
+        next_pid=14479 next_prio=120\n\
+    a b-[c] d-14479   [000] ..... 11032.771748: sys_clone(clone_flags: \
+        0x1200011, newsp: 0, parent_tidptr: 0, child_tidptr: 0x7f6f7c0bba10, \
+        tls: 0)\n\
+    a b-[c] d-14479   [000] ..... 11032.771784: sched_process_fork: comm=a \
+        b-[c] d pid=14479 child_comm=a b-[c] d child_pid=14481\n\
+    a b-[c] d-14479   [000] d..2. 11032.771786: sched_wakeup_new: comm=a \
+        b-[c] d pid=14481 prio=120 target_cpu=000\n\
+    a b-[c] d-14479   [000] d..2. 11032.771821: sched_switch: prev_comm=a \
+        b-[c] d prev_pid=14479 prev_prio=120 prev_state=S ==> next_comm=a \
+        b-[c] d next_pid=14481 next_prio=120\n\
+    true-14481   [000] d..2. 11032.772297: sched_switch: prev_comm=true \
+        prev_pid=14481 prev_prio=120 prev_state=Z ==> next_comm=a b-[c] d \
+        next_pid=14479 next_prio=120\n\
+    ";
+
+    /// Check `actual` is within a nanosecond of `expected`
+    fn assert_ns(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("No timing");
+        assert!((actual - expected).abs() < 1., "{} isn't {}", actual,
+                expected);
+    }
+
+    #[test]
+    fn parses_lines() {
+        let line = CAPTURED.lines().nth(12).unwrap();
+        assert_eq!(parse_line(line), Some((14479, 11032.771133,
+            "sys_clone(clone_flags: 0x1200011, newsp: 0, parent_tidptr: 0, \
+             child_tidptr: 0x7f6f7c0bba10, tls: 0)")));
+
+        // Names with dashes, spaces and what looks like a CPU
+        assert_eq!(parse_line("   kworker/0:1-events-12 [003] d..2. \
+                               7.5: sched_switch: x"),
+                   Some((12, 7.5, "sched_switch: x")));
+        assert_eq!(parse_line("   x [7] y-77   [001] ..... 1.25: \
+                               sched_process_fork: comm=x [7] y pid=77"),
+                   Some((77, 1.25, "sched_process_fork: comm=x [7] y \
+                                     pid=77")));
+
+        // Neither the header nor garbage are events
+        for line in CAPTURED.lines().take(12) {
+            assert_eq!(parse_line(line), None);
+        }
+        assert_eq!(parse_line(""), None);
+        assert_eq!(parse_line("  task-x [000] ..... 1.0: event"), None);
+    }
+
+    #[test]
+    fn reads_fields() {
+        let switch = "prev_comm=a b pid=3 prev_pid=14479 prev_state=R ==> \
+                      next_comm=x next_pid=14480 next_prio=120";
+        assert_eq!(field(switch, "prev_pid"), Some(14479));
+        assert_eq!(field(switch, "pid"), Some(3));
+        assert_eq!(field(switch, "next_pid"), Some(14480));
+        assert_eq!(field(switch, "tid"), None);
+    }
+
+    #[test]
+    fn analyzes_captured() {
+        let timing = analyze(CAPTURED, 1);
+        assert_eq!(timing.forks, 2);
+
+        // 56 and 36 µs in `clone()`, the children ran 4 and 35 µs after
+        // being woken up
+        assert_ns(timing.fork_ns, 46_000.);
+        assert_ns(timing.sched_delay_ns, 19_500.);
+    }
+
+    #[test]
+    fn ignores_benchmark() {
+        // Everything was created by the ignored task
+        let timing = analyze(CAPTURED, 14479);
+        assert_eq!(timing, Timing { fork_ns: None, sched_delay_ns: None,
+                                    forks: 0 });
+    }
+
+    #[test]
+    fn matches_syscall_entries() {
+        // The benchmark (100) creates a worker, whose forks overlap with
+        // those of another worker on another CPU
+        let trace = "\
+        forkbench-100 [000] ..... 1.000000: sys_clone3(uargs: 0x7ffd, size: \
+            0x58)\n\
+        forkbench-100 [000] ..... 1.000500: sched_process_fork: \
+            comm=forkbench pid=100 child_comm=forkbench child_pid=101\n\
+        forkbench-100 [000] d..2. 1.000510: sched_wakeup_new: comm=forkbench \
+            pid=101 prio=120 target_cpu=001\n\
+        forkbench-101 [001] ..... 2.000000: sys_close(fd: 3)\n\
+        forkbench-101 [001] ..... 2.000010: sys_vfork()\n\
+        forkbench-102 [002] ..... 2.000020: sys_clone(clone_flags: \
+            0x1200011)\n\
+        forkbench-101 [001] ..... 2.000030: sched_process_fork: \
+            comm=forkbench pid=101 child_comm=forkbench child_pid=103\n\
+        forkbench-102 [002] ..... 2.000060: sched_process_fork: \
+            comm=forkbench pid=102 child_comm=forkbench child_pid=104\n\
+        forkbench-102 [002] ..... 2.000100: sched_process_fork: \
+            comm=forkbench pid=102 child_comm=forkbench child_pid=105\n\
+        ";
+        let timing = analyze(trace, 100);
+
+        // The last fork had no syscall entry traced, it still counts
+        assert_eq!(timing.forks, 3);
+        assert_ns(timing.fork_ns, 30_000.);
+        assert_eq!(timing.sched_delay_ns, None);
+    }
+}
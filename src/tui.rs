@@ -0,0 +1,242 @@
+//! Live dashboard of the sweep, drawn on stderr with ANSI escape sequences
+//!
+//! The dashboard takes over the alternate screen of the terminal and shows
+//! the test point which is running, the fork rate of each of its workers
+//! (sampled from their statistics slots in shared memory) and a sparkline
+//! of the efficiency of the test points completed so far. Messages logged
+//! while it's shown are printed again once the terminal is restored.
+
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::sampler::{self, Watch};
+
+/// Interval the fork rates are sampled and the dashboard is redrawn at
+const REFRESH: Duration = Duration::from_millis(500);
+
+/// Sparkline glyphs, from lowest to highest
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Width of the cell showing a single worker
+const CELL_WIDTH: usize = 20;
+
+/// Number of logged messages shown at the bottom of the dashboard
+const MESSAGES: usize = 4;
+
+/// Lines of the dashboard which aren't used by worker cells
+const FIXED_LINES: usize = 10 + MESSAGES;
+
+/// State of the sweep shared with the thread drawing the dashboard
+#[derive(Default)]
+struct State {
+    /// Progress of the sweep, describing the running test point
+    status: String,
+
+    /// Mean efficiency of each completed test point, in sweep order
+    efficiency: Vec<f64>,
+
+    /// Messages (eg. failed test points) logged during the sweep
+    messages: Vec<String>,
+}
+
+/// Live view of the sweep, does nothing unless `--tui` was given
+pub struct Dashboard {
+    /// Dashboard is shown
+    enabled: bool,
+
+    /// Logical processor each worker is pinned to, indexed by worker ID
+    cpus: Vec<usize>,
+
+    /// State of the sweep
+    state: Arc<Mutex<State>>,
+}
+
+/// Get the size of the terminal on stderr as `(columns, rows)`
+#[cfg(unix)]
+fn terminal_size() -> (usize, usize) {
+    let mut size: libc::winsize = unsafe { core::mem::zeroed() };
+    let ret = unsafe {
+        libc::ioctl(libc::STDERR_FILENO, libc::TIOCGWINSZ, &mut size)
+    };
+    if ret == 0 && size.ws_col > 0 && size.ws_row > 0 {
+        (size.ws_col as usize, size.ws_row as usize)
+    } else {
+        (80, 24)
+    }
+}
+
+/// Get the size of the terminal on stderr as `(columns, rows)`
+#[cfg(not(unix))]
+fn terminal_size() -> (usize, usize) {
+    (80, 24)
+}
+
+/// Render `values` as a sparkline of at most `width` glyphs, the most
+/// recent values are kept. Scaled from 0 to the highest value.
+fn sparkline(values: &[f64], width: usize) -> String {
+    let top = values.iter().copied().fold(f64::MIN_POSITIVE, f64::max);
+    values[values.len().saturating_sub(width)..].iter().map(|&x| {
+        let level = (x.max(0.0) / top * SPARKS.len() as f64) as usize;
+        SPARKS[level.min(SPARKS.len() - 1)]
+    }).collect()
+}
+
+/// Draw a single frame of the dashboard on stderr, `rates` are the fork
+/// rates of the workers of the running test point
+fn draw(state: &State, cpus: &[usize], rates: &[f64]) {
+    let (columns, rows) = terminal_size();
+    let mut lines = Vec::new();
+
+    // Running test point
+    lines.push(format!("\x1b[1mforkbench\x1b[0m {}", state.status));
+    lines.push(String::new());
+
+    // Fork rate of each worker, in as many columns as fit
+    let total: f64 = rates.iter().sum();
+    lines.push(format!("\x1b[1mfork rate\x1b[0m {:.0}/s total, {:.0}/s per \
+        worker (worker/cpu forks/s)", total,
+        total / rates.len().max(1) as f64));
+    let per_line = (columns / CELL_WIDTH).max(1);
+    let max_lines = rows.saturating_sub(FIXED_LINES).max(1);
+    let cells: Vec<String> = rates.iter().enumerate().map(|(thr_id, rate)| {
+        format!("{:>5}/{:<4}{:>10.0}", thr_id, cpus[thr_id], rate)
+    }).collect();
+    let chunks: Vec<&[String]> = cells.chunks(per_line).collect();
+    for chunk in chunks.iter().take(max_lines) {
+        lines.push(chunk.concat());
+    }
+    if chunks.len() > max_lines {
+        lines.push(format!("  ... and {} more workers",
+                           rates.len() - max_lines * per_line));
+    }
+    lines.push(String::new());
+
+    // Efficiency of the completed test points
+    lines.push(format!("\x1b[1mefficiency\x1b[0m of {} completed test points",
+                       state.efficiency.len()));
+    match state.efficiency.last() {
+        Some(last) => {
+            let min = state.efficiency.iter().copied().fold(f64::MAX, f64::min);
+            let max = state.efficiency.iter().copied().fold(0.0, f64::max);
+            lines.push(sparkline(&state.efficiency, columns));
+            lines.push(format!("last {:.4}, min {:.4}, max {:.4}", last, min,
+                               max));
+        }
+        None => {
+            lines.push(String::new());
+            lines.push(String::new());
+        }
+    }
+    lines.push(String::new());
+
+    // Most recent messages
+    let skip = state.messages.len().saturating_sub(MESSAGES);
+    lines.extend(state.messages[skip..].iter().cloned());
+
+    // Redraw in place, clearing whatever was left of the last frame
+    let mut frame = String::from("\x1b[H");
+    for line in lines.iter().take(rows) {
+        frame += line;
+        frame += "\x1b[K\n";
+    }
+    frame += "\x1b[J";
+
+    let mut stderr = std::io::stderr().lock();
+    let _ = stderr.write_all(frame.as_bytes());
+    let _ = stderr.flush();
+}
+
+impl Dashboard {
+    /// Create the dashboard, taking over the terminal if `--tui` was given
+    pub fn new(config: &Config) -> Self {
+        if config.tui {
+            // Switch to the alternate screen and hide the cursor
+            eprint!("\x1b[?1049h\x1b[?25l\x1b[H\x1b[J");
+        }
+
+        Dashboard {
+            enabled: config.tui,
+            cpus:    config.processors.iter().map(|x| x.cpu()).collect(),
+            state:   Arc::new(Mutex::new(State::default())),
+        }
+    }
+
+    /// Set the progress of the sweep shown for the running test point
+    pub fn status(&self, status: String) {
+        self.state.lock().unwrap().status = status;
+    }
+
+    /// Record the mean efficiency of a completed test point
+    pub fn finish_point(&self, efficiency: f64) {
+        self.state.lock().unwrap().efficiency.push(efficiency);
+    }
+
+    /// Log a message, which is printed right away if the dashboard isn't
+    /// shown
+    pub fn log(&self, message: String) {
+        if self.enabled {
+            self.state.lock().unwrap().messages.push(message);
+        } else {
+            eprint!("{}\n", message);
+        }
+    }
+
+    /// Start redrawing the dashboard while `threads` workers are running,
+    /// see [`sampler`] for when that's allowed
+    pub fn watch(&self, threads: usize) -> Watch {
+        if !self.enabled {
+            return Watch::none();
+        }
+
+        let state = self.state.clone();
+        let cpus  = self.cpus[..threads].to_vec();
+        let shmem = unsafe { crate::shmem::get() };
+        let sample = move || -> Vec<u64> {
+            (0..threads).map(|thr_id| {
+                shmem.slot(thr_id).forks.load(Ordering::Relaxed)
+            }).collect()
+        };
+
+        let mut last  = sample();
+        let mut rates = vec![0.0; threads];
+        let mut drawn = false;
+        Watch::start(REFRESH, move |secs| {
+            // Redraw right away, then with every sample
+            if let Some(secs) = secs {
+                let now = sample();
+                for ((rate, &new), &old) in
+                        rates.iter_mut().zip(now.iter()).zip(last.iter()) {
+                    *rate = sampler::delta(new, old) as f64 / secs;
+                }
+                last = now;
+            } else if drawn {
+                return;
+            }
+            drawn = true;
+            draw(&state.lock().unwrap(), &cpus, &rates);
+        })
+    }
+
+    /// Restore the terminal and print the messages logged while the
+    /// dashboard was shown
+    pub fn finish(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        self.enabled = false;
+
+        eprint!("\x1b[?25h\x1b[?1049l");
+        for message in self.state.lock().unwrap().messages.iter() {
+            eprint!("{}\n", message);
+        }
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
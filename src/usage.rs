@@ -0,0 +1,86 @@
+//! Resource usage of fuzz cases, as returned by `wait4()` when they are
+//! reaped
+//!
+//! Unlike the `perf_event_open()` counters this needs no privileges, but
+//! only covers fuzz cases run in processes of their own. Fuzz cases run in
+//! threads (and on Windows) report no usage.
+
+/// Number of fields of `Usage`
+pub const FIELDS: usize = 6;
+
+/// Names of the fields of `Usage`, as reported in the results
+pub const NAMES: [&str; FIELDS] = [
+    "minor_faults",
+    "major_faults",
+    "voluntary_switches",
+    "involuntary_switches",
+    "user_ns",
+    "system_ns",
+];
+
+/// Resource usage of one or more reaped fuzz cases (and any of their
+/// children they reaped)
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Usage {
+    /// Page faults serviced without any I/O
+    pub minor_faults: u64,
+
+    /// Page faults which required I/O
+    pub major_faults: u64,
+
+    /// Context switches because the fuzz case blocked
+    pub voluntary_switches: u64,
+
+    /// Context switches because the fuzz case was preempted
+    pub involuntary_switches: u64,
+
+    /// Time spent in user mode, in nanoseconds
+    pub user_ns: u64,
+
+    /// Time spent in the kernel, in nanoseconds
+    pub system_ns: u64,
+}
+
+impl Usage {
+    /// All fields, in the order of `NAMES`
+    pub fn fields(&self) -> [u64; FIELDS] {
+        [
+            self.minor_faults,
+            self.major_faults,
+            self.voluntary_switches,
+            self.involuntary_switches,
+            self.user_ns,
+            self.system_ns,
+        ]
+    }
+
+    /// Create from all fields, in the order of `NAMES`
+    pub fn from_fields(fields: [u64; FIELDS]) -> Self {
+        Usage {
+            minor_faults:         fields[0],
+            major_faults:         fields[1],
+            voluntary_switches:   fields[2],
+            involuntary_switches: fields[3],
+            user_ns:              fields[4],
+            system_ns:            fields[5],
+        }
+    }
+}
+
+#[cfg(unix)]
+impl From<&libc::rusage> for Usage {
+    fn from(usage: &libc::rusage) -> Self {
+        let ns = |time: &libc::timeval| {
+            time.tv_sec as u64 * 1_000_000_000 + time.tv_usec as u64 * 1_000
+        };
+
+        Usage {
+            minor_faults:         usage.ru_minflt as u64,
+            major_faults:         usage.ru_majflt as u64,
+            voluntary_switches:   usage.ru_nvcsw as u64,
+            involuntary_switches: usage.ru_nivcsw as u64,
+            user_ns:              ns(&usage.ru_utime),
+            system_ns:            ns(&usage.ru_stime),
+        }
+    }
+}
@@ -0,0 +1,295 @@
+//! Fits of the scaling curves of a sweep to the Universal Scalability Law,
+//! a compact characterization of where process creation serializes
+//!
+//! The USL models the throughput of `N` workers as
+//! `X(N) = λN / (1 + σ(N - 1) + κN(N - 1))`, where `λ` is the throughput of
+//! a single worker, `σ` the contention (the fraction of the work which is
+//! serialized, eg. behind a lock) and `κ` the coherency cost (the crosstalk
+//! between every pair of workers, eg. bouncing cache lines). Its linear form
+//! `N / X(N) = (1 + σ(N - 1) + κN(N - 1)) / λ` is fit to the fork rates of
+//! every curve of a sweep by least squares, which needs at least 3 thread
+//! counts but not necessarily a single thread.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use crate::output::{PointSummary, INSTS_PER_WORKLOAD};
+use crate::sweep::TestPoint;
+
+/// Parameters of the USL fit to a scaling curve
+#[derive(Clone, Copy, Debug)]
+pub struct Fit {
+    /// Fork rate of a single worker
+    pub lambda: f64,
+
+    /// Contention, the serialized fraction
+    pub sigma: f64,
+
+    /// Coherency cost between every pair of workers
+    pub kappa: f64,
+
+    /// Coefficient of determination of the modeled fork rates
+    pub r2: f64,
+}
+
+impl Fit {
+    /// Fork rate the model predicts for `threads` workers
+    pub fn throughput(&self, threads: f64) -> f64 {
+        self.lambda * threads / (1. + self.sigma * (threads - 1.) +
+            self.kappa * threads * (threads - 1.))
+    }
+
+    /// Number of workers the fork rate peaks at, `None` if it doesn't as
+    /// there's no coherency cost (or everything is serialized)
+    pub fn peak(&self) -> Option<f64> {
+        (self.kappa > 0. && self.sigma < 1.)
+            .then(|| ((1. - self.sigma) / self.kappa).sqrt())
+    }
+}
+
+/// Smallest ratio between the diagonal entries of the triangular factor of
+/// a least squares problem which isn't taken for it being rank deficient
+const CONDITION: f64 = 1e-10;
+
+/// Solve the linear least squares problem `min |a x - b|`, where `a` is
+/// made of `rows` (at least 3 of them), by Householder QR. `None` if `a` is
+/// (numerically) rank deficient.
+fn least_squares(rows: &[[f64; 3]], b: &[f64]) -> Option<[f64; 3]> {
+    let mut a = rows.to_vec();
+    let mut b = b.to_vec();
+    if a.len() < 3 || a.len() != b.len() {
+        return None;
+    }
+
+    // Reduce `a` to upper triangular, reflecting `b` along
+    for column in 0..3 {
+        let norm = a[column..].iter().map(|x| x[column].powi(2)).sum::<f64>()
+            .sqrt();
+        if norm == 0. {
+            return None;
+        }
+
+        // Reflector taking the column below the diagonal onto it
+        let alpha = if a[column][column] > 0. { -norm } else { norm };
+        let mut v: Vec<f64> = a[column..].iter().map(|x| x[column])
+            .collect();
+        v[0] -= alpha;
+        let length = v.iter().map(|x| x * x).sum::<f64>();
+        if length == 0. {
+            continue;
+        }
+
+        for other in column..3 {
+            let dot = a[column..].iter().zip(&v).map(|(x, v)| x[other] * v)
+                .sum::<f64>();
+            for (x, v) in a[column..].iter_mut().zip(&v) {
+                x[other] -= 2. * dot / length * v;
+            }
+        }
+        let dot = b[column..].iter().zip(&v).map(|(x, v)| x * v)
+            .sum::<f64>();
+        for (x, v) in b[column..].iter_mut().zip(&v) {
+            *x -= 2. * dot / length * v;
+        }
+    }
+
+    // The diagonal tells how close to rank deficient `a` is, relative to
+    // its scale
+    let largest = (0..3).map(|ii| a[ii][ii].abs()).fold(0., f64::max);
+    if (0..3).any(|ii| a[ii][ii].abs() <= largest * CONDITION) {
+        return None;
+    }
+
+    // Back substitution
+    let mut ret = [0.; 3];
+    for row in (0..3).rev() {
+        let known = (row + 1..3).map(|x| a[row][x] * ret[x]).sum::<f64>();
+        ret[row] = (b[row] - known) / a[row][row];
+    }
+    Some(ret)
+}
+
+/// Fit the USL to the fork rates `samples` of a curve, as thread counts and
+/// fork rates. `None` with fewer than 3 distinct thread counts, or if the
+/// fit is degenerate.
+pub fn fit(samples: &[(u64, f64)]) -> Option<Fit> {
+    let samples: Vec<(f64, f64)> = samples.iter()
+        .filter(|x| x.0 > 0 && x.1 > 0. && x.1.is_finite())
+        .map(|&(n, x)| (n as f64, x)).collect();
+    let mut threads: Vec<u64> = samples.iter().map(|x| x.0 as u64).collect();
+    threads.sort();
+    threads.dedup();
+    if threads.len() < 3 {
+        return None;
+    }
+
+    // Fit `N / X = c0 + c1 (N - 1) + c2 N (N - 1)`, with the thread counts
+    // scaled to at most 1 such that the terms are of similar magnitude
+    let scale = threads[threads.len() - 1] as f64;
+    let rows: Vec<[f64; 3]> = samples.iter().map(|&(n, _)| {
+        [1., (n - 1.) / scale, n * (n - 1.) / (scale * scale)]
+    }).collect();
+    let b: Vec<f64> = samples.iter().map(|&(n, x)| n / x).collect();
+    let [c0, c1, c2] = least_squares(&rows, &b)?;
+    let (c1, c2) = (c1 / scale, c2 / (scale * scale));
+    if c0 <= 0. {
+        return None;
+    }
+
+    let mut ret = Fit { lambda: 1. / c0, sigma: c1 / c0, kappa: c2 / c0,
+                        r2: 0. };
+
+    // How much of the variation of the fork rate the model explains
+    let mean = samples.iter().map(|x| x.1).sum::<f64>() /
+        samples.len() as f64;
+    let residual: f64 = samples.iter()
+        .map(|&(n, x)| (x - ret.throughput(n)).powi(2)).sum();
+    let total: f64 = samples.iter().map(|&(_, x)| (x - mean).powi(2)).sum();
+    ret.r2 = if total > 0. { 1. - residual / total } else { 1. };
+
+    Some(ret)
+}
+
+/// Fit the USL to every scaling curve of `summaries`, one for each
+/// combination of the axes other than the thread count (keyed by the test
+/// point with 0 threads). Curves which can't be fit are `None`.
+pub fn fit_curves(summaries: &[PointSummary])
+        -> BTreeMap<TestPoint, Option<Fit>> {
+    let mut curves: BTreeMap<TestPoint, Vec<(u64, f64)>> = BTreeMap::new();
+    for summary in summaries {
+        curves.entry(TestPoint { threads: 0, ..summary.point })
+            .or_default()
+            .push((summary.point.threads, summary.forks_per_second.mean));
+    }
+
+    curves.into_iter().map(|(curve, samples)| (curve, fit(&samples)))
+        .collect()
+}
+
+/// Write the USL fits of the scaling curves of `summaries` to `writer`, as
+/// CSV if `csv` is set
+pub fn write(writer: &mut dyn Write, summaries: &[PointSummary], csv: bool)
+        -> io::Result<()> {
+    let fits = fit_curves(summaries);
+    let na = if csv { "" } else { "-" };
+    let number = |value: Option<f64>, precision: usize| {
+        value.map(|x| format!("{:.*}", precision, x))
+            .unwrap_or_else(|| na.into())
+    };
+
+    if csv {
+        write!(writer, "effective_workload_insts,prefork_memory_mib,vmas,\
+            fds,idle_threads,lambda,sigma,kappa,r2,peak_threads\n")?;
+    } else {
+        write!(writer, "# universal scalability law fits of the fork rate\n\
+            # X(N) = lambda N / (1 + sigma (N - 1) + kappa N (N - 1))\n")?;
+        write!(writer, "{:>14} {:>8} {:>8} {:>8} {:>8} {:>12} {:>10} \
+            {:>12} {:>8} {:>8}\n", "workload", "memory", "vmas", "fds",
+            "idle", "lambda", "sigma", "kappa", "r2", "peak")?;
+    }
+
+    for (curve, fit) in &fits {
+        let lambda = number(fit.map(|x| x.lambda), 2);
+        let sigma  = number(fit.map(|x| x.sigma), 6);
+        let kappa  = number(fit.map(|x| x.kappa), 8);
+        let r2     = number(fit.map(|x| x.r2), 4);
+        let peak   = number(fit.and_then(|x| x.peak()), 1);
+        if csv {
+            write!(writer, "{},{},{},{},{},{},{},{},{},{}\n",
+                   curve.workload * INSTS_PER_WORKLOAD, curve.memory_mib,
+                   curve.vmas, curve.fds, curve.idle_threads, lambda, sigma,
+                   kappa, r2, peak)?;
+        } else {
+            write!(writer, "{:14} {:8} {:8} {:8} {:8} {:>12} {:>10} {:>12} \
+                {:>8} {:>8}\n", curve.workload * INSTS_PER_WORKLOAD,
+                curve.memory_mib, curve.vmas, curve.fds, curve.idle_threads,
+                lambda, sigma, kappa, r2, peak)?;
+        }
+    }
+
+    if !csv && fits.values().any(Option::is_none) {
+        write!(writer, "# curves with fewer than 3 thread counts can't be \
+            fit\n")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fork rates of a curve following the USL exactly
+    fn curve(lambda: f64, sigma: f64, kappa: f64, threads: &[u64])
+            -> Vec<(u64, f64)> {
+        let model = Fit { lambda, sigma, kappa, r2: 1. };
+        threads.iter().map(|&n| (n, model.throughput(n as f64))).collect()
+    }
+
+    /// Check `actual` is within `tolerance` of `expected`, relatively
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!((actual - expected).abs() <= expected.abs() * tolerance,
+                "{} isn't {}", actual, expected);
+    }
+
+    #[test]
+    fn recovers_parameters() {
+        let samples = curve(1000., 0.05, 0.001, &[1, 2, 4, 8, 16, 32, 64]);
+        let fit = fit(&samples).unwrap();
+        assert_close(fit.lambda, 1000., 1e-9);
+        assert_close(fit.sigma, 0.05, 1e-9);
+        assert_close(fit.kappa, 0.001, 1e-9);
+        assert_close(fit.r2, 1., 1e-9);
+        assert_close(fit.peak().unwrap(), (0.95f64 / 0.001).sqrt(), 1e-9);
+    }
+
+    #[test]
+    fn recovers_parameters_of_large_machines() {
+        // A tiny coherency cost only shows with thousands of threads, where
+        // the quadratic term dwarfs the others
+        let threads: Vec<u64> = (0..=12).map(|x| 1 << x).collect();
+        let fit = fit(&curve(250_000., 0.002, 2e-7, &threads)).unwrap();
+        assert_close(fit.lambda, 250_000., 1e-9);
+        assert_close(fit.sigma, 0.002, 1e-7);
+        assert_close(fit.kappa, 2e-7, 1e-7);
+    }
+
+    #[test]
+    fn recovers_parameters_without_single_thread() {
+        let fit = fit(&curve(500., 0.1, 0., &[2, 3, 5, 7])).unwrap();
+        assert_close(fit.lambda, 500., 1e-9);
+        assert_close(fit.sigma, 0.1, 1e-9);
+        assert!(fit.kappa.abs() < 1e-12, "{}", fit.kappa);
+
+        // Rounding leaves a coherency cost of next to nothing at most
+        assert!(fit.peak().is_none_or(|x| x > 1e5));
+    }
+
+    #[test]
+    fn needs_three_thread_counts() {
+        assert!(fit(&[]).is_none());
+        assert!(fit(&curve(1000., 0.05, 0.001, &[4, 4, 4])).is_none());
+
+        // Repetitions of the same thread counts don't add information
+        let samples = curve(1000., 0.05, 0.001, &[1, 8, 1, 8, 8]);
+        assert!(fit(&samples).is_none());
+
+        // Nor do samples which aren't a fork rate
+        let mut samples = curve(1000., 0.05, 0.001, &[1, 2, 4]);
+        samples[2].1 = 0.;
+        assert!(fit(&samples).is_none());
+        samples[2].1 = 1000.;
+        assert!(fit(&samples).is_some());
+    }
+
+    #[test]
+    fn least_squares_rejects_rank_deficient() {
+        let rows = [[1., 2., 3.], [2., 4., 6.], [3., 6., 9.], [1., 0., 1.]];
+        assert!(least_squares(&rows, &[1., 2., 3., 4.]).is_none());
+
+        let rows = [[1., 0., 0.], [0., 1., 0.], [0., 0., 1.], [1., 1., 1.]];
+        let x = least_squares(&rows, &[1., 2., 3., 6.]).unwrap();
+        for (x, expected) in x.iter().zip([1., 2., 3.]) {
+            assert_close(*x, expected, 1e-12);
+        }
+    }
+}
@@ -0,0 +1,331 @@
+//! Live view of a sweep running in another process, attached to through the
+//! shared memory holding its statistics
+//!
+//! `forkbench watch [<pid>]` finds the statistics among the open descriptors
+//! of the processes of the sweep (the `memfd_create()` file, or the removed
+//! file in the `--shm-dir` directory), maps them read-only and prints the
+//! fork rate of the sweep and of each of its workers until it exits. The
+//! fields are found through the versioned layout at the start of the
+//! statistics, such that the sweep may be run by another build.
+
+use std::time::Duration;
+
+/// Usage string printed for `watch --help` and on argument errors
+pub const USAGE: &str = r#"usage: forkbench watch [options] [<pid>]
+
+Follows the sweep running as process <pid> (or the only sweep running if no
+PID is given), printing how many of its workers are fuzzing, their fork rate
+and the fork rate of each of them until it exits. The sweep may be run by
+any build of forkbench with the same version of the statistics layout
+(Linux).
+
+options:
+    --interval <secs>           Interval the fork rate is sampled at
+                                (default 1)
+    --no-workers                Don't print the fork rate of each worker
+    --help                      Print this message
+"#;
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::collections::BTreeMap;
+    use std::fs::File;
+    use std::os::unix::fs::MetadataExt;
+    use std::os::unix::io::AsRawFd;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::{Duration, Instant};
+
+    use crate::{Layout, LAYOUT_MAGIC, LAYOUT_VERSION};
+    use crate::sampler;
+
+    /// Number of worker rates printed per line
+    const RATES_PER_LINE: usize = 6;
+
+    /// A process holding the statistics of a sweep open
+    struct Holder {
+        /// The process
+        pid: u32,
+
+        /// Parent of the process
+        ppid: u32,
+
+        /// Path the descriptor of the statistics can be opened through
+        path: PathBuf,
+    }
+
+    /// Get the parent of process `pid`
+    fn parent(pid: u32) -> Option<u32> {
+        // The command name may contain anything, the fields after it are
+        // separated by spaces
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid))
+            .ok()?;
+        stat[stat.rfind(')')? + 1..].split_whitespace().nth(1)?
+            .parse().ok()
+    }
+
+    /// Find the processes holding the statistics of a sweep open, which we
+    /// may inspect, by the inode of the statistics
+    fn find() -> BTreeMap<u64, Vec<Holder>> {
+        let mut ret: BTreeMap<u64, Vec<Holder>> = BTreeMap::new();
+        for entry in std::fs::read_dir("/proc").into_iter().flatten()
+                .flatten() {
+            let pid = match entry.file_name().to_str()
+                    .and_then(|x| x.parse().ok()) {
+                Some(pid) => pid,
+                None      => continue,
+            };
+
+            let fds = std::fs::read_dir(entry.path().join("fd"));
+            for fd in fds.into_iter().flatten().flatten() {
+                // Statistics are backed by an anonymous file or a removed
+                // file in the `--shm-dir` directory
+                let target = match std::fs::read_link(fd.path()) {
+                    Ok(target) => target.to_string_lossy().into_owned(),
+                    Err(_)     => continue,
+                };
+                let name = target.rsplit('/').next().unwrap_or("");
+                if !target.starts_with("/memfd:forkbench") &&
+                        !name.starts_with("forkbench_") {
+                    continue;
+                }
+
+                let (inode, ppid) = match (std::fs::metadata(fd.path()),
+                                           parent(pid)) {
+                    (Ok(metadata), Some(ppid)) => (metadata.ino(), ppid),
+                    _ => continue,
+                };
+                ret.entry(inode).or_default()
+                    .push(Holder { pid, ppid, path: fd.path() });
+            }
+        }
+        ret
+    }
+
+    /// Statistics of a sweep mapped read-only, accessed through their layout
+    struct Region {
+        /// Start of the mapping
+        ptr: *const u8,
+
+        /// Layout of the statistics, as described by the sweep
+        layout: Layout,
+    }
+
+    impl Region {
+        /// Get the field at `offset`
+        fn field(&self, offset: u64) -> u64 {
+            unsafe {
+                (*(self.ptr.add(offset as usize) as *const AtomicU64))
+                    .load(Ordering::SeqCst)
+            }
+        }
+
+        /// Number of workers fuzzing
+        fn workers(&self) -> u64 {
+            self.field(self.layout.workers)
+        }
+
+        /// Number of workers of the running test point
+        fn threads(&self) -> usize {
+            (self.field(self.layout.threads) as usize)
+                .min(self.layout.max_workers as usize)
+        }
+
+        /// Get the field at `offset` of the slot of worker `thr_id`
+        fn slot(&self, thr_id: usize, offset: u64) -> u64 {
+            self.field(self.layout.slots +
+                       thr_id as u64 * self.layout.slot_size + offset)
+        }
+
+        /// Number of fuzz cases created by each worker
+        fn forks(&self) -> Vec<u64> {
+            (0..self.layout.max_workers as usize)
+                .map(|x| self.slot(x, self.layout.forks)).collect()
+        }
+
+        /// Number of fuzz cases which failed to be created
+        fn fork_failures(&self) -> u64 {
+            (0..self.layout.max_workers as usize)
+                .map(|x| self.slot(x, self.layout.fork_failures)).sum()
+        }
+    }
+
+    /// Check that `layout` of a region of `len` bytes describes statistics
+    /// we know how to read
+    fn check(layout: &Layout, len: u64) -> bool {
+        // Every field has to be aligned and within what it's located in
+        let field = |offset: u64, size: u64| {
+            offset.is_multiple_of(8) && offset.saturating_add(8) <= size
+        };
+        let slots = layout.max_workers.checked_mul(layout.slot_size)
+            .and_then(|x| x.checked_add(layout.slots));
+
+        layout.magic == LAYOUT_MAGIC && layout.version == LAYOUT_VERSION &&
+            layout.size <= len &&
+            field(layout.workers, layout.size) &&
+            field(layout.threads, layout.size) &&
+            layout.slots.is_multiple_of(8) &&
+            layout.slot_size.is_multiple_of(8) &&
+            slots.is_some_and(|x| x <= layout.size) &&
+            field(layout.forks, layout.slot_size) &&
+            field(layout.fork_failures, layout.slot_size)
+    }
+
+    /// Map the statistics of the sweep running as process `pid`, or of the
+    /// only sweep running if `None`, returning the PID of the sweep. The
+    /// mapping lives until we exit.
+    fn attach(pid: Option<u32>) -> Result<(u32, Region), String> {
+        // The sweep is the holder whose parent doesn't hold its statistics,
+        // the others are its workers and their fuzz cases
+        let runs = find();
+        let sweeps: Vec<&Holder> = runs.values()
+            .filter(|holders| {
+                pid.is_none_or(|pid| holders.iter().any(|x| x.pid == pid))
+            })
+            .filter_map(|holders| holders.iter().find(|x| {
+                !holders.iter().any(|y| y.pid == x.ppid)
+            }))
+            .collect();
+        let sweep = match (sweeps.as_slice(), pid) {
+            ([sweep], _) => sweep,
+            ([], Some(pid)) => return Err(format!("Process {} isn't part \
+                of a running sweep (or can't be inspected)", pid)),
+            ([], None) => return Err(String::from("No running sweep found")),
+            _ => return Err(format!("Several sweeps are running ({}), pass \
+                the PID of one", sweeps.iter().map(|x| x.pid.to_string())
+                    .collect::<Vec<_>>().join(", "))),
+        };
+
+        let file = File::open(&sweep.path).map_err(|err| {
+            format!("Failed to open the statistics of {}: {}", sweep.pid,
+                    err)
+        })?;
+        let len = file.metadata().map(|x| x.len()).unwrap_or(0);
+        if len < core::mem::size_of::<Layout>() as u64 {
+            return Err(format!("The statistics of {} have no layout",
+                               sweep.pid));
+        }
+
+        let ptr = unsafe {
+            libc::mmap(core::ptr::null_mut(), len as usize, libc::PROT_READ,
+                       libc::MAP_SHARED, file.as_raw_fd(), 0)
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(format!("Failed to map the statistics of {}: {}",
+                               sweep.pid, std::io::Error::last_os_error()));
+        }
+
+        // Everything is located through the layout the sweep describes,
+        // which is only usable if we know its version
+        let layout = unsafe { core::ptr::read(ptr as *const Layout) };
+        if !check(&layout, len) {
+            return Err(format!("Sweep {} is run by a build of forkbench with \
+                an unknown statistics layout", sweep.pid));
+        }
+
+        Ok((sweep.pid, Region { ptr: ptr as *const u8, layout }))
+    }
+
+    /// Print the fork rate of the sweep running as process `pid` (or the
+    /// only one running) every `interval` until it exits, and the one of
+    /// each worker if `workers`
+    pub fn watch(pid: Option<u32>, interval: Duration, workers: bool)
+            -> Result<(), String> {
+        let (pid, shmem) = attach(pid)?;
+        let alive = || Path::new(&format!("/proc/{}", pid)).exists();
+
+        print!("# watching sweep {}\n", pid);
+        print!("{:>10} {:>8} {:>14} {:>14} {:>10}\n", "secs", "workers",
+               "forks/s", "forks/s/worker", "failures");
+
+        let start         = Instant::now();
+        let mut last      = shmem.forks();
+        let mut last_time = Instant::now();
+        while alive() {
+            std::thread::sleep(interval);
+
+            // Counts are reset between tests, only the forks since are
+            // known then
+            let forks = shmem.forks();
+            let secs  = last_time.elapsed().as_secs_f64();
+            let rates: Vec<f64> = forks.iter().zip(last.iter())
+                .map(|(&new, &old)| {
+                    sampler::delta(new, old) as f64 / secs
+                }).collect();
+            let rate    = rates.iter().sum::<f64>();
+            let fuzzing = shmem.workers();
+            last      = forks;
+            last_time = Instant::now();
+
+            print!("{:10.1} {:8} {:14.1} {:14.1} {:10}\n",
+                   start.elapsed().as_secs_f64(), fuzzing, rate,
+                   rate / fuzzing.max(1) as f64, shmem.fork_failures());
+
+            // The rate of each worker of the running test point, by worker
+            // ID
+            if workers && fuzzing > 0 {
+                let rates = &rates[..shmem.threads()];
+                for (line, chunk) in
+                        rates.chunks(RATES_PER_LINE).enumerate() {
+                    print!("{:10}", "");
+                    for (ii, rate) in chunk.iter().enumerate() {
+                        print!(" {:>4}:{:>6.0}",
+                               line * RATES_PER_LINE + ii, rate);
+                    }
+                    print!("\n");
+                }
+            }
+        }
+
+        print!("# sweep {} exited\n", pid);
+        Ok(())
+    }
+}
+
+/// Follow a sweep in another process, not supported on this platform
+#[cfg(not(target_os = "linux"))]
+fn watch(_pid: Option<u32>, _interval: Duration, _workers: bool)
+        -> Result<(), String> {
+    Err(String::from("`watch` is only supported on Linux"))
+}
+
+#[cfg(target_os = "linux")]
+use linux::watch;
+
+/// Follow the sweep named on the command line (not including the program
+/// name and command), printing its fork rate until it exits
+pub fn run(args: impl IntoIterator<Item = String>) -> Result<(), String> {
+    let mut interval = 1.0;
+    let mut workers = true;
+    let mut pid = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--interval" => {
+                interval = args.next()
+                    .ok_or("`--interval` requires a value")?
+                    .parse::<f64>()
+                    .map_err(|_| "Invalid value for `--interval`")?;
+            }
+            "--no-workers" => workers = false,
+            "--help" | "-h" => {
+                print!("{}", USAGE);
+                std::process::exit(0);
+            }
+            _ if arg.starts_with("--") =>
+                return Err(format!("Unknown argument `{}`", arg)),
+            _ if pid.is_none() => {
+                pid = Some(arg.parse::<u32>()
+                    .map_err(|_| format!("Invalid PID `{}`", arg))?);
+            }
+            _ => return Err(String::from("Expected at most one PID")),
+        }
+    }
+
+    if !(interval > 0.0 && interval.is_finite()) {
+        return Err(String::from("`--interval` must be positive"));
+    }
+
+    watch(pid, Duration::from_secs_f64(interval), workers)
+}
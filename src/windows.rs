@@ -0,0 +1,322 @@
+//! Windows process-creation backend
+//!
+//! Windows has no `fork()`, thus workers and fuzz cases are both created with
+//! `CreateProcessW()` of this binary acting as a stub, which attach to the
+//! statistics section by name.
+
+use std::ffi::c_void;
+use std::sync::atomic::Ordering;
+
+use crate::EXIT_ABORTED;
+use crate::error::{Error, Result};
+use crate::config::Config;
+use crate::threading;
+use crate::sweep::TestPoint;
+
+/// First argument passed to a worker stub process
+pub const WORKER_ARG: &str = "__forkbench_worker";
+
+/// First argument passed to a fuzz case stub process
+pub const HELPER_ARG: &str = "__forkbench_helper";
+
+const INFINITE:      u32 = 0xffffffff;
+const WAIT_OBJECT_0: u32 = 0;
+const WAIT_TIMEOUT:  u32 = 0x102;
+
+/// Milliseconds to wait on each worker before checking the others
+const WORKER_POLL_MS: u32 = 10;
+
+#[derive(Default)]
+#[repr(C)]
+#[allow(non_snake_case)]
+struct STARTUPINFOW {
+    cb:              u32,
+    lpReserved:      usize,
+    lpDesktop:       usize,
+    lpTitle:         usize,
+    dwX:             u32,
+    dwY:             u32,
+    dwXSize:         u32,
+    dwYSize:         u32,
+    dwXCountChars:   u32,
+    dwYCountChars:   u32,
+    dwFillAttribute: u32,
+    dwFlags:         u32,
+    wShowWindow:     u16,
+    cbReserved2:     u16,
+    lpReserved2:     usize,
+    hStdInput:       usize,
+    hStdOutput:      usize,
+    hStdError:       usize,
+}
+
+#[derive(Default)]
+#[repr(C)]
+#[allow(non_snake_case)]
+struct PROCESS_INFORMATION {
+    hProcess:    usize,
+    hThread:     usize,
+    dwProcessId: u32,
+    dwThreadId:  u32,
+}
+
+extern "system" {
+    fn CreateProcessW(lpApplicationName: *const u16,
+                      lpCommandLine: *mut u16,
+                      lpProcessAttributes: *mut c_void,
+                      lpThreadAttributes: *mut c_void,
+                      bInheritHandles: i32, dwCreationFlags: u32,
+                      lpEnvironment: *mut c_void,
+                      lpCurrentDirectory: *const u16,
+                      lpStartupInfo: *mut STARTUPINFOW,
+                      lpProcessInformation: *mut PROCESS_INFORMATION) -> i32;
+
+    fn WaitForSingleObject(hHandle: usize, dwMilliseconds: u32) -> u32;
+
+    fn GetExitCodeProcess(hProcess: usize, lpExitCode: *mut u32) -> i32;
+
+    fn TerminateProcess(hProcess: usize, uExitCode: u32) -> i32;
+
+    fn CloseHandle(hObject: usize) -> i32;
+}
+
+/// Convert a string into a null terminated UTF-16 string
+pub fn wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(Some(0)).collect()
+}
+
+/// Launch this binary with `args`, returning the process handle
+fn spawn_stub(args: &[String]) -> usize {
+    let exe = std::env::current_exe()
+        .expect("Failed to get path to the current executable");
+
+    // Build the command line, the first argument is the program name
+    let mut cmdline = format!("\"{}\"", exe.display());
+    for arg in args {
+        cmdline.push(' ');
+        cmdline.push_str(arg);
+    }
+    let mut cmdline = wide(&cmdline);
+
+    let mut si = STARTUPINFOW::default();
+    si.cb = core::mem::size_of::<STARTUPINFOW>() as u32;
+    let mut pi = PROCESS_INFORMATION::default();
+
+    unsafe {
+        assert!(CreateProcessW(core::ptr::null(), cmdline.as_mut_ptr(),
+            core::ptr::null_mut(), core::ptr::null_mut(), 0, 0,
+            core::ptr::null_mut(), core::ptr::null(), &mut si, &mut pi) != 0,
+            "CreateProcessW() failed");
+
+        // We never need the primary thread
+        CloseHandle(pi.hThread);
+    }
+
+    pi.hProcess
+}
+
+/// Wait for a process to exit and close its handle, returning its exit code
+fn wait_process(process: usize) -> u32 {
+    let mut exit_code = 0;
+    unsafe {
+        assert!(WaitForSingleObject(process, INFINITE) == WAIT_OBJECT_0);
+        assert!(GetExitCodeProcess(process, &mut exit_code) != 0);
+        CloseHandle(process);
+    }
+    exit_code
+}
+
+/// Run the worker processes for a single test point and wait for them all to
+/// exit while `monitor` (called with the number of workers) watches them.
+/// Fails with the first failure of any worker.
+pub fn run_workers<T>(config: &Config, point: TestPoint,
+                      monitor: impl FnOnce(usize) -> T) -> Result<()> {
+    let workers: Vec<usize> = (0..point.threads).map(|thr_id| {
+        spawn_stub(&[
+            WORKER_ARG.into(),
+            crate::shmem::handle().into(),
+            config.processors[thr_id as usize].cpu().to_string(),
+            point.threads.to_string(),
+            point.workload.to_string(),
+            config.duration_ticks().to_string(),
+            config.dirty_memory.unwrap_or(0).to_string(),
+            point.memory_mib.to_string(),
+            crate::clock::clock().name().into(),
+            thr_id.to_string(),
+            config.warmup.to_string(),
+            config.iterations.unwrap_or(0).to_string(),
+        ])
+    }).collect();
+
+    // Watch the workers as they run
+    let watch = monitor(workers.len());
+
+    // Poll the workers such that a failed one releases the others right
+    // away, rather than leaving them waiting for it forever, and such that
+    // they can be killed once they time out
+    let shmem = unsafe { crate::shmem::get() };
+    let start = std::time::Instant::now();
+    let mut timed_out = false;
+    let mut codes: Vec<Option<u32>> = vec![None; workers.len()];
+    while codes.iter().any(|x| x.is_none()) {
+        if !timed_out && config.timeout_secs
+                .is_some_and(|x| start.elapsed().as_secs_f64() > x) {
+            for (worker, code) in workers.iter().zip(codes.iter()) {
+                if code.is_none() {
+                    unsafe { TerminateProcess(*worker, 1); }
+                }
+            }
+            timed_out = true;
+        }
+
+        for (worker, code) in workers.iter().zip(codes.iter_mut()) {
+            if code.is_some() {
+                continue;
+            }
+
+            let status = unsafe {
+                WaitForSingleObject(*worker, WORKER_POLL_MS)
+            };
+            if status == WAIT_TIMEOUT {
+                continue;
+            }
+            assert!(status == WAIT_OBJECT_0);
+
+            let mut exit_code = 0;
+            unsafe {
+                assert!(GetExitCodeProcess(*worker, &mut exit_code) != 0);
+                CloseHandle(*worker);
+            }
+            if exit_code != 0 {
+                shmem.abort();
+            }
+            *code = Some(exit_code);
+        }
+    }
+    drop(watch);
+
+    if timed_out {
+        return Err(Error::Timeout {
+            secs: config.timeout_secs.unwrap_or(0.),
+        });
+    }
+
+    // Workers can't record errors on Windows, report the first failure by
+    // its exit code
+    for (thr_id, code) in codes.iter().enumerate() {
+        match code.unwrap() as i32 {
+            0 | EXIT_ABORTED => {}
+            code => return Err(Error::WorkerExited { thr_id, code }),
+        }
+    }
+    if shmem.failed.load(Ordering::SeqCst) != 0 {
+        return Err(Error::Aborted);
+    }
+
+    Ok(())
+}
+
+/// If this process was launched as a worker or fuzz case stub, run the stub
+/// and exit, otherwise return
+pub fn dispatch_stub() {
+    let args: Vec<String> = std::env::args().collect();
+
+    match args.get(1).map(|x| x.as_str()) {
+        Some(WORKER_ARG) => {
+            assert!(args.len() == 13, "Invalid worker arguments");
+            let cpu: usize         = args[3].parse().unwrap();
+            let num_threads: u64   = args[4].parse().unwrap();
+            let workload: u64      = args[5].parse().unwrap();
+            let duration: u64      = args[6].parse().unwrap();
+
+            unsafe { crate::shmem::attach(&args[2]) }.unwrap_or_else(|err|
+                panic!("Failed to attach to shared memory: {}", err));
+            let shmem = unsafe { crate::shmem::get() };
+            crate::clock::select(args[9].parse().unwrap());
+            let thr_id: usize = args[10].parse().unwrap();
+            let warmup: u64   = args[11].parse().unwrap();
+            let iters: u64    = args[12].parse().unwrap();
+
+            // Pin to a specific processor
+            let procs = threading::get_logical_processors();
+            threading::pin_to_logical_processor(procs[cpu]).unwrap_or_else(
+                |err| panic!("Failed to pin to processor {}: {}", cpu, err));
+
+            // Make the requested amount of memory resident before spawning
+            let memory_mib: usize = args[8].parse().unwrap();
+            let _prefork = vec![5u8; memory_mib * 1024 * 1024];
+
+            // Wait for all workers to be started
+            if shmem.start(num_threads).is_err() {
+                std::process::exit(EXIT_ABORTED);
+            }
+
+            let helper_args = [
+                HELPER_ARG.into(),
+                args[2].clone(),
+                thr_id.to_string(),
+                workload.to_string(),
+                args[7].clone(),
+                args[9].clone(),
+            ];
+            let stats = shmem.slot(thr_id);
+            let iteration = || {
+                let it = stats.start_iteration();
+                let process = spawn_stub(&helper_args);
+                let wait = crate::clock::now();
+                let exit_code = wait_process(process);
+                stats.reap_cycles.fetch_add(crate::clock::now() - wait,
+                                            Ordering::Relaxed);
+                if exit_code != 0 {
+                    stats.crashes.fetch_add(1, Ordering::Relaxed);
+                }
+                stats.record_latency(crate::clock::now() - it);
+                stats.forks.fetch_add(1, Ordering::Relaxed);
+            };
+
+            // Warm up without keeping any statistics
+            if warmup > 0 {
+                let timeout = crate::clock::now() + warmup;
+                while crate::clock::now() < timeout {
+                    iteration();
+                }
+                if shmem.finish_warmup(thr_id, num_threads).is_err() {
+                    std::process::exit(EXIT_ABORTED);
+                }
+            }
+
+            if iters > 0 {
+                for _ in 0..iters {
+                    iteration();
+                }
+            } else {
+                let timeout = crate::clock::now() + duration;
+                while crate::clock::now() < timeout {
+                    iteration();
+                }
+            }
+
+            // We're done working
+            shmem.workers.fetch_sub(1, Ordering::SeqCst);
+            std::process::exit(0);
+        }
+        Some(HELPER_ARG) => {
+            assert!(args.len() == 7, "Invalid helper arguments");
+            let thr_id: usize = args[3].parse().unwrap();
+            let workload: u64 = args[4].parse().unwrap();
+            let dirty: usize  = args[5].parse().unwrap();
+            crate::clock::select(args[6].parse().unwrap());
+
+            unsafe { crate::shmem::attach(&args[2]) }.unwrap_or_else(|err|
+                panic!("Failed to attach to shared memory: {}", err));
+            let shmem = unsafe { crate::shmem::get() };
+
+            let mut dirtyme = vec![0u8; dirty];
+            crate::fuzz_case(shmem.slot(thr_id), None,
+                &crate::workload::StackLoads { iterations: workload },
+                &mut dirtyme);
+            std::process::exit(0);
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,327 @@
+//! Work done by each fuzz case, inside the process (or thread) created for it
+//!
+//! The benchmark runs one of the built-in kernels selected with
+//! `--workload-kind`, scaled by the workload of the test point. Library
+//! users can supply any other `Workload` (including a closure), as long as
+//! the backend runs fuzz cases in a copy of the benchmark process rather
+//! than a freshly launched executable.
+
+use std::arch::asm;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::config::Config;
+
+/// Number of buffer accesses per iteration of the buffer kernels, matching
+/// the loads per iteration of `StackLoads`
+const ACCESSES_PER_ITERATION: u64 = 16;
+
+/// Number of operations per iteration of each chain of `DependencyChains`
+const OPS_PER_ITERATION: usize = 16;
+
+/// Built-in kernels which can be selected on the command line
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WorkloadKind {
+    /// `StackLoads`, touching nothing beyond the stack
+    Stack,
+
+    /// `DependencyChains` of integer and floating-point arithmetic, touching
+    /// no memory at all
+    Compute,
+
+    /// Read and write consecutive words of a buffer
+    Sequential,
+
+    /// Read and write words of a buffer a fixed stride apart
+    Strided,
+
+    /// Read and write uniformly random words of a buffer
+    Random,
+
+    /// `getpid()` system calls, made with `syscall()` such that each one
+    /// enters the kernel
+    Getpid,
+
+    /// `clock_gettime()` system calls, made with `syscall()` rather than
+    /// through the vDSO such that each one enters the kernel
+    ClockGettime,
+}
+
+impl std::str::FromStr for WorkloadKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stack"      => Ok(WorkloadKind::Stack),
+            "compute"    => Ok(WorkloadKind::Compute),
+            "sequential" => Ok(WorkloadKind::Sequential),
+            "strided"    => Ok(WorkloadKind::Strided),
+            "random"     => Ok(WorkloadKind::Random),
+            "getpid"     => Ok(WorkloadKind::Getpid),
+            "clock_gettime" => Ok(WorkloadKind::ClockGettime),
+            _ => Err(format!("Unknown workload kind `{}`", s)),
+        }
+    }
+}
+
+impl WorkloadKind {
+    /// Name of the kind, as selected on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            WorkloadKind::Stack      => "stack",
+            WorkloadKind::Compute    => "compute",
+            WorkloadKind::Sequential => "sequential",
+            WorkloadKind::Strided    => "strided",
+            WorkloadKind::Random     => "random",
+            WorkloadKind::Getpid     => "getpid",
+            WorkloadKind::ClockGettime => "clock_gettime",
+        }
+    }
+
+    /// Whether this kind can be used on the current platform
+    pub fn is_supported(&self) -> bool {
+        match self {
+            WorkloadKind::Getpid | WorkloadKind::ClockGettime =>
+                cfg!(target_os = "linux"),
+            _ => true,
+        }
+    }
+
+    /// Whether fuzz cases of this kind access a buffer inherited from the
+    /// worker
+    pub fn uses_buffer(&self) -> bool {
+        matches!(self, WorkloadKind::Sequential | WorkloadKind::Strided |
+                       WorkloadKind::Random)
+    }
+
+    /// Create the kernel of this kind doing `iterations` iterations per fuzz
+    /// case. Any buffer is allocated and made resident here, such that it is
+    /// inherited by the fuzz cases.
+    pub fn workload(&self, config: &Config, iterations: u64)
+            -> Box<dyn Workload> {
+        match self {
+            WorkloadKind::Stack => Box::new(StackLoads { iterations }),
+            WorkloadKind::Compute =>
+                Box::new(DependencyChains { iterations }),
+            WorkloadKind::Sequential | WorkloadKind::Strided |
+                WorkloadKind::Random =>
+                Box::new(BufferWalk::new(*self, config.working_set,
+                                         config.stride, iterations)),
+            #[cfg(target_os = "linux")]
+            WorkloadKind::Getpid | WorkloadKind::ClockGettime =>
+                Box::new(Syscalls { kind: *self, iterations }),
+            #[allow(unreachable_patterns)]
+            _ => unreachable!("Workload kind {} is not supported",
+                              self.name()),
+        }
+    }
+}
+
+/// Work done by a single fuzz case
+pub trait Workload: Sync {
+    /// Do the work once. The result is consumed by the benchmark, such that
+    /// work it depends on can't be optimized out.
+    fn run(&self) -> u64;
+}
+
+impl<F: Fn() -> u64 + Sync> Workload for F {
+    fn run(&self) -> u64 {
+        self()
+    }
+}
+
+/// Tight loop of `iterations` iterations, each doing 16 loads from the
+/// stack, a decrement and a branch. Touches no memory beyond the stack, so
+/// a copy-on-write fork has nothing to copy.
+#[derive(Clone, Copy, Debug)]
+pub struct StackLoads {
+    /// Number of iterations of the loop
+    pub iterations: u64,
+}
+
+impl Workload for StackLoads {
+    fn run(&self) -> u64 {
+        let workload = self.iterations;
+
+        #[cfg(target_arch = "x86_64")]
+        unsafe {
+            asm!(r#"
+
+                test rcx, rcx
+                jz   3f
+
+                mov rax, rcx
+            2:
+            .rept 16
+                mov rdx, [rsp]
+            .endr
+
+                dec rax
+                jnz 2b
+
+            3:
+
+            "#, in("rcx") workload, out("rax") _, out("rdx") _);
+        }
+
+        // Same loop for aarch64, 16 loads from the stack, a decrement and a
+        // branch
+        #[cfg(target_arch = "aarch64")]
+        unsafe {
+            asm!(r#"
+
+                cbz {workload}, 3f
+
+                mov {count}, {workload}
+            2:
+            .rept 16
+                ldr {scratch}, [sp]
+            .endr
+
+                subs {count}, {count}, #1
+                b.ne 2b
+
+            3:
+
+            "#, workload = in(reg) workload, count = out(reg) _,
+                scratch = out(reg) _);
+        }
+
+        workload
+    }
+}
+
+/// Two independent dependency chains, one of integer multiply-adds and one
+/// of floating-point multiply-adds, each doing `OPS_PER_ITERATION`
+/// operations per iteration. Everything stays in registers, so the time
+/// spent is independent of the memory system.
+#[derive(Clone, Copy, Debug)]
+pub struct DependencyChains {
+    /// Number of iterations of the chains
+    pub iterations: u64,
+}
+
+impl Workload for DependencyChains {
+    fn run(&self) -> u64 {
+        // Start from values the compiler can't see, such that nothing is
+        // computed ahead of time
+        let mut int = std::hint::black_box(1u64);
+        let mut fp  = std::hint::black_box(1f64);
+
+        for _ in 0..self.iterations {
+            for _ in 0..OPS_PER_ITERATION {
+                int = int.wrapping_mul(0x5851f42d4c957f2d).wrapping_add(1);
+                fp  = fp * 0.999_999_9 + 1e-7;
+            }
+        }
+
+        int ^ fp.to_bits()
+    }
+}
+
+/// Walk over a buffer of 64-bit words in the pattern of a `WorkloadKind`,
+/// each iteration reading and writing `ACCESSES_PER_ITERATION` of them. The
+/// writes make a forked fuzz case copy every page it touches.
+#[derive(Debug)]
+pub struct BufferWalk {
+    /// Pattern the buffer is walked in
+    kind: WorkloadKind,
+
+    /// The buffer walked over, atomics such that it can be written to
+    /// through a shared reference
+    buffer: Vec<AtomicU64>,
+
+    /// Distance between strided accesses, in words
+    stride: usize,
+
+    /// Number of iterations per fuzz case
+    iterations: u64,
+}
+
+impl BufferWalk {
+    /// Allocate and fill a buffer of `size` bytes walked in the pattern of
+    /// `kind`, strided walks advance `stride` bytes per access
+    pub fn new(kind: WorkloadKind, size: usize, stride: usize,
+               iterations: u64) -> Self {
+        let words = (size / 8).max(1);
+
+        // Fill the buffer with non-zero values, such that it's resident
+        // rather than mapping the zero page
+        let buffer = (1..=words as u64).map(AtomicU64::new).collect();
+
+        BufferWalk {
+            kind,
+            buffer,
+            stride: (stride / 8) % words,
+            iterations,
+        }
+    }
+}
+
+impl Workload for BufferWalk {
+    fn run(&self) -> u64 {
+        let words = self.buffer.len();
+
+        let mut index = 0;
+        let mut state = 0x2545f4914f6cdd1du64;
+        let mut sum = 0u64;
+        for _ in 0..self.iterations * ACCESSES_PER_ITERATION {
+            // Plain loads and stores, nothing is written concurrently
+            let word = &self.buffer[index];
+            let value = word.load(Ordering::Relaxed);
+            word.store(value.wrapping_add(1), Ordering::Relaxed);
+            sum = sum.wrapping_add(value);
+
+            index = match self.kind {
+                WorkloadKind::Sequential => {
+                    if index + 1 == words { 0 } else { index + 1 }
+                }
+                WorkloadKind::Strided => {
+                    let next = index + self.stride;
+                    if next >= words { next - words } else { next }
+                }
+                _ => {
+                    // xorshift64, scaled to the buffer without a division
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    ((state as u128 * words as u128) >> 64) as usize
+                }
+            };
+        }
+
+        sum
+    }
+}
+
+/// System calls which enter the kernel every time, one per iteration
+#[cfg(target_os = "linux")]
+#[derive(Clone, Copy, Debug)]
+pub struct Syscalls {
+    /// Which system call is made, `Getpid` or `ClockGettime`
+    kind: WorkloadKind,
+
+    /// Number of system calls per fuzz case
+    iterations: u64,
+}
+
+#[cfg(target_os = "linux")]
+impl Workload for Syscalls {
+    fn run(&self) -> u64 {
+        let mut sum = 0u64;
+        for _ in 0..self.iterations {
+            let ret = unsafe {
+                if self.kind == WorkloadKind::Getpid {
+                    libc::syscall(libc::SYS_getpid)
+                } else {
+                    let mut ts: libc::timespec = core::mem::zeroed();
+                    libc::syscall(libc::SYS_clock_gettime,
+                                  libc::CLOCK_MONOTONIC, &mut ts);
+                    ts.tv_nsec as libc::c_long
+                }
+            };
+            sum = sum.wrapping_add(ret as u64);
+        }
+
+        sum
+    }
+}